@@ -0,0 +1,91 @@
+// The reader thread's per-event work: parsing a js_event and folding it into
+// the key input, over a mix close to a song's (mostly turntable movement,
+// with key presses and releases in between).
+
+use beatble::emulation::Emulation;
+use beatble::input::{convert_scratch, update_key_input, Event, InputMapping, SCRATCH_SENSITIVITY};
+use beatble::KeyInput;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const BUTTON: u8 = 0x01;
+const AXIS: u8 = 0x02;
+
+fn js_event(time: u32, value: i16, typ: u8, number: u8) -> [u8; 8] {
+    let mut buf = [0u8; 8];
+    buf[..4].copy_from_slice(&time.to_ne_bytes());
+    buf[4..6].copy_from_slice(&value.to_ne_bytes());
+    buf[6] = typ;
+    buf[7] = number;
+    buf
+}
+
+// three axis events to every button event, keys pressed and released in turn
+fn event_mix(len: usize) -> Vec<[u8; 8]> {
+    let mut position = 0i16;
+    (0..len)
+        .map(|i| {
+            let time = i as u32;
+            if i % 4 == 3 {
+                let key = (i / 4 % 7) as u8;
+                let pressed = i / 4 % 2 == 0;
+                js_event(time, pressed as i16, BUTTON, key)
+            } else {
+                position = (position + 3) % 256;
+                js_event(time, position, AXIS, 0)
+            }
+        })
+        .collect()
+}
+
+fn parse(c: &mut Criterion) {
+    let raw = event_mix(1024);
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(raw.len() as u64));
+    group.bench_function("event_mix", |b| {
+        b.iter(|| {
+            for &buf in &raw {
+                black_box(Event::parse(black_box(buf)));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn update(c: &mut Criterion) {
+    let events: Vec<Event> = event_mix(1024)
+        .into_iter()
+        .filter_map(Event::parse)
+        .collect();
+    let mut group = c.benchmark_group("update_key_input");
+    group.throughput(Throughput::Elements(events.len() as u64));
+    for emulation in [Emulation::Iidx, Emulation::Sdvx] {
+        let mapping = InputMapping::from(emulation);
+        group.bench_function(emulation.to_string(), |b| {
+            b.iter(|| {
+                let mut key_input = KeyInput::init();
+                for event in &events {
+                    update_key_input(&mut key_input, black_box(event), &mapping);
+                }
+                black_box(key_input)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn scratch(c: &mut Criterion) {
+    let values: Vec<i16> = (0..256).map(|v| v << 8).collect();
+    let mut group = c.benchmark_group("convert_scratch");
+    group.throughput(Throughput::Elements(values.len() as u64));
+    group.bench_function("full_rotation", |b| {
+        b.iter(|| {
+            for &value in &values {
+                black_box(convert_scratch(black_box(value), SCRATCH_SENSITIVITY));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse, update, scratch);
+criterion_main!(benches);
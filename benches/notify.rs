@@ -0,0 +1,82 @@
+// Heap allocations of the notify loop in steady state, counted by a global
+// allocator around a local notifier. Not a criterion bench: the count is the
+// result, and the frame rate is the notifier's own.
+
+use std::alloc::{GlobalAlloc, Layout as AllocLayout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use beatble::ble::{spawn_local_notifier, NotifyConfig, NotifyContext, NotifyMode, RepeatSpacing};
+use beatble::control::Control;
+use beatble::emulation::Emulation;
+use beatble::input::SharedKeyInput;
+use beatble::latency::Latency;
+use beatble::payload::{Layout, ScratchMode};
+use beatble::stats::Stats;
+use futures::StreamExt;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: AllocLayout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: AllocLayout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const WARMUP: usize = 100;
+const FRAMES: u64 = 1000;
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .expect("a runtime");
+    runtime.block_on(async {
+        let interval = Duration::from_millis(1);
+        let context = NotifyContext {
+            key_input: Arc::new(SharedKeyInput::new()),
+            control: Arc::new(Control::new(interval, NotifyMode::Periodic)),
+            latency: Arc::new(Latency::new()),
+            stats: Arc::new(Stats::new()),
+            dump: None,
+        };
+        let config = NotifyConfig {
+            interval,
+            mode: NotifyMode::Periodic,
+            warmup_frames: 0,
+            counter_start: 0,
+            frame_repeat: 1,
+            repeat_spacing: RepeatSpacing::BackToBack,
+            busy_poll: false,
+            layout: Layout::default(),
+            emulation: Emulation::Iidx,
+            scratch_mode: ScratchMode::Position,
+            scratch_predict: None,
+        };
+        let mut frames = spawn_local_notifier(context, config);
+        // past the first timer registrations
+        for _ in 0..WARMUP {
+            frames.next().await;
+        }
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..FRAMES {
+            frames.next().await;
+        }
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!(
+            "notify: {allocations} allocations in {FRAMES} frames ({:.2} per frame)",
+            allocations as f64 / FRAMES as f64
+        );
+    });
+}
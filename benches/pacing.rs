@@ -0,0 +1,64 @@
+// How evenly the notifier spaces its frames, with and without --busy-poll,
+// from the frame interval histogram it keeps for --stats. Not a criterion
+// bench: the spread of the intervals is the result.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use beatble::ble::{spawn_local_notifier, NotifyConfig, NotifyContext, NotifyMode, RepeatSpacing};
+use beatble::control::Control;
+use beatble::emulation::Emulation;
+use beatble::input::SharedKeyInput;
+use beatble::latency::Latency;
+use beatble::payload::{Layout, ScratchMode};
+use beatble::stats::Stats;
+use futures::StreamExt;
+
+const INTERVAL: Duration = Duration::from_millis(8);
+const WARMUP: usize = 50;
+const FRAMES: usize = 1000;
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_time()
+        .build()
+        .expect("a runtime");
+    for busy_poll in [false, true] {
+        runtime.block_on(async {
+            let context = NotifyContext {
+                key_input: Arc::new(SharedKeyInput::new()),
+                control: Arc::new(Control::new(INTERVAL, NotifyMode::Periodic)),
+                latency: Arc::new(Latency::new()),
+                stats: Arc::new(Stats::new()),
+                dump: None,
+            };
+            let config = NotifyConfig {
+                interval: INTERVAL,
+                mode: NotifyMode::Periodic,
+                warmup_frames: 0,
+                counter_start: 0,
+                frame_repeat: 1,
+                repeat_spacing: RepeatSpacing::BackToBack,
+                busy_poll,
+                layout: Layout::default(),
+                emulation: Emulation::Iidx,
+                scratch_mode: ScratchMode::Position,
+                scratch_predict: None,
+            };
+            let latency = Arc::clone(&context.latency);
+            let mut frames = spawn_local_notifier(context, config);
+            for _ in 0..WARMUP {
+                frames.next().await;
+            }
+            latency.reset();
+            for _ in 0..FRAMES {
+                frames.next().await;
+            }
+            let busy_poll = if busy_poll { "busy-poll" } else { "sleep" };
+            println!(
+                "pacing ({busy_poll}): frame interval {}",
+                latency.frame_interval.summary()
+            );
+        });
+    }
+}
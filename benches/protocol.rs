@@ -0,0 +1,62 @@
+// The notifier's per-frame work: loading the shared key input and encoding it
+// into a notification, for every layout a receiver can ask for.
+
+use beatble::payload::{Frame, Layout, PayloadFormat};
+use beatble::{KeyInput, NormalButton, OptionButton};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn key_input() -> KeyInput {
+    KeyInput {
+        scratch: 0x7f,
+        normal_button: NormalButton::B1 | NormalButton::B4,
+        option_button: OptionButton::E2,
+        analog: 0x40,
+    }
+}
+
+fn pack(c: &mut Criterion) {
+    let key_input = key_input();
+    let packed = key_input.pack();
+    let mut group = c.benchmark_group("key_input");
+    group.bench_function("pack", |b| b.iter(|| black_box(key_input).pack()));
+    group.bench_function("unpack", |b| b.iter(|| KeyInput::unpack(black_box(packed))));
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let frame = Frame::new(key_input(), key_input(), 0x10);
+    let layouts = [
+        ("v1", Layout::default()),
+        (
+            "v2",
+            Layout {
+                format: PayloadFormat::V2,
+                ..Layout::default()
+            },
+        ),
+        (
+            "v1_hires",
+            Layout {
+                scratch_hires: true,
+                ..Layout::default()
+            },
+        ),
+        (
+            "v1_single",
+            Layout {
+                single_report: true,
+                ..Layout::default()
+            },
+        ),
+    ];
+    let mut group = c.benchmark_group("encode");
+    for (name, layout) in layouts {
+        group.bench_function(name, |b| {
+            b.iter(|| black_box(&frame).encode(black_box(layout)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pack, encode);
+criterion_main!(benches);
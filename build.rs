@@ -1,19 +1,24 @@
+use std::env;
 use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const PACKAGE_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-fn git_version() -> Option<String> {
-    let output = Command::new("git")
-        .args(["describe", "--always", "--dirty=-modified"])
-        .output()
-        .ok()?;
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
     if output.status.success() {
-        String::from_utf8(output.stdout).ok()
+        String::from_utf8(output.stdout)
+            .ok()
+            .map(|s| s.trim().to_owned())
     } else {
         None
     }
 }
 
+fn git_version() -> Option<String> {
+    output("git", &["describe", "--always", "--dirty=-modified"])
+}
+
 fn version() -> String {
     format!(
         "{}-{}",
@@ -22,7 +27,64 @@ fn version() -> String {
     )
 }
 
+fn git_dirty() -> bool {
+    output("git", &["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty())
+}
+
+/// UTC date of the build, or of SOURCE_DATE_EPOCH for reproducible builds
+fn build_date() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs())
+        });
+    // days to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+fn rustc_version() -> Option<String> {
+    output(&env::var("RUSTC").ok()?, &["--version"])
+}
+
+/// enabled cargo features, e.g. "dbus,metrics"
+fn features() -> String {
+    let mut features = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect::<Vec<_>>();
+    features.sort();
+    features.join(",")
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=.git");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
     println!("cargo::rustc-env=VERSION={}", version());
+    println!(
+        "cargo::rustc-env=BUILD_GIT_COMMIT={}",
+        output("git", &["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".to_owned())
+    );
+    println!("cargo::rustc-env=BUILD_GIT_DIRTY={}", git_dirty());
+    println!("cargo::rustc-env=BUILD_DATE={}", build_date());
+    println!(
+        "cargo::rustc-env=BUILD_RUSTC={}",
+        rustc_version().unwrap_or_else(|| "unknown".to_owned())
+    );
+    println!("cargo::rustc-env=BUILD_FEATURES={}", features());
 }
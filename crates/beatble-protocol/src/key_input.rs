@@ -0,0 +1,176 @@
+use std::mem::size_of;
+
+use bitflags::bitflags;
+
+bitflags! {
+    // serialized as button names, e.g. "B1 | B3"
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "serde", serde(transparent))]
+    pub struct NormalButton: u8 {
+        const B1 = 0b00000001;
+        const B2 = 0b00000010;
+        const B3 = 0b00000100;
+        const B4 = 0b00001000;
+        const B5 = 0b00010000;
+        const B6 = 0b00100000;
+        const B7 = 0b01000000;
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "serde", serde(transparent))]
+    pub struct OptionButton: u8 {
+        const E1 = 0b0001;
+        const E2 = 0b0010;
+        const E3 = 0b0100;
+        const E4 = 0b1000;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyInput {
+    pub scratch: u8,
+    pub normal_button: NormalButton,
+    pub option_button: OptionButton,
+    /// second analog byte: low byte of the scratch position for IIDX, VOL-R for SDVX
+    pub analog: u8,
+}
+
+impl KeyInput {
+    pub fn init() -> Self {
+        Self {
+            scratch: 0x00,
+            normal_button: NormalButton::empty(),
+            option_button: OptionButton::empty(),
+            analog: 0x00,
+        }
+    }
+
+    /// scratch, normal buttons, option buttons and the analog byte, lowest byte first
+    #[inline]
+    pub fn pack(self) -> u32 {
+        u32::from_le_bytes([
+            self.scratch,
+            self.normal_button.bits(),
+            self.option_button.bits(),
+            self.analog,
+        ])
+    }
+
+    #[inline]
+    pub fn unpack(packed: u32) -> Self {
+        let [scratch, normal_button, option_button, analog] = packed.to_le_bytes();
+        Self {
+            scratch,
+            normal_button: NormalButton::from_bits_truncate(normal_button),
+            option_button: OptionButton::from_bits_truncate(option_button),
+            analog,
+        }
+    }
+
+    /// the low byte only reaches the wire with a hi-res payload
+    #[inline]
+    pub fn set_scratch_position(&mut self, position: u16) {
+        [self.scratch, self.analog] = position.to_be_bytes();
+    }
+
+    /// the 16-bit turntable position set_scratch_position stored
+    #[inline]
+    pub fn scratch_position(self) -> u16 {
+        u16::from_be_bytes([self.scratch, self.analog])
+    }
+}
+
+// pack() stores every field in one byte of a u32
+const _: () = assert!(size_of::<NormalButton>() == 1);
+const _: () = assert!(size_of::<OptionButton>() == 1);
+const _: () = assert!(size_of::<KeyInput>() == size_of::<u32>());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_puts_each_field_in_its_byte() {
+        let key_input = KeyInput {
+            scratch: 0x12,
+            normal_button: NormalButton::B1 | NormalButton::B7,
+            option_button: OptionButton::E4,
+            analog: 0x34,
+        };
+        assert_eq!(key_input.pack(), 0x34_08_41_12);
+    }
+
+    #[test]
+    fn pack_round_trips() {
+        for normal in 0..=NormalButton::all().bits() {
+            for option in 0..=OptionButton::all().bits() {
+                for (scratch, analog) in [(0x00, 0x00), (0xff, 0xff), (0x80, 0x01), (0x5a, 0xa5)] {
+                    let key_input = KeyInput {
+                        scratch,
+                        normal_button: NormalButton::from_bits_truncate(normal),
+                        option_button: OptionButton::from_bits_truncate(option),
+                        analog,
+                    };
+                    assert_eq!(KeyInput::unpack(key_input.pack()), key_input);
+                }
+            }
+        }
+    }
+
+    // the format released with the feature, which saved files depend on
+    #[cfg(feature = "serde")]
+    const PINNED_JSON: &str =
+        r#"{"scratch":18,"normal_button":"B1 | B7","option_button":"E4","analog":52}"#;
+
+    #[cfg(feature = "serde")]
+    const PINNED_TOML: &str = r#"scratch = 18
+normal_button = "B1 | B7"
+option_button = "E4"
+analog = 52
+"#;
+
+    #[cfg(feature = "serde")]
+    fn pinned() -> KeyInput {
+        KeyInput {
+            scratch: 0x12,
+            normal_button: NormalButton::B1 | NormalButton::B7,
+            option_button: OptionButton::E4,
+            analog: 0x34,
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trips() {
+        for key_input in [KeyInput::init(), pinned()] {
+            let json = serde_json::to_string(&key_input).unwrap();
+            assert_eq!(serde_json::from_str::<KeyInput>(&json).unwrap(), key_input);
+            let toml = toml::to_string(&key_input).unwrap();
+            assert_eq!(toml::from_str::<KeyInput>(&toml).unwrap(), key_input);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_keeps_the_pinned_format() {
+        assert_eq!(serde_json::to_string(&pinned()).unwrap(), PINNED_JSON);
+        assert_eq!(
+            serde_json::from_str::<KeyInput>(PINNED_JSON).unwrap(),
+            pinned()
+        );
+        assert_eq!(toml::to_string(&pinned()).unwrap(), PINNED_TOML);
+        assert_eq!(toml::from_str::<KeyInput>(PINNED_TOML).unwrap(), pinned());
+    }
+
+    #[test]
+    fn unpack_drops_undefined_buttons() {
+        let key_input = KeyInput::unpack(0x00_f0_80_00);
+        assert!(key_input.normal_button.is_empty());
+        assert!(key_input.option_button.is_empty());
+    }
+}
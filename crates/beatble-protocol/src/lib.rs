@@ -0,0 +1,6 @@
+//! Key input notification format shared by beatble and its receivers.
+
+pub use self::key_input::{KeyInput, NormalButton, OptionButton};
+
+mod key_input;
+pub mod payload;
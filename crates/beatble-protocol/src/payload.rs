@@ -0,0 +1,647 @@
+// Key input characteristic (0xFF01) notification layout.
+//
+// A frame carries two sub-reports of five bytes each:
+//
+//   offset  field
+//   0       scratch position
+//   1       reserved
+//   2       normal buttons (B1-B7 bitflags)
+//   3       option buttons (E1-E4 bitflags)
+//   4       counter
+//
+// The second sub-report's counter is the first one's plus one (mod 256).
+// Formats differ in how the counter advances between frames and in the
+// reserved byte:
+//
+//   v1  counter +2 per frame (c, c+1 then c+2, c+3), reserved 0x00
+//   v2  counter +1 per frame (c, c+1 then c+1, c+2), reserved 0x01
+//
+// With scratch_hires the reserved byte carries the low byte of the 16-bit
+// scratch position instead, and single_report sends only the first
+// sub-report with the counter advancing by one per frame. Only custom
+// receivers understand either.
+//
+// In the velocity scratch mode, also only for custom receivers, the scratch
+// byte is the movement since the previous frame instead of the position: a
+// signed count of 1/256 turns biased by 0x80, so 0x80 is a still turntable,
+// 0x81 one unit up and 0x7f one unit down. It saturates at 0x00 and 0xff.
+//
+// RawSubReport and RawFrame are this layout as structs; the bytes are their
+// memory, which the assertions below pin to the offsets above.
+
+use std::fmt;
+use std::mem::{offset_of, size_of, transmute};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::{KeyInput, NormalButton, OptionButton};
+
+pub mod sdvx;
+
+pub const SUB_REPORT_LEN: usize = 5;
+pub const FRAME_LEN: usize = SUB_REPORT_LEN * 2;
+
+pub const SCRATCH: usize = 0;
+pub const RESERVED: usize = 1;
+pub const NORMAL_BUTTON: usize = 2;
+pub const OPTION_BUTTON: usize = 3;
+pub const COUNTER: usize = 4;
+
+/// One sub-report as it goes over the air.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RawSubReport {
+    pub scratch: u8,
+    pub reserved: u8,
+    pub normal_button: u8,
+    pub option_button: u8,
+    pub counter: u8,
+}
+
+/// Both sub-reports of a frame as they go over the air.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RawFrame {
+    pub first: RawSubReport,
+    pub second: RawSubReport,
+}
+
+const _: () = assert!(size_of::<RawSubReport>() == SUB_REPORT_LEN);
+const _: () = assert!(size_of::<RawFrame>() == FRAME_LEN);
+const _: () = assert!(offset_of!(RawSubReport, scratch) == SCRATCH);
+const _: () = assert!(offset_of!(RawSubReport, reserved) == RESERVED);
+const _: () = assert!(offset_of!(RawSubReport, normal_button) == NORMAL_BUTTON);
+const _: () = assert!(offset_of!(RawSubReport, option_button) == OPTION_BUTTON);
+const _: () = assert!(offset_of!(RawSubReport, counter) == COUNTER);
+const _: () = assert!(offset_of!(RawFrame, first) == 0);
+const _: () = assert!(offset_of!(RawFrame, second) == SUB_REPORT_LEN);
+
+// the transmutes below are sound because every field is a u8 and the sizes
+// above leave no room for padding
+
+impl From<[u8; SUB_REPORT_LEN]> for RawSubReport {
+    #[inline]
+    fn from(bytes: [u8; SUB_REPORT_LEN]) -> Self {
+        unsafe { transmute(bytes) }
+    }
+}
+
+impl From<RawSubReport> for [u8; SUB_REPORT_LEN] {
+    #[inline]
+    fn from(raw: RawSubReport) -> Self {
+        unsafe { transmute(raw) }
+    }
+}
+
+impl From<[u8; FRAME_LEN]> for RawFrame {
+    #[inline]
+    fn from(bytes: [u8; FRAME_LEN]) -> Self {
+        unsafe { transmute(bytes) }
+    }
+}
+
+impl From<RawFrame> for [u8; FRAME_LEN] {
+    #[inline]
+    fn from(raw: RawFrame) -> Self {
+        unsafe { transmute(raw) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PayloadFormat {
+    #[default]
+    V1,
+    V2,
+}
+
+impl PayloadFormat {
+    /// counter increment between the first sub-reports of consecutive frames
+    pub fn counter_step(self) -> u8 {
+        match self {
+            PayloadFormat::V1 => 2,
+            PayloadFormat::V2 => 1,
+        }
+    }
+
+    /// value of the reserved byte when scratch_hires is off
+    pub fn reserved(self) -> u8 {
+        match self {
+            PayloadFormat::V1 => 0x00,
+            PayloadFormat::V2 => 0x01,
+        }
+    }
+}
+
+impl FromStr for PayloadFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(PayloadFormat::V1),
+            "v2" => Ok(PayloadFormat::V2),
+            _ => Err(format!("unknown payload format: {s} (expected v1 or v2)")),
+        }
+    }
+}
+
+impl fmt::Display for PayloadFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PayloadFormat::V1 => write!(f, "v1"),
+            PayloadFormat::V2 => write!(f, "v2"),
+        }
+    }
+}
+
+/// What the scratch byte carries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScratchMode {
+    /// the turntable position
+    #[default]
+    Position,
+    /// the movement since the previous frame
+    Velocity,
+}
+
+impl FromStr for ScratchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "position" => Ok(ScratchMode::Position),
+            "velocity" => Ok(ScratchMode::Velocity),
+            _ => Err(format!(
+                "unknown scratch mode: {s} (expected position or velocity)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ScratchMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScratchMode::Position => write!(f, "position"),
+            ScratchMode::Velocity => write!(f, "velocity"),
+        }
+    }
+}
+
+/// scratch byte of a turntable that didn't move, in the velocity scratch mode
+pub const SCRATCH_VELOCITY_IDLE: u8 = 0x80;
+
+/// Scratch byte of a movement of units 1/256 turns in the velocity scratch
+/// mode, saturating at what the byte holds.
+#[inline]
+pub fn encode_scratch_velocity(units: i32) -> u8 {
+    (units.clamp(i8::MIN.into(), i8::MAX.into()) as i8 as u8) ^ SCRATCH_VELOCITY_IDLE
+}
+
+/// Inverse of encode_scratch_velocity.
+#[inline]
+pub fn decode_scratch_velocity(scratch: u8) -> i8 {
+    (scratch ^ SCRATCH_VELOCITY_IDLE) as i8
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("expected {expected} bytes, got {actual}")]
+    Length { expected: usize, actual: usize },
+    #[error("reserved byte is {actual:#04x}, expected {expected:#04x}")]
+    Reserved { expected: u8, actual: u8 },
+    #[error("second sub-report counter is {actual}, expected {expected}")]
+    SubReportCounter { expected: u8, actual: u8 },
+}
+
+/// Everything that decides the bytes of a frame besides its content.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Layout {
+    pub format: PayloadFormat,
+    pub scratch_hires: bool,
+    pub single_report: bool,
+}
+
+impl Layout {
+    pub fn counter_step(self) -> u8 {
+        if self.single_report {
+            1
+        } else {
+            self.format.counter_step()
+        }
+    }
+}
+
+/// Encoded notification of up to FRAME_LEN bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Payload {
+    bytes: [u8; FRAME_LEN],
+    len: usize,
+}
+
+impl Payload {
+    pub fn new(bytes: [u8; FRAME_LEN], len: usize) -> Self {
+        debug_assert!(len <= FRAME_LEN);
+        Self { bytes, len }
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub first: KeyInput,
+    pub second: KeyInput,
+    pub counter: u8,
+}
+
+impl Frame {
+    pub fn new(first: KeyInput, second: KeyInput, counter: u8) -> Self {
+        Self {
+            first,
+            second,
+            counter,
+        }
+    }
+
+    /// the same sample in both sub-reports
+    pub fn repeated(key_input: KeyInput, counter: u8) -> Self {
+        Self::new(key_input, key_input, counter)
+    }
+
+    pub fn encode(&self, layout: Layout) -> Payload {
+        let first = encode_sub_report(layout, self.first, self.counter);
+        if layout.single_report {
+            let raw = RawFrame {
+                first,
+                second: RawSubReport::default(),
+            };
+            return Payload::new(raw.into(), SUB_REPORT_LEN);
+        }
+        let second = encode_sub_report(layout, self.second, self.counter.wrapping_add(1));
+        Payload::new(RawFrame { first, second }.into(), FRAME_LEN)
+    }
+
+    /// Inverse of encode. A single_report frame decodes with its sample repeated.
+    pub fn decode(bytes: &[u8], layout: Layout) -> Result<Self, DecodeError> {
+        if layout.single_report {
+            let raw = <[u8; SUB_REPORT_LEN]>::try_from(bytes).map_err(|_| DecodeError::Length {
+                expected: SUB_REPORT_LEN,
+                actual: bytes.len(),
+            })?;
+            let (first, counter) = decode_sub_report(raw.into(), layout)?;
+            return Ok(Self::repeated(first, counter));
+        }
+        let raw = <[u8; FRAME_LEN]>::try_from(bytes).map_err(|_| DecodeError::Length {
+            expected: FRAME_LEN,
+            actual: bytes.len(),
+        })?;
+        let RawFrame { first, second } = raw.into();
+        let (first, counter) = decode_sub_report(first, layout)?;
+        let (second, second_counter) = decode_sub_report(second, layout)?;
+        if second_counter != counter.wrapping_add(1) {
+            return Err(DecodeError::SubReportCounter {
+                expected: counter.wrapping_add(1),
+                actual: second_counter,
+            });
+        }
+        Ok(Self::new(first, second, counter))
+    }
+}
+
+#[inline]
+fn encode_sub_report(layout: Layout, key_input: KeyInput, counter: u8) -> RawSubReport {
+    RawSubReport {
+        scratch: key_input.scratch,
+        reserved: if layout.scratch_hires {
+            key_input.analog
+        } else {
+            layout.format.reserved()
+        },
+        normal_button: key_input.normal_button.bits(),
+        option_button: key_input.option_button.bits(),
+        counter,
+    }
+}
+
+#[inline]
+fn decode_sub_report(raw: RawSubReport, layout: Layout) -> Result<(KeyInput, u8), DecodeError> {
+    let analog = if layout.scratch_hires {
+        raw.reserved
+    } else if raw.reserved != layout.format.reserved() {
+        return Err(DecodeError::Reserved {
+            expected: layout.format.reserved(),
+            actual: raw.reserved,
+        });
+    } else {
+        0x00
+    };
+    let key_input = KeyInput {
+        scratch: raw.scratch,
+        normal_button: NormalButton::from_bits_truncate(raw.normal_button),
+        option_button: OptionButton::from_bits_truncate(raw.option_button),
+        analog,
+    };
+    Ok((key_input, raw.counter))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn sub_report_counter_wraps() {
+        for format in [PayloadFormat::V1, PayloadFormat::V2] {
+            let layout = Layout {
+                format,
+                ..Layout::default()
+            };
+            let payload = Frame::repeated(KeyInput::init(), 0xff).encode(layout);
+            let bytes = payload.as_bytes();
+            assert_eq!(bytes[COUNTER], 0xff);
+            assert_eq!(bytes[SUB_REPORT_LEN + COUNTER], 0x00);
+        }
+    }
+
+    #[test]
+    fn frames_never_share_a_counter_in_v1() {
+        // frame N carries c and c + 1, frame N + 1 c + 2 and c + 3
+        let step = PayloadFormat::V1.counter_step();
+        let mut counters = Vec::new();
+        let mut counter = 0xfau8;
+        for _ in 0..8 {
+            let payload = Frame::repeated(KeyInput::init(), counter).encode(Layout::default());
+            let bytes = payload.as_bytes();
+            counters.extend([bytes[COUNTER], bytes[SUB_REPORT_LEN + COUNTER]]);
+            counter = counter.wrapping_add(step);
+        }
+        let expected: Vec<u8> = (0..16).map(|i: u8| 0xfau8.wrapping_add(i)).collect();
+        assert_eq!(counters, expected);
+    }
+
+    fn key_input(scratch: u8, normal: u8, option: u8) -> KeyInput {
+        KeyInput {
+            scratch,
+            normal_button: NormalButton::from_bits_truncate(normal),
+            option_button: OptionButton::from_bits_truncate(option),
+            analog: 0x00,
+        }
+    }
+
+    #[test]
+    fn golden_v1() {
+        let frame = Frame::repeated(key_input(0x12, 0b0000101, 0b0010), 0x05);
+        assert_eq!(
+            frame.encode(Layout::default()).as_bytes(),
+            [0x12, 0x00, 0x05, 0x02, 0x05, 0x12, 0x00, 0x05, 0x02, 0x06]
+        );
+        let frame = Frame::new(
+            key_input(0x00, 0b1111111, 0b1111),
+            key_input(0xff, 0, 0),
+            0xfe,
+        );
+        assert_eq!(
+            frame.encode(Layout::default()).as_bytes(),
+            [0x00, 0x00, 0x7f, 0x0f, 0xfe, 0xff, 0x00, 0x00, 0x00, 0xff]
+        );
+    }
+
+    #[test]
+    fn golden_v2() {
+        let layout = Layout {
+            format: PayloadFormat::V2,
+            ..Layout::default()
+        };
+        let frame = Frame::repeated(key_input(0x80, 0b1000000, 0b1000), 0x10);
+        assert_eq!(
+            frame.encode(layout).as_bytes(),
+            [0x80, 0x01, 0x40, 0x08, 0x10, 0x80, 0x01, 0x40, 0x08, 0x11]
+        );
+    }
+
+    #[test]
+    fn golden_hires_and_single_report() {
+        let mut sample = key_input(0x00, 0b0000001, 0);
+        sample.set_scratch_position(0x1234);
+        let hires = Layout {
+            scratch_hires: true,
+            ..Layout::default()
+        };
+        assert_eq!(
+            Frame::repeated(sample, 0x00).encode(hires).as_bytes(),
+            [0x12, 0x34, 0x01, 0x00, 0x00, 0x12, 0x34, 0x01, 0x00, 0x01]
+        );
+        let single = Layout {
+            single_report: true,
+            ..Layout::default()
+        };
+        assert_eq!(
+            Frame::repeated(sample, 0x07).encode(single).as_bytes(),
+            [0x12, 0x00, 0x01, 0x00, 0x07]
+        );
+    }
+
+    #[test]
+    fn every_field_round_trips() {
+        let layouts = [
+            Layout::default(),
+            Layout {
+                format: PayloadFormat::V2,
+                ..Layout::default()
+            },
+            Layout {
+                scratch_hires: true,
+                ..Layout::default()
+            },
+            Layout {
+                single_report: true,
+                ..Layout::default()
+            },
+        ];
+        for layout in layouts {
+            for normal in 0..=NormalButton::all().bits() {
+                for option in 0..=OptionButton::all().bits() {
+                    for scratch in (0..=255u8).step_by(17) {
+                        let mut sample = key_input(scratch, normal, option);
+                        if layout.scratch_hires {
+                            sample.analog = scratch ^ 0x5a;
+                        }
+                        let counter = scratch.wrapping_add(normal);
+                        let frame = Frame::repeated(sample, counter);
+                        let decoded = Frame::decode(frame.encode(layout).as_bytes(), layout);
+                        assert_eq!(decoded, Ok(frame), "{layout:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_golden_v1() {
+        let bytes = [0x12, 0x00, 0x05, 0x02, 0x05, 0x34, 0x00, 0x40, 0x00, 0x06];
+        let frame = Frame::decode(&bytes, Layout::default()).expect("a valid frame");
+        assert_eq!(frame.first, key_input(0x12, 0b0000101, 0b0010));
+        assert_eq!(frame.second, key_input(0x34, 0b1000000, 0));
+        assert_eq!(frame.counter, 0x05);
+    }
+
+    #[test]
+    fn decode_rejects_malformed_frames() {
+        let v2 = Layout {
+            format: PayloadFormat::V2,
+            ..Layout::default()
+        };
+        let single = Layout {
+            single_report: true,
+            ..Layout::default()
+        };
+        let frame = [0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x11];
+        assert_eq!(
+            Frame::decode(&frame[..9], Layout::default()),
+            Err(DecodeError::Length {
+                expected: FRAME_LEN,
+                actual: 9
+            })
+        );
+        assert_eq!(
+            Frame::decode(&frame, single),
+            Err(DecodeError::Length {
+                expected: SUB_REPORT_LEN,
+                actual: FRAME_LEN
+            })
+        );
+        // a v1 frame read as v2
+        assert_eq!(
+            Frame::decode(&frame, v2),
+            Err(DecodeError::Reserved {
+                expected: 0x01,
+                actual: 0x00
+            })
+        );
+        let mut skipped = frame;
+        skipped[SUB_REPORT_LEN + COUNTER] = 0x12;
+        assert_eq!(
+            Frame::decode(&skipped, Layout::default()),
+            Err(DecodeError::SubReportCounter {
+                expected: 0x11,
+                actual: 0x12
+            })
+        );
+    }
+
+    #[test]
+    fn decode_drops_undefined_button_bits() {
+        let bytes = [0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x80, 0xf0, 0x01];
+        let frame = Frame::decode(&bytes, Layout::default()).expect("a valid frame");
+        assert_eq!(frame.first.normal_button, NormalButton::all());
+        assert_eq!(frame.first.option_button, OptionButton::all());
+        assert!(frame.second.normal_button.is_empty());
+        assert!(frame.second.option_button.is_empty());
+    }
+
+    #[test]
+    fn scratch_velocity_bytes_are_as_documented() {
+        assert_eq!(encode_scratch_velocity(0), SCRATCH_VELOCITY_IDLE);
+        assert_eq!(encode_scratch_velocity(1), 0x81);
+        assert_eq!(encode_scratch_velocity(-1), 0x7f);
+        assert_eq!(encode_scratch_velocity(127), 0xff);
+        assert_eq!(encode_scratch_velocity(-128), 0x00);
+        // saturating instead of wrapping around to the other direction
+        assert_eq!(encode_scratch_velocity(128), 0xff);
+        assert_eq!(encode_scratch_velocity(-129), 0x00);
+        assert_eq!(encode_scratch_velocity(i32::MAX), 0xff);
+        assert_eq!(encode_scratch_velocity(i32::MIN), 0x00);
+        assert_eq!(decode_scratch_velocity(SCRATCH_VELOCITY_IDLE), 0);
+    }
+
+    #[test]
+    fn a_turntable_turning_back_and_forth_keeps_its_direction_per_frame() {
+        let movements = [3, -3, 0, 1, -1, 0, 127, -128];
+        let bytes = movements.map(encode_scratch_velocity);
+        assert_eq!(bytes, [0x83, 0x7d, 0x80, 0x81, 0x7f, 0x80, 0xff, 0x00]);
+        assert_eq!(
+            bytes.map(|byte| decode_scratch_velocity(byte) as i32),
+            movements
+        );
+    }
+
+    fn any_key_input() -> impl Strategy<Value = KeyInput> {
+        any::<u32>().prop_map(KeyInput::unpack)
+    }
+
+    fn any_format() -> impl Strategy<Value = PayloadFormat> {
+        prop_oneof![Just(PayloadFormat::V1), Just(PayloadFormat::V2)]
+    }
+
+    proptest! {
+        #[test]
+        fn repeated_frames_are_consistent(
+            key_input in any_key_input(),
+            counter in any::<u8>(),
+            format in any_format(),
+        ) {
+            let layout = Layout { format, ..Layout::default() };
+            let payload = Frame::repeated(key_input, counter).encode(layout);
+            let bytes = payload.as_bytes();
+            prop_assert_eq!(bytes[SCRATCH], bytes[SUB_REPORT_LEN + SCRATCH]);
+            for offset in [0, SUB_REPORT_LEN] {
+                prop_assert_eq!(bytes[offset + NORMAL_BUTTON] & !NormalButton::all().bits(), 0);
+                prop_assert_eq!(bytes[offset + OPTION_BUTTON] & !OptionButton::all().bits(), 0);
+            }
+            prop_assert_eq!(bytes[SUB_REPORT_LEN + COUNTER], bytes[COUNTER].wrapping_add(1));
+        }
+
+        #[test]
+        fn scratch_velocity_round_trips_within_the_byte(units in any::<i32>()) {
+            let decoded = decode_scratch_velocity(encode_scratch_velocity(units));
+            prop_assert_eq!(decoded as i32, units.clamp(-128, 127));
+        }
+
+        // as the notifier sends it: what didn't fit into a frame carries over
+        #[test]
+        fn scratch_velocity_frames_add_up_to_the_movement(
+            movements in proptest::collection::vec(-2000..2000i32, 1..32),
+        ) {
+            let mut residual = 0;
+            let mut sent = 0;
+            for movement in &movements {
+                residual += movement;
+                let carried = decode_scratch_velocity(encode_scratch_velocity(residual)) as i32;
+                residual -= carried;
+                sent += carried;
+            }
+            while residual != 0 {
+                let carried = decode_scratch_velocity(encode_scratch_velocity(residual)) as i32;
+                prop_assert_ne!(carried, 0);
+                residual -= carried;
+                sent += carried;
+            }
+            prop_assert_eq!(sent, movements.iter().sum::<i32>());
+        }
+
+        #[test]
+        fn consecutive_frames_continue_the_counter(
+            samples in proptest::collection::vec(any_key_input(), 2..64),
+            start in any::<u8>(),
+            format in any_format(),
+        ) {
+            let layout = Layout { format, ..Layout::default() };
+            let mut counter = start;
+            let mut previous: Option<Frame> = None;
+            for sample in samples {
+                let payload = Frame::repeated(sample, counter).encode(layout);
+                let frame = Frame::decode(payload.as_bytes(), layout).expect("a valid frame");
+                if let Some(previous) = previous {
+                    let step = format.counter_step();
+                    prop_assert_eq!(frame.counter, previous.counter.wrapping_add(step));
+                }
+                previous = Some(frame);
+                counter = counter.wrapping_add(layout.counter_step());
+            }
+        }
+    }
+}
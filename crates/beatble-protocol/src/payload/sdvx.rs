@@ -0,0 +1,249 @@
+// SOUND VOLTEX Entry model notification layout, not verified against hardware.
+//
+// Same framing as IIDX (two five-byte sub-reports, counter +2 per frame):
+//
+//   offset  field
+//   0       VOL-L knob position (KeyInput::scratch)
+//   1       VOL-R knob position (KeyInput::analog)
+//   2       buttons, using the normal button bits:
+//           B1-B4 = BT-A..BT-D, B5 = FX-L, B6 = FX-R, B7 = Start
+//   3       reserved, always 0x00
+//   4       counter
+
+use std::mem::{offset_of, size_of, transmute};
+
+use super::{DecodeError, Payload, FRAME_LEN, SUB_REPORT_LEN};
+use crate::{KeyInput, NormalButton, OptionButton};
+
+const VOL_L: usize = 0;
+const VOL_R: usize = 1;
+const BUTTON: usize = 2;
+const RESERVED: usize = 3;
+const COUNTER: usize = 4;
+
+pub const COUNTER_STEP: u8 = 2;
+
+/// One sub-report as it goes over the air.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RawSubReport {
+    pub vol_l: u8,
+    pub vol_r: u8,
+    pub button: u8,
+    pub reserved: u8,
+    pub counter: u8,
+}
+
+/// Both sub-reports of a frame as they go over the air.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RawFrame {
+    pub first: RawSubReport,
+    pub second: RawSubReport,
+}
+
+const _: () = assert!(size_of::<RawSubReport>() == SUB_REPORT_LEN);
+const _: () = assert!(size_of::<RawFrame>() == FRAME_LEN);
+const _: () = assert!(offset_of!(RawSubReport, vol_l) == VOL_L);
+const _: () = assert!(offset_of!(RawSubReport, vol_r) == VOL_R);
+const _: () = assert!(offset_of!(RawSubReport, button) == BUTTON);
+const _: () = assert!(offset_of!(RawSubReport, reserved) == RESERVED);
+const _: () = assert!(offset_of!(RawSubReport, counter) == COUNTER);
+const _: () = assert!(offset_of!(RawFrame, first) == 0);
+const _: () = assert!(offset_of!(RawFrame, second) == SUB_REPORT_LEN);
+
+// sound for the same reasons as the IIDX transmutes
+impl From<[u8; FRAME_LEN]> for RawFrame {
+    #[inline]
+    fn from(bytes: [u8; FRAME_LEN]) -> Self {
+        unsafe { transmute(bytes) }
+    }
+}
+
+impl From<RawFrame> for [u8; FRAME_LEN] {
+    #[inline]
+    fn from(raw: RawFrame) -> Self {
+        unsafe { transmute(raw) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub key_input: KeyInput,
+    pub counter: u8,
+}
+
+impl Frame {
+    pub fn new(key_input: KeyInput, counter: u8) -> Self {
+        Self { key_input, counter }
+    }
+
+    pub fn encode(&self) -> Payload {
+        let raw = RawFrame {
+            first: encode_sub_report(self.key_input, self.counter),
+            second: encode_sub_report(self.key_input, self.counter.wrapping_add(1)),
+        };
+        Payload::new(raw.into(), FRAME_LEN)
+    }
+
+    /// Inverse of encode; the second sub-report only has to carry the next counter.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let raw = <[u8; FRAME_LEN]>::try_from(bytes).map_err(|_| DecodeError::Length {
+            expected: FRAME_LEN,
+            actual: bytes.len(),
+        })?;
+        let RawFrame { first, second } = raw.into();
+        for sub_report in [first, second] {
+            if sub_report.reserved != 0x00 {
+                return Err(DecodeError::Reserved {
+                    expected: 0x00,
+                    actual: sub_report.reserved,
+                });
+            }
+        }
+        let counter = first.counter;
+        if second.counter != counter.wrapping_add(1) {
+            return Err(DecodeError::SubReportCounter {
+                expected: counter.wrapping_add(1),
+                actual: second.counter,
+            });
+        }
+
+        let key_input = KeyInput {
+            scratch: first.vol_l,
+            normal_button: NormalButton::from_bits_truncate(first.button),
+            option_button: OptionButton::empty(),
+            analog: first.vol_r,
+        };
+        Ok(Self::new(key_input, counter))
+    }
+}
+
+#[inline]
+fn encode_sub_report(key_input: KeyInput, counter: u8) -> RawSubReport {
+    RawSubReport {
+        vol_l: key_input.scratch,
+        vol_r: key_input.analog,
+        button: key_input.normal_button.bits(),
+        reserved: 0x00,
+        counter,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn key_input(vol_l: u8, vol_r: u8, buttons: NormalButton) -> KeyInput {
+        KeyInput {
+            scratch: vol_l,
+            normal_button: buttons,
+            option_button: OptionButton::empty(),
+            analog: vol_r,
+        }
+    }
+
+    #[test]
+    fn golden() {
+        // BT-A, FX-R and Start, both knobs off center
+        let frame = Frame::new(
+            key_input(
+                0x80,
+                0x3f,
+                NormalButton::B1 | NormalButton::B6 | NormalButton::B7,
+            ),
+            0x10,
+        );
+        let bytes = [0x80, 0x3f, 0x61, 0x00, 0x10, 0x80, 0x3f, 0x61, 0x00, 0x11];
+        assert_eq!(frame.encode().as_bytes(), bytes);
+        assert_eq!(Frame::decode(&bytes), Ok(frame));
+    }
+
+    #[test]
+    fn buttons_follow_the_documented_bits() {
+        let buttons = [
+            (0x01, NormalButton::B1), // BT-A
+            (0x02, NormalButton::B2), // BT-B
+            (0x04, NormalButton::B3), // BT-C
+            (0x08, NormalButton::B4), // BT-D
+            (0x10, NormalButton::B5), // FX-L
+            (0x20, NormalButton::B6), // FX-R
+            (0x40, NormalButton::B7), // Start
+        ];
+        for (bit, button) in buttons {
+            let bytes = [0x00, 0x00, bit, 0x00, 0xff, 0x00, 0x00, bit, 0x00, 0x00];
+            let frame = Frame::decode(&bytes).expect("a valid frame");
+            assert_eq!(frame.key_input, key_input(0x00, 0x00, button), "{bit:#04x}");
+            assert_eq!(frame.counter, 0xff);
+        }
+        // no button has the top bit
+        let bytes = [0x00, 0x00, 0x80, 0x00, 0x02, 0x00, 0x00, 0x80, 0x00, 0x03];
+        assert_eq!(
+            Frame::decode(&bytes).map(|frame| frame.key_input),
+            Ok(KeyInput::init())
+        );
+    }
+
+    #[test]
+    fn option_buttons_are_not_sent() {
+        let mut key_input = key_input(0x00, 0x00, NormalButton::B2);
+        key_input.option_button = OptionButton::all();
+        let payload = Frame::new(key_input, 0x00).encode();
+        assert_eq!(
+            payload.as_bytes(),
+            [0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn decode_rejects_malformed_frames() {
+        let frame = [0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x05];
+        assert_eq!(
+            Frame::decode(&frame[..SUB_REPORT_LEN]),
+            Err(DecodeError::Length {
+                expected: FRAME_LEN,
+                actual: SUB_REPORT_LEN
+            })
+        );
+        for offset in [RESERVED, SUB_REPORT_LEN + RESERVED] {
+            let mut bytes = frame;
+            bytes[offset] = 0x01;
+            assert_eq!(
+                Frame::decode(&bytes),
+                Err(DecodeError::Reserved {
+                    expected: 0x00,
+                    actual: 0x01
+                })
+            );
+        }
+        let mut bytes = frame;
+        bytes[SUB_REPORT_LEN + COUNTER] = 0x04;
+        assert_eq!(
+            Frame::decode(&bytes),
+            Err(DecodeError::SubReportCounter {
+                expected: 0x05,
+                actual: 0x04
+            })
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn frames_round_trip(
+            vol_l in any::<u8>(),
+            vol_r in any::<u8>(),
+            buttons in any::<u8>(),
+            counter in any::<u8>(),
+        ) {
+            let frame = Frame::new(
+                key_input(vol_l, vol_r, NormalButton::from_bits_truncate(buttons)),
+                counter,
+            );
+            let payload = frame.encode();
+            prop_assert_eq!(payload.as_bytes()[SUB_REPORT_LEN + COUNTER], counter.wrapping_add(1));
+            prop_assert_eq!(Frame::decode(payload.as_bytes()), Ok(frame));
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// A pipeline built from the library instead of the beatble binary: one
+// joystick, notified every 4 ms under a custom advertising name, until
+// advertising stops or the reader fails.
+//
+//   cargo run --example custom_pipeline -- /dev/input/js0
+
+use std::sync::Arc;
+
+use beatble::ble::{create_key_input, NotifyConfig, NotifyContext, NotifyMode, RepeatSpacing};
+use beatble::control::Control;
+use beatble::emulation::Emulation;
+use beatble::input::create_input_handler;
+use beatble::latency::Latency;
+use beatble::payload::{Layout, ScratchMode};
+use beatble::peripheral::PeripheralBuilder;
+use beatble::stats::Stats;
+use eyre::{eyre, Result};
+use tokio::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let device = std::env::args()
+        .nth(1)
+        .ok_or_else(|| eyre!("usage: custom_pipeline DEVICE"))?;
+
+    let notify_config = NotifyConfig {
+        interval: Duration::from_millis(4),
+        mode: NotifyMode::Periodic,
+        warmup_frames: 0,
+        counter_start: 0,
+        frame_repeat: 1,
+        repeat_spacing: RepeatSpacing::BackToBack,
+        busy_poll: false,
+        layout: Layout::default(),
+        emulation: Emulation::Iidx,
+        scratch_mode: ScratchMode::Position,
+        scratch_predict: None,
+    };
+    let stats = Arc::new(Stats::new());
+    let (key_input, input_handler) = create_input_handler(
+        &device,
+        notify_config.emulation.into(),
+        notify_config.busy_poll,
+        false,
+        Arc::clone(&stats),
+    )?;
+    let context = NotifyContext {
+        key_input,
+        control: Arc::new(Control::new(notify_config.interval, notify_config.mode)),
+        latency: Arc::new(Latency::new()),
+        stats,
+        dump: None,
+    };
+
+    let peripheral = PeripheralBuilder::new("my launcher")
+        .service(create_key_input(context.clone(), notify_config))
+        .run(context.clone());
+    tokio::select! {
+        result = peripheral => result?,
+        result = input_handler => result??,
+    }
+    context.latency.log_summary();
+    Ok(())
+}
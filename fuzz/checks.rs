@@ -0,0 +1,42 @@
+// What the fuzz targets check, shared with the corpus smoke test
+// (fuzz/smoke.rs), which runs without cargo-fuzz.
+
+use beatble::input::{SdlControls, SdlMapping};
+use beatble::payload::{sdvx, Frame, Layout, PayloadFormat};
+
+/// Whatever the bytes, decoding doesn't panic, and a frame that decodes
+/// encodes to bytes that decode to the same frame, in every layout.
+pub fn decode_payload(data: &[u8]) {
+    for format in [PayloadFormat::V1, PayloadFormat::V2] {
+        for (scratch_hires, single_report) in [(false, false), (true, false), (false, true)] {
+            let layout = Layout {
+                format,
+                scratch_hires,
+                single_report,
+            };
+            if let Ok(frame) = Frame::decode(data, layout) {
+                let payload = frame.encode(layout);
+                assert_eq!(
+                    Frame::decode(payload.as_bytes(), layout),
+                    Ok(frame),
+                    "{layout:?}"
+                );
+            }
+        }
+    }
+    if let Ok(frame) = sdvx::Frame::decode(data) {
+        assert_eq!(sdvx::Frame::decode(frame.encode().as_bytes()), Ok(frame));
+    }
+}
+
+/// Whatever the text, parsing a gamecontrollerdb line or a control list
+/// doesn't panic, and a parsed mapping makes a keymap.
+pub fn parse_mapping(data: &[u8]) {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let controls = text.parse::<SdlControls>();
+    if let Ok(mapping) = text.parse::<SdlMapping>() {
+        mapping.keymap(&controls.unwrap_or_default());
+    }
+}
@@ -0,0 +1,35 @@
+// Runs the fuzz corpus through the checks of the fuzz targets, so an input
+// added to fuzz/corpus stays covered by `cargo test --features fuzz-smoke`
+// on a stable toolchain, without cargo-fuzz.
+
+use std::fs;
+use std::panic;
+use std::path::Path;
+
+#[path = "checks.rs"]
+mod checks;
+
+fn run_corpus(target: &str, check: fn(&[u8])) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz/corpus")
+        .join(target);
+    let mut inputs = 0;
+    for entry in fs::read_dir(&dir).expect("the corpus") {
+        let path = entry.unwrap().path();
+        let data = fs::read(&path).unwrap();
+        let checked = panic::catch_unwind(|| check(&data));
+        assert!(checked.is_ok(), "{target} failed on {}", path.display());
+        inputs += 1;
+    }
+    assert!(inputs > 0, "{} is empty", dir.display());
+}
+
+#[test]
+fn decode_payload_corpus() {
+    run_corpus("decode_payload", checks::decode_payload);
+}
+
+#[test]
+fn parse_mapping_corpus() {
+    run_corpus("parse_mapping", checks::parse_mapping);
+}
@@ -0,0 +1,44 @@
+#!/bin/sh
+# Lints every feature combination a build is expected to work with, so code
+# only one of them uses doesn't turn into dead code warnings in the others.
+#
+#   scripts/check-features.sh [FEATURE...]
+#
+# With features given, only the combinations made of them are checked, e.g.
+# without midi where ALSA isn't installed.
+set -eu
+
+cd "$(dirname "$0")/.."
+
+combinations='
+ble
+input
+ble,input
+ble,input,serde
+ble,input,verify
+ble,input,metrics
+ble,input,overlay
+ble,input,dbus
+ble,input,midi
+ble,input,sandbox
+ble,input,serde,verify,metrics,overlay,dbus,midi,sandbox
+'
+
+# no features at all, the library alone
+echo "== no features"
+cargo clippy --workspace --all-targets --no-default-features -- -D warnings
+
+for features in $combinations; do
+    if [ $# -gt 0 ]; then
+        skip=
+        for feature in $(echo "$features" | tr , ' '); do
+            case " $* " in
+                *" $feature "*) ;;
+                *) skip=1 ;;
+            esac
+        done
+        [ -z "$skip" ] || continue
+    fi
+    echo "== $features"
+    cargo clippy --workspace --all-targets --no-default-features --features "$features" -- -D warnings
+done
@@ -0,0 +1,150 @@
+// `beatble bench`: how fast input reaches userspace, before beatble or
+// bluetooth are involved. The joystick node only has millisecond timestamps,
+// so this reads the evdev node of the same device, and per report records
+//
+// - read latency: from the kernel's timestamp until read() returns
+// - report gap: kernel time since the previous report; while playing this is
+//   the USB polling interval, e.g. 8 ms with usbhid's default for joysticks
+//
+// and counts the input events of the busiest second.
+
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use beatble::exit::ErrorKind;
+use beatble::input::{device_info, evdev_siblings, EventDevice, TimedEvent};
+use beatble::latency::{Histogram, Summary};
+use eyre::{eyre, Result, WrapErr};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+// a longer gap is a pause in play, not the encoder
+const IDLE_GAP: Duration = Duration::from_millis(100);
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+// slower reports than this point at the polling interval
+const SLOW_REPORT_GAP_US: u64 = 1500;
+
+#[derive(Default)]
+struct Measurement {
+    read_latency: Histogram,
+    report_gap: Histogram,
+    events: u64,
+    reports: u64,
+    last_report: Option<Duration>,
+    window_started_at: Duration,
+    window_events: u64,
+    peak_rate: u64,
+}
+
+impl Measurement {
+    fn record(&mut self, event: TimedEvent, read_at: Duration) {
+        if !event.is_report() {
+            self.events += 1;
+            if event.time.saturating_sub(self.window_started_at) >= RATE_WINDOW {
+                self.window_started_at = event.time;
+                self.window_events = 0;
+            }
+            self.window_events += 1;
+            self.peak_rate = self.peak_rate.max(self.window_events);
+            return;
+        }
+        self.reports += 1;
+        self.read_latency
+            .record(read_at.saturating_sub(event.time).as_micros() as u64);
+        if let Some(last_report) = self.last_report.replace(event.time) {
+            let gap = event.time.saturating_sub(last_report);
+            if gap < IDLE_GAP {
+                self.report_gap.record(gap.as_micros() as u64);
+            }
+        }
+    }
+}
+
+pub async fn run(device: &str, seconds: u64, json: bool) -> Result<()> {
+    let info = device_info(device).wrap_err(ErrorKind::InputDevice)?;
+    let evdev = evdev_siblings(device)
+        .ok()
+        .and_then(|siblings| siblings.into_iter().next())
+        .ok_or_else(|| eyre!("{device} has no evdev node to read timestamps from"))
+        .wrap_err(ErrorKind::InputDevice)?;
+    let events = EventDevice::open(&evdev)
+        .wrap_err_with(|| format!("failed to open {evdev}"))
+        .wrap_err(ErrorKind::InputDevice)?;
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    // a plain thread rather than spawn_blocking, so a read that never returns
+    // doesn't hold up the runtime's shutdown
+    thread::spawn(move || loop {
+        let event = events.read();
+        let read_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if sender.send((event, read_at)).is_err() {
+            break;
+        }
+    });
+
+    if !json {
+        eprintln!("Reading {evdev} for {seconds}s, play now");
+    }
+    let mut measurement = Measurement::default();
+    let deadline = tokio::time::sleep(Duration::from_secs(seconds));
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            Some((event, read_at)) = receiver.recv() => {
+                let event = event.wrap_err(ErrorKind::InputRuntime)?;
+                measurement.record(event, read_at);
+            }
+        }
+    }
+
+    let read_latency = measurement.read_latency.summary();
+    let report_gap = measurement.report_gap.summary();
+    if json {
+        let result = json!({
+            "device": device,
+            "name": info.name,
+            "evdev": evdev,
+            "seconds": seconds,
+            "input_events": measurement.events,
+            "reports": measurement.reports,
+            "peak_events_per_sec": measurement.peak_rate,
+            "read_latency_us": read_latency,
+            "report_gap_us": report_gap,
+        });
+        println!("{result:#}");
+        return Ok(());
+    }
+
+    println!("{device}: {info} via {evdev}, {seconds}s");
+    println!(
+        "{} input events in {} reports, at most {} events/s",
+        measurement.events, measurement.reports, measurement.peak_rate
+    );
+    println!();
+    println!(
+        "{:<18}{:>8}{:>8}{:>8}{:>8}{:>8}",
+        "", "count", "p50", "p95", "p99", "max"
+    );
+    print_row("read latency (us)", read_latency);
+    print_row("report gap (us)", report_gap);
+    if report_gap.count == 0 {
+        println!("\nNo reports in quick succession; play during the measurement.");
+    } else if report_gap.p50 > SLOW_REPORT_GAP_US {
+        println!(
+            "\nThe device reports about every {}us. For a USB joystick, usbhid.jspoll=1 on the \
+             kernel command line polls every 1ms.",
+            report_gap.p50
+        );
+    }
+    Ok(())
+}
+
+fn print_row(name: &str, summary: Summary) {
+    println!(
+        "{name:<18}{:>8}{:>8}{:>8}{:>8}{:>8}",
+        summary.count, summary.p50, summary.p95, summary.p99, summary.max
+    );
+}
@@ -1,3 +1,15 @@
-pub use self::key_input::create_key_input;
+pub use self::conn_params::{watch_conn_params, Address, ConnParams, Connection};
+pub use self::connection::{
+    align_to_conn_interval, read_conn_interval, request_conn_interval, ConnInterval,
+    ConnIntervalError,
+};
+#[cfg(feature = "ble")]
+pub use self::key_input::{create_key_input, create_key_input_dp, create_key_input_with_events};
+pub use self::key_input::{
+    spawn_local_notifier, spawn_local_payloads, NotifyConfig, NotifyContext, NotifyMode,
+    RepeatSpacing,
+};
 
+mod conn_params;
+mod connection;
 mod key_input;
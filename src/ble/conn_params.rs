@@ -0,0 +1,314 @@
+// The parameters the central picked for the LE connection. Neither BlueZ's
+// D-Bus API nor debugfs has them for a connection that is up, so they are
+// taken from the HCI events themselves, on the monitor channel btmon reads
+// too, which takes CAP_NET_RAW.
+// https://github.com/torvalds/linux/blob/v5.10/include/net/bluetooth/hci_mon.h
+//
+// The kernel sends a connection parameter update request of its own when a
+// central connects with an interval outside conn_min_interval and
+// conn_max_interval, see request_conn_interval; the central may still refuse.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::thread;
+
+use nix::libc;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use tracing::debug;
+
+const BTPROTO_HCI: libc::c_int = 1;
+const HCI_DEV_NONE: u16 = 0xffff;
+const HCI_CHANNEL_MONITOR: u16 = 2;
+// hci0, as with the debugfs entries
+const ADAPTER_INDEX: u16 = 0;
+
+const MONITOR_EVENT_PKT: u16 = 0x0003;
+const EVT_DISCONN_COMPLETE: u8 = 0x05;
+const EVT_LE_META: u8 = 0x3e;
+const LE_CONN_COMPLETE: u8 = 0x01;
+const LE_CONN_UPDATE_COMPLETE: u8 = 0x03;
+const LE_ENHANCED_CONN_COMPLETE: u8 = 0x0a;
+const LE_ENHANCED_CONN_COMPLETE_V2: u8 = 0x29;
+const ROLE_PERIPHERAL: u8 = 0x01;
+
+// the interval is in units of 1.25 ms, the supervision timeout of 10 ms
+const INTERVAL_UNIT_MICROS: u64 = 1250;
+const TIMEOUT_UNIT_MILLIS: u64 = 10;
+
+/// The parameters of an LE connection, as the central set them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnParams {
+    pub interval: Duration,
+    /// connection events the central lets the peripheral skip
+    pub latency: u16,
+    pub supervision_timeout: Duration,
+}
+
+/// A Bluetooth device address, in the byte order of HCI: the last byte first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Address(pub [u8; 6]);
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{g:02X}:{e:02X}:{d:02X}:{c:02X}:{b:02X}:{a:02X}")
+    }
+}
+
+/// The central of an LE connection and the parameters it set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Connection {
+    pub central: Address,
+    pub params: ConnParams,
+}
+
+impl fmt::Display for ConnParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "interval {:?}, latency {}, supervision timeout {:?}",
+            self.interval, self.latency, self.supervision_timeout
+        )
+    }
+}
+
+#[repr(C)]
+struct SockaddrHci {
+    family: libc::sa_family_t,
+    dev: u16,
+    channel: u16,
+}
+
+/// Watches the adapter's connections from a thread of its own. The receiver
+/// holds the central and the parameters of the latest connection where the
+/// adapter is the peripheral, None while there is none.
+pub fn watch_conn_params() -> io::Result<watch::Receiver<Option<Connection>>> {
+    let monitor = open_monitor()?;
+    let (sender, receiver) = watch::channel(None);
+    thread::Builder::new()
+        .name("hci-monitor".to_owned())
+        .spawn(move || {
+            if let Err(e) = read_events(monitor, &sender) {
+                debug!("stopped watching connection parameters: {e}");
+            }
+        })?;
+    Ok(receiver)
+}
+
+fn open_monitor() -> io::Result<File> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_BLUETOOTH,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            BTPROTO_HCI,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let address = SockaddrHci {
+        family: libc::AF_BLUETOOTH as libc::sa_family_t,
+        dev: HCI_DEV_NONE,
+        channel: HCI_CHANNEL_MONITOR,
+    };
+    let res = unsafe {
+        libc::bind(
+            std::os::fd::AsRawFd::as_raw_fd(&fd),
+            &address as *const SockaddrHci as *const libc::sockaddr,
+            size_of::<SockaddrHci>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(File::from(fd))
+}
+
+fn read_events(mut monitor: File, sender: &watch::Sender<Option<Connection>>) -> io::Result<()> {
+    // the connection the parameters belong to
+    let mut handle = None;
+    let mut central = Address([0; 6]);
+    let mut packet = [0u8; 1024];
+    loop {
+        let len = monitor.read(&mut packet)?;
+        let Some(event) = parse(&packet[..len]) else {
+            continue;
+        };
+        let connection = match event {
+            Change::Connected(connected, connection) => {
+                handle = Some(connected);
+                central = connection.central;
+                Some(connection)
+            }
+            Change::Updated(updated, params) if handle == Some(updated) => {
+                Some(Connection { central, params })
+            }
+            Change::Disconnected(disconnected) if handle == Some(disconnected) => {
+                handle = None;
+                None
+            }
+            _ => continue,
+        };
+        if sender.send(connection).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+enum Change {
+    Connected(u16, Connection),
+    Updated(u16, ConnParams),
+    Disconnected(u16),
+}
+
+/// a monitor packet: opcode, adapter index and length, then an HCI event
+fn parse(packet: &[u8]) -> Option<Change> {
+    let (opcode, index) = (le16(packet, 0)?, le16(packet, 2)?);
+    if opcode != MONITOR_EVENT_PKT || index != ADAPTER_INDEX {
+        return None;
+    }
+    let (&code, event) = packet.get(6..)?.split_first()?;
+    // the parameter length
+    let params = event.get(1..)?;
+    match code {
+        EVT_DISCONN_COMPLETE if params.first() == Some(&0) => {
+            Some(Change::Disconnected(handle(params, 1)?))
+        }
+        EVT_LE_META => {
+            let (&subevent, params) = params.split_first()?;
+            // every one starts with the status and the connection handle
+            if params.first() != Some(&0) {
+                return None;
+            }
+            let handle = handle(params, 1)?;
+            match subevent {
+                LE_CONN_COMPLETE if params.get(3) == Some(&ROLE_PERIPHERAL) => {
+                    Some(Change::Connected(handle, connection(params, 11)?))
+                }
+                LE_ENHANCED_CONN_COMPLETE | LE_ENHANCED_CONN_COMPLETE_V2
+                    if params.get(3) == Some(&ROLE_PERIPHERAL) =>
+                {
+                    Some(Change::Connected(handle, connection(params, 23)?))
+                }
+                LE_CONN_UPDATE_COMPLETE => Some(Change::Updated(handle, conn_params(params, 3)?)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// the peer address after the role and its type, both connection complete
+/// events have it there, then the parameters at offset
+fn connection(params: &[u8], offset: usize) -> Option<Connection> {
+    Some(Connection {
+        central: Address(params.get(5..11)?.try_into().ok()?),
+        params: conn_params(params, offset)?,
+    })
+}
+
+/// the interval, latency and supervision timeout at offset
+fn conn_params(params: &[u8], offset: usize) -> Option<ConnParams> {
+    Some(ConnParams {
+        interval: Duration::from_micros(u64::from(le16(params, offset)?) * INTERVAL_UNIT_MICROS),
+        latency: le16(params, offset + 2)?,
+        supervision_timeout: Duration::from_millis(
+            u64::from(le16(params, offset + 4)?) * TIMEOUT_UNIT_MILLIS,
+        ),
+    })
+}
+
+fn handle(params: &[u8], offset: usize) -> Option<u16> {
+    // the top bits are flags
+    Some(le16(params, offset)? & 0x0fff)
+}
+
+fn le16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(code: u8, params: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x03, 0x00, 0x00, 0x00, params.len() as u8 + 2, 0x00];
+        packet.extend([code, params.len() as u8]);
+        packet.extend(params);
+        packet
+    }
+
+    #[test]
+    fn a_connection_has_the_central_and_its_parameters() {
+        let complete = event(
+            EVT_LE_META,
+            &[
+                LE_CONN_COMPLETE,
+                0x00,
+                0x40,
+                0x00,
+                ROLE_PERIPHERAL,
+                0x00,
+                0x66,
+                0x55,
+                0x44,
+                0x33,
+                0x22,
+                0x11,
+                0x06,
+                0x00,
+                0x00,
+                0x00,
+                0xf4,
+                0x01,
+                0x00,
+            ],
+        );
+        let Some(Change::Connected(0x40, connection)) = parse(&complete) else {
+            panic!("not a connection");
+        };
+        assert_eq!(connection.central.to_string(), "11:22:33:44:55:66");
+        assert_eq!(
+            connection.params,
+            ConnParams {
+                interval: Duration::from_micros(7500),
+                latency: 0,
+                supervision_timeout: Duration::from_secs(5),
+            }
+        );
+
+        let update = event(
+            EVT_LE_META,
+            &[
+                LE_CONN_UPDATE_COMPLETE,
+                0x00,
+                0x40,
+                0x00,
+                0x09,
+                0x00,
+                0x00,
+                0x00,
+                0xf4,
+                0x01,
+            ],
+        );
+        let Some(Change::Updated(0x40, params)) = parse(&update) else {
+            panic!("not an update");
+        };
+        assert_eq!(params.interval, Duration::from_micros(11250));
+    }
+
+    #[test]
+    fn central_role_connections_are_ignored() {
+        let mut params = [0u8; 19];
+        params[0] = LE_CONN_COMPLETE;
+        assert!(parse(&event(EVT_LE_META, &params)).is_none());
+    }
+}
@@ -0,0 +1,185 @@
+// BlueZ has no D-Bus property for LE connection parameters, so they are
+// read from (and requested through) the kernel's debugfs entries.
+// https://github.com/torvalds/linux/blob/v5.10/net/bluetooth/hci_debugfs.c
+
+use std::fs;
+use std::io;
+use std::num::ParseIntError;
+
+use thiserror::Error;
+use tokio::time::Duration;
+
+const DEBUGFS_ROOT: &str = "/sys/kernel/debug/bluetooth";
+const ADAPTER: &str = "hci0";
+
+// connection intervals are expressed in units of 1.25 ms
+const UNIT_MICROS: u64 = 1250;
+const MIN_UNITS: u16 = 0x0006;
+const MAX_UNITS: u16 = 0x0C80;
+
+/// A BLE connection interval range, as the adapter negotiates it.
+#[derive(Clone, Copy, Debug)]
+pub struct ConnInterval {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+/// Why the connection interval couldn't be read or requested.
+#[derive(Debug, Error)]
+pub enum ConnIntervalError {
+    #[error("connection interval out of range (7.5ms - 4000ms): {0:?}")]
+    OutOfRange(Duration),
+    #[error("minimum connection interval is larger than maximum: {min:?} > {max:?}")]
+    MinAboveMax { min: Duration, max: Duration },
+    #[error("failed to read {path}")]
+    Read {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("unexpected content in {path}: {value}")]
+    Content {
+        path: String,
+        value: String,
+        #[source]
+        source: ParseIntError,
+    },
+    #[error("failed to write {path}")]
+    Write {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+fn debugfs_path(name: &str) -> String {
+    format!("{DEBUGFS_ROOT}/{ADAPTER}/{name}")
+}
+
+fn to_units(interval: Duration) -> Result<u16, ConnIntervalError> {
+    let units = interval.as_micros() / UNIT_MICROS as u128;
+    u16::try_from(units)
+        .ok()
+        .filter(|units| (MIN_UNITS..=MAX_UNITS).contains(units))
+        .ok_or(ConnIntervalError::OutOfRange(interval))
+}
+
+fn from_units(units: u16) -> Duration {
+    Duration::from_micros(units as u64 * UNIT_MICROS)
+}
+
+fn read_units(name: &str) -> Result<u16, ConnIntervalError> {
+    let path = debugfs_path(name);
+    let value = match fs::read_to_string(&path) {
+        Ok(value) => value,
+        Err(source) => return Err(ConnIntervalError::Read { path, source }),
+    };
+    value
+        .trim()
+        .parse()
+        .map_err(|source| ConnIntervalError::Content {
+            path,
+            value,
+            source,
+        })
+}
+
+fn write_units(name: &str, units: u16) -> Result<(), ConnIntervalError> {
+    let path = debugfs_path(name);
+    fs::write(&path, units.to_string()).map_err(|source| ConnIntervalError::Write { path, source })
+}
+
+/// connection interval range the adapter is configured to accept
+pub fn read_conn_interval() -> Result<ConnInterval, ConnIntervalError> {
+    Ok(ConnInterval {
+        min: from_units(read_units("conn_min_interval")?),
+        max: from_units(read_units("conn_max_interval")?),
+    })
+}
+
+/// Asks the stack to prefer the given connection interval range: a central
+/// that connects with an interval outside it gets a connection parameter
+/// update request for it.
+pub fn request_conn_interval(interval: ConnInterval) -> Result<(), ConnIntervalError> {
+    let min = to_units(interval.min)?;
+    let max = to_units(interval.max)?;
+    if min > max {
+        return Err(ConnIntervalError::MinAboveMax {
+            min: interval.min,
+            max: interval.max,
+        });
+    }
+
+    // the kernel rejects min > max, so the order of the writes matters
+    if min > read_units("conn_max_interval")? {
+        write_units("conn_max_interval", max)?;
+        write_units("conn_min_interval", min)?;
+    } else {
+        write_units("conn_min_interval", min)?;
+        write_units("conn_max_interval", max)?;
+    }
+
+    Ok(())
+}
+
+/// nearest multiple (at least one) of the connection interval
+pub fn align_to_conn_interval(interval: Duration, conn_interval: Duration) -> Duration {
+    let conn_micros = conn_interval.as_micros().max(1);
+    let multiple = ((interval.as_micros() + conn_micros / 2) / conn_micros).max(1);
+    Duration::from_micros((multiple * conn_micros) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn out_of_range_intervals_are_errors_that_say_the_range() {
+        let error = to_units(Duration::from_millis(5)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "connection interval out of range (7.5ms - 4000ms): 5ms"
+        );
+        assert_eq!(to_units(Duration::from_micros(7500)).unwrap(), MIN_UNITS);
+        assert_eq!(to_units(Duration::from_secs(4)).unwrap(), MAX_UNITS);
+        assert!(to_units(Duration::from_millis(4002)).is_err());
+    }
+
+    #[test]
+    fn messages_name_the_file_and_keep_the_cause() {
+        let error = ConnIntervalError::MinAboveMax {
+            min: Duration::from_millis(15),
+            max: Duration::from_micros(7500),
+        };
+        assert_eq!(
+            error.to_string(),
+            "minimum connection interval is larger than maximum: 15ms > 7.5ms"
+        );
+
+        let error = ConnIntervalError::Content {
+            path: debugfs_path("conn_min_interval"),
+            value: "six".to_string(),
+            source: "six".parse::<u16>().unwrap_err(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "unexpected content in /sys/kernel/debug/bluetooth/hci0/conn_min_interval: six"
+        );
+        assert_eq!(
+            error.source().map(ToString::to_string).as_deref(),
+            Some("invalid digit found in string")
+        );
+
+        let error = ConnIntervalError::Write {
+            path: debugfs_path("conn_max_interval"),
+            source: io::Error::from(io::ErrorKind::PermissionDenied),
+        };
+        assert_eq!(
+            error.to_string(),
+            "failed to write /sys/kernel/debug/bluetooth/hci0/conn_max_interval"
+        );
+        assert!(error.source().is_some());
+    }
+}
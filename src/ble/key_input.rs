@@ -1,26 +1,194 @@
+#[cfg(feature = "ble")]
 use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
-use bluster::gatt::service::Service;
-use crossbeam::atomic::AtomicCell;
+use beatble_protocol::payload::{Layout, Payload, ScratchMode};
+#[cfg(feature = "ble")]
+use bluster::gatt::{event::Event, service::Service};
+#[cfg(feature = "ble")]
+use futures::channel::mpsc::Sender;
+use futures::channel::mpsc::{channel, Receiver};
 use tokio::time::Duration;
 
-use crate::input::KeyInput;
+use crate::control::Control;
+use crate::dump::PayloadDump;
+use crate::emulation::Emulation;
+use crate::input::SharedKeyInput;
+#[cfg(feature = "ble")]
+use crate::input::{KeyInputDp, Side};
+use crate::latency::Latency;
+use crate::stats::Stats;
 
-use self::{characteristics::create_key_input_characteristic, service::create_key_input_service};
+use self::notifier::Notifier;
+#[cfg(feature = "ble")]
+use self::{
+    characteristics::create_key_input_characteristic, ping::create_ping_characteristic,
+    service::create_key_input_service,
+};
 
+#[cfg(feature = "ble")]
 mod characteristics;
+mod notifier;
+mod pacer;
+#[cfg(feature = "ble")]
+mod ping;
+mod predictor;
+#[cfg(feature = "ble")]
 mod service;
+#[cfg(feature = "ble")]
 mod uuid;
+#[cfg(feature = "ble")]
+mod watchdog;
 
-pub fn create_key_input(key_input: Arc<AtomicCell<KeyInput>>, sleep_duration: Duration) -> Service {
-    create_key_input_service(true, {
-        let mut characteristics = HashSet::new();
-        characteristics.insert(create_key_input_characteristic(
-            key_input,
-            sleep_duration,
-            HashSet::new(),
-        ));
-        characteristics
-    })
+/// When a notifier sends frames.
+#[derive(Clone, Copy, Debug)]
+pub enum NotifyMode {
+    /// send a frame every interval
+    Periodic,
+    /// send a frame only when the input changes, or when keep_alive has elapsed since the last one
+    OnChange { keep_alive: Duration },
+}
+
+/// When the repeats of a frame go out, see NotifyConfig::frame_repeat.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RepeatSpacing {
+    /// right after the frame, in the same connection event if there's room
+    #[default]
+    BackToBack,
+    /// on the following ticks, holding back newer input meanwhile
+    Ticks,
+}
+
+impl FromStr for RepeatSpacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "back-to-back" => Ok(RepeatSpacing::BackToBack),
+            "ticks" => Ok(RepeatSpacing::Ticks),
+            _ => Err(format!(
+                "unknown repeat spacing: {s} (expected back-to-back or ticks)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RepeatSpacing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepeatSpacing::BackToBack => write!(f, "back-to-back"),
+            RepeatSpacing::Ticks => write!(f, "ticks"),
+        }
+    }
+}
+
+/// How the key input service notifies, fixed for its lifetime.
+#[derive(Clone, Copy, Debug)]
+pub struct NotifyConfig {
+    /// initial interval and mode; Control holds the current ones
+    pub interval: Duration,
+    pub mode: NotifyMode,
+    /// neutral frames sent right after a subscription before real input
+    pub warmup_frames: usize,
+    /// counter of the first frame after a subscription
+    pub counter_start: u8,
+    /// times each frame is sent, with the same counter so the console takes
+    /// it once; 1 sends every frame once
+    pub frame_repeat: usize,
+    pub repeat_spacing: RepeatSpacing,
+    /// spin instead of sleeping for the last part of each interval
+    pub busy_poll: bool,
+    /// payload encoding
+    pub layout: Layout,
+    /// which controller's service and payloads to send
+    pub emulation: Emulation,
+    /// what the iidx scratch byte carries
+    pub scratch_mode: ScratchMode,
+    /// experimental: how far ahead to extrapolate the iidx scratch position
+    pub scratch_predict: Option<Duration>,
+}
+
+/// State shared between the notifiers and the rest of the process.
+#[derive(Clone)]
+pub struct NotifyContext {
+    /// the input the frames are built from
+    pub key_input: Arc<SharedKeyInput>,
+    pub control: Arc<Control>,
+    pub latency: Arc<Latency>,
+    pub stats: Arc<Stats>,
+    /// records every sent payload when set
+    pub dump: Option<Arc<PayloadDump>>,
+}
+
+/// The key input service; each subscription to it gets its own notifier.
+#[cfg(feature = "ble")]
+pub fn create_key_input(context: NotifyContext, notify_config: NotifyConfig) -> Service {
+    create_key_input_with_events(context, notify_config).0
+}
+
+/// create_key_input, along with a sender of the key input characteristic's
+/// events, to subscribe and unsubscribe as a mock central would. bluster
+/// keeps the characteristics of a built service to itself.
+#[cfg(feature = "ble")]
+// bluster hashes its characteristics by uuid, the mutable parts aren't hashed
+#[allow(clippy::mutable_key_type)]
+pub fn create_key_input_with_events(
+    context: NotifyContext,
+    notify_config: NotifyConfig,
+) -> (Service, Sender<Event>) {
+    let (key_input, events) =
+        create_key_input_characteristic(context.clone(), notify_config, HashSet::new());
+    let mut characteristics = HashSet::new();
+    characteristics.insert(create_ping_characteristic(Arc::clone(&context.stats)));
+    characteristics.insert(key_input);
+    let service = create_key_input_service(notify_config.emulation, true, characteristics);
+    (service, events)
+}
+
+/// one complete key input service per side, so the console sees two controllers
+#[cfg(feature = "ble")]
+pub fn create_key_input_dp(
+    context: NotifyContext,
+    key_input: &KeyInputDp,
+    notify_config: NotifyConfig,
+) -> Vec<Service> {
+    [Side::P1, Side::P2]
+        .into_iter()
+        .map(|side| {
+            let context = NotifyContext {
+                key_input: Arc::clone(key_input.side(side)),
+                ..context.clone()
+            };
+            create_key_input(context, notify_config)
+        })
+        .collect()
+}
+
+/// runs a notifier without bluetooth and returns the frames it sends
+pub fn spawn_local_notifier(
+    context: NotifyContext,
+    notify_config: NotifyConfig,
+) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = channel(1);
+    tokio::spawn(notifier::forward(
+        spawn_local_payloads(context, notify_config),
+        sender,
+    ));
+    receiver
+}
+
+/// Like spawn_local_notifier, but returns the payloads as the notifier
+/// queues them, before they are copied for bluster.
+pub fn spawn_local_payloads(
+    context: NotifyContext,
+    notify_config: NotifyConfig,
+) -> tokio::sync::mpsc::Receiver<Payload> {
+    let (frames, payloads) = tokio::sync::mpsc::channel(1);
+    let notifying = Arc::new(AtomicBool::new(true));
+    let subscriber = context.stats.register(1);
+    tokio::spawn(Notifier::new(context, notify_config, notifying, subscriber, frames).run());
+    payloads
 }
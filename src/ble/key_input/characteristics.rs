@@ -5,70 +5,130 @@ use bluster::{
     gatt::{
         characteristic::{Characteristic, Properties},
         descriptor::Descriptor,
-        event::Event,
+        event::{Event, NotifySubscribe},
     },
     SdpShortUuid,
 };
-use crossbeam::atomic::AtomicCell;
-use futures::channel::mpsc::channel;
-use futures::StreamExt;
-use log::{debug, info, trace};
+use futures::channel::mpsc::{channel, Sender};
+use futures::{SinkExt, StreamExt};
 use tokio::time::Duration;
+use tracing::{debug, info, info_span, Instrument};
 
+use super::notifier::{self, Notifier};
 use super::uuid::Uuid;
-use crate::input::KeyInput;
+use super::watchdog;
+use super::{NotifyConfig, NotifyContext};
+use crate::chaos::{self, Chaos};
+use crate::emulation::Emulation;
 
-const CHARACTERISTIC_UUID: u16 = 0xFF01;
+const IIDX_CHARACTERISTIC_UUID: u16 = 0xFF01;
+// not verified against hardware
+const SDVX_CHARACTERISTIC_UUID: u16 = 0xFE01;
+// long enough for the watchdog to retire the notifier of the dropped subscription
+const RESUBSCRIBE_AFTER: Duration = Duration::from_secs(2);
 
+/// The characteristic, and a sender of the events it handles for whoever
+/// wants to play the central without bluetooth.
+// bluster hashes its descriptors by uuid, the mutable parts aren't hashed
+#[allow(clippy::mutable_key_type)]
 pub fn create_key_input_characteristic(
-    key_input: Arc<AtomicCell<KeyInput>>,
-    sleep_duration: Duration,
+    context: NotifyContext,
+    notify_config: NotifyConfig,
     descriptors: HashSet<Descriptor>,
-) -> Characteristic {
+) -> (Characteristic, Sender<Event>) {
     debug!("create_key_input_characteristic");
 
+    let characteristic_uuid = match notify_config.emulation {
+        Emulation::Iidx => IIDX_CHARACTERISTIC_UUID,
+        Emulation::Sdvx => SDVX_CHARACTERISTIC_UUID,
+    };
+
     let (sender, receiver) = channel(1);
+    // only kept when injecting resubscriptions, so the handler still ends with bluster's sender
+    let chaos = chaos::get()
+        .filter(|chaos| chaos.resubscribe > 0.0)
+        .map(|chaos| (chaos, sender.clone()));
 
     let characteristic_handler = async move {
         debug!("create_key_input_characteristic: handler spawned");
         let notifying = Arc::new(atomic::AtomicBool::new(false));
+        // bluster doesn't tell which central subscribed, so subscriptions are numbered
+        let mut subscriptions = 0u64;
         let mut rx = receiver;
         while let Some(event) = rx.next().await {
             match event {
                 Event::NotifySubscribe(notify_subscribe) => {
-                    info!("notify request to UUID({}) received", CHARACTERISTIC_UUID);
+                    info!(
+                        uuid = characteristic_uuid,
+                        "notify request to UUID({}) received", characteristic_uuid
+                    );
                     let notifying = Arc::clone(&notifying);
-                    notifying.store(true, atomic::Ordering::Relaxed);
-
-                    let mut counter: u16 = 0;
-                    let key_input = Arc::clone(&key_input);
-                    tokio::spawn(async move {
-                        loop {
-                            if !notifying.load(atomic::Ordering::Relaxed) {
-                                break;
-                            };
-
-                            let payload = { key_input.load().to_payload((counter & 0xFF) as u8) };
-                            trace!("payload: {:?}", payload);
+                    if !notifying.swap(true, atomic::Ordering::Relaxed) {
+                        context.stats.subscribed();
+                    }
 
-                            notify_subscribe
-                                .clone()
-                                .notification
-                                .try_send(payload.to_vec())
-                                .unwrap();
+                    subscriptions += 1;
+                    context
+                        .stats
+                        .subscriptions
+                        .fetch_add(1, atomic::Ordering::Relaxed);
+                    let span = info_span!("subscription", id = subscriptions);
 
-                            counter = (counter + 2) & 0xFF;
-                            tokio::time::sleep(sleep_duration).await;
+                    let subscriber = context.stats.register(subscriptions);
+                    if let Some((chaos, events)) = &chaos {
+                        tokio::spawn(
+                            resubscribe_at_random(
+                                chaos,
+                                events.clone(),
+                                Arc::clone(&notifying),
+                                notify_subscribe.notification.clone(),
+                            )
+                            .instrument(span.clone()),
+                        );
+                    }
+                    // outlives a notifier the watchdog retires, with its queued frame
+                    let (frames, outgoing) = tokio::sync::mpsc::channel(1);
+                    tokio::spawn(
+                        notifier::forward(outgoing, notify_subscribe.notification.clone())
+                            .instrument(span.clone()),
+                    );
+                    let spawn_notifier = {
+                        let context = context.clone();
+                        let notifying = Arc::clone(&notifying);
+                        let subscriber = Arc::clone(&subscriber);
+                        let span = span.clone();
+                        move |respawn| {
+                            let notifier = Notifier::new(
+                                context.clone(),
+                                notify_config,
+                                Arc::clone(&notifying),
+                                Arc::clone(&subscriber),
+                                frames.clone(),
+                            );
+                            let notifier = if respawn { notifier.resume() } else { notifier };
+                            tokio::spawn(notifier.run().instrument(span.clone()))
                         }
-                        debug!("ble_notifier finished");
-                    });
+                    };
+                    tokio::spawn(
+                        watchdog::supervise(
+                            spawn_notifier,
+                            subscriber,
+                            notifying,
+                            notify_subscribe.notification,
+                            Arc::clone(&context.control),
+                            Arc::clone(&context.stats),
+                        )
+                        .instrument(span),
+                    );
                 }
                 Event::NotifyUnsubscribe => {
                     info!(
-                        "unsubscribe request to UUID({}) received",
-                        CHARACTERISTIC_UUID
+                        uuid = characteristic_uuid,
+                        "unsubscribe request to UUID({}) received", characteristic_uuid
                     );
-                    notifying.store(false, atomic::Ordering::Relaxed);
+                    if notifying.swap(false, atomic::Ordering::Relaxed) {
+                        context.stats.unsubscribed();
+                    }
                 }
                 _ => {
                     info!(
@@ -80,12 +140,49 @@ pub fn create_key_input_characteristic(
         }
     };
 
-    tokio::spawn(characteristic_handler);
+    tokio::spawn(
+        characteristic_handler
+            .instrument(info_span!("ble.characteristic", uuid = characteristic_uuid)),
+    );
 
-    Characteristic::new(
-        Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID),
+    let events = sender.clone();
+    let characteristic = Characteristic::new(
+        Uuid::from_sdp_short_uuid(characteristic_uuid),
         Properties::new(None, None, Some(sender), None),
         None,
         descriptors,
-    )
+    );
+    (characteristic, events)
+}
+
+/// Drops the subscription at the rate of a Chaos, as if the central had
+/// unsubscribed, and subscribes it again a little later.
+async fn resubscribe_at_random(
+    chaos: &Chaos,
+    mut events: Sender<Event>,
+    notifying: Arc<atomic::AtomicBool>,
+    notification: Sender<Vec<u8>>,
+) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if !notifying.load(atomic::Ordering::Relaxed) || notification.is_closed() {
+            return;
+        }
+        if chaos::roll(chaos.resubscribe) {
+            break;
+        }
+    }
+    info!("injecting an unsubscribe");
+    if events.send(Event::NotifyUnsubscribe).await.is_err() {
+        return;
+    }
+    tokio::time::sleep(RESUBSCRIBE_AFTER).await;
+    if notification.is_closed() {
+        return;
+    }
+    info!("injecting a subscribe");
+    let subscribe = NotifySubscribe { notification };
+    let _ = events.send(Event::NotifySubscribe(subscribe)).await;
 }
@@ -0,0 +1,728 @@
+use std::future::poll_fn;
+use std::sync::{atomic, Arc};
+
+use beatble_protocol::payload::{
+    decode_scratch_velocity, encode_scratch_velocity, sdvx, Frame, Payload, ScratchMode,
+    SCRATCH_VELOCITY_IDLE,
+};
+use beatble_protocol::KeyInput;
+use futures::channel::mpsc::Sender;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{debug, trace};
+
+use super::pacer::Pacer;
+use super::predictor::ScratchPredictor;
+use super::{NotifyConfig, NotifyContext, NotifyMode, RepeatSpacing};
+use crate::chaos;
+use crate::clock;
+use crate::emulation::Emulation;
+use crate::recent;
+use crate::stats::SubscriberStats;
+
+// position units in one unit of the velocity scratch mode, 1/256 turn
+const VELOCITY_UNIT: i32 = 256;
+
+/// Sends key input frames to a single subscriber until it unsubscribes.
+pub struct Notifier {
+    context: NotifyContext,
+    config: NotifyConfig,
+    notifying: Arc<atomic::AtomicBool>,
+    subscriber: Arc<SubscriberStats>,
+    // frames on their way to forward(), which hands them to bluster
+    frames: mpsc::Sender<Payload>,
+    // the counter advances per sent frame, not per elapsed tick,
+    // so skipped ticks in on-change mode never leave a gap
+    counter: u8,
+    last_sent: Option<(KeyInput, Instant)>,
+    last_sent_at: Option<u64>,
+    last_updated_at: u64,
+    // newest frame that didn't fit into the congested channel
+    pending: Option<KeyInput>,
+    // payload of the latest frame and how many more times it goes out
+    repeat: Option<(Payload, usize)>,
+    congestion_streak: u64,
+    warmup_frames: usize,
+    // taking over from a notifier the watchdog retired
+    resumed: bool,
+    paused: bool,
+    predictor: Option<ScratchPredictor>,
+    // velocity scratch mode: turntable movement taken from the input but not
+    // sent yet, in position units
+    movement: i32,
+    // what an idle on-change notifier waits for
+    input_changes: watch::Receiver<u64>,
+    control_changes: watch::Receiver<u64>,
+}
+
+impl Notifier {
+    pub fn new(
+        context: NotifyContext,
+        config: NotifyConfig,
+        notifying: Arc<atomic::AtomicBool>,
+        subscriber: Arc<SubscriberStats>,
+        frames: mpsc::Sender<Payload>,
+    ) -> Self {
+        let last_updated_at = context.key_input.updated_at();
+        let input_changes = context.key_input.subscribe();
+        let control_changes = context.control.subscribe();
+        // only the turntable is predicted
+        let predictor = config
+            .scratch_predict
+            .filter(|_| config.emulation == Emulation::Iidx)
+            .map(ScratchPredictor::new);
+        Self {
+            context,
+            config,
+            notifying,
+            subscriber,
+            frames,
+            counter: config.counter_start,
+            last_sent: None,
+            last_sent_at: None,
+            last_updated_at,
+            pending: None,
+            repeat: None,
+            congestion_streak: 0,
+            warmup_frames: config.warmup_frames,
+            resumed: false,
+            paused: false,
+            predictor,
+            movement: 0,
+            input_changes,
+            control_changes,
+        }
+    }
+
+    /// Takes over the subscription of a notifier that stalled or exited: the
+    /// counter goes on from its latest frame, without a second warm-up, so
+    /// the console sees no jump back.
+    // only the characteristic respawns a notifier
+    #[cfg_attr(not(feature = "ble"), allow(dead_code))]
+    pub fn resume(mut self) -> Self {
+        if self.subscriber.sent_frames.load(atomic::Ordering::Relaxed) > 0 {
+            let counter = self.subscriber.counter.load(atomic::Ordering::Relaxed);
+            self.counter = counter.wrapping_add(self.counter_step());
+        }
+        self.warmup_frames = 0;
+        self.resumed = true;
+        self
+    }
+
+    pub async fn run(mut self) {
+        if !self.resumed {
+            // presses latched before the subscription belong to no frame
+            self.context.key_input.take();
+            self.context.key_input.take_movement();
+        }
+
+        let mut pacer = Pacer::new(self.context.control.interval(), self.config.busy_poll);
+        loop {
+            if !self.notifying.load(atomic::Ordering::Relaxed) {
+                break;
+            };
+            self.control_changes.borrow_and_update();
+            let now = clock::now();
+            self.subscriber
+                .heartbeat
+                .store(now, atomic::Ordering::Relaxed);
+            self.context
+                .stats
+                .last_tick
+                .store(now, atomic::Ordering::Relaxed);
+
+            let frame = if self.repeat.is_some() {
+                // the same frame again; newer input stays latched for the next one
+                if !self.send_repeat() {
+                    debug!("subscriber dropped the notification channel");
+                    break;
+                }
+                None
+            } else {
+                match (self.next_frame(), self.pending.take()) {
+                    // keep presses from the frame that never went out, but the latest state wins
+                    (Some(mut key_input), Some(pending)) if !self.paused => {
+                        key_input.normal_button |= pending.normal_button;
+                        key_input.option_button |= pending.option_button;
+                        Some(key_input)
+                    }
+                    (Some(key_input), _) => Some(key_input),
+                    (None, pending) => pending,
+                }
+            };
+            if let Some(key_input) = frame {
+                if let Some(chaos) = chaos::get() {
+                    if chaos::roll(chaos.delay_frames) {
+                        tokio::time::sleep(chaos.delay).await;
+                    }
+                }
+                if !self.send(key_input) {
+                    debug!("subscriber dropped the notification channel");
+                    break;
+                }
+            }
+            if self.config.repeat_spacing == RepeatSpacing::BackToBack
+                && !self.repeat_back_to_back().await
+            {
+                debug!("subscriber dropped the notification channel");
+                break;
+            }
+            // picks up a rate reloaded from the config
+            let interval = self.context.control.interval();
+            if interval.is_zero() {
+                // as fast as the link takes frames, see check_interval
+                tokio::task::yield_now().await;
+                if self.frames.reserve().await.is_err() {
+                    debug!("subscriber dropped the notification channel");
+                    break;
+                }
+            } else {
+                pacer.set_interval(interval);
+                pacer.wait().await;
+            }
+            if let Some(keep_alive_at) = self.idle_until() {
+                self.wait_for_change(keep_alive_at).await;
+                pacer.reset();
+            }
+        }
+        debug!("ble_notifier finished");
+    }
+
+    /// key input to send on this tick, if any
+    fn next_frame(&mut self) -> Option<KeyInput> {
+        if self.warmup_frames > 0 {
+            // start the console's input state machine from a known neutral state
+            self.warmup_frames -= 1;
+            return Some(self.neutral());
+        }
+
+        if self.context.control.is_paused() {
+            if self.paused {
+                return None;
+            }
+            // release everything once, then go quiet
+            self.paused = true;
+            self.movement = 0;
+            return Some(self.neutral());
+        }
+        if self.paused {
+            // drop presses latched and movement made while paused
+            self.paused = false;
+            self.context.key_input.take();
+            self.context.key_input.take_movement();
+        }
+
+        // take() also consumes latched presses, so short taps count as a change
+        self.input_changes.borrow_and_update();
+        let mut key_input = self.context.key_input.take();
+        if let Some(predictor) = &mut self.predictor {
+            let position = predictor.predict(
+                key_input.scratch_position(),
+                self.context.key_input.updated_at(),
+                clock::now(),
+            );
+            key_input.set_scratch_position(position);
+        }
+        if self.velocity_mode() {
+            self.movement = self
+                .movement
+                .saturating_add(self.context.key_input.take_movement());
+            // what doesn't fit into this frame goes into the next ones
+            key_input.scratch = encode_scratch_velocity(self.movement / VELOCITY_UNIT);
+            key_input.analog = 0;
+        }
+        should_notify(self.context.control.mode(), key_input, self.last_sent).then_some(key_input)
+    }
+
+    /// In on-change mode, when the keep-alive frame is due unless something
+    /// changes first. None while there is something to send anyway.
+    fn idle_until(&self) -> Option<Instant> {
+        let NotifyMode::OnChange { keep_alive } = self.context.control.mode() else {
+            return None;
+        };
+        if self.paused || self.warmup_frames > 0 || self.pending.is_some() || self.repeat.is_some()
+        {
+            return None;
+        }
+        // a predicted frame is followed by the input catching up with it
+        if self
+            .predictor
+            .as_ref()
+            .is_some_and(ScratchPredictor::is_ahead)
+        {
+            return None;
+        }
+        // movement that didn't fit into the previous frame
+        if self.movement.abs() >= VELOCITY_UNIT {
+            return None;
+        }
+        let (_, sent_at) = self.last_sent?;
+        Some(sent_at + keep_alive)
+    }
+
+    /// sleeps until the input or the controls change, or keep_alive_at
+    async fn wait_for_change(&mut self, keep_alive_at: Instant) {
+        // the senders live in the context, so neither ends while waiting
+        tokio::select! {
+            _ = self.input_changes.changed() => {}
+            _ = self.control_changes.changed() => {}
+            _ = tokio::time::sleep_until(keep_alive_at) => {}
+        }
+    }
+
+    /// returns false once the subscriber is gone
+    fn send(&mut self, key_input: KeyInput) -> bool {
+        if chaos::get().is_some_and(|chaos| chaos::roll(chaos.full_channel)) {
+            self.congested(key_input);
+            return true;
+        }
+        let payload = self.encode(key_input);
+        trace!("payload: {:?}", payload.as_bytes());
+        match self.frames.try_send(payload) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.congested(key_input);
+                return true;
+            }
+            Err(TrySendError::Closed(_)) => return false,
+        }
+        if self.congestion_streak > 0 {
+            debug!(
+                frames = self.congestion_streak,
+                "notification channel congested for {} frames", self.congestion_streak
+            );
+            self.congestion_streak = 0;
+        }
+
+        self.context
+            .stats
+            .sent_frames
+            .fetch_add(1, atomic::Ordering::Relaxed);
+        self.subscriber
+            .sent_frames
+            .fetch_add(1, atomic::Ordering::Relaxed);
+        self.subscriber
+            .counter
+            .store(self.counter, atomic::Ordering::Relaxed);
+        if let Some(dump) = &self.context.dump {
+            dump.record(self.counter, payload);
+        }
+        recent::record_frame(self.counter, key_input);
+        self.counter = self.counter.wrapping_add(self.counter_step());
+        self.last_sent = Some((key_input, Instant::now()));
+        if self.velocity_mode() {
+            self.movement -= i32::from(decode_scratch_velocity(key_input.scratch)) * VELOCITY_UNIT;
+        }
+        self.record_latency();
+        self.repeat =
+            (self.config.frame_repeat > 1).then_some((payload, self.config.frame_repeat - 1));
+        true
+    }
+
+    /// Sends the latest frame once more, with its counter. A repeat that
+    /// doesn't fit is skipped rather than counted as congested, the frame
+    /// itself went out. Returns false once the subscriber is gone.
+    fn send_repeat(&mut self) -> bool {
+        let Some((payload, left)) = self.repeat.take() else {
+            return true;
+        };
+        match self.frames.try_send(payload) {
+            Ok(()) => {
+                self.context
+                    .stats
+                    .repeated_frames
+                    .fetch_add(1, atomic::Ordering::Relaxed);
+            }
+            Err(TrySendError::Full(_)) => trace!("no room for a repeat"),
+            Err(TrySendError::Closed(_)) => return false,
+        }
+        if left > 1 {
+            self.repeat = Some((payload, left - 1));
+        }
+        true
+    }
+
+    /// the repeats of the frame just sent, each after bluster had a chance to
+    /// take the one before
+    async fn repeat_back_to_back(&mut self) -> bool {
+        while self.repeat.is_some() {
+            tokio::task::yield_now().await;
+            if !self.send_repeat() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// retried with fresh state on the next tick instead of queueing a stale frame
+    fn congested(&mut self, key_input: KeyInput) {
+        self.pending = Some(key_input);
+        self.congestion_streak += 1;
+        self.subscriber
+            .congested_frames
+            .fetch_add(1, atomic::Ordering::Relaxed);
+        self.context
+            .stats
+            .congested_frames
+            .fetch_add(1, atomic::Ordering::Relaxed);
+        self.context
+            .stats
+            .max_congestion_streak
+            .fetch_max(self.congestion_streak, atomic::Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn velocity_mode(&self) -> bool {
+        self.config.scratch_mode == ScratchMode::Velocity
+            && self.config.emulation == Emulation::Iidx
+    }
+
+    /// a frame with nothing pressed and the turntable at rest
+    fn neutral(&self) -> KeyInput {
+        let mut key_input = KeyInput::init();
+        if self.velocity_mode() {
+            key_input.scratch = SCRATCH_VELOCITY_IDLE;
+        }
+        key_input
+    }
+
+    #[inline]
+    fn encode(&self, key_input: KeyInput) -> Payload {
+        match self.config.emulation {
+            Emulation::Iidx => Frame::repeated(key_input, self.counter).encode(self.config.layout),
+            Emulation::Sdvx => sdvx::Frame::new(key_input, self.counter).encode(),
+        }
+    }
+
+    #[inline]
+    fn counter_step(&self) -> u8 {
+        match self.config.emulation {
+            Emulation::Iidx => self.config.layout.counter_step(),
+            Emulation::Sdvx => sdvx::COUNTER_STEP,
+        }
+    }
+
+    #[inline]
+    fn record_latency(&mut self) {
+        let now = clock::now();
+        if let Some(last_sent_at) = self.last_sent_at.replace(now) {
+            self.context
+                .latency
+                .frame_interval
+                .record((now - last_sent_at) / 1000);
+        }
+
+        // only frames carrying new input have a meaningful age
+        let updated_at = self.context.key_input.updated_at();
+        if updated_at != self.last_updated_at {
+            self.last_updated_at = updated_at;
+            self.context
+                .latency
+                .data_age
+                .record(now.saturating_sub(updated_at) / 1000);
+        }
+    }
+}
+
+/// Hands the frames of a subscription's notifiers to bluster, which takes an
+/// owned Vec<u8> per notification. A frame is only taken once bluster has
+/// room for it, so a congested link still fills the notifier's channel.
+pub async fn forward(mut frames: mpsc::Receiver<Payload>, mut notification: Sender<Vec<u8>>) {
+    while poll_fn(|cx| notification.poll_ready(cx)).await.is_ok() {
+        let Some(payload) = frames.recv().await else {
+            return;
+        };
+        if notification
+            .start_send(payload.as_bytes().to_vec())
+            .is_err()
+        {
+            return;
+        }
+    }
+    debug!("subscriber dropped the notification channel");
+}
+
+#[inline]
+fn should_notify(
+    mode: NotifyMode,
+    key_input: KeyInput,
+    last_sent: Option<(KeyInput, Instant)>,
+) -> bool {
+    match (mode, last_sent) {
+        (NotifyMode::Periodic, _) | (_, None) => true,
+        (NotifyMode::OnChange { keep_alive }, Some((sent, sent_at))) => {
+            sent != key_input || sent_at.elapsed() >= keep_alive
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use beatble_protocol::payload::{Layout, PayloadFormat};
+    use beatble_protocol::NormalButton;
+    use futures::channel::mpsc::Receiver;
+    use futures::StreamExt;
+    use tokio::time::Duration;
+
+    use super::super::spawn_local_notifier;
+    use super::*;
+    use crate::control::Control;
+    use crate::input::SharedKeyInput;
+    use crate::latency::Latency;
+    use crate::stats::Stats;
+
+    const INTERVAL: Duration = Duration::from_millis(8);
+
+    fn config() -> NotifyConfig {
+        NotifyConfig {
+            interval: INTERVAL,
+            mode: NotifyMode::Periodic,
+            warmup_frames: 0,
+            counter_start: 0,
+            frame_repeat: 1,
+            repeat_spacing: RepeatSpacing::BackToBack,
+            busy_poll: false,
+            layout: Layout::default(),
+            emulation: Emulation::Iidx,
+            scratch_mode: ScratchMode::Position,
+            scratch_predict: None,
+        }
+    }
+
+    fn context(config: &NotifyConfig) -> NotifyContext {
+        NotifyContext {
+            key_input: Arc::new(SharedKeyInput::new()),
+            control: Arc::new(Control::new(config.interval, config.mode)),
+            latency: Arc::new(Latency::new()),
+            stats: Arc::new(Stats::new()),
+            dump: None,
+        }
+    }
+
+    fn pressed(buttons: NormalButton) -> KeyInput {
+        KeyInput {
+            normal_button: buttons,
+            ..KeyInput::init()
+        }
+    }
+
+    async fn next_frame(frames: &mut Receiver<Vec<u8>>) -> Frame {
+        let payload = frames.next().await.expect("the notifier stopped");
+        Frame::decode(&payload, Layout::default()).expect("a valid frame")
+    }
+
+    async fn next_queued_frame(frames: &mut mpsc::Receiver<Payload>) -> Frame {
+        let payload = frames.recv().await.expect("the notifier stopped");
+        Frame::decode(payload.as_bytes(), Layout::default()).expect("a valid frame")
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warmup_frames_are_neutral_whatever_is_held() {
+        let config = NotifyConfig {
+            warmup_frames: 3,
+            ..config()
+        };
+        let context = context(&config);
+        context.key_input.store(pressed(NormalButton::B1));
+        let mut frames = spawn_local_notifier(context.clone(), config);
+
+        for _ in 0..3 {
+            let frame = next_frame(&mut frames).await;
+            assert_eq!(frame.first, KeyInput::init());
+            assert_eq!(frame.second, KeyInput::init());
+            // pressed meanwhile, and held on
+            context
+                .key_input
+                .store(pressed(NormalButton::B1 | NormalButton::B2));
+        }
+        let frame = next_frame(&mut frames).await;
+        assert_eq!(
+            frame.first.normal_button,
+            NormalButton::B1 | NormalButton::B2
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warmup_starts_at_the_counter_start() {
+        let config = NotifyConfig {
+            warmup_frames: 2,
+            counter_start: 0x40,
+            ..config()
+        };
+        let mut frames = spawn_local_notifier(context(&config), config);
+
+        let counters = [
+            next_frame(&mut frames).await.counter,
+            next_frame(&mut frames).await.counter,
+            next_frame(&mut frames).await.counter,
+        ];
+        assert_eq!(counters, [0x40, 0x42, 0x44]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn counters_continue_across_wraps() {
+        for format in [PayloadFormat::V1, PayloadFormat::V2] {
+            let layout = Layout {
+                format,
+                ..Layout::default()
+            };
+            let config = NotifyConfig {
+                counter_start: 0xf0,
+                layout,
+                ..config()
+            };
+            let mut frames = spawn_local_notifier(context(&config), config);
+
+            let mut expected = 0xf0u8;
+            for _ in 0..1000 {
+                let payload = frames.next().await.expect("the notifier stopped");
+                let frame = Frame::decode(&payload, layout).expect("a valid frame");
+                // decode checks the second sub-report carries counter + 1
+                assert_eq!(frame.counter, expected, "{format}");
+                expected = expected.wrapping_add(format.counter_step());
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn each_frame_goes_out_twice_with_its_counter() {
+        for repeat_spacing in [RepeatSpacing::BackToBack, RepeatSpacing::Ticks] {
+            let config = NotifyConfig {
+                frame_repeat: 2,
+                repeat_spacing,
+                ..config()
+            };
+            let context = context(&config);
+            context.key_input.store(pressed(NormalButton::B1));
+            let mut frames = spawn_local_notifier(context.clone(), config);
+
+            let mut sent = vec![];
+            for input in [
+                pressed(NormalButton::B2),
+                // a tap while the repeat is due
+                pressed(NormalButton::B3),
+                KeyInput::init(),
+                KeyInput::init(),
+                KeyInput::init(),
+            ] {
+                let frame = next_frame(&mut frames).await;
+                sent.push((frame.counter, frame.first.normal_button));
+                context.key_input.store(input);
+            }
+            let sent_last = next_frame(&mut frames).await;
+            sent.push((sent_last.counter, sent_last.first.normal_button));
+
+            // the repeat carries the frame as it was, newer input the next frame
+            assert_eq!(
+                sent,
+                [
+                    (0, NormalButton::B1),
+                    (0, NormalButton::B1),
+                    (2, NormalButton::B2 | NormalButton::B3),
+                    (2, NormalButton::B2 | NormalButton::B3),
+                    (4, NormalButton::empty()),
+                    (4, NormalButton::empty()),
+                ],
+                "{repeat_spacing}"
+            );
+            assert_eq!(
+                context
+                    .stats
+                    .repeated_frames
+                    .load(atomic::Ordering::Relaxed),
+                3,
+                "{repeat_spacing}"
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_subscriber_that_stops_draining_gets_the_latest_frame() {
+        let config = config();
+        let context = context(&config);
+        let notifying = Arc::new(atomic::AtomicBool::new(true));
+        let subscriber = context.stats.register(1);
+        let (sender, mut frames) = mpsc::channel(1);
+        let notifier = Notifier::new(
+            context.clone(),
+            config,
+            notifying,
+            Arc::clone(&subscriber),
+            sender,
+        );
+        let notifier = tokio::spawn(notifier.run());
+
+        // takes one frame, then nothing for 20 ticks while B3 is pressed
+        next_queued_frame(&mut frames).await;
+        context.key_input.store(pressed(NormalButton::B3));
+        tokio::time::sleep(INTERVAL * 20).await;
+        assert!(!notifier.is_finished(), "the notifier stopped");
+        let congested = subscriber.congested_frames.load(atomic::Ordering::Relaxed);
+        assert!(congested >= 18, "{congested} frames congested");
+        let ticked_at = subscriber.heartbeat.load(atomic::Ordering::Relaxed);
+        tokio::time::sleep(INTERVAL * 2).await;
+        assert!(subscriber.heartbeat.load(atomic::Ordering::Relaxed) > ticked_at);
+
+        // the frame that filled the channel, then the newest state instead of
+        // the ones that didn't fit
+        let stale = next_queued_frame(&mut frames).await;
+        let latest = next_queued_frame(&mut frames).await;
+        assert_eq!(latest.counter, stale.counter.wrapping_add(2));
+        assert_eq!(latest.first.normal_button, NormalButton::B3);
+        let congested = subscriber.congested_frames.load(atomic::Ordering::Relaxed);
+        assert_eq!(
+            context
+                .stats
+                .max_congestion_streak
+                .load(atomic::Ordering::Relaxed),
+            congested
+        );
+
+        // and the frames go on at the usual rate once it drains again
+        context.key_input.store(KeyInput::init());
+        let next = next_queued_frame(&mut frames).await;
+        assert_eq!(next.counter, latest.counter.wrapping_add(2));
+        assert!(!notifier.is_finished());
+        drop(frames);
+        notifier.await.expect("the notifier not to panic");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_resumed_notifier_continues_the_counter_without_warmup() {
+        let config = NotifyConfig {
+            warmup_frames: 2,
+            ..config()
+        };
+        let context = context(&config);
+        let notifying = Arc::new(atomic::AtomicBool::new(true));
+        let subscriber = context.stats.register(1);
+        let (sender, mut frames) = mpsc::channel(1);
+        let spawn = |notifier: Notifier| tokio::spawn(notifier.run());
+        let notifier = || {
+            Notifier::new(
+                context.clone(),
+                config,
+                Arc::clone(&notifying),
+                Arc::clone(&subscriber),
+                sender.clone(),
+            )
+        };
+
+        let stalled = spawn(notifier());
+        for _ in 0..3 {
+            next_queued_frame(&mut frames).await;
+        }
+        stalled.abort();
+        let _ = stalled.await;
+        // whatever the aborted one still had queued
+        while frames.try_recv().is_ok() {}
+        let last = subscriber.counter.load(atomic::Ordering::Relaxed);
+
+        context.key_input.store(pressed(NormalButton::B3));
+        spawn(notifier().resume());
+        let frame = next_queued_frame(&mut frames).await;
+        assert_eq!(frame.counter, last.wrapping_add(2));
+        assert_eq!(frame.first.normal_button, NormalButton::B3);
+    }
+}
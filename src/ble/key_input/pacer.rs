@@ -0,0 +1,64 @@
+use tokio::time::{Duration, Instant};
+
+// how long before the deadline busy-poll mode stops sleeping and starts spinning
+const SPIN_MARGIN: Duration = Duration::from_micros(500);
+
+/// Waits for the next notification tick.
+pub struct Pacer {
+    interval: Duration,
+    busy_poll: bool,
+    deadline: Instant,
+}
+
+impl Pacer {
+    pub fn new(interval: Duration, busy_poll: bool) -> Self {
+        Self {
+            interval,
+            busy_poll,
+            deadline: Instant::now() + interval,
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// starts the next period now, after a wait of its own
+    pub fn reset(&mut self) {
+        self.deadline = Instant::now() + self.interval;
+    }
+
+    pub async fn wait(&mut self) {
+        if !self.busy_poll {
+            tokio::time::sleep(self.interval).await;
+            return;
+        }
+
+        // sleep through most of the period, then spin past the timer's granularity
+        let spin_from = self.deadline.checked_sub(SPIN_MARGIN);
+        if let Some(spin_from) = spin_from.filter(|&spin_from| spin_from > Instant::now()) {
+            tokio::time::sleep_until(spin_from).await;
+        }
+        // the spin takes a blocking thread, a runtime worker would stall every
+        // other task on it meanwhile
+        let deadline = self.deadline.into_std();
+        if deadline > std::time::Instant::now() {
+            let _ = tokio::task::spawn_blocking(move || spin_until(deadline)).await;
+        }
+
+        let now = Instant::now();
+        self.deadline += self.interval;
+        if self.deadline < now {
+            // fell behind by more than a period; don't burst to catch up
+            self.deadline = now + self.interval;
+        }
+    }
+}
+
+fn spin_until(deadline: std::time::Instant) {
+    while std::time::Instant::now() < deadline {
+        // yield rather than only spin, so realtime threads pinned to this core still run
+        std::hint::spin_loop();
+        std::thread::yield_now();
+    }
+}
@@ -0,0 +1,91 @@
+// A round-trip probe next to the key input: a central writes a token without
+// response and gets it back as a notification of the same characteristic,
+// through the same stack as the frames. Echoes are rate limited so a flood of
+// pings can't crowd out the key input notifications.
+
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use bluster::{
+    gatt::{
+        characteristic::{Characteristic, Properties, Write},
+        event::{Event, Response},
+    },
+    SdpShortUuid,
+};
+use futures::channel::mpsc::{channel, Sender};
+use futures::StreamExt;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, info_span, trace, Instrument};
+
+use super::uuid::Uuid;
+use crate::stats::Stats;
+
+pub const PING_CHARACTERISTIC_UUID: u16 = 0xFF05;
+/// the longest token echoed, what fits one notification at the default MTU
+pub const MAX_TOKEN_LEN: usize = 20;
+// at most 20 echoes a second, a fraction of the frames at any rate
+const MIN_ECHO_INTERVAL: Duration = Duration::from_millis(50);
+
+pub fn create_ping_characteristic(stats: Arc<Stats>) -> Characteristic {
+    let (sender, receiver) = channel(1);
+
+    let handler = async move {
+        let mut events = receiver;
+        let mut echoes: Option<Sender<Vec<u8>>> = None;
+        let mut last_echo: Option<Instant> = None;
+        while let Some(event) = events.next().await {
+            match event {
+                Event::NotifySubscribe(subscribe) => {
+                    debug!("ping subscribed");
+                    echoes = Some(subscribe.notification);
+                }
+                Event::NotifyUnsubscribe => {
+                    debug!("ping unsubscribed");
+                    echoes = None;
+                }
+                Event::WriteRequest(request) => {
+                    let echoed = match echoes.as_mut() {
+                        Some(_) if request.data.len() > MAX_TOKEN_LEN => false,
+                        Some(_) if last_echo.is_some_and(|at| at.elapsed() < MIN_ECHO_INTERVAL) => {
+                            false
+                        }
+                        // never waits, a full channel is a throttled ping too
+                        Some(echoes) => echoes.try_send(request.data.clone()).is_ok(),
+                        None => false,
+                    };
+                    if echoed {
+                        last_echo = Some(Instant::now());
+                        stats.pings.fetch_add(1, Ordering::Relaxed);
+                        trace!(len = request.data.len(), "ping echoed");
+                    } else {
+                        stats.throttled_pings.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if !request.without_response {
+                        let _ = request.response.send(Response::Success(Vec::new()));
+                    }
+                }
+                Event::ReadRequest(request) => {
+                    let _ = request.response.send(Response::UnlikelyError);
+                }
+            }
+        }
+    };
+    tokio::spawn(handler.instrument(info_span!(
+        "ble.characteristic",
+        uuid = PING_CHARACTERISTIC_UUID
+    )));
+
+    Characteristic::new(
+        Uuid::from_sdp_short_uuid(PING_CHARACTERISTIC_UUID),
+        Properties::new(
+            None,
+            Some(Write::WithoutResponse(sender.clone())),
+            Some(sender),
+            None,
+        ),
+        None,
+        HashSet::new(),
+    )
+}
@@ -0,0 +1,173 @@
+// Experimental scratch prediction: a frame reaches the console some time after
+// it was sampled, so the scratch position in it is extrapolated by a fixed time
+// along the turntable's recent velocity.
+//
+// The velocity is estimated from the positions the frames pick up, each with
+// the time of the store that brought it. When no new position comes for a
+// while, the turntable is taken to be slowing down and the prediction fades
+// out. A position against the estimated direction drops the estimate at once,
+// so a reversal is overshot for one frame at most.
+
+use tokio::time::Duration;
+
+// how much a new velocity counts against the running estimate
+const SMOOTHING: f64 = 0.5;
+// how long a position keeps the estimate fresh
+const STALE_AFTER: Duration = Duration::from_millis(20);
+// over which the prediction of a stale estimate fades to nothing
+const FADE_OUT: Duration = Duration::from_millis(30);
+// position units per nanosecond of 8 turns per second, beyond any hand
+const MAX_VELOCITY: f64 = 8.0 * 65536.0 / 1e9;
+// an eighth of a turn, however far ahead
+const MAX_OFFSET: f64 = 8192.0;
+
+/// Extrapolates the scratch position of one notifier's frames.
+pub struct ScratchPredictor {
+    ahead: Duration,
+    // the newest position and the clock::now() of its store
+    last: Option<(u16, u64)>,
+    // position units per nanosecond
+    velocity: f64,
+    // what the previous frame was moved by
+    offset: i16,
+}
+
+impl ScratchPredictor {
+    pub fn new(ahead: Duration) -> Self {
+        Self {
+            ahead,
+            last: None,
+            velocity: 0.0,
+            offset: 0,
+        }
+    }
+
+    /// The position to send for position, stored at updated_at, in a frame
+    /// built at now; all times in clock::now() nanoseconds.
+    pub fn predict(&mut self, position: u16, updated_at: u64, now: u64) -> u16 {
+        self.observe(position, updated_at);
+        self.offset = self.offset_at(now);
+        // the turntable wraps, and so does the prediction
+        position.wrapping_add(self.offset as u16)
+    }
+
+    /// whether the previous frame was moved ahead of the input
+    pub fn is_ahead(&self) -> bool {
+        self.offset != 0
+    }
+
+    fn observe(&mut self, position: u16, updated_at: u64) {
+        let Some((last, last_at)) = self.last else {
+            self.last = Some((position, updated_at));
+            return;
+        };
+        if position == last {
+            return;
+        }
+        self.last = Some((position, updated_at));
+        // the shorter way round is the movement
+        let delta = position.wrapping_sub(last) as i16 as f64;
+        if delta * self.velocity < 0.0 {
+            // a reversal; start over rather than average it away
+            self.velocity = 0.0;
+            return;
+        }
+        let elapsed = updated_at.saturating_sub(last_at);
+        if elapsed == 0 {
+            return;
+        }
+        let velocity = delta / elapsed as f64;
+        self.velocity = (SMOOTHING * velocity + (1.0 - SMOOTHING) * self.velocity)
+            .clamp(-MAX_VELOCITY, MAX_VELOCITY);
+    }
+
+    fn offset_at(&self, now: u64) -> i16 {
+        let Some((_, last_at)) = self.last else {
+            return 0;
+        };
+        let stale_for =
+            Duration::from_nanos(now.saturating_sub(last_at)).saturating_sub(STALE_AFTER);
+        let fade = 1.0 - (stale_for.as_secs_f64() / FADE_OUT.as_secs_f64()).min(1.0);
+        let offset = self.velocity * self.ahead.as_nanos() as f64 * fade;
+        offset.clamp(-MAX_OFFSET, MAX_OFFSET).round() as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+    const AHEAD: Duration = Duration::from_millis(16);
+
+    /// a frame every 8ms of a turntable turning by step units a frame,
+    /// returning how far ahead each frame was sent
+    fn spin(predictor: &mut ScratchPredictor, start: u16, step: i16, frames: u64) -> Vec<i16> {
+        (0..frames)
+            .map(|frame| {
+                let position = start.wrapping_add((step as i64 * frame as i64) as u16);
+                let sent = predictor.predict(position, frame * 8 * MS, frame * 8 * MS);
+                sent.wrapping_sub(position) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_constant_spin_is_sent_ahead_by_its_velocity() {
+        // 2 turns per second, 16ms of which is 2097 units
+        let mut predictor = ScratchPredictor::new(AHEAD);
+        let offsets = spin(&mut predictor, 0, 1049, 20);
+        assert_eq!(offsets[0], 0);
+        assert!(
+            offsets.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{offsets:?}"
+        );
+        assert_eq!(*offsets.last().unwrap(), 2098);
+        assert!(predictor.is_ahead());
+
+        // the other way round, across the wrap
+        let mut predictor = ScratchPredictor::new(AHEAD);
+        let offsets = spin(&mut predictor, 100, -1049, 20);
+        assert_eq!(*offsets.last().unwrap(), -2098);
+    }
+
+    #[test]
+    fn a_stop_fades_the_prediction_to_nothing() {
+        let mut predictor = ScratchPredictor::new(AHEAD);
+        spin(&mut predictor, 0, 1049, 20);
+        let stopped_at = 19 * 8 * MS;
+        let position = (1049 * 19) as u16;
+        let offset = |predictor: &mut ScratchPredictor, after: u64| {
+            let sent = predictor.predict(position, stopped_at, stopped_at + after * MS);
+            sent.wrapping_sub(position) as i16
+        };
+        // fresh for 20ms, then fading over 30ms
+        assert_eq!(offset(&mut predictor, 20), 2098);
+        assert_eq!(offset(&mut predictor, 35), 1049);
+        assert_eq!(offset(&mut predictor, 50), 0);
+        assert!(!predictor.is_ahead());
+        assert_eq!(offset(&mut predictor, 1000), 0);
+    }
+
+    #[test]
+    fn a_reversal_drops_the_estimate_at_once() {
+        let mut predictor = ScratchPredictor::new(AHEAD);
+        spin(&mut predictor, 0, 1049, 20);
+        let position = (1049 * 18) as u16;
+        let now = 20 * 8 * MS;
+        assert_eq!(predictor.predict(position, now, now), position);
+        assert!(!predictor.is_ahead());
+    }
+
+    #[test]
+    fn the_velocity_and_the_offset_are_capped() {
+        // 16 turns per second would be 16777 units ahead
+        let mut predictor = ScratchPredictor::new(AHEAD);
+        let offsets = spin(&mut predictor, 0, 8389, 20);
+        assert_eq!(*offsets.last().unwrap(), MAX_OFFSET as i16);
+        let mut predictor = ScratchPredictor::new(Duration::from_millis(4));
+        let offsets = spin(&mut predictor, 0, 8389, 20);
+        // 8 turns per second for 4ms
+        assert_eq!(*offsets.last().unwrap(), 2097);
+    }
+}
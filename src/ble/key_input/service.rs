@@ -6,16 +6,22 @@ use bluster::{
 };
 
 use super::uuid::Uuid;
+use crate::emulation::Emulation;
 
-const SERVICE_UUID: u16 = 0xFF00;
+const IIDX_SERVICE_UUID: u16 = 0xFF00;
+// not verified against hardware
+const SDVX_SERVICE_UUID: u16 = 0xFE00;
 
+// bluster hashes its characteristics by uuid, the mutable parts aren't hashed
+#[allow(clippy::mutable_key_type)]
 pub fn create_key_input_service(
+    emulation: Emulation,
     primary: bool,
     characteristics: HashSet<Characteristic>,
 ) -> Service {
-    Service::new(
-        Uuid::from_sdp_short_uuid(SERVICE_UUID),
-        primary,
-        characteristics,
-    )
+    let uuid = match emulation {
+        Emulation::Iidx => IIDX_SERVICE_UUID,
+        Emulation::Sdvx => SDVX_SERVICE_UUID,
+    };
+    Service::new(Uuid::from_sdp_short_uuid(uuid), primary, characteristics)
 }
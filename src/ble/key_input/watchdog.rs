@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::channel::mpsc::Sender;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tracing::{debug, error};
+
+use super::NotifyMode;
+use crate::clock;
+use crate::control::Control;
+use crate::stats::{Stats, SubscriberStats};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// a notifier missing this many ticks in a row is considered stuck
+const STALL_TICKS: u32 = 10;
+const MIN_STALL_DURATION: Duration = Duration::from_millis(250);
+
+/// Respawns the notifier of an active subscription when its heartbeat stops.
+/// spawn is told whether it respawns, and then resumes the subscription.
+pub async fn supervise(
+    spawn: impl Fn(bool) -> JoinHandle<()>,
+    subscriber: Arc<SubscriberStats>,
+    notifying: Arc<AtomicBool>,
+    notification: Sender<Vec<u8>>,
+    control: Arc<Control>,
+    stats: Arc<Stats>,
+) {
+    subscriber.heartbeat.store(clock::now(), Ordering::Relaxed);
+    let mut notifier = spawn(false);
+    let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        ticker.tick().await;
+        // notifying is shared with later subscriptions, the channel is this one's
+        if !notifying.load(Ordering::Relaxed) || notification.is_closed() {
+            break;
+        }
+
+        // the interval can be reloaded while running
+        let mut stall_after = (control.interval() * STALL_TICKS).max(MIN_STALL_DURATION);
+        // an idle notifier in on-change mode only ticks for the keep-alive
+        if let NotifyMode::OnChange { keep_alive } = control.mode() {
+            stall_after += keep_alive;
+        }
+        let since_heartbeat = subscriber.since_heartbeat();
+        if notifier.is_finished() {
+            error!("notifier exited while subscribed; respawning");
+        } else if since_heartbeat > stall_after {
+            error!(
+                "notifier stalled: no heartbeat for {:?} (limit {:?}); respawning",
+                since_heartbeat, stall_after
+            );
+            notifier.abort();
+        } else {
+            continue;
+        }
+
+        stats.stalls.fetch_add(1, Ordering::Relaxed);
+        subscriber.heartbeat.store(clock::now(), Ordering::Relaxed);
+        notifier = spawn(true);
+    }
+    stats.unregister(&subscriber);
+    debug!("watchdog finished");
+}
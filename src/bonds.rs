@@ -0,0 +1,336 @@
+// Bonds with centrals, kept across restarts. BlueZ stores the keys of a bond
+// under the adapter's identity address, so a bond is lost when that address
+// changes (a new adapter, or a reset one that comes up with another static
+// address) and when BlueZ's storage is wiped; the console then fails to
+// reconnect until it is paired again, without saying why.
+//
+// beatble advertises with the adapter's identity address, never a resolvable
+// private one, so it has no IRK of its own to keep. What it keeps is a record
+// in the state directory, bonds.json: the identity address it last ran with
+// and the centrals bonded to it. On startup the record is checked against
+// BlueZ: a different identity or a bond BlueZ lost is warned about, and bonds
+// BlueZ has that the record misses are added to it. The keys themselves can't
+// be restored from here, they stay in BlueZ's storage only root can read.
+//
+// `beatble bonds list` shows the record next to BlueZ's bonds, `beatble bonds
+// forget MAC` removes a bond from both.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eyre::{bail, Result, WrapErr};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use zbus::message::Type;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::{Connection, MatchRule, MessageStream};
+
+use crate::crash;
+
+// the adapter the session advertises on, as with the debugfs entries
+const ADAPTER_PATH: &str = "/org/bluez/hci0";
+
+type Properties = HashMap<String, OwnedValue>;
+type ManagedObjects = HashMap<OwnedObjectPath, HashMap<String, Properties>>;
+
+/// What bonds.json holds.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Record {
+    /// the adapter's identity address the centrals bonded with
+    adapter: Option<String>,
+    /// by address
+    #[serde(default)]
+    centrals: BTreeMap<String, Central>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Central {
+    name: Option<String>,
+    /// unix seconds when the bond was first seen
+    bonded_at: u64,
+}
+
+impl Record {
+    fn path() -> PathBuf {
+        crash::dir().join("bonds.json")
+    }
+
+    /// an empty record when there is no file yet
+    fn load() -> Result<Self> {
+        let path = Self::path();
+        match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .wrap_err_with(|| format!("{} is not a bond record", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).wrap_err_with(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(dir) = path.parent().filter(|dir| !dir.is_dir()) {
+            fs::create_dir_all(dir)?;
+        }
+        // written whole and renamed, a crash leaves the old record
+        let partial = path.with_extension("json.partial");
+        fs::write(&partial, serde_json::to_string_pretty(self)?)
+            .and_then(|()| fs::rename(&partial, &path))
+            .wrap_err_with(|| format!("failed to write {}", path.display()))
+    }
+
+    fn add(&mut self, bond: &Bond) -> bool {
+        if self.centrals.contains_key(&bond.address) {
+            return false;
+        }
+        let bonded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.centrals.insert(
+            bond.address.clone(),
+            Central {
+                name: bond.name.clone(),
+                bonded_at,
+            },
+        );
+        true
+    }
+}
+
+/// A central BlueZ has a bond with.
+struct Bond {
+    path: OwnedObjectPath,
+    address: String,
+    name: Option<String>,
+    connected: bool,
+}
+
+/// The adapter as BlueZ sees it: its identity address and its bonds.
+struct Bluez {
+    adapter: String,
+    bonds: Vec<Bond>,
+}
+
+impl Bluez {
+    async fn read(connection: &Connection) -> Result<Self> {
+        let reply = connection
+            .call_method(
+                Some("org.bluez"),
+                "/",
+                Some("org.freedesktop.DBus.ObjectManager"),
+                "GetManagedObjects",
+                &(),
+            )
+            .await
+            .wrap_err("failed to ask BlueZ for its devices")?;
+        let objects: ManagedObjects = reply.body().deserialize()?;
+        let adapter = objects
+            .iter()
+            .find(|(path, _)| path.as_str() == ADAPTER_PATH)
+            .and_then(|(_, interfaces)| interfaces.get("org.bluez.Adapter1"))
+            .and_then(|adapter| string(adapter, "Address"));
+        let Some(adapter) = adapter else {
+            bail!("BlueZ has no adapter at {ADAPTER_PATH}");
+        };
+        let mut bonds: Vec<_> = objects
+            .into_iter()
+            .filter_map(|(path, interfaces)| {
+                let device = interfaces.get("org.bluez.Device1")?;
+                let on_adapter = device
+                    .get("Adapter")
+                    .and_then(|value| <&zbus::zvariant::ObjectPath>::try_from(value).ok())
+                    .is_some_and(|adapter| adapter.as_str() == ADAPTER_PATH);
+                if !on_adapter || !bonded(device) {
+                    return None;
+                }
+                Some(Bond {
+                    address: string(device, "Address")?,
+                    name: string(device, "Alias").or_else(|| string(device, "Name")),
+                    connected: flag(device, "Connected").unwrap_or(false),
+                    path,
+                })
+            })
+            .collect();
+        bonds.sort_by(|a, b| a.address.cmp(&b.address));
+        Ok(Self { adapter, bonds })
+    }
+
+    fn bond(&self, address: &str) -> Option<&Bond> {
+        self.bonds.iter().find(|bond| bond.address == address)
+    }
+}
+
+/// Bonded is BlueZ 5.66 and later; before it, a paired LE device is bonded
+/// as long as the adapter is bondable, which it is by default.
+fn bonded(device: &Properties) -> bool {
+    flag(device, "Bonded")
+        .or_else(|| flag(device, "Paired"))
+        .unwrap_or(false)
+}
+
+fn flag(properties: &Properties, name: &str) -> Option<bool> {
+    properties
+        .get(name)
+        .and_then(|value| bool::try_from(value).ok())
+}
+
+fn string(properties: &Properties, name: &str) -> Option<String> {
+    properties
+        .get(name)
+        .and_then(|value| <&str>::try_from(value).ok())
+        .map(str::to_owned)
+}
+
+/// Checks the record against BlueZ and keeps it up to date with the bonds
+/// made while the session runs.
+pub async fn spawn() -> Result<()> {
+    let connection = Connection::system()
+        .await
+        .wrap_err("failed to connect to the system bus")?;
+    let bluez = Bluez::read(&connection).await?;
+    let mut record = Record::load()?;
+    check(&mut record, &bluez);
+    record.save()?;
+
+    tokio::spawn(async move {
+        if let Err(e) = watch_bonded(&connection, &mut record).await {
+            debug!("not watching BlueZ for new bonds: {e}");
+        }
+    });
+    Ok(())
+}
+
+fn check(record: &mut Record, bluez: &Bluez) {
+    match record.adapter.as_deref() {
+        Some(adapter) if adapter != bluez.adapter => {
+            warn!(
+                "the adapter's identity address changed from {adapter} to {}, centrals bonded \
+                 before have to pair again",
+                bluez.adapter
+            );
+            record.centrals.clear();
+        }
+        _ => {}
+    }
+    record.adapter = Some(bluez.adapter.clone());
+    record.centrals.retain(|address, central| {
+        let kept = bluez.bond(address).is_some();
+        if !kept {
+            warn!(
+                "BlueZ lost the bond with {address} ({}), it has to pair again",
+                central.name.as_deref().unwrap_or("unnamed")
+            );
+        }
+        kept
+    });
+    for bond in &bluez.bonds {
+        if record.add(bond) {
+            info!("recorded the bond with {}", bond.address);
+        }
+    }
+    info!(
+        "{} bonded central(s) with {}",
+        record.centrals.len(),
+        bluez.adapter
+    );
+}
+
+/// records a device whose Bonded or Paired turns true
+async fn watch_bonded(connection: &Connection, record: &mut Record) -> Result<()> {
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender("org.bluez")?
+        .interface("org.freedesktop.DBus.Properties")?
+        .member("PropertiesChanged")?
+        .arg(0, "org.bluez.Device1")?
+        .build();
+    let mut signals = MessageStream::for_match_rule(rule, connection, None).await?;
+    while let Some(signal) = signals.next().await {
+        let signal = signal?;
+        let (_, changed, _): (String, Properties, Vec<String>) = signal.body().deserialize()?;
+        if !bonded(&changed) {
+            continue;
+        }
+        // the address isn't in the signal, and a bond may come before Paired
+        let bluez = Bluez::read(connection).await?;
+        let new = bluez.bonds.iter().filter(|bond| record.add(bond)).count();
+        if new > 0 {
+            info!("recorded {new} new bond(s)");
+            if let Err(e) = record.save() {
+                warn!("{e:#}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `beatble bonds list`
+pub async fn list() -> Result<()> {
+    let connection = Connection::system()
+        .await
+        .wrap_err("failed to connect to the system bus")?;
+    let bluez = Bluez::read(&connection).await?;
+    let record = Record::load()?;
+    println!("adapter: {}", bluez.adapter);
+    if let Some(adapter) = record.adapter.as_deref().filter(|&a| a != bluez.adapter) {
+        println!("  recorded with {adapter}, bonds made with it are void");
+    }
+    let mut addresses: Vec<&str> = record.centrals.keys().map(String::as_str).collect();
+    addresses.extend(bluez.bonds.iter().map(|bond| bond.address.as_str()));
+    addresses.sort_unstable();
+    addresses.dedup();
+    if addresses.is_empty() {
+        println!("no bonded centrals");
+        return Ok(());
+    }
+    for address in addresses {
+        let bond = bluez.bond(address);
+        let central = record.centrals.get(address);
+        let name = bond
+            .and_then(|bond| bond.name.as_deref())
+            .or_else(|| central.and_then(|central| central.name.as_deref()))
+            .unwrap_or("unnamed");
+        let state = match (bond, central) {
+            (Some(bond), _) if bond.connected => "connected",
+            (Some(_), Some(_)) => "bonded",
+            (Some(_), None) => "bonded, not recorded yet",
+            (None, _) => "lost by BlueZ, has to pair again",
+        };
+        println!("  {address}  {name}  ({state})");
+    }
+    Ok(())
+}
+
+/// `beatble bonds forget MAC`
+pub async fn forget(address: &str) -> Result<()> {
+    let address = address.to_ascii_uppercase();
+    let connection = Connection::system()
+        .await
+        .wrap_err("failed to connect to the system bus")?;
+    let bluez = Bluez::read(&connection).await?;
+    let mut record = Record::load()?;
+    let recorded = record.centrals.remove(&address).is_some();
+    let bond = bluez.bond(&address);
+    if !recorded && bond.is_none() {
+        bail!("no bond with {address}");
+    }
+    if let Some(bond) = bond {
+        connection
+            .call_method(
+                Some("org.bluez"),
+                ADAPTER_PATH,
+                Some("org.bluez.Adapter1"),
+                "RemoveDevice",
+                &(&bond.path,),
+            )
+            .await
+            .wrap_err_with(|| format!("failed to remove {address} from BlueZ"))?;
+    }
+    record.save()?;
+    println!("forgot {address}");
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// How this binary was built, embedded by build.rs.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct BuildInfo {
+    /// the crate version and `git describe`, as in `--version`
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    /// tracked files differed from the commit
+    pub git_dirty: bool,
+    /// UTC, YYYY-MM-DD
+    pub build_date: &'static str,
+    pub rustc: &'static str,
+    /// comma-separated cargo features, e.g. "dbus,metrics"
+    pub features: &'static str,
+}
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    version: env!("VERSION"),
+    git_commit: env!("BUILD_GIT_COMMIT"),
+    git_dirty: matches!(env!("BUILD_GIT_DIRTY").as_bytes(), b"true"),
+    build_date: env!("BUILD_DATE"),
+    rustc: env!("BUILD_RUSTC"),
+    features: env!("BUILD_FEATURES"),
+};
+
+/// `--version --verbose`
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "beatble {}", self.version)?;
+        writeln!(
+            f,
+            "commit: {}{}",
+            self.git_commit,
+            if self.git_dirty { " (modified)" } else { "" }
+        )?;
+        writeln!(f, "build date: {}", self.build_date)?;
+        writeln!(f, "rustc: {}", self.rustc)?;
+        write!(
+            f,
+            "features: {}",
+            if self.features.is_empty() {
+                "none"
+            } else {
+                self.features
+            }
+        )
+    }
+}
@@ -0,0 +1,146 @@
+// Faults injected at random into a running pipeline, to check that it
+// recovers from them. Nothing is injected until install() is called, and the
+// injection points only test the static for being set.
+
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::clock;
+
+static CHAOS: OnceLock<Chaos> = OnceLock::new();
+
+const DEFAULT_DELAY: Duration = Duration::from_millis(20);
+
+/// How often each fault is injected, as probabilities from 0 to 1.
+///
+/// Parsed from a comma separated list of `fault=probability`, e.g.
+/// `drop=0.01,eio=0.001,delay=0.05,delay-ms=30`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chaos {
+    /// per input event, which is lost
+    pub drop_events: f64,
+    /// per device read, which reads nothing this time
+    pub eagain: f64,
+    /// per device read, which fails the reader
+    pub eio: f64,
+    /// per frame, which goes out `delay` late
+    pub delay_frames: f64,
+    pub delay: Duration,
+    /// per frame, which finds the notification channel full
+    pub full_channel: f64,
+    /// per second of a subscription, which is dropped and renewed
+    pub resubscribe: f64,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self {
+            drop_events: 0.0,
+            eagain: 0.0,
+            eio: 0.0,
+            delay_frames: 0.0,
+            delay: DEFAULT_DELAY,
+            full_channel: 0.0,
+            resubscribe: 0.0,
+        }
+    }
+}
+
+/// Starts injecting faults for the rest of the process; false if some
+/// already are.
+pub fn install(chaos: Chaos) -> bool {
+    CHAOS.set(chaos).is_ok()
+}
+
+/// The installed faults, if any.
+#[inline]
+pub fn get() -> Option<&'static Chaos> {
+    CHAOS.get()
+}
+
+/// true with probability p
+pub(crate) fn roll(p: f64) -> bool {
+    p > 0.0 && next_random() < p
+}
+
+thread_local! {
+    static STATE: Cell<u64> = const { Cell::new(0) };
+}
+
+/// uniform in [0, 1), from a xorshift64* per thread; good enough to pick faults
+fn next_random() -> f64 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        if x == 0 {
+            // seeded apart per thread by when it first rolls
+            x = clock::now() | 1;
+        }
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+impl FromStr for Chaos {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chaos = Chaos::default();
+        for fault in s
+            .split(',')
+            .map(str::trim)
+            .filter(|fault| !fault.is_empty())
+        {
+            let (name, value) = fault
+                .split_once('=')
+                .ok_or_else(|| format!("expected fault=value: {fault}"))?;
+            if name == "delay-ms" {
+                let ms = value
+                    .parse()
+                    .map_err(|_| format!("not a number of milliseconds: {value}"))?;
+                chaos.delay = Duration::from_millis(ms);
+                continue;
+            }
+            let rate = match value.parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => rate,
+                _ => return Err(format!("{name}: not a probability from 0 to 1: {value}")),
+            };
+            let field = match name {
+                "drop" => &mut chaos.drop_events,
+                "eagain" => &mut chaos.eagain,
+                "eio" => &mut chaos.eio,
+                "delay" => &mut chaos.delay_frames,
+                "full" => &mut chaos.full_channel,
+                "resubscribe" => &mut chaos.resubscribe,
+                _ => {
+                    return Err(format!(
+                        "unknown fault: {name} (expected drop, eagain, eio, delay, delay-ms, full or resubscribe)"
+                    ))
+                }
+            };
+            *field = rate;
+        }
+        Ok(chaos)
+    }
+}
+
+impl fmt::Display for Chaos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "drop={},eagain={},eio={},delay={},delay-ms={},full={},resubscribe={}",
+            self.drop_events,
+            self.eagain,
+            self.eio,
+            self.delay_frames,
+            self.delay.as_millis(),
+            self.full_channel,
+            self.resubscribe
+        )
+    }
+}
@@ -0,0 +1,875 @@
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use beatble_protocol::payload::{PayloadFormat, ScratchMode};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueHint};
+use clap_complete::Shell;
+use eyre::{eyre, Result};
+
+#[cfg(feature = "dbus")]
+use crate::dbus::Bus;
+use crate::logging::LogFormat;
+#[cfg(feature = "midi")]
+use crate::midi::MidiNotes;
+#[cfg(feature = "dbus")]
+use crate::pairing::Pairing;
+use crate::runtime::Flavor;
+use crate::supervisor::RestartPolicy;
+use beatble::ble::RepeatSpacing;
+use beatble::chaos::Chaos;
+use beatble::emulation::Emulation;
+use beatble::input::{
+    ActiveLow, AntiWobble, Backend, Chord, DeviceId, DeviceSelector, InputMapping, Keymap,
+    LongPress, Profile, Recenter, SdlControls, SCRATCH_SENSITIVITY,
+};
+
+#[derive(Parser)]
+#[clap(name = "beatble")]
+#[clap(version = env!("VERSION"), disable_version_flag = true)]
+pub struct Cli {
+    /// print version; with --verbose, also the commit, build date, rustc and features
+    #[arg(short = 'V', long)]
+    pub version: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    /// `beatble DEVICE` is `beatble run DEVICE`
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Args)]
+pub struct GlobalArgs {
+    /// configuration file; defaults to ~/.config/beatble/config.toml or
+    /// /etc/beatble/config.toml, whichever exists first
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, env = "BEATBLE_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// log info output of beatble itself, -vv for debug and -vvv for trace output;
+    /// dependencies stay at warnings. RUST_LOG, when set, takes precedence over -v and -q
+    #[arg(short, long, action = ArgAction::Count, global = true, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// log only warnings and errors, repeat for errors only
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub quiet: u8,
+
+    /// log format: text or json (one object per line)
+    #[arg(long, value_name = "FORMAT", default_value_t = LogFormat::Text, env = "BEATBLE_LOG_FORMAT", global = true)]
+    pub log_format: LogFormat,
+}
+
+impl GlobalArgs {
+    /// log filter selected by -v/-q, used when RUST_LOG isn't set
+    pub fn log_filter(&self) -> &'static str {
+        // the beatble target covers beatble_protocol too
+        match (self.verbose, self.quiet) {
+            (0, 0) => "info",
+            (0, 1) => "warn",
+            (0, _) => "error",
+            (1, _) => "warn,beatble=info",
+            (2, _) => "warn,beatble=debug",
+            (_, _) => "warn,beatble=trace",
+        }
+    }
+}
+
+#[derive(Args, Clone)]
+pub struct RunArgs {
+    /// input device path
+    #[arg(value_name = "DEVICE", value_hint = ValueHint::FilePath, env = "BEATBLE_DEVICE")]
+    pub input: Option<String>,
+
+    /// second input device path for double play; exposes a 2P key input service
+    #[arg(long, value_name = "DEVICE", value_hint = ValueHint::FilePath, env = "BEATBLE_DP_DEVICE")]
+    pub dp_device: Option<String>,
+
+    /// read the first joystick device whose name contains NAME, in any case, instead of DEVICE
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with = "input",
+        env = "BEATBLE_DEVICE_NAME"
+    )]
+    pub device_name: Option<String>,
+
+    /// read the first joystick device with this USB id, e.g. 1ccf:1234, instead of DEVICE
+    #[arg(
+        long,
+        value_name = "VENDOR:PRODUCT",
+        conflicts_with = "input",
+        env = "BEATBLE_DEVICE_ID"
+    )]
+    pub device_id: Option<DeviceId>,
+
+    /// with run, start even if the device isn't plugged in yet and wait for it to turn up
+    #[arg(long, env = "BEATBLE_WAIT_FOR_DEVICE")]
+    pub wait_for_device: bool,
+
+    /// how devices are read: joydev (/dev/input/jsN) or evdev (/dev/input/eventN, or the event
+    /// node of the jsN given), which numbers buttons and axes the same way
+    #[arg(long, value_name = "BACKEND", default_value_t = Backend::Joydev, env = "BEATBLE_BACKEND")]
+    pub backend: Backend,
+
+    /// sleep duration in ms, up to 100; 0 with --busy-poll sends as fast as the link takes frames
+    // 8 = 1000 / 120
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value_t = 8,
+        env = "BEATBLE_SLEEP_DURATION"
+    )]
+    pub sleep_duration: u64,
+
+    /// notify only when the input changes, plus periodic keep-alive frames
+    #[arg(long, env = "BEATBLE_NOTIFY_ON_CHANGE")]
+    pub notify_on_change: bool,
+
+    /// keep-alive interval in ms for --notify-on-change
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value_t = 100,
+        env = "BEATBLE_KEEP_ALIVE"
+    )]
+    pub keep_alive: u64,
+
+    /// number of neutral frames sent right after the console subscribes
+    #[arg(
+        long,
+        value_name = "FRAMES",
+        default_value_t = 3,
+        env = "BEATBLE_WARMUP_FRAMES"
+    )]
+    pub warmup_frames: usize,
+
+    /// counter value of the first frame after the console subscribes
+    #[arg(
+        long,
+        value_name = "COUNTER",
+        default_value_t = 0,
+        env = "BEATBLE_COUNTER_START"
+    )]
+    pub counter_start: u8,
+
+    /// send each frame this many times, with the same counter so the console takes it once;
+    /// for links that lose notifications
+    #[arg(
+        long,
+        value_name = "TIMES",
+        default_value_t = 1,
+        env = "BEATBLE_FRAME_REPEAT"
+    )]
+    pub frame_repeat: usize,
+
+    /// when repeats go out: back-to-back, or ticks (on the following ticks, delaying newer input)
+    #[arg(long, value_name = "SPACING", default_value_t = RepeatSpacing::BackToBack, env = "BEATBLE_FRAME_REPEAT_SPACING")]
+    pub frame_repeat_spacing: RepeatSpacing,
+
+    /// controller to emulate: iidx or sdvx
+    #[arg(long, value_name = "MODEL", default_value_t = Emulation::Iidx, env = "BEATBLE_EMULATE")]
+    pub emulate: Emulation,
+
+    /// payload layout: v1 (counter +2 per frame) or v2 (counter +1, reserved byte set)
+    #[arg(long, value_name = "FORMAT", default_value_t = PayloadFormat::V1, env = "BEATBLE_PAYLOAD_FORMAT")]
+    pub payload_format: PayloadFormat,
+
+    /// start from a built-in keymap and turntable setup: default, dao, phoenixwan,
+    /// konami-entry or mirror-2p; the config file, environment and flags override it
+    #[arg(long, value_name = "NAME", env = "BEATBLE_PROFILE")]
+    pub profile: Option<Profile>,
+
+    /// which joystick buttons are the keys and option buttons and which axis is the
+    /// turntable, e.g. B1=0,B2=1,...,E1=8,TT=0; a control left out is never pressed and
+    /// without TT any axis turns the turntable [default: the profile's]
+    #[arg(long, value_name = "MAP", default_value_t = Keymap::DEFAULT, hide_default_value = true, env = "BEATBLE_KEYMAP")]
+    pub keymap: Keymap,
+
+    /// take the keymap from a TOML file naming the control on each button and axis, e.g.
+    /// `[buttons]` `12 = "E1"` and `[axes]` `0 = "scratch"`, instead of --keymap
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, conflicts_with = "keymap", env = "BEATBLE_MAPPING")]
+    pub mapping: Option<PathBuf>,
+
+    /// turntable position units per axis unit; 2 turns the turntable twice over the
+    /// axis range
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        default_value_t = SCRATCH_SENSITIVITY,
+        env = "BEATBLE_TURNTABLE_SENSITIVITY"
+    )]
+    pub turntable_sensitivity: u8,
+
+    /// turn the turntable the other way round
+    #[arg(long, env = "BEATBLE_REVERSE_TURNTABLE")]
+    pub reverse_turntable: bool,
+
+    /// buttons that report 0 when pressed, e.g. 3,7, or all for a device that reports every
+    /// button that way
+    #[arg(long, value_name = "BUTTONS", env = "BEATBLE_ACTIVE_LOW")]
+    pub active_low: Option<ActiveLow>,
+
+    /// take the keymap from an SDL game controller mapping, a line of gamecontrollerdb.txt,
+    /// instead of --keymap
+    #[arg(
+        long,
+        value_name = "MAPPING",
+        conflicts_with_all = ["keymap", "mapping", "sdl_mapping_file"],
+        env = "BEATBLE_SDL_MAPPING"
+    )]
+    pub sdl_mapping: Option<String>,
+
+    /// take the keymap from the entry for the device's GUID in an SDL gamecontrollerdb.txt,
+    /// instead of --keymap
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, conflicts_with_all = ["keymap", "mapping"], env = "BEATBLE_SDL_MAPPING_FILE")]
+    pub sdl_mapping_file: Option<PathBuf>,
+
+    /// which SDL controls are the keys, option buttons and turntable, e.g.
+    /// B1=a,B2=b,...,E1=back,TT=leftx [default: a, b, x, y, leftshoulder, rightshoulder and
+    /// lefttrigger for the keys, back, start, guide and misc1 for E1 to E4, leftx for TT]
+    #[arg(long, value_name = "MAP", default_value_t = SdlControls::default(), hide_default_value = true, env = "BEATBLE_SDL_CONTROLS")]
+    pub sdl_controls: SdlControls,
+
+    /// give extra buttons a short and a long press action, e.g. 8=E1/E3,9=E2/E4: BUTTON is the
+    /// joystick button number from 0, SHORT is sent on release and LONG held once the button is
+    /// held past 400 ms, or BUTTON=SHORT/LONG@MS
+    #[arg(long, value_name = "BUTTONS", env = "BEATBLE_LONG_PRESS")]
+    pub long_press: Option<LongPress>,
+
+    /// hold back turntable movements smaller than DEGREES against the direction it was
+    /// turning, so jitter doesn't break a charge scratch; off by default
+    #[arg(long, value_name = "DEGREES", env = "BEATBLE_ANTI_WOBBLE")]
+    pub anti_wobble: Option<f64>,
+
+    /// how long in ms after the last movement forward --anti-wobble holds movements back
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value_t = 200,
+        env = "BEATBLE_ANTI_WOBBLE_GRACE"
+    )]
+    pub anti_wobble_grace: u64,
+
+    /// report the turntable relative to where it is first seen, for turntables with no fixed
+    /// zero; only in the position scratch mode
+    #[arg(long, env = "BEATBLE_SCRATCH_RECENTER")]
+    pub scratch_recenter: bool,
+
+    /// joystick buttons that zero the turntable again on its current position when held
+    /// together, e.g. 8+9; implies --scratch-recenter
+    #[arg(long, value_name = "BUTTONS", env = "BEATBLE_SCRATCH_RECENTER_CHORD")]
+    pub scratch_recenter_chord: Option<Chord>,
+
+    /// what the scratch byte carries: position (the turntable angle) or velocity (the
+    /// movement since the previous frame); NOT understood by the console, only for custom receivers
+    #[arg(long, value_name = "MODE", default_value_t = ScratchMode::Position, env = "BEATBLE_SCRATCH_MODE")]
+    pub scratch_mode: ScratchMode,
+
+    /// EXPERIMENTAL: send the scratch position extrapolated DURATION ms ahead along the
+    /// turntable's recent velocity, to make up for the latency to the console
+    #[arg(long, value_name = "DURATION", env = "BEATBLE_SCRATCH_PREDICT")]
+    pub scratch_predict: Option<f64>,
+
+    /// put the scratch position's low byte into the reserved payload byte;
+    /// NOT understood by the console, only for custom receivers
+    #[arg(long, env = "BEATBLE_SCRATCH_HIRES")]
+    pub scratch_hires: bool,
+
+    /// send a single 5-byte sub-report per notification instead of two;
+    /// NOT understood by the console, only for custom receivers
+    #[arg(long, env = "BEATBLE_SINGLE_REPORT")]
+    pub single_report: bool,
+
+    /// spin instead of sleeping to cut timer and wakeup jitter;
+    /// keeps one core fully busy for input and adds load for notifications
+    #[arg(long, env = "BEATBLE_BUSY_POLL")]
+    pub busy_poll: bool,
+
+    /// tokio runtime: multi (a worker thread per core) or current-thread (a single thread,
+    /// for single-core boards; --busy-poll then spins on that thread)
+    #[arg(long, value_name = "RUNTIME", default_value_t = Flavor::Multi, env = "BEATBLE_RUNTIME")]
+    pub runtime: Flavor,
+
+    /// when an input device fails: restart (reopen it until it is back) or exit
+    #[arg(long, value_name = "POLICY", default_value_t = RestartPolicy::Exit, env = "BEATBLE_ON_INPUT_ERROR")]
+    pub on_input_error: RestartPolicy,
+
+    /// when advertising stops without a connected central, e.g. after an adapter reset:
+    /// restart (set up and advertise again) or exit
+    #[arg(long, value_name = "POLICY", default_value_t = RestartPolicy::Exit, env = "BEATBLE_ON_ADVERTISING_STOP")]
+    pub on_advertising_stop: RestartPolicy,
+
+    /// when bluetoothd or the system bus goes away while advertising, e.g. on a bluetoothd
+    /// restart: restart (set the peripheral up again, up to --bluetooth-retries times) or exit
+    #[arg(long, value_name = "POLICY", default_value_t = RestartPolicy::Exit, env = "BEATBLE_ON_BLUETOOTH_LOSS")]
+    pub on_bluetooth_loss: RestartPolicy,
+
+    /// how often --on-bluetooth-loss restart tries to set the peripheral up again
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 5,
+        env = "BEATBLE_BLUETOOTH_RETRIES"
+    )]
+    pub bluetooth_retries: u32,
+
+    /// seconds to wait for the bluetooth adapter to be powered before giving up
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 30,
+        env = "BEATBLE_POWER_TIMEOUT"
+    )]
+    pub power_timeout: u64,
+
+    /// seconds to wait for advertising to start before giving up
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 30,
+        env = "BEATBLE_ADVERTISING_TIMEOUT"
+    )]
+    pub advertising_timeout: u64,
+
+    /// connection interval in ms to assume instead of reading it from the adapter
+    #[arg(long, value_name = "DURATION", env = "BEATBLE_CONN_INTERVAL_HINT")]
+    pub conn_interval_hint: Option<f64>,
+
+    /// round the sleep duration to the nearest multiple of the connection interval
+    #[arg(long, env = "BEATBLE_ALIGN_TO_CONN_INTERVAL")]
+    pub align_to_conn_interval: bool,
+
+    /// minimum connection interval in ms to request from the stack
+    #[arg(
+        long,
+        value_name = "DURATION",
+        requires = "conn_interval_max",
+        env = "BEATBLE_CONN_INTERVAL_MIN"
+    )]
+    pub conn_interval_min: Option<f64>,
+
+    /// maximum connection interval in ms to request from the stack
+    #[arg(
+        long,
+        value_name = "DURATION",
+        requires = "conn_interval_min",
+        env = "BEATBLE_CONN_INTERVAL_MAX"
+    )]
+    pub conn_interval_max: Option<f64>,
+
+    /// warn when the central picks a connection interval longer than this many ms
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value_t = 11.25,
+        env = "BEATBLE_CONN_INTERVAL_WARN"
+    )]
+    pub conn_interval_warn: f64,
+
+    /// write every sent frame with its timestamp and counter to FILE as JSON lines
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, env = "BEATBLE_DUMP_PAYLOADS")]
+    pub dump_payloads: Option<PathBuf>,
+
+    /// build frames as usual but print them instead of advertising over bluetooth
+    #[arg(long, env = "BEATBLE_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// read the input only for the other outputs, e.g. --midi or --status-listen,
+    /// without advertising over bluetooth
+    #[arg(long, conflicts_with_all = ["dry_run", "tui"], env = "BEATBLE_NO_BLUETOOTH")]
+    pub no_bluetooth: bool,
+
+    /// mirror the 1P input to a uinput gamepad named "beatble mirror", for software on this
+    /// machine while the controller is in use; needs write access to /dev/uinput
+    #[arg(long, env = "BEATBLE_MIRROR_UINPUT")]
+    pub mirror_uinput: bool,
+
+    /// with --dry-run, print only frames whose content differs from the previous one
+    #[arg(long, requires = "dry_run", env = "BEATBLE_CHANGES_ONLY")]
+    pub changes_only: bool,
+
+    /// use the input device even if another instance is reading it
+    #[arg(long)]
+    pub force: bool,
+
+    /// with run, read the device in a child process that drops root once it is open,
+    /// so only the bluetooth half keeps its privileges
+    #[arg(long, env = "BEATBLE_SPLIT_PRIVILEGES")]
+    pub split_privileges: bool,
+
+    /// for development: inject faults at random, e.g.
+    /// drop=0.01,eagain=0.01,eio=0.001,delay=0.05,delay-ms=30,full=0.02,resubscribe=0.01
+    #[arg(long, value_name = "SPEC", hide = true)]
+    pub chaos: Option<Chaos>,
+
+    /// once set up, kill the process on any syscall a session doesn't need and
+    /// deny files other than its devices, config, sockets and dump
+    #[cfg(feature = "sandbox")]
+    #[arg(long, env = "BEATBLE_SANDBOX")]
+    pub sandbox: bool,
+
+    /// detach from the terminal and keep running in the background
+    #[arg(long, conflicts_with = "tui")]
+    pub daemonize: bool,
+
+    /// with --daemonize, write the daemon's pid to FILE and hold a lock on it
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, requires = "daemonize")]
+    pub pidfile: Option<PathBuf>,
+
+    /// append log output to FILE instead of writing it to stderr, rotating FILE to FILE.1
+    /// once it reaches --log-file-size; a daemon's stray output goes there too
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, conflicts_with = "tui")]
+    pub log_file: Option<PathBuf>,
+
+    /// size in MiB at which --log-file is rotated
+    #[arg(long, value_name = "SIZE", default_value_t = 10, requires = "log_file")]
+    pub log_file_size: u64,
+
+    /// how many rotated log files to keep, FILE.1 being the newest
+    #[arg(long, value_name = "FILES", default_value_t = 5, requires = "log_file")]
+    pub log_file_keep: usize,
+
+    /// log filter, e.g. debug or warn,beatble=debug, for when neither RUST_LOG nor -v/-q
+    /// pick one; a config reload applies it too
+    #[arg(long, value_name = "FILTER", env = "BEATBLE_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// show a full-screen dashboard instead of log output
+    #[arg(long, conflicts_with = "dry_run")]
+    pub tui: bool,
+
+    /// name to advertise instead of the emulated controller's
+    #[arg(long, value_name = "NAME", env = "BEATBLE_ADVERTISING_NAME")]
+    pub advertising_name: Option<String>,
+
+    /// log a line of rates and counters every SECONDS, e.g. for bug reports
+    #[arg(long, value_name = "SECONDS", env = "BEATBLE_STATS")]
+    pub stats: Option<u64>,
+
+    /// control socket for `beatble ctl` [default: $XDG_RUNTIME_DIR/beatble.sock]
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, env = "BEATBLE_CONTROL_SOCKET")]
+    pub control_socket: Option<PathBuf>,
+
+    /// serve the session status as JSON at /status and a health check at /healthz on ADDR,
+    /// e.g. 127.0.0.1:8720
+    #[arg(long, value_name = "ADDR", env = "BEATBLE_STATUS_LISTEN")]
+    pub status_listen: Option<SocketAddr>,
+
+    /// publish the dev.watiko.Beatble1 D-Bus service on the session or system bus
+    #[cfg(feature = "dbus")]
+    #[arg(long, value_name = "BUS", env = "BEATBLE_DBUS")]
+    pub dbus: Option<Bus>,
+
+    /// how centrals pair: just-works, passkey (show a passkey to type on the central) or
+    /// confirm (numeric comparison, confirmed with E1 on the controller)
+    #[cfg(feature = "dbus")]
+    #[arg(long, value_name = "MODE", default_value_t = Pairing::default(), env = "BEATBLE_PAIRING")]
+    pub pairing: Pairing,
+
+    /// with --pairing confirm, accept the code after SECONDS without waiting for E1
+    #[cfg(feature = "dbus")]
+    #[arg(long, value_name = "SECONDS", env = "BEATBLE_PAIRING_AUTO_CONFIRM")]
+    pub pairing_auto_confirm: Option<u64>,
+
+    /// push the input state to stream overlays over a WebSocket on ADDR, e.g. 127.0.0.1:9702
+    #[cfg(feature = "overlay")]
+    #[arg(long, value_name = "ADDR", env = "BEATBLE_OVERLAY_LISTEN")]
+    pub overlay_listen: Option<SocketAddr>,
+
+    /// play a note per key and option button and send the turntable as a controller on a
+    /// virtual ALSA MIDI port named beatble, as each input event comes in
+    #[cfg(feature = "midi")]
+    #[arg(long, env = "BEATBLE_MIDI")]
+    pub midi: bool,
+
+    /// MIDI channel of --midi, from 1 to 16
+    #[cfg(feature = "midi")]
+    #[arg(
+        long,
+        value_name = "CHANNEL",
+        default_value_t = 1,
+        env = "BEATBLE_MIDI_CHANNEL"
+    )]
+    pub midi_channel: u8,
+
+    /// the notes of B1 to B7 and E1 to E4 for --midi
+    #[cfg(feature = "midi")]
+    #[arg(long, value_name = "NOTES", default_value_t = MidiNotes::default(), env = "BEATBLE_MIDI_NOTES")]
+    pub midi_notes: MidiNotes,
+
+    /// the controller --midi sends 7 bits of the turntable angle on; when emulating sdvx,
+    /// VOL-L on it and VOL-R on the next one
+    #[cfg(feature = "midi")]
+    #[arg(
+        long,
+        value_name = "CC",
+        default_value_t = 16,
+        env = "BEATBLE_MIDI_SCRATCH_CC"
+    )]
+    pub midi_scratch_cc: u8,
+
+    /// serve Prometheus metrics on ADDR, e.g. 127.0.0.1:9641
+    #[cfg(feature = "metrics")]
+    #[arg(long, value_name = "ADDR", env = "BEATBLE_METRICS_LISTEN")]
+    pub metrics_listen: Option<SocketAddr>,
+}
+
+// a build without bluetooth or input support reads no device
+#[cfg_attr(not(any(feature = "ble", feature = "input")), allow(dead_code))]
+impl RunArgs {
+    /// how readers map the device, with the filters the flags ask for
+    pub fn input_mapping(&self) -> InputMapping {
+        let grace = Duration::from_millis(self.anti_wobble_grace);
+        InputMapping {
+            backend: self.backend,
+            emulation: self.emulate,
+            keymap: self.keymap,
+            turntable_sensitivity: self.turntable_sensitivity,
+            reverse_turntable: self.reverse_turntable,
+            active_low: self.active_low.clone(),
+            anti_wobble: self
+                .anti_wobble
+                .map(|degrees| AntiWobble::new(degrees, grace)),
+            recenter: self.recenter(),
+            long_press: self.long_press.clone(),
+        }
+    }
+
+    /// relative positions only make sense where the scratch byte is a position
+    fn recenter(&self) -> Option<Recenter> {
+        let asked = self.scratch_recenter || self.scratch_recenter_chord.is_some();
+        (asked && self.scratch_mode == ScratchMode::Position && self.emulate == Emulation::Iidx)
+            .then(|| Recenter {
+                chord: self.scratch_recenter_chord.clone(),
+            })
+    }
+
+    /// what --device-name and --device-id ask for, empty if neither is set
+    pub fn device_selector(&self) -> DeviceSelector {
+        DeviceSelector {
+            name: self.device_name.clone(),
+            id: self.device_id,
+        }
+    }
+
+    pub fn input(&self) -> Result<&str> {
+        self.input.as_deref().ok_or_else(|| {
+            eyre!("no input device given: pass DEVICE, set BEATBLE_DEVICE or device in the config file")
+        })
+    }
+}
+
+#[derive(Subcommand)]
+// only config show carries the run settings without run
+#[cfg_attr(
+    not(all(feature = "ble", feature = "input")),
+    allow(clippy::large_enum_variant)
+)]
+pub enum Command {
+    /// emulate the controller with the given input device (the default)
+    #[cfg(all(feature = "ble", feature = "input"))]
+    Run(RunArgs),
+    /// show the sampled input of a device live, without bluetooth
+    #[cfg(feature = "input")]
+    Test(RunArgs),
+    /// emulate the controller with input played from a script instead of a device
+    #[cfg(feature = "ble")]
+    Simulate(SimulateArgs),
+    /// list joystick devices
+    #[cfg(feature = "input")]
+    List,
+    /// show the name, axis and button count of a joystick device
+    #[cfg(feature = "input")]
+    Info {
+        /// input device path
+        #[arg(value_name = "DEVICE", value_hint = ValueHint::FilePath)]
+        input: String,
+    },
+    /// inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// measure how late and how often a device's input events reach userspace
+    #[cfg(feature = "input")]
+    Bench {
+        /// input device path
+        #[arg(value_name = "DEVICE", value_hint = ValueHint::FilePath)]
+        device: String,
+        /// how long to measure; play during the whole time
+        #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+        seconds: u64,
+        /// print the results as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// time injected button presses from a virtual joystick to the notification carrying
+    /// them, through the usual reader and notifier; needs write access to /dev/uinput
+    #[cfg(all(feature = "ble", feature = "input"))]
+    MeasureLatency {
+        /// how many presses and releases to time
+        #[arg(long, value_name = "COUNT", default_value_t = 2000)]
+        samples: u32,
+        /// frame period in ms, as with run
+        #[arg(long, value_name = "DURATION", default_value_t = 8)]
+        sleep_duration: u64,
+        /// spin on the joystick and the frame clock, as with run
+        #[arg(long)]
+        busy_poll: bool,
+        /// print the results as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// replay recorded joystick events through the mapping and notifier on a virtual clock and
+    /// compare the frames with a golden file; for maintainers
+    #[cfg(all(feature = "ble", feature = "input"))]
+    Regress(crate::regress::RegressArgs),
+    /// notify a mock central for hours with churn and fail if memory, tasks or file
+    /// descriptors grow; for maintainers
+    #[cfg(feature = "ble")]
+    Soak {
+        /// how long to run
+        #[arg(long, value_name = "HOURS", default_value_t = 8.0)]
+        hours: f64,
+        /// seconds between samples
+        #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+        sample_every: u64,
+        /// write the JSON report to FILE instead of stdout
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        report: Option<PathBuf>,
+    },
+    /// set up a device, the bluetooth adapter and a user config file step by step
+    #[cfg(feature = "input")]
+    Init,
+    /// check the environment and print hints for anything that is off
+    #[cfg(feature = "input")]
+    Doctor {
+        /// input device path; defaults to every joystick
+        #[arg(value_name = "DEVICE", value_hint = ValueHint::FilePath)]
+        device: Option<String>,
+    },
+    /// print a udev rule for the controller, and install it with --install
+    #[cfg(feature = "input")]
+    SetupUdev {
+        /// input device path; defaults to the first joystick
+        #[arg(long, value_name = "DEVICE", value_hint = ValueHint::FilePath)]
+        device: Option<String>,
+        /// group that gets read access to the device
+        #[arg(long, value_name = "GROUP", default_value = "input")]
+        group: String,
+        /// write the rule to /etc/udev/rules.d and reload udev; needs root
+        #[arg(long)]
+        install: bool,
+    },
+    /// list the centrals bonded with the adapter, or forget one
+    #[cfg(feature = "dbus")]
+    Bonds {
+        #[command(subcommand)]
+        command: BondsCommand,
+    },
+    /// send a command to a running instance: stats, pause, resume, rate MS, profile NAME,
+    /// device PATH or quit
+    Ctl {
+        /// control socket [default: $XDG_RUNTIME_DIR/beatble.sock]
+        #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, env = "BEATBLE_CONTROL_SOCKET")]
+        socket: Option<PathBuf>,
+        #[arg(value_name = "COMMAND", required = true, num_args = 1..)]
+        command: Vec<String>,
+    },
+    /// print a shell completion script to stdout
+    Completions {
+        /// bash, zsh, fish, elvish or powershell
+        #[arg(value_name = "SHELL")]
+        shell: Shell,
+    },
+    /// connect to a peripheral as a central and check its notification stream
+    #[cfg(feature = "verify")]
+    Verify(crate::verify::VerifyArgs),
+    /// connect to a real controller as a central and record its GATT database and notifications
+    #[cfg(feature = "verify")]
+    Capture(crate::verify::capture::CaptureArgs),
+    /// the input process of --split-privileges, relaying a device's events to stdout
+    #[cfg(all(feature = "ble", feature = "input"))]
+    #[command(hide = true)]
+    RelayInput {
+        device: String,
+        /// user to switch to once the device is open
+        #[arg(long)]
+        uid: u32,
+        /// group to switch to once the device is open
+        #[arg(long)]
+        gid: u32,
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[cfg(feature = "ble")]
+#[derive(Args, Clone)]
+pub struct SimulateArgs {
+    /// timed presses, releases and scratches, one per line; see the README
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub script: PathBuf,
+
+    /// start the script over when it ends instead of ending the session
+    #[arg(long = "loop")]
+    pub repeat: bool,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+/// Write the completion script for `shell` to `out`.
+pub fn write_completions(shell: Shell, out: &mut impl Write) {
+    clap_complete::generate(shell, &mut Cli::command(), "beatble", out);
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// print the effective configuration after merging file, environment and flags
+    Show(RunArgs),
+}
+
+#[cfg(feature = "dbus")]
+#[derive(Subcommand)]
+pub enum BondsCommand {
+    /// show the bonds BlueZ has and the ones beatble recorded
+    List,
+    /// remove the bond with a central, from BlueZ and the record
+    Forget {
+        /// address of the central
+        #[arg(value_name = "MAC")]
+        address: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::*;
+
+    #[test]
+    fn cli_definition_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[cfg(all(feature = "ble", feature = "input"))]
+    #[test]
+    fn invocations_from_before_the_subcommands_still_run() {
+        let parse = |command: &[&str], flags: &[&str]| {
+            let argv = ["beatble"].iter().chain(command).chain(flags);
+            Cli::try_parse_from(argv).unwrap_or_else(|e| panic!("{flags:?}: {e}"))
+        };
+        let js0 = parse(&[], &["/dev/input/js0"]);
+        assert!(js0.command.is_none());
+        assert_eq!(js0.run.input.as_deref(), Some("/dev/input/js0"));
+        assert_eq!(js0.run.sleep_duration, 8);
+
+        // every flag there was then, each the same as with run
+        for flags in [
+            &["/dev/input/js0"][..],
+            &["/dev/input/js0", "--sleep-duration", "4"],
+            &["--sleep-duration", "4", "/dev/input/js0"],
+            &["/dev/input/js0", "--dp-device", "/dev/input/js1"],
+            &["/dev/input/js0", "--notify-on-change", "--keep-alive", "50"],
+            &[
+                "/dev/input/js0",
+                "--warmup-frames",
+                "5",
+                "--counter-start",
+                "7",
+            ],
+            &[
+                "/dev/input/js0",
+                "--emulate",
+                "sdvx",
+                "--payload-format",
+                "v2",
+            ],
+            &[
+                "/dev/input/js0",
+                "--scratch-hires",
+                "--single-report",
+                "--busy-poll",
+            ],
+            &[
+                "/dev/input/js0",
+                "--conn-interval-hint",
+                "7.5",
+                "--align-to-conn-interval",
+            ],
+            &[
+                "/dev/input/js0",
+                "--conn-interval-min",
+                "7.5",
+                "--conn-interval-max",
+                "15",
+            ],
+            &["/dev/input/js0", "--dump-payloads", "frames.jsonl"],
+            &["/dev/input/js0", "--advertising-name", "IIDX"],
+            &["--config", "beatble.toml", "/dev/input/js0"],
+        ] {
+            let bare = parse(&[], flags);
+            assert!(bare.command.is_none(), "{flags:?}");
+            let run = parse(&["run"], flags);
+            let Some(Command::Run(args)) = &run.command else {
+                panic!("{flags:?} isn't run");
+            };
+            assert_eq!(
+                crate::config::show(&bare.run).unwrap(),
+                crate::config::show(args).unwrap(),
+                "{flags:?}"
+            );
+            assert_eq!(bare.global.config, run.global.config, "{flags:?}");
+        }
+    }
+
+    #[test]
+    fn verbosity_raises_only_beatbles_own_level() {
+        let filter = |flags: &[&str]| {
+            let argv = ["beatble"].iter().chain(flags);
+            Cli::try_parse_from(argv)
+                .expect("valid flags")
+                .global
+                .log_filter()
+        };
+        for (flags, expected) in [
+            (&[][..], "info"),
+            (&["-v"], "warn,beatble=info"),
+            (&["-vv"], "warn,beatble=debug"),
+            (&["-v", "-v"], "warn,beatble=debug"),
+            (&["-vvv"], "warn,beatble=trace"),
+            (&["-vvvv"], "warn,beatble=trace"),
+            (&["--verbose", "run"], "warn,beatble=info"),
+            (&["run", "-vv"], "warn,beatble=debug"),
+            (&["-q"], "warn"),
+            (&["-qq"], "error"),
+            (&["config", "show", "-qqq"], "error"),
+        ] {
+            let filter = filter(flags);
+            assert_eq!(filter, expected, "{flags:?}");
+            tracing_subscriber::EnvFilter::try_new(filter).expect("a valid filter");
+        }
+        assert!(Cli::try_parse_from(["beatble", "-v", "-q"]).is_err());
+    }
+
+    #[test]
+    fn completions_name_the_flags_for_every_shell() {
+        for shell in Shell::value_variants() {
+            let mut script = Vec::new();
+            write_completions(*shell, &mut script);
+            let script = String::from_utf8(script).expect("a utf-8 script");
+            // fish spells long flags as `-l name`, so match without the dashes
+            for name in ["sleep-duration", "dp-device", "config", "completions"] {
+                assert!(script.contains(name), "{shell} completions lack {name}");
+            }
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// nanoseconds on a process-wide monotonic clock, small enough to share through an atomic
+#[inline]
+pub fn now() -> u64 {
+    EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+/// time since `at`, a value of now()
+#[inline]
+pub fn since(at: u64) -> Duration {
+    Duration::from_nanos(now().saturating_sub(at))
+}
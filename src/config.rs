@@ -0,0 +1,1027 @@
+// Settings are merged with the precedence
+//
+//   built-in defaults < config file < BEATBLE_* environment variables < flags
+//
+// The config file is --config, or else the first of
+// $XDG_CONFIG_HOME/beatble/config.toml (written by `beatble init`) and
+// /etc/beatble/config.toml that exists.
+//
+// clap already resolves flags over environment variables over defaults, so
+// the config file only fills in settings whose value came from a default.
+//
+// On SIGHUP `beatble run` merges the file again with the same flags and
+// environment. Only the RELOADABLE settings are applied to the running
+// notifiers and input readers; the rest need a restart.
+
+#[cfg(feature = "ble")]
+use std::collections::BTreeSet;
+use std::env;
+#[cfg(feature = "ble")]
+use std::fmt;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use beatble::ble::RepeatSpacing;
+use beatble::control::check_interval;
+use beatble::emulation::Emulation;
+use beatble::input::{
+    ActiveLow, Backend, Chord, DeviceId, Keymap, LongPress, Profile, SdlControls,
+};
+use beatble_protocol::payload::{PayloadFormat, ScratchMode};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, Id};
+use eyre::{Result, WrapErr};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use tracing::debug;
+use tracing_subscriber::EnvFilter;
+
+use crate::cli::{Cli, Command, ConfigCommand, RunArgs};
+use crate::runtime::Flavor;
+use crate::supervisor::RestartPolicy;
+
+const DEFAULT_CONFIG_PATH: &str = "/etc/beatble/config.toml";
+// well past any latency to the console, a prediction further ahead is a guess
+const MAX_SCRATCH_PREDICT_MS: f64 = 100.0;
+// past this the repeats take more of a connection event than the frames
+const MAX_FRAME_REPEAT: usize = 4;
+#[cfg(feature = "ble")]
+const RELOADABLE: [&str; 4] = [
+    "sleep-duration",
+    "notify-on-change",
+    "keep-alive",
+    "log-level",
+];
+// applied through SharedKeyInput::remap
+#[cfg(feature = "ble")]
+const RELOADABLE_MAPPING: [&str; 5] = [
+    "keymap",
+    "mapping",
+    "turntable-sensitivity",
+    "anti-wobble",
+    "anti-wobble-grace",
+];
+
+/// Config file contents; every key is optional and named after its flag.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dp_device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_name: Option<String>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    device_id: Option<DeviceId>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    backend: Option<Backend>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sleep_duration: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notify_on_change: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warmup_frames: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    counter_start: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame_repeat: Option<usize>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    frame_repeat_spacing: Option<RepeatSpacing>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    emulate: Option<Emulation>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    payload_format: Option<PayloadFormat>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    profile: Option<Profile>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    keymap: Option<Keymap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mapping: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    turntable_sensitivity: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reverse_turntable: Option<bool>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    active_low: Option<ActiveLow>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sdl_mapping: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sdl_mapping_file: Option<PathBuf>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    sdl_controls: Option<SdlControls>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    long_press: Option<LongPress>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anti_wobble: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anti_wobble_grace: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scratch_recenter: Option<bool>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    scratch_recenter_chord: Option<Chord>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    scratch_mode: Option<ScratchMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scratch_predict: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scratch_hires: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    single_report: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    busy_poll: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split_privileges: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wait_for_device: Option<bool>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    on_input_error: Option<RestartPolicy>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    on_advertising_stop: Option<RestartPolicy>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    on_bluetooth_loss: Option<RestartPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bluetooth_retries: Option<u32>,
+    #[serde(
+        deserialize_with = "from_str",
+        serialize_with = "to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    runtime: Option<Flavor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    power_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    advertising_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conn_interval_hint: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    align_to_conn_interval: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conn_interval_min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conn_interval_max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conn_interval_warn: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dump_payloads: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dry_run: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changes_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    no_bluetooth: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mirror_uinput: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    advertising_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_level: Option<String>,
+}
+
+impl Config {
+    /// the explicit path must exist, the default ones may not
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let default_path = || {
+            user_config_path()
+                .into_iter()
+                .chain([PathBuf::from(DEFAULT_CONFIG_PATH)])
+                .find(|path| path.exists())
+        };
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) => path,
+                None => return Ok(Self::default()),
+            },
+        };
+        debug!("loading config from {}", path.display());
+        let contents = fs::read_to_string(&path)
+            .wrap_err_with(|| format!("failed to read config {}", path.display()))?;
+        toml::from_str(&contents).wrap_err_with(|| format!("invalid config {}", path.display()))
+    }
+
+    /// the profile's settings where the file has none
+    fn fill(&mut self, profile: &Profile) {
+        self.keymap.get_or_insert(profile.keymap);
+        self.turntable_sensitivity
+            .get_or_insert(profile.turntable_sensitivity);
+        self.reverse_turntable
+            .get_or_insert(profile.reverse_turntable);
+        if self.anti_wobble.is_none() {
+            self.anti_wobble = profile.anti_wobble;
+        }
+        self.anti_wobble_grace
+            .get_or_insert(profile.anti_wobble_grace.as_millis() as u64);
+    }
+}
+
+impl From<&RunArgs> for Config {
+    fn from(args: &RunArgs) -> Self {
+        Self {
+            device: args.input.clone(),
+            dp_device: args.dp_device.clone(),
+            device_name: args.device_name.clone(),
+            device_id: args.device_id,
+            backend: Some(args.backend),
+            sleep_duration: Some(args.sleep_duration),
+            notify_on_change: Some(args.notify_on_change),
+            keep_alive: Some(args.keep_alive),
+            warmup_frames: Some(args.warmup_frames),
+            counter_start: Some(args.counter_start),
+            frame_repeat: Some(args.frame_repeat),
+            frame_repeat_spacing: Some(args.frame_repeat_spacing),
+            emulate: Some(args.emulate),
+            payload_format: Some(args.payload_format),
+            profile: args.profile,
+            keymap: Some(args.keymap),
+            mapping: args.mapping.clone(),
+            turntable_sensitivity: Some(args.turntable_sensitivity),
+            reverse_turntable: Some(args.reverse_turntable),
+            active_low: args.active_low.clone(),
+            sdl_mapping: args.sdl_mapping.clone(),
+            sdl_mapping_file: args.sdl_mapping_file.clone(),
+            sdl_controls: Some(args.sdl_controls.clone()),
+            long_press: args.long_press.clone(),
+            anti_wobble: args.anti_wobble,
+            anti_wobble_grace: Some(args.anti_wobble_grace),
+            scratch_recenter: Some(args.scratch_recenter),
+            scratch_recenter_chord: args.scratch_recenter_chord.clone(),
+            scratch_mode: Some(args.scratch_mode),
+            scratch_predict: args.scratch_predict,
+            scratch_hires: Some(args.scratch_hires),
+            single_report: Some(args.single_report),
+            busy_poll: Some(args.busy_poll),
+            split_privileges: Some(args.split_privileges),
+            wait_for_device: Some(args.wait_for_device),
+            on_input_error: Some(args.on_input_error),
+            on_advertising_stop: Some(args.on_advertising_stop),
+            on_bluetooth_loss: Some(args.on_bluetooth_loss),
+            bluetooth_retries: Some(args.bluetooth_retries),
+            runtime: Some(args.runtime),
+            power_timeout: Some(args.power_timeout),
+            advertising_timeout: Some(args.advertising_timeout),
+            conn_interval_hint: args.conn_interval_hint,
+            align_to_conn_interval: Some(args.align_to_conn_interval),
+            conn_interval_min: args.conn_interval_min,
+            conn_interval_max: args.conn_interval_max,
+            conn_interval_warn: Some(args.conn_interval_warn),
+            dump_payloads: args.dump_payloads.clone(),
+            dry_run: Some(args.dry_run),
+            changes_only: Some(args.changes_only),
+            no_bluetooth: Some(args.no_bluetooth),
+            mirror_uinput: Some(args.mirror_uinput),
+            advertising_name: args.advertising_name.clone(),
+            log_level: args.log_level.clone(),
+        }
+    }
+}
+
+/// Merges the config file into the run args again, as at startup.
+// only a session reloads
+#[cfg_attr(not(feature = "ble"), allow(dead_code))]
+pub struct Reloader {
+    path: Option<PathBuf>,
+    // before the config file was merged in
+    args: RunArgs,
+    matches: ArgMatches,
+}
+
+#[cfg(feature = "ble")]
+impl Reloader {
+    /// the config file, if any
+    #[cfg(feature = "sandbox")]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// the new effective run args; the running ones are left alone on error
+    pub fn reload(&self) -> Result<RunArgs> {
+        let mut args = self.args.clone();
+        merge(
+            &mut args,
+            &self.matches,
+            Config::load(self.path.as_deref())?,
+        );
+        load_mapping(&mut args)?;
+        #[cfg(feature = "input")]
+        select_device(&mut args)?;
+        validate(&args)?;
+        Ok(args)
+    }
+}
+
+/// A setting that differs between two configurations.
+#[cfg(feature = "ble")]
+pub struct Change {
+    pub key: String,
+    old: Option<toml::Value>,
+    new: Option<toml::Value>,
+}
+
+#[cfg(feature = "ble")]
+impl Change {
+    pub fn is_reloadable(&self) -> bool {
+        RELOADABLE.contains(&self.key.as_str()) || self.is_mapping()
+    }
+
+    /// whether the readers' input mapping has to change
+    pub fn is_mapping(&self) -> bool {
+        RELOADABLE_MAPPING.contains(&self.key.as_str())
+    }
+}
+
+#[cfg(feature = "ble")]
+impl Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = |value: &Option<toml::Value>| match value {
+            Some(value) => value.to_string(),
+            None => "unset".to_string(),
+        };
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.key,
+            value(&self.old),
+            value(&self.new)
+        )
+    }
+}
+
+/// Merges the config file into the run settings of whichever command has them.
+/// Returns None for subcommands without run settings.
+pub fn apply(cli: &mut Cli, matches: &ArgMatches) -> Result<Option<Reloader>> {
+    // `beatble --sleep-duration 4 run` would otherwise drop the flag silently
+    if cli.command.is_some() {
+        let command = Cli::command();
+        // ids also include the flattened groups
+        let is_run_arg = |id: &Id| {
+            command
+                .get_arguments()
+                .any(|arg| arg.get_id() == id && !arg.is_global_set())
+        };
+        if let Some(id) = matches.ids().find(|id| {
+            is_run_arg(id) && matches.value_source(id.as_str()) == Some(ValueSource::CommandLine)
+        }) {
+            eyre::bail!("{id} has to be given after the subcommand");
+        }
+    }
+
+    let (args, matches) = match &mut cli.command {
+        None => (&mut cli.run, matches),
+        #[cfg(all(feature = "ble", feature = "input"))]
+        Some(Command::Run(args)) => (args, subcommand_matches(matches, &["run"])),
+        #[cfg(feature = "input")]
+        Some(Command::Test(args)) => (args, subcommand_matches(matches, &["test"])),
+        #[cfg(feature = "ble")]
+        Some(Command::Simulate(args)) => {
+            (&mut args.run, subcommand_matches(matches, &["simulate"]))
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Show(args),
+        }) => (args, subcommand_matches(matches, &["config", "show"])),
+        Some(_) => return Ok(None),
+    };
+    let reloader = Reloader {
+        path: cli.global.config.clone(),
+        args: args.clone(),
+        matches: matches.clone(),
+    };
+    let config = Config::load(cli.global.config.as_deref())?;
+    merge(args, matches, config);
+    load_mapping(args)?;
+    #[cfg(feature = "input")]
+    select_device(args)?;
+    validate(args)?;
+    Ok(Some(reloader))
+}
+
+/// settings whose value differs, by config key
+#[cfg(feature = "ble")]
+pub fn diff(old: &RunArgs, new: &RunArgs) -> Result<Vec<Change>> {
+    let old = toml::Table::try_from(Config::from(old))?;
+    let new = toml::Table::try_from(Config::from(new))?;
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    Ok(keys
+        .into_iter()
+        .filter(|&key| old.get(key) != new.get(key))
+        .map(|key| Change {
+            key: key.clone(),
+            old: old.get(key).cloned(),
+            new: new.get(key).cloned(),
+        })
+        .collect())
+}
+
+/// the keymap of the mapping file, which stands in for keymap
+fn load_mapping(args: &mut RunArgs) -> Result<()> {
+    let Some(path) = &args.mapping else {
+        return Ok(());
+    };
+    let contents = fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read mapping {}", path.display()))?;
+    args.keymap = Keymap::from_mapping_file(&contents)
+        .map_err(|e| eyre::eyre!(e))
+        .wrap_err_with(|| format!("invalid mapping {}", path.display()))?;
+    Ok(())
+}
+
+/// the device --device-name and --device-id pick, which stands in for the
+/// device path, even one from the config file
+#[cfg(feature = "input")]
+fn select_device(args: &mut RunArgs) -> Result<()> {
+    let selector = args.device_selector();
+    if selector.is_empty() {
+        return Ok(());
+    }
+    let Some(path) = beatble::input::select_device(&selector)? else {
+        if args.wait_for_device {
+            // the session waits for it
+            return Ok(());
+        }
+        eyre::bail!("no joystick device matches {selector}, see `beatble list`");
+    };
+    debug!("{path} matches {selector}");
+    args.input = Some(path);
+    Ok(())
+}
+
+fn validate(args: &RunArgs) -> Result<()> {
+    // clap only checks this pair for flags and environment variables
+    if args.conn_interval_min.is_some() != args.conn_interval_max.is_some() {
+        eyre::bail!("conn-interval-min and conn-interval-max must be set together");
+    }
+    for (key, ms) in [
+        ("conn-interval-hint", args.conn_interval_hint),
+        ("conn-interval-min", args.conn_interval_min),
+        ("conn-interval-max", args.conn_interval_max),
+    ] {
+        if let Some(ms) = ms.filter(|ms| !(ms.is_finite() && *ms > 0.0)) {
+            eyre::bail!("{key} must be a positive number of ms, got {ms}");
+        }
+    }
+    if let (Some(min), Some(max)) = (args.conn_interval_min, args.conn_interval_max) {
+        if min > max {
+            eyre::bail!("conn-interval-min {min} is above conn-interval-max {max}");
+        }
+    }
+    if !(args.conn_interval_warn.is_finite() && args.conn_interval_warn > 0.0) {
+        eyre::bail!(
+            "conn-interval-warn must be a positive number of ms, got {}",
+            args.conn_interval_warn
+        );
+    }
+    if args.dry_run && args.no_bluetooth {
+        eyre::bail!("dry-run and no-bluetooth can't be set together");
+    }
+    if args.sdl_mapping.is_some() && args.sdl_mapping_file.is_some() {
+        eyre::bail!("sdl-mapping and sdl-mapping-file can't be set together");
+    }
+    if let Some(level) = &args.log_level {
+        EnvFilter::try_new(level).wrap_err_with(|| format!("invalid log-level {level}"))?;
+    }
+    if args.backend == Backend::Evdev && args.split_privileges {
+        eyre::bail!("backend evdev and split-privileges can't be set together");
+    }
+    if args.mapping.is_some() && (args.sdl_mapping.is_some() || args.sdl_mapping_file.is_some()) {
+        eyre::bail!("mapping and an SDL mapping can't be set together");
+    }
+    if args.turntable_sensitivity == 0 {
+        eyre::bail!("turntable-sensitivity must be at least 1");
+    }
+    if let Some(degrees) = args.anti_wobble {
+        if !(degrees.is_finite() && degrees > 0.0) {
+            eyre::bail!("anti-wobble must be a positive number of degrees, got {degrees}");
+        }
+    }
+    // what the console may not take well is only warned about, at startup
+    if let Err(e) = check_interval(Duration::from_millis(args.sleep_duration), args.busy_poll) {
+        eyre::bail!("sleep-duration {}: {e}", args.sleep_duration);
+    }
+    if !(1..=MAX_FRAME_REPEAT).contains(&args.frame_repeat) {
+        eyre::bail!(
+            "frame-repeat must be from 1 to {MAX_FRAME_REPEAT}, got {}",
+            args.frame_repeat
+        );
+    }
+    if let Some(ms) = args.scratch_predict {
+        if !(ms > 0.0 && ms <= MAX_SCRATCH_PREDICT_MS) {
+            eyre::bail!(
+                "scratch-predict must be more than 0 and at most {MAX_SCRATCH_PREDICT_MS} ms, got {ms}"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// the effective configuration as a config file
+pub fn show(args: &RunArgs) -> Result<String> {
+    Ok(toml::to_string(&Config::from(args))?)
+}
+
+/// show() for reports that get shared: no key holds a secret, but paths
+/// under the home directory give away the user name
+pub fn show_redacted(args: &RunArgs) -> Result<String> {
+    let config = show(args)?;
+    Ok(match env::var("HOME") {
+        Ok(home) if home.len() > 1 => config.replace(home.trim_end_matches('/'), "~"),
+        _ => config,
+    })
+}
+
+/// a config file with just these settings, everything else at its default
+#[cfg(feature = "input")]
+pub fn generate(device: Option<String>, advertising_name: Option<String>) -> Result<String> {
+    let config = Config {
+        device,
+        advertising_name,
+        ..Config::default()
+    };
+    Ok(toml::to_string(&config)?)
+}
+
+/// $XDG_CONFIG_HOME/beatble/config.toml, or under ~/.config
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("beatble").join("config.toml"))
+}
+
+fn subcommand_matches<'a>(matches: &'a ArgMatches, path: &[&str]) -> &'a ArgMatches {
+    path.iter().fold(matches, |matches, name| {
+        matches
+            .subcommand_matches(name)
+            .expect("matches follow the parsed command")
+    })
+}
+
+fn merge(args: &mut RunArgs, matches: &ArgMatches, mut config: Config) {
+    // the arg id of a derived field is the field name
+    let unset = |id: &str| {
+        matches!(
+            matches.value_source(id),
+            None | Some(ValueSource::DefaultValue)
+        )
+    };
+
+    macro_rules! merge {
+        ($($field:ident),* $(,)?) => {
+            $(
+                if let Some(value) = config.$field {
+                    if unset(stringify!($field)) {
+                        args.$field = value;
+                    }
+                }
+            )*
+        };
+    }
+    macro_rules! merge_optional {
+        ($($field:ident),* $(,)?) => {
+            $(
+                if config.$field.is_some() && unset(stringify!($field)) {
+                    args.$field = config.$field;
+                }
+            )*
+        };
+    }
+
+    // a profile stands in for the built-in defaults
+    if config.profile.is_some() && unset("profile") {
+        args.profile = config.profile;
+    }
+    if let Some(profile) = &args.profile {
+        config.fill(profile);
+    }
+
+    if config.device.is_some() && unset("input") {
+        args.input = config.device;
+    }
+    // the file's mapping would replace a keymap from the environment or flags
+    if config.mapping.is_some() && unset("mapping") && unset("keymap") {
+        args.mapping = config.mapping;
+    }
+    merge_optional!(
+        dp_device,
+        device_name,
+        device_id,
+        sdl_mapping,
+        sdl_mapping_file,
+        active_low,
+        long_press,
+        anti_wobble,
+        scratch_recenter_chord,
+        scratch_predict,
+        conn_interval_hint,
+        conn_interval_min,
+        conn_interval_max,
+        dump_payloads,
+        advertising_name,
+        log_level,
+    );
+    merge!(
+        backend,
+        sleep_duration,
+        notify_on_change,
+        keep_alive,
+        warmup_frames,
+        counter_start,
+        frame_repeat,
+        frame_repeat_spacing,
+        emulate,
+        payload_format,
+        keymap,
+        turntable_sensitivity,
+        reverse_turntable,
+        sdl_controls,
+        anti_wobble_grace,
+        scratch_recenter,
+        scratch_mode,
+        scratch_hires,
+        single_report,
+        busy_poll,
+        split_privileges,
+        wait_for_device,
+        on_input_error,
+        on_advertising_stop,
+        on_bluetooth_loss,
+        bluetooth_retries,
+        runtime,
+        power_timeout,
+        advertising_timeout,
+        align_to_conn_interval,
+        conn_interval_warn,
+        dry_run,
+        changes_only,
+        no_bluetooth,
+        mirror_uinput,
+    );
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+fn to_string<S, T>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Display,
+{
+    match value {
+        Some(value) => serializer.collect_str(value),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use beatble::control::MAX_INTERVAL;
+    use clap::{FromArgMatches, Parser};
+
+    use super::*;
+
+    fn args(flags: &[&str]) -> RunArgs {
+        let argv = ["beatble"].iter().chain(flags);
+        Cli::try_parse_from(argv).expect("valid flags").run
+    }
+
+    /// args with the config file merged in, as apply does
+    fn merged(flags: &[&str], config: &str) -> RunArgs {
+        let argv = ["beatble"].iter().chain(flags);
+        let matches = Cli::command().try_get_matches_from(argv).unwrap();
+        let mut args = Cli::from_arg_matches(&matches).unwrap().run;
+        merge(&mut args, &matches, toml::from_str(config).unwrap());
+        load_mapping(&mut args).unwrap();
+        args
+    }
+
+    const FILE: &str = r#"
+device = "/dev/input/js1"
+sleep-duration = 4
+advertising-name = "from the file"
+"#;
+    const FLAGS: [&str; 5] = [
+        "/dev/input/js3",
+        "--sleep-duration",
+        "6",
+        "--advertising-name",
+        "from a flag",
+    ];
+    // only set for precedence_with_environment, which runs in a process of its own
+    const ENVIRONMENT: [(&str, &str); 3] = [
+        ("BEATBLE_DEVICE", "/dev/input/js2"),
+        ("BEATBLE_SLEEP_DURATION", "5"),
+        ("BEATBLE_ADVERTISING_NAME", "from the environment"),
+    ];
+
+    fn settings(args: &RunArgs) -> (Option<&str>, u64, Option<&str>) {
+        (
+            args.input.as_deref(),
+            args.sleep_duration,
+            args.advertising_name.as_deref(),
+        )
+    }
+
+    #[test]
+    fn the_file_beats_defaults_and_flags_beat_the_file() {
+        assert_eq!(settings(&merged(&[], "")), (None, 8, None));
+        assert_eq!(
+            settings(&merged(&[], FILE)),
+            (Some("/dev/input/js1"), 4, Some("from the file"))
+        );
+        assert_eq!(
+            settings(&merged(&FLAGS, FILE)),
+            (Some("/dev/input/js3"), 6, Some("from a flag"))
+        );
+        // a flag set to the default value still counts as set
+        let flagged = merged(&["--sleep-duration", "8"], FILE);
+        assert_eq!(
+            settings(&flagged),
+            (Some("/dev/input/js1"), 8, Some("from the file"))
+        );
+    }
+
+    #[test]
+    fn the_environment_beats_the_file_and_flags_beat_the_environment() {
+        let status = std::process::Command::new(env::current_exe().unwrap())
+            .args(["--exact", "config::tests::precedence_with_environment"])
+            .args(["--ignored", "--test-threads=1", "-q"])
+            .envs(ENVIRONMENT)
+            .status()
+            .unwrap();
+        assert!(status.success(), "{status}");
+    }
+
+    #[test]
+    #[ignore = "run by the_environment_beats_the_file_and_flags_beat_the_environment"]
+    fn precedence_with_environment() {
+        if env::var_os(ENVIRONMENT[0].0).is_none() {
+            return;
+        }
+        assert_eq!(
+            settings(&merged(&[], "")),
+            (Some("/dev/input/js2"), 5, Some("from the environment"))
+        );
+        assert_eq!(
+            settings(&merged(&[], FILE)),
+            (Some("/dev/input/js2"), 5, Some("from the environment"))
+        );
+        assert_eq!(
+            settings(&merged(&FLAGS, FILE)),
+            (Some("/dev/input/js3"), 6, Some("from a flag"))
+        );
+    }
+
+    #[test]
+    fn conn_intervals_must_be_positive_and_ordered() {
+        for flags in [
+            &["--conn-interval-hint=-7.5"][..],
+            &["--conn-interval-hint", "NaN"],
+            &["--conn-interval-hint", "inf"],
+            &["--conn-interval-min=-1", "--conn-interval-max", "15"],
+            &["--conn-interval-min", "7.5", "--conn-interval-max", "NaN"],
+            &["--conn-interval-min", "15", "--conn-interval-max", "7.5"],
+        ] {
+            assert!(validate(&args(flags)).is_err(), "{flags:?} was accepted");
+        }
+        for flags in [
+            &["--conn-interval-hint", "7.5"][..],
+            &["--conn-interval-min", "7.5", "--conn-interval-max", "7.5"],
+            &["--conn-interval-min", "7.5", "--conn-interval-max", "15"],
+        ] {
+            assert!(validate(&args(flags)).is_ok(), "{flags:?} was rejected");
+        }
+    }
+
+    #[test]
+    fn sleep_duration_is_checked() {
+        #[derive(Debug, PartialEq)]
+        enum Outcome {
+            Fine,
+            Warned,
+            Rejected,
+        }
+        let outcome = |flags: &[&str]| {
+            let args = args(flags);
+            if validate(&args).is_err() {
+                return Outcome::Rejected;
+            }
+            match check_interval(Duration::from_millis(args.sleep_duration), args.busy_poll) {
+                Ok(None) => Outcome::Fine,
+                Ok(Some(_)) => Outcome::Warned,
+                Err(e) => panic!("validated, but {e}"),
+            }
+        };
+        let max = MAX_INTERVAL.as_millis().to_string();
+        let past_max = (MAX_INTERVAL.as_millis() + 1).to_string();
+        for (flags, expected) in [
+            (&["--sleep-duration", "0"][..], Outcome::Rejected),
+            (&["--sleep-duration", "0", "--busy-poll"], Outcome::Fine),
+            (&["--sleep-duration", "1"], Outcome::Warned),
+            (&["--sleep-duration", "3"], Outcome::Warned),
+            (&["--sleep-duration", "4"], Outcome::Fine),
+            (&["--sleep-duration", "8"], Outcome::Fine),
+            (&["--sleep-duration", "20"], Outcome::Fine),
+            (&["--sleep-duration", "21"], Outcome::Warned),
+            (&["--sleep-duration", &max], Outcome::Warned),
+            (&["--sleep-duration", &past_max], Outcome::Rejected),
+            (
+                &["--sleep-duration", &past_max, "--busy-poll"],
+                Outcome::Rejected,
+            ),
+            (
+                &["--sleep-duration", "18446744073709551615"],
+                Outcome::Rejected,
+            ),
+        ] {
+            assert_eq!(outcome(flags), expected, "{flags:?}");
+        }
+    }
+
+    #[test]
+    fn frame_repeat_is_parsed_and_bounded() {
+        let defaults = args(&[]);
+        assert_eq!(defaults.frame_repeat, 1);
+        assert_eq!(defaults.frame_repeat_spacing, RepeatSpacing::BackToBack);
+
+        let repeated = args(&["--frame-repeat", "2", "--frame-repeat-spacing", "ticks"]);
+        assert_eq!(repeated.frame_repeat, 2);
+        assert_eq!(repeated.frame_repeat_spacing, RepeatSpacing::Ticks);
+
+        let config: Config =
+            toml::from_str("frame-repeat = 3\nframe-repeat-spacing = \"back-to-back\"").unwrap();
+        assert_eq!(config.frame_repeat, Some(3));
+        assert_eq!(config.frame_repeat_spacing, Some(RepeatSpacing::BackToBack));
+        assert!(toml::from_str::<Config>("frame-repeat-spacing = \"sideways\"").is_err());
+
+        for flags in [
+            &["--frame-repeat", "-1"][..],
+            &["--frame-repeat", "two"],
+            &["--frame-repeat-spacing", "sideways"],
+        ] {
+            let argv = ["beatble"].iter().chain(flags);
+            assert!(Cli::try_parse_from(argv).is_err(), "{flags:?} was parsed");
+        }
+        for times in ["0", "5"] {
+            let flags = ["--frame-repeat", times];
+            assert!(validate(&args(&flags)).is_err(), "{flags:?} was accepted");
+        }
+        for times in ["1", "4"] {
+            let flags = ["--frame-repeat", times];
+            assert!(validate(&args(&flags)).is_ok(), "{flags:?} was rejected");
+        }
+    }
+
+    #[test]
+    fn a_keymap_flag_beats_the_files_mapping() {
+        let dir = env::temp_dir().join(format!("beatble-mapping-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mapping = dir.join("mapping.toml");
+        fs::write(&mapping, "[buttons]\n1 = \"B1\"\n\n[axes]\n0 = \"scratch\"").unwrap();
+        let config = format!("mapping = {:?}", mapping.display().to_string());
+
+        let from_file = merged(&[], &config);
+        assert_eq!(from_file.mapping.as_deref(), Some(mapping.as_path()));
+        assert_eq!(from_file.keymap.to_string(), "B1=1,TT=0");
+
+        let flagged = merged(&["--keymap", "B1=3"], &config);
+        assert_eq!(flagged.mapping, None);
+        assert_eq!(flagged.keymap.to_string(), "B1=3");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "ble")]
+    #[test]
+    fn reload_diff_tells_live_settings_from_restarts() {
+        let old = args(&[]);
+        assert!(diff(&old, &args(&[])).expect("comparable").is_empty());
+
+        let new = args(&[
+            "--keymap",
+            "B1=1,B2=0,TT=1",
+            "--turntable-sensitivity",
+            "3",
+            "--anti-wobble",
+            "2.5",
+            "--sleep-duration",
+            "4",
+            "--emulate",
+            "sdvx",
+            "--log-level",
+            "warn,beatble=debug",
+        ]);
+        let changes = diff(&old, &new).expect("comparable");
+        let keys = |pick: fn(&Change) -> bool| {
+            changes
+                .iter()
+                .filter(|&change| pick(change))
+                .map(|change| change.key.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            keys(Change::is_mapping),
+            ["anti-wobble", "keymap", "turntable-sensitivity"]
+        );
+        assert_eq!(
+            keys(Change::is_reloadable),
+            [
+                "anti-wobble",
+                "keymap",
+                "log-level",
+                "sleep-duration",
+                "turntable-sensitivity"
+            ]
+        );
+        assert_eq!(keys(|change| !change.is_reloadable()), ["emulate"]);
+
+        let shown: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        assert!(shown.contains(&"turntable-sensitivity: 2 -> 3".to_string()));
+        assert!(shown.contains(&"anti-wobble: unset -> 2.5".to_string()));
+        assert!(shown.contains(&"log-level: unset -> \"warn,beatble=debug\"".to_string()));
+    }
+
+    #[test]
+    fn a_log_level_has_to_be_a_filter() {
+        let mut args = merged(&[], "log-level = \"warn,beatble=debug\"");
+        assert_eq!(args.log_level.as_deref(), Some("warn,beatble=debug"));
+        assert!(validate(&args).is_ok());
+        args.log_level = Some("beatble=loud".to_string());
+        let e = validate(&args).unwrap_err();
+        assert!(
+            format!("{e}").starts_with("invalid log-level beatble=loud"),
+            "{e}"
+        );
+    }
+}
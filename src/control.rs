@@ -0,0 +1,178 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use thiserror::Error;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use tracing::info;
+
+use crate::ble::NotifyMode;
+
+/// The longest interval between two frames; at 10 frames per second every
+/// press is late and short taps merge.
+pub const MAX_INTERVAL: Duration = Duration::from_millis(100);
+// around the 8 ms of the original controller, what the console takes without
+// frames piling up in the link or presses arriving a video frame late
+const FASTEST_USEFUL: Duration = Duration::from_millis(4);
+const SLOWEST_USEFUL: Duration = Duration::from_millis(20);
+
+/// A notification interval the notifiers can't work with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum IntervalError {
+    /// 0 without busy polling would spin the notifier through the sleeps
+    #[error("an interval of 0 only paces by the link with --busy-poll, which spins instead")]
+    Zero,
+    #[error("an interval of {0:?} is longer than the longest usable, {MAX_INTERVAL:?}")]
+    TooLong(Duration),
+}
+
+/// Checks a notification interval, e.g. from --sleep-duration or `beatble ctl
+/// rate`. 0 sends as fast as the link takes frames, which takes busy_poll.
+/// Ok carries why the console may not take an interval well, if it may not.
+pub fn check_interval(
+    interval: Duration,
+    busy_poll: bool,
+) -> Result<Option<&'static str>, IntervalError> {
+    if interval.is_zero() {
+        return if busy_poll {
+            Ok(None)
+        } else {
+            Err(IntervalError::Zero)
+        };
+    }
+    if interval > MAX_INTERVAL {
+        return Err(IntervalError::TooLong(interval));
+    }
+    if interval < FASTEST_USEFUL {
+        return Ok(Some(
+            "the console expects a frame about every 8 ms, below 4 ms frames only pile up in \
+             the link's connection events and get dropped",
+        ));
+    }
+    if interval > SLOWEST_USEFUL {
+        return Ok(Some(
+            "the console expects a frame about every 8 ms, above 20 ms presses reach it a video \
+             frame late or more",
+        ));
+    }
+    Ok(None)
+}
+
+/// A runtime change, whether it comes from a signal, the dashboard, a config
+/// reload, D-Bus or the control socket.
+#[derive(Clone, Copy, Debug)]
+pub enum Command {
+    /// stop sending frames, subscriptions stay
+    Pause,
+    Resume,
+    TogglePause,
+    /// the interval between two frames
+    SetInterval(Duration),
+    SetMode(NotifyMode),
+}
+
+/// Runtime state that can be changed while the peripheral is running.
+/// Every change goes through `apply`, so observers only have to watch it.
+#[derive(Debug)]
+pub struct Control {
+    paused: AtomicBool,
+    // nanoseconds, like the keep-alive below
+    interval: AtomicU64,
+    on_change: AtomicBool,
+    keep_alive: AtomicU64,
+    // bumped after every command
+    changes: watch::Sender<u64>,
+}
+
+impl Control {
+    pub fn new(interval: Duration, mode: NotifyMode) -> Self {
+        let control = Self {
+            paused: AtomicBool::new(false),
+            interval: AtomicU64::new(0),
+            on_change: AtomicBool::new(false),
+            keep_alive: AtomicU64::new(0),
+            changes: watch::Sender::new(0),
+        };
+        control.set_interval(interval);
+        control.set_mode(mode);
+        control
+    }
+
+    /// whether frames are held back
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Applies the command and wakes everything watching for changes.
+    pub fn apply(&self, command: Command) {
+        match command {
+            Command::Pause => self.set_paused(true),
+            Command::Resume => self.set_paused(false),
+            Command::TogglePause => self.set_paused(!self.is_paused()),
+            Command::SetInterval(interval) => {
+                self.set_interval(interval);
+                info!("Notification interval set to {interval:?}");
+            }
+            Command::SetMode(mode) => {
+                self.set_mode(mode);
+                info!("Notification mode set to {mode:?}");
+            }
+        }
+        self.changes.send_modify(|changes| *changes += 1);
+    }
+
+    /// resolves on the next change through `apply`
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    fn set_paused(&self, paused: bool) {
+        if self.paused.swap(paused, Ordering::Relaxed) == paused {
+            return;
+        }
+        if paused {
+            info!("Notifications paused");
+        } else {
+            info!("Notifications resumed");
+        }
+    }
+
+    /// the current notification interval
+    #[inline]
+    pub fn interval(&self) -> Duration {
+        Duration::from_nanos(self.interval.load(Ordering::Relaxed))
+    }
+
+    fn set_interval(&self, interval: Duration) {
+        self.interval
+            .store(saturating_nanos(interval), Ordering::Relaxed);
+    }
+
+    /// the current notification mode
+    #[inline]
+    pub fn mode(&self) -> NotifyMode {
+        if self.on_change.load(Ordering::Relaxed) {
+            NotifyMode::OnChange {
+                keep_alive: Duration::from_nanos(self.keep_alive.load(Ordering::Relaxed)),
+            }
+        } else {
+            NotifyMode::Periodic
+        }
+    }
+
+    fn set_mode(&self, mode: NotifyMode) {
+        match mode {
+            NotifyMode::Periodic => self.on_change.store(false, Ordering::Relaxed),
+            NotifyMode::OnChange { keep_alive } => {
+                self.keep_alive
+                    .store(saturating_nanos(keep_alive), Ordering::Relaxed);
+                self.on_change.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// an interval too long to count in nanoseconds is as good as forever
+fn saturating_nanos(duration: Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
@@ -0,0 +1,140 @@
+// Crash reports: when beatble panics, or a session ends on an input device or
+// bluetooth failure, a report is written to the state directory, and its path
+// to stderr. It has what it takes to make sense of the crash without
+// reproducing it under trace logging: the build, the effective config, the
+// last input events and sent frames, the last log lines and the panic or
+// error with its backtrace.
+
+use std::backtrace::Backtrace;
+use std::env;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::panic;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use beatble::clock;
+use beatble::exit::ErrorKind;
+use beatble::recent;
+use eyre::Report;
+
+use crate::build_info::BUILD_INFO;
+use crate::logging;
+
+const LOG_LINES: usize = 100;
+
+// the effective config, redacted
+static CONFIG: OnceLock<String> = OnceLock::new();
+// one report per process, the first crash explains the rest
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Writes a report on any panic from now on; config is the effective config
+/// with secrets redacted.
+pub fn install(config: String) {
+    if CONFIG.set(config).is_err() {
+        return;
+    }
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        hook(info);
+        let thread = thread::current();
+        let cause = format!(
+            "{info}\nthread: {}\n\n{}",
+            thread.name().unwrap_or("unnamed"),
+            Backtrace::force_capture()
+        );
+        report(&cause);
+    }));
+}
+
+/// Writes a report for an error a session ended on, unless it is one the
+/// user can fix, such as a config error or a missing device.
+pub fn report_error(error: &Report) {
+    if let Some(ErrorKind::InputRuntime | ErrorKind::BleRuntime) = error.downcast_ref::<ErrorKind>()
+    {
+        report(&format!("{error:?}"));
+    }
+}
+
+/// $XDG_STATE_HOME/beatble, or under ~/.local/state
+pub fn dir() -> PathBuf {
+    let state_home = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".local").join("state"),
+            None => env::temp_dir(),
+        },
+    };
+    state_home.join("beatble")
+}
+
+fn report(cause: &str) {
+    let Some(config) = CONFIG.get() else {
+        return;
+    };
+    if REPORTED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    match write_report(cause, config) {
+        Ok(path) => eprintln!(
+            "A crash report was written to {}, please attach it to an issue",
+            path.display()
+        ),
+        Err(e) => eprintln!("failed to write a crash report: {e}"),
+    }
+}
+
+fn write_report(cause: &str, config: &str) -> io::Result<PathBuf> {
+    let dir = dir();
+    // under --sandbox, mkdir isn't allowed even for a directory that exists
+    if !dir.is_dir() {
+        fs::create_dir_all(&dir)?;
+    }
+    let unix_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = dir.join(format!("crash-{unix_time}-{}.txt", process::id()));
+    let mut file = File::create(&path)?;
+    file.write_all(render(cause, config, unix_time).as_bytes())?;
+    file.sync_all()?;
+    Ok(path)
+}
+
+fn render(cause: &str, config: &str, unix_time: u64) -> String {
+    let now = clock::now();
+    // milliseconds before the report
+    let ago = |at: u64| now.saturating_sub(at) as f64 / 1e6;
+
+    let mut report = String::new();
+    let _ = writeln!(
+        report,
+        "beatble crash report, unix time {unix_time}, pid {}\n",
+        process::id()
+    );
+    let _ = writeln!(report, "## cause\n\n{}\n", cause.trim_end());
+    let _ = writeln!(report, "## build\n\n{BUILD_INFO}\n");
+    let _ = writeln!(report, "## config\n\n{}\n", config.trim_end());
+
+    let events = recent::events();
+    let _ = writeln!(report, "## last {} input events\n", events.len());
+    for (at, event) in events {
+        let _ = writeln!(report, "{:>10.3} ms ago  {event}", ago(at));
+    }
+    let frames = recent::frames();
+    let _ = writeln!(report, "\n## last {} sent frames\n", frames.len());
+    for (at, frame) in frames {
+        let _ = writeln!(report, "{:>10.3} ms ago  {frame}", ago(at));
+    }
+    let lines = logging::recent_lines(LOG_LINES);
+    let _ = writeln!(report, "\n## last {} log lines\n", lines.len());
+    for line in lines {
+        let _ = writeln!(report, "{line}");
+    }
+    report
+}
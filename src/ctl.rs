@@ -0,0 +1,171 @@
+// Runtime control without D-Bus: `beatble run` listens on a unix socket and
+// `beatble ctl` sends it one command per line, e.g.
+//
+//   $ beatble ctl rate 6
+//   $ echo stats | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/beatble.sock
+//   ok subscribers=1 ...
+//
+// Every reply is one line, "ok" with an optional payload or "err" with a
+// reason. The socket is created owner-only, so whoever may open it may
+// control the session.
+
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use beatble::exit::ErrorKind;
+use eyre::{eyre, Result, WrapErr};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+#[cfg(feature = "ble")]
+pub use self::server::{spawn, ReaderHandle, Session};
+
+#[cfg(feature = "ble")]
+mod server;
+
+const SOCKET_NAME: &str = "beatble.sock";
+// without a runtime dir
+const FALLBACK_DIR: &str = "/tmp";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    Stats,
+    Pause,
+    Resume,
+    /// notification interval in milliseconds, like --sleep-duration
+    Rate(u64),
+    Profile(String),
+    /// read 1P input from another device from now on
+    Device(String),
+    Quit,
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let request = match (words.next(), words.next()) {
+            (Some("stats"), None) => Request::Stats,
+            (Some("pause"), None) => Request::Pause,
+            (Some("resume"), None) => Request::Resume,
+            (Some("rate"), Some(ms)) => match ms.parse() {
+                // 0 only with --busy-poll, which the session checks
+                Ok(ms) => Request::Rate(ms),
+                _ => return Err(format!("invalid interval: {ms} (expected milliseconds)")),
+            },
+            (Some("profile"), Some(name)) => Request::Profile(name.to_string()),
+            (Some("device"), Some(path)) => Request::Device(path.to_string()),
+            (Some("quit"), None) => Request::Quit,
+            (Some(_), _) => {
+                return Err(format!(
+                    "unknown command: {s} (expected stats, pause, resume, rate MS, \
+                     profile NAME, device PATH or quit)"
+                ))
+            }
+            (None, _) => return Err("empty command".to_string()),
+        };
+        if words.next().is_some() {
+            return Err(format!("too many arguments: {s}"));
+        }
+        Ok(request)
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Request::Stats => write!(f, "stats"),
+            Request::Pause => write!(f, "pause"),
+            Request::Resume => write!(f, "resume"),
+            Request::Rate(ms) => write!(f, "rate {ms}"),
+            Request::Profile(name) => write!(f, "profile {name}"),
+            Request::Device(path) => write!(f, "device {path}"),
+            Request::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(FALLBACK_DIR))
+        .join(SOCKET_NAME)
+}
+
+/// `beatble ctl`: sends one command and prints the reply's payload.
+pub async fn send(path: Option<PathBuf>, command: &[String]) -> Result<()> {
+    let request = command
+        .join(" ")
+        .parse::<Request>()
+        .map_err(|e| eyre!(e))
+        .wrap_err(ErrorKind::Config)?;
+    let path = path.unwrap_or_else(default_path);
+    let mut stream = UnixStream::connect(&path).await.wrap_err_with(|| {
+        format!(
+            "failed to connect to {}; is beatble running?",
+            path.display()
+        )
+    })?;
+    stream.write_all(format!("{request}\n").as_bytes()).await?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply).await?;
+    let reply = reply.trim_end();
+    match reply.split_once(' ').unwrap_or((reply, "")) {
+        ("ok", "") => Ok(()),
+        ("ok", payload) => {
+            println!("{payload}");
+            Ok(())
+        }
+        ("err", reason) => Err(eyre!("{request}: {reason}")),
+        _ => Err(eyre!("unexpected reply: {reply:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_parse_and_print_back() {
+        for (line, request) in [
+            ("stats", Request::Stats),
+            ("pause", Request::Pause),
+            ("resume", Request::Resume),
+            ("rate 6", Request::Rate(6)),
+            ("profile sdvx", Request::Profile("sdvx".to_string())),
+            (
+                "device /dev/input/js1",
+                Request::Device("/dev/input/js1".to_string()),
+            ),
+            ("quit", Request::Quit),
+        ] {
+            assert_eq!(line.parse(), Ok(request.clone()));
+            assert_eq!(request.to_string(), line);
+        }
+        assert_eq!("  rate\t4 \n".parse(), Ok(Request::Rate(4)));
+    }
+
+    #[test]
+    fn malformed_requests_are_rejected() {
+        for (line, error) in [
+            ("", "empty command"),
+            ("rate", "unknown command"),
+            ("rate -1", "invalid interval"),
+            ("rate 4ms", "invalid interval"),
+            ("stats now", "unknown command"),
+            ("rate 4 5", "too many arguments"),
+            ("device a b", "too many arguments"),
+            ("restart", "unknown command"),
+            ("STATS", "unknown command"),
+        ] {
+            let parsed = line.parse::<Request>();
+            assert!(
+                parsed.as_ref().is_err_and(|e| e.starts_with(error)),
+                "{line:?} gave {parsed:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,397 @@
+// The listening side of the control socket, run alongside a session.
+
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use beatble::ble::NotifyContext;
+use beatble::control::{check_interval, Command};
+use beatble::input::InputMapping;
+#[cfg(feature = "input")]
+use beatble::input::{attach_input_handler, InputHandler};
+use eyre::{Result, WrapErr};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, Notify};
+#[cfg(not(feature = "input"))]
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::Request;
+#[cfg(feature = "input")]
+use crate::sdl::SdlLookup;
+use crate::supervisor::ActiveDevice;
+
+// the longest command is a device path
+const MAX_LINE: usize = 256;
+
+/// A reader started by `device`.
+#[cfg(feature = "input")]
+pub type ReaderHandle = InputHandler;
+/// A build without input support starts none.
+#[cfg(not(feature = "input"))]
+pub type ReaderHandle = JoinHandle<Result<()>>;
+
+/// What the commands act on.
+// a build without input support never swaps the reader
+#[cfg_attr(not(feature = "input"), allow(dead_code))]
+pub struct Session {
+    pub context: NotifyContext,
+    /// how `device` maps a new reader
+    pub mapping: InputMapping,
+    /// the keymap of a new reader's own SDL mapping instead, if any
+    #[cfg(feature = "input")]
+    pub sdl: Option<SdlLookup>,
+    /// what `device` falls back to when the new one fails
+    pub active: Arc<ActiveDevice>,
+    pub busy_poll: bool,
+    pub force: bool,
+    #[cfg(feature = "input")]
+    pub split_privileges: bool,
+    /// readers started by `device` and their device, for the supervisor
+    pub readers: mpsc::UnboundedSender<(String, ReaderHandle)>,
+    pub quit: Arc<Notify>,
+}
+
+/// Removes the socket file once the server is gone.
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Listens unless another instance already does; the session works without
+/// its control socket, so failures are only logged.
+pub fn spawn(path: &Path, session: Session) {
+    let listener = match bind(path) {
+        Ok(Some(listener)) => listener,
+        Ok(None) => {
+            warn!(
+                "control socket {} belongs to another instance, not listening",
+                path.display()
+            );
+            return;
+        }
+        Err(e) => {
+            warn!("no control socket: {e:#}");
+            return;
+        }
+    };
+    info!("Listening for commands on {}", path.display());
+    let socket_file = SocketFile(path.to_path_buf());
+    let session = Arc::new(session);
+    tokio::spawn(async move {
+        let _socket_file = socket_file;
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let session = Arc::clone(&session);
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, &session).await {
+                            debug!("control connection failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => debug!("control accept failed: {e}"),
+            }
+        }
+    });
+}
+
+fn bind(path: &Path) -> Result<Option<UnixListener>> {
+    let listener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Ok(None);
+            }
+            // left behind by an instance that didn't exit cleanly
+            fs::remove_file(path)?;
+            UnixListener::bind(path)?
+        }
+        Err(e) => return Err(e).wrap_err_with(|| format!("failed to bind {}", path.display())),
+    };
+    fs::set_permissions(path, Permissions::from_mode(0o600))
+        .wrap_err_with(|| format!("failed to restrict {}", path.display()))?;
+    Ok(Some(listener))
+}
+
+async fn serve(stream: UnixStream, session: &Session) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        // one byte over the limit tells an over-long line from one that just fits
+        let n = (&mut reader)
+            .take(MAX_LINE as u64 + 1)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if n == 0 {
+            return Ok(());
+        }
+        if line.len() > MAX_LINE {
+            writer
+                .write_all(format!("err line too long (max {MAX_LINE} bytes)\n").as_bytes())
+                .await?;
+            return Ok(());
+        }
+        let reply = match std::str::from_utf8(&line) {
+            Ok(line) => match line.trim().parse() {
+                Ok(request) => execute(request, session),
+                Err(e) => Err(e),
+            },
+            Err(_) => Err("not UTF-8".to_string()),
+        };
+        let reply = match reply {
+            Ok(payload) if payload.is_empty() => "ok\n".to_string(),
+            Ok(payload) => format!("ok {payload}\n"),
+            Err(e) => format!("err {e}\n"),
+        };
+        writer.write_all(reply.as_bytes()).await?;
+    }
+}
+
+fn execute(request: Request, session: &Session) -> Result<String, String> {
+    info!("Control command: {request}");
+    let control = &session.context.control;
+    match request {
+        Request::Stats => Ok(stats(session)),
+        Request::Pause => {
+            control.apply(Command::Pause);
+            Ok(String::new())
+        }
+        Request::Resume => {
+            control.apply(Command::Resume);
+            Ok(String::new())
+        }
+        Request::Rate(ms) => {
+            let interval = Duration::from_millis(ms);
+            let warning = check_interval(interval, session.busy_poll).map_err(|e| e.to_string())?;
+            control.apply(Command::SetInterval(interval));
+            Ok(warning
+                .map(|why| format!("warning: {why}"))
+                .unwrap_or_default())
+        }
+        Request::Profile(name) if name == session.mapping.emulation.to_string() => {
+            Ok(String::new())
+        }
+        Request::Profile(name) => Err(format!(
+            "the emulation can't change while advertising; restart with --emulate {name}"
+        )),
+        #[cfg(feature = "input")]
+        Request::Device(_) if session.split_privileges => Err(
+            "devices can't be switched with --split-privileges; restart with the new device"
+                .to_string(),
+        ),
+        #[cfg(feature = "input")]
+        Request::Device(path) => {
+            let mut mapping = session.mapping.clone();
+            if let Some(sdl) = &session.sdl {
+                mapping.keymap = sdl
+                    .keymap(&path)
+                    .map_err(|e| swap_failed(session, &path, e))?;
+            }
+            // the old reader keeps going until the new one claims the input
+            let reader = attach_input_handler(
+                &path,
+                Arc::clone(&session.context.key_input),
+                mapping,
+                session.busy_poll,
+                session.force,
+                Arc::clone(&session.context.stats),
+            )
+            .map_err(|e| swap_failed(session, &path, eyre::Report::new(e)))?;
+            session
+                .readers
+                .send((path, reader))
+                .map_err(|_| "the session is shutting down".to_string())?;
+            Ok(String::new())
+        }
+        #[cfg(not(feature = "input"))]
+        Request::Device(_) => Err("this build can't read input devices".to_string()),
+        Request::Quit => {
+            session.quit.notify_one();
+            Ok(String::new())
+        }
+    }
+}
+
+/// why path didn't replace the 1P device, and what is read instead
+#[cfg(feature = "input")]
+fn swap_failed(session: &Session, path: &str, error: eyre::Report) -> String {
+    let fallback = match session.active.get() {
+        Some(device) => format!("still reading {device}"),
+        None => "no active device".to_string(),
+    };
+    warn!("failed to switch to {path}, {fallback}: {error:#}");
+    format!("{error:#}; {fallback}")
+}
+
+fn stats(session: &Session) -> String {
+    let context = &session.context;
+    let stats = context.stats.snapshot();
+    [
+        format!("paused={}", context.control.is_paused()),
+        format!(
+            "device={}",
+            session.active.get().as_deref().unwrap_or("none")
+        ),
+        format!("interval_ms={}", context.control.interval().as_millis()),
+        format!("subscribers={}", stats.subscribers),
+        format!("subscriptions={}", stats.subscriptions),
+        format!("connections={}", stats.connections),
+        format!("disconnections={}", stats.disconnections),
+        format!("readvertisements={}", stats.readvertisements),
+        format!("bluez_losses={}", stats.bluez_losses),
+        format!("pairings={}", stats.pairings),
+        format!("failed_pairings={}", stats.failed_pairings),
+        format!("pings={}", stats.pings),
+        format!("throttled_pings={}", stats.throttled_pings),
+        format!("sent_frames={}", stats.sent_frames),
+        format!("repeated_frames={}", stats.repeated_frames),
+        format!("congested_frames={}", stats.congested_frames),
+        format!("notifier_stalls={}", stats.stalls),
+    ]
+    .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use beatble::ble::NotifyMode;
+    use beatble::control::Control;
+    use beatble::emulation::Emulation;
+    use beatble::input::SharedKeyInput;
+    use beatble::latency::Latency;
+    use beatble::stats::Stats;
+    use tokio::io::{Lines, ReadHalf, WriteHalf};
+
+    use super::*;
+
+    fn session(quit: Arc<Notify>) -> Session {
+        let interval = Duration::from_millis(8);
+        Session {
+            context: NotifyContext {
+                key_input: Arc::new(SharedKeyInput::new()),
+                control: Arc::new(Control::new(interval, NotifyMode::Periodic)),
+                latency: Arc::new(Latency::new()),
+                stats: Arc::new(Stats::new()),
+                dump: None,
+            },
+            mapping: InputMapping::from(Emulation::Iidx),
+            #[cfg(feature = "input")]
+            sdl: None,
+            active: Arc::new(ActiveDevice::default()),
+            busy_poll: false,
+            force: false,
+            #[cfg(feature = "input")]
+            split_privileges: false,
+            readers: mpsc::unbounded_channel().0,
+            quit,
+        }
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("beatble-ctl-{}-{name}.sock", process::id()))
+    }
+
+    struct Client {
+        lines: Lines<BufReader<ReadHalf<UnixStream>>>,
+        writer: WriteHalf<UnixStream>,
+    }
+
+    impl Client {
+        async fn connect(path: &Path) -> Self {
+            let stream = UnixStream::connect(path).await.expect("listening");
+            let (reader, writer) = tokio::io::split(stream);
+            Self {
+                lines: BufReader::new(reader).lines(),
+                writer,
+            }
+        }
+
+        async fn send(&mut self, line: &[u8]) -> Option<String> {
+            self.writer.write_all(line).await.expect("writable");
+            self.lines.next_line().await.expect("readable")
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_get_one_line_replies() {
+        let path = socket_path("commands");
+        let quit = Arc::new(Notify::new());
+        let session = session(Arc::clone(&quit));
+        let control = Arc::clone(&session.context.control);
+        spawn(&path, session);
+        let mode = fs::metadata(&path).expect("bound").permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut client = Client::connect(&path).await;
+        assert_eq!(client.send(b"rate 6\n").await.as_deref(), Some("ok"));
+        assert_eq!(control.interval(), Duration::from_millis(6));
+        assert_eq!(client.send(b"pause\n").await.as_deref(), Some("ok"));
+        assert!(control.is_paused());
+        assert_eq!(client.send(b"resume\n").await.as_deref(), Some("ok"));
+        assert!(!control.is_paused());
+        let reply = client.send(b"rate 0\n").await.expect("a reply");
+        assert!(reply.starts_with("err "), "{reply}");
+        assert_eq!(control.interval(), Duration::from_millis(6));
+        assert_eq!(client.send(b"profile iidx\n").await.as_deref(), Some("ok"));
+        let reply = client.send(b"profile sdvx\n").await.expect("a reply");
+        assert!(
+            reply.starts_with("err the emulation can't change"),
+            "{reply}"
+        );
+        let reply = client.send(b"reboot\n").await.expect("a reply");
+        assert!(reply.starts_with("err unknown command"), "{reply}");
+        let reply = client.send(b"\xff\n").await.expect("a reply");
+        assert_eq!(reply, "err not UTF-8");
+        let reply = client.send(b"stats\n").await.expect("a reply");
+        assert!(reply.starts_with("ok "), "{reply}");
+
+        assert_eq!(client.send(b"quit\n").await.as_deref(), Some("ok"));
+        tokio::time::timeout(Duration::from_secs(1), quit.notified())
+            .await
+            .expect("quit notified");
+    }
+
+    #[tokio::test]
+    async fn an_over_long_line_is_refused_and_closes_the_connection() {
+        let path = socket_path("long");
+        spawn(&path, session(Arc::new(Notify::new())));
+
+        let mut client = Client::connect(&path).await;
+        let mut line = format!("device /dev/input/{}", "j".repeat(MAX_LINE));
+        line.push('\n');
+        let reply = client.send(line.as_bytes()).await;
+        assert_eq!(reply.as_deref(), Some("err line too long (max 256 bytes)"));
+        assert_eq!(client.lines.next_line().await.expect("readable"), None);
+
+        // a line that just fits is read as a command
+        let mut client = Client::connect(&path).await;
+        let mut line = "stats".to_string();
+        line.push_str(&" ".repeat(MAX_LINE - line.len() - 1));
+        line.push('\n');
+        let reply = client.send(line.as_bytes()).await.expect("a reply");
+        assert!(reply.starts_with("ok "), "{reply}");
+    }
+
+    #[tokio::test]
+    async fn a_stale_socket_is_replaced_and_a_live_one_kept() {
+        let path = socket_path("stale");
+        let _ = fs::remove_file(&path);
+        drop(std::os::unix::net::UnixListener::bind(&path).expect("bindable"));
+        let listener = bind(&path).expect("rebound").expect("not in use");
+        assert!(bind(&path).expect("checked").is_none());
+        drop(listener);
+        fs::remove_file(&path).expect("removable");
+    }
+}
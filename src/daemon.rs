@@ -0,0 +1,103 @@
+// --daemonize for systems without a service manager: the classic double fork
+// and setsid, with stdio pointed at --log-file. It has to happen before the
+// tokio runtime starts, since only the forking thread survives a fork.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use eyre::{Result, WrapErr};
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use nix::unistd::{dup2, fork, setsid, ForkResult};
+
+use crate::cli::RunArgs;
+
+const DEV_NULL: &str = "/dev/null";
+
+/// Locked pidfile, removed again on a graceful exit.
+pub struct Pidfile {
+    path: PathBuf,
+    file: Flock<File>,
+}
+
+impl Pidfile {
+    fn lock(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .wrap_err_with(|| format!("failed to open pidfile {}", path.display()))?;
+        match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Ok(file) => Ok(Self {
+                path: path.to_path_buf(),
+                file,
+            }),
+            Err((mut file, Errno::EWOULDBLOCK)) => {
+                let mut pid = String::new();
+                file.read_to_string(&mut pid)?;
+                eyre::bail!(
+                    "beatble is already running with pidfile {} (pid {})",
+                    path.display(),
+                    pid.trim()
+                );
+            }
+            Err((_, e)) => Err(e).wrap_err_with(|| format!("failed to lock {}", path.display())),
+        }
+    }
+
+    fn write_pid(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        write!(self.file, "{}", process::id())?;
+        Ok(())
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Detaches from the terminal if --daemonize was given. Only the daemon
+/// returns; errors are reported before forking where possible.
+pub fn start(args: &RunArgs) -> Result<Option<Pidfile>> {
+    if !args.daemonize {
+        return Ok(None);
+    }
+    // the lock is inherited through the forks
+    let mut pidfile = args.pidfile.as_deref().map(Pidfile::lock).transpose()?;
+    // the log is written by the logging layer, rotation included; stdout and
+    // stderr catch the rest, e.g. a panic, in whatever the file is by then
+    let log_file = match &args.log_file {
+        Some(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("failed to open log file {}", path.display()))?,
+        None => OpenOptions::new().write(true).open(DEV_NULL)?,
+    };
+    let dev_null = File::open(DEV_NULL)?;
+
+    // safe as long as no other thread exists yet
+    if let ForkResult::Parent { .. } = unsafe { fork() }? {
+        process::exit(0);
+    }
+    setsid()?;
+    // the session leader exits, so the daemon can never reacquire a terminal
+    if let ForkResult::Parent { .. } = unsafe { fork() }? {
+        process::exit(0);
+    }
+
+    dup2(dev_null.as_raw_fd(), 0)?;
+    dup2(log_file.as_raw_fd(), 1)?;
+    dup2(log_file.as_raw_fd(), 2)?;
+    if let Some(pidfile) = &mut pidfile {
+        pidfile.write_pid()?;
+    }
+    Ok(pidfile)
+}
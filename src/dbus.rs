@@ -0,0 +1,266 @@
+// `--dbus session|system`: the dev.watiko.Beatble1 service, so desktop tools
+// and scripts can watch and steer a running session without signals. Every
+// method goes through Control::apply like the signal handlers do.
+//
+//   busctl --user call dev.watiko.Beatble1 /dev/watiko/Beatble1 dev.watiko.Beatble1 Pause
+//   busctl --user get-property dev.watiko.Beatble1 /dev/watiko/Beatble1 dev.watiko.Beatble1 ConnectionState
+//
+// Independently of --dbus, BlueZ's Device1.Disconnected signal on the system
+// bus gives the reason of the latest disconnect, where BlueZ is new enough
+// (5.67) to send it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use beatble::ble::NotifyContext;
+use beatble::control::{check_interval, Command};
+use beatble::emulation::Emulation;
+use beatble::stats::Stats;
+use eyre::{Result, WrapErr};
+use futures::StreamExt;
+use tokio::time::{interval, Duration};
+use tracing::{debug, info, warn};
+use zbus::message::Type;
+use zbus::{connection, fdo, interface, Connection, MatchRule, MessageStream};
+
+const NAME: &str = "dev.watiko.Beatble1";
+const PATH: &str = "/dev/watiko/Beatble1";
+// subscriptions come and go without a Control change
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// an interval of 1 ms, the shortest --sleep-duration
+const MAX_RATE: f64 = 1000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bus {
+    Session,
+    System,
+}
+
+impl FromStr for Bus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "session" => Ok(Bus::Session),
+            "system" => Ok(Bus::System),
+            _ => Err(format!("unknown bus: {s} (expected session or system)")),
+        }
+    }
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bus::Session => write!(f, "session"),
+            Bus::System => write!(f, "system"),
+        }
+    }
+}
+
+struct Beatble {
+    context: NotifyContext,
+    emulation: Emulation,
+}
+
+#[interface(name = "dev.watiko.Beatble1")]
+impl Beatble {
+    /// "advertising", "notifying" or "paused"
+    #[zbus(property)]
+    fn connection_state(&self) -> String {
+        connection_state(&self.context).to_string()
+    }
+
+    /// the emulated controller; there is only one profile per emulation
+    #[zbus(property)]
+    fn active_profile(&self) -> String {
+        self.emulation.to_string()
+    }
+
+    /// notifications per second
+    #[zbus(property)]
+    fn notification_rate(&self) -> f64 {
+        rate(&self.context)
+    }
+
+    fn pause(&self) {
+        self.context.control.apply(Command::Pause);
+    }
+
+    fn resume(&self) {
+        self.context.control.apply(Command::Resume);
+    }
+
+    fn set_rate(&self, rate: f64) -> fdo::Result<()> {
+        if !(rate > 0.0 && rate <= MAX_RATE) {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "rate must be above 0 and at most {MAX_RATE}, got {rate}"
+            )));
+        }
+        let interval = Duration::from_secs_f64(1.0 / rate);
+        if let Some(why) = check_interval(interval, false)
+            .map_err(|e| fdo::Error::InvalidArgs(format!("rate {rate}: {e}")))?
+        {
+            warn!("rate {rate}: {why}");
+        }
+        self.context.control.apply(Command::SetInterval(interval));
+        Ok(())
+    }
+
+    fn switch_profile(&self, name: &str) -> fdo::Result<()> {
+        if name == self.emulation.to_string() {
+            return Ok(());
+        }
+        Err(fdo::Error::NotSupported(format!(
+            "the emulation can't change while advertising; restart with --emulate {name}"
+        )))
+    }
+
+    fn get_stats(&self) -> HashMap<String, u64> {
+        let stats = self.context.stats.snapshot();
+        let frame_interval = self.context.latency.frame_interval.summary();
+        let data_age = self.context.latency.data_age.summary();
+        [
+            ("subscribers", stats.subscribers),
+            ("subscriptions", stats.subscriptions),
+            ("unsubscriptions", stats.unsubscriptions),
+            ("connections", stats.connections),
+            ("disconnections", stats.disconnections),
+            ("readvertisements", stats.readvertisements),
+            ("bluez_losses", stats.bluez_losses),
+            ("pairings", stats.pairings),
+            ("failed_pairings", stats.failed_pairings),
+            ("pings", stats.pings),
+            ("throttled_pings", stats.throttled_pings),
+            ("connected_ms", stats.connected_ms),
+            ("last_connection_ms", stats.last_connection_ms),
+            ("sent_frames", stats.sent_frames),
+            ("repeated_frames", stats.repeated_frames),
+            ("congested_frames", stats.congested_frames),
+            ("max_congestion_streak", stats.max_congestion_streak),
+            ("notifier_stalls", stats.stalls),
+            ("frame_interval_p50_us", frame_interval.p50),
+            ("frame_interval_p99_us", frame_interval.p99),
+            ("data_age_p50_us", data_age.p50),
+            ("data_age_p99_us", data_age.p99),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+    }
+}
+
+fn connection_state(context: &NotifyContext) -> &'static str {
+    match (
+        context.stats.subscribers.load(Ordering::Relaxed),
+        context.control.is_paused(),
+    ) {
+        (0, _) => "advertising",
+        (_, false) => "notifying",
+        (_, true) => "paused",
+    }
+}
+
+fn rate(context: &NotifyContext) -> f64 {
+    let interval = context.control.interval();
+    // an interval of 0 is paced by the link, at no fixed rate
+    if interval.is_zero() {
+        return 0.0;
+    }
+    1.0 / interval.as_secs_f64()
+}
+
+/// Claims the bus name right away, so a second instance fails at startup.
+pub async fn spawn(bus: Bus, context: NotifyContext, emulation: Emulation) -> Result<()> {
+    let builder = match bus {
+        Bus::Session => connection::Builder::session(),
+        Bus::System => connection::Builder::system(),
+    }?;
+    let beatble = Beatble {
+        context: context.clone(),
+        emulation,
+    };
+    let connection = builder
+        .name(NAME)?
+        .serve_at(PATH, beatble)?
+        .build()
+        .await
+        .wrap_err_with(|| format!("failed to publish {NAME} on the {bus} bus"))?;
+    info!("Published {NAME} on the {bus} bus");
+
+    tokio::spawn(async move {
+        if let Err(e) = emit_changes(&connection, &context).await {
+            warn!("stopped emitting D-Bus property changes: {e}");
+        }
+        // methods and properties keep working for as long as the connection does
+        std::future::pending::<()>().await;
+    });
+    Ok(())
+}
+
+/// PropertiesChanged for every state transition
+async fn emit_changes(connection: &Connection, context: &NotifyContext) -> zbus::Result<()> {
+    let interface = connection
+        .object_server()
+        .interface::<_, Beatble>(PATH)
+        .await?;
+    let mut changes = context.control.subscribe();
+    let mut ticker = interval(POLL_INTERVAL);
+    let mut last_state = connection_state(context);
+    let mut last_rate = rate(context);
+    loop {
+        tokio::select! {
+            _ = changes.changed() => {}
+            _ = ticker.tick() => {}
+        }
+        let beatble = interface.get().await;
+        let signal_context = interface.signal_context();
+        let state = connection_state(context);
+        if state != last_state {
+            beatble.connection_state_changed(signal_context).await?;
+            last_state = state;
+        }
+        let rate = rate(context);
+        if rate != last_rate {
+            beatble.notification_rate_changed(signal_context).await?;
+            last_rate = rate;
+        }
+    }
+}
+
+/// Keeps the reason of BlueZ's latest Disconnected signal in stats, for as
+/// long as the system bus is there.
+pub fn spawn_disconnect_reasons(stats: Arc<Stats>) {
+    tokio::spawn(async move {
+        if let Err(e) = watch_disconnect_reasons(&stats).await {
+            debug!("not watching BlueZ for disconnect reasons: {e}");
+        }
+    });
+}
+
+async fn watch_disconnect_reasons(stats: &Stats) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender("org.bluez")?
+        .interface("org.bluez.Device1")?
+        .member("Disconnected")?
+        .build();
+    let mut signals = MessageStream::for_match_rule(rule, &connection, None).await?;
+    while let Some(signal) = signals.next().await {
+        let signal = signal?;
+        // e.g. org.bluez.Reason.Remote, "Connection terminated by remote user"
+        let (name, message): (String, String) = signal.body().deserialize()?;
+        let device = signal.header().path().map(ToString::to_string);
+        let reason = format!("{message} ({name})");
+        info!(
+            device,
+            reason = name,
+            "BlueZ reports a disconnect: {message}"
+        );
+        stats.set_disconnect_reason(reason);
+    }
+    Ok(())
+}
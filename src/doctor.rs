@@ -0,0 +1,516 @@
+// `beatble doctor`: checks the environment a session depends on and prints a
+// hint for everything that is off. Each probe returns its checks instead of
+// printing, so they can be reused elsewhere.
+
+#[cfg(feature = "ble")]
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use beatble::input::{
+    device_info, evdev_siblings, is_grabbed, list_devices, InputError, OpenError,
+};
+#[cfg(feature = "ble")]
+use bluster::gatt::service::Service;
+#[cfg(feature = "ble")]
+use bluster::{Peripheral, SdpShortUuid};
+use eyre::Result;
+use tokio::time::{Duration, Instant};
+
+const TIMER_SAMPLES: u32 = 50;
+const TIMER_SLEEP: Duration = Duration::from_millis(1);
+// the default 4 ms interval can't absorb much more than this
+const TIMER_OVERSHOOT_LIMIT: Duration = Duration::from_micros(1500);
+// what the peripheral probe registers and advertises, taken back right away;
+// a service of its own, so it can't be mistaken for a controller
+#[cfg(feature = "ble")]
+const PROBE_SERVICE_UUID: u16 = 0xFFEF;
+#[cfg(feature = "ble")]
+const PROBE_NAME: &str = "beatble doctor";
+#[cfg(feature = "ble")]
+const BLUEZ_POLICY_HINT: &str =
+    "run beatble as root, or allow this user to talk to org.bluez in /etc/dbus-1/system.d/bluetooth.conf";
+
+#[cfg(feature = "ble")]
+struct ProbeUuid;
+#[cfg(feature = "ble")]
+impl SdpShortUuid<u16> for ProbeUuid {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    /// a session won't work until this is fixed
+    Fail,
+}
+
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    pub hint: Option<&'static str>,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(name, Status::Pass, detail, None)
+    }
+
+    fn new(
+        name: impl Into<String>,
+        status: Status,
+        detail: impl Into<String>,
+        hint: Option<&'static str>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+            hint,
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.status {
+            Status::Pass => " ok ",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "[{status}] {}: {}", self.name, self.detail)?;
+        if let (Some(hint), false) = (self.hint, self.status == Status::Pass) {
+            write!(f, "\n       {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(device: Option<String>) -> Result<()> {
+    let mut checks = Vec::new();
+    let devices = match device {
+        Some(device) => vec![device],
+        None => list_devices().unwrap_or_default(),
+    };
+    if devices.is_empty() {
+        checks.push(Check::new(
+            "input device",
+            Status::Fail,
+            "no joystick device found",
+            Some("plug in the controller and check that the joydev module is loaded"),
+        ));
+    }
+    for device in &devices {
+        checks.push(probe_device(device));
+        checks.extend(probe_evdev(device));
+    }
+    checks.push(probe_bluetoothd());
+    checks.extend(probe_rfkill());
+    #[cfg(feature = "ble")]
+    checks.push(probe_adapter().await);
+    #[cfg(feature = "ble")]
+    checks.extend(probe_peripheral().await);
+    checks.push(probe_atomics());
+    checks.push(probe_timer().await);
+
+    for check in &checks {
+        println!("{check}");
+    }
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed > 0 {
+        eyre::bail!("{failed} critical check(s) failed");
+    }
+    Ok(())
+}
+
+pub fn probe_device(device: &str) -> Check {
+    let name = format!("input device {device}");
+    match device_info(device) {
+        Ok(info) => Check::pass(name, info.name),
+        Err(e) => {
+            let hint = match &e {
+                InputError::Open {
+                    source: OpenError::PermissionDenied(_),
+                    ..
+                } => "add yourself to the input group or install a rule with `beatble setup-udev`",
+                _ => "check the path with `beatble list`",
+            };
+            let e = eyre::Report::new(e);
+            Check::new(name, Status::Fail, format!("{e:#}"), Some(hint))
+        }
+    }
+}
+
+/// a grabbed evdev node starves the joystick node of events
+pub fn probe_evdev(device: &str) -> Vec<Check> {
+    let Ok(siblings) = evdev_siblings(device) else {
+        return Vec::new();
+    };
+    siblings
+        .into_iter()
+        .map(|evdev| {
+            let name = format!("evdev grab {evdev}");
+            match is_grabbed(&evdev) {
+                Ok(false) => Check::pass(name, "not grabbed"),
+                Ok(true) => Check::new(
+                    name,
+                    Status::Fail,
+                    "grabbed by another process",
+                    Some("stop the program holding the grab, e.g. a remapper or a game"),
+                ),
+                Err(e) => Check::new(name, Status::Warn, format!("not checked: {e:#}"), None),
+            }
+        })
+        .collect()
+}
+
+pub fn probe_bluetoothd() -> Check {
+    bluetoothd_in(Path::new("/proc"))
+}
+
+fn bluetoothd_in(proc: &Path) -> Check {
+    let running = fs::read_dir(proc).into_iter().flatten().any(|entry| {
+        entry.is_ok_and(|entry| {
+            fs::read_to_string(entry.path().join("comm"))
+                .is_ok_and(|comm| comm.trim() == "bluetoothd")
+        })
+    });
+    if running {
+        Check::pass("bluetoothd", "running")
+    } else {
+        Check::new(
+            "bluetoothd",
+            Status::Fail,
+            "not running",
+            Some("sudo systemctl enable --now bluetooth"),
+        )
+    }
+}
+
+pub fn probe_rfkill() -> Vec<Check> {
+    rfkill_in(Path::new("/sys/class/rfkill"))
+}
+
+fn rfkill_in(class: &Path) -> Vec<Check> {
+    let Ok(entries) = fs::read_dir(class) else {
+        return Vec::new();
+    };
+    let read = |path: &Path, attr: &str| {
+        fs::read_to_string(path.join(attr))
+            .map(|value| value.trim().to_string())
+            .unwrap_or_default()
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| read(path, "type") == "bluetooth")
+        .map(|path| {
+            let name = format!("rfkill {}", read(&path, "name"));
+            match (read(&path, "hard") == "1", read(&path, "soft") == "1") {
+                (true, _) => Check::new(
+                    name,
+                    Status::Fail,
+                    "hard blocked",
+                    Some("turn on the bluetooth switch"),
+                ),
+                (false, true) => Check::new(
+                    name,
+                    Status::Fail,
+                    "soft blocked",
+                    Some("sudo rfkill unblock bluetooth"),
+                ),
+                (false, false) => Check::pass(name, "unblocked"),
+            }
+        })
+        .collect()
+}
+
+/// the same D-Bus connection and adapter lookup a session starts with
+#[cfg(feature = "ble")]
+pub async fn probe_adapter() -> Check {
+    let peripheral = match Peripheral::new().await {
+        Ok(peripheral) => peripheral,
+        Err(e) => {
+            return Check::new(
+                "bluetooth adapter",
+                Status::Fail,
+                format!("{e:?}"),
+                Some("check that an adapter is present and this user may talk to org.bluez on the system bus"),
+            )
+        }
+    };
+    match peripheral.is_powered().await {
+        Ok(true) => Check::pass("bluetooth adapter", "powered"),
+        Ok(false) => Check::new(
+            "bluetooth adapter",
+            Status::Fail,
+            "not powered",
+            Some("bluetoothctl power on"),
+        ),
+        Err(e) => Check::new("bluetooth adapter", Status::Fail, format!("{e:?}"), None),
+    }
+}
+
+/// Whether this user may register a GATT application and the adapter can
+/// advertise, which BlueZ only tells by trying: registers both the way a
+/// session does and takes them back. Nothing without a powered adapter, which
+/// probe_adapter reports.
+#[cfg(feature = "ble")]
+pub async fn probe_peripheral() -> Vec<Check> {
+    let Ok(peripheral) = Peripheral::new().await else {
+        return Vec::new();
+    };
+    if !matches!(peripheral.is_powered().await, Ok(true)) {
+        return Vec::new();
+    }
+    let service = Service::new(
+        ProbeUuid::from_sdp_short_uuid(PROBE_SERVICE_UUID),
+        true,
+        HashSet::new(),
+    );
+    let registered = match peripheral.add_service(&service) {
+        Ok(()) => peripheral.register_gatt().await,
+        Err(e) => Err(e),
+    };
+    if registered.is_ok() {
+        let _ = peripheral.unregister_gatt().await;
+    }
+    let advertised = peripheral.start_advertising(PROBE_NAME, &[]).await;
+    if advertised.is_ok() {
+        let _ = peripheral.stop_advertising().await;
+    }
+    vec![
+        gatt_check(registered.map_err(|e| format!("{e:?}"))),
+        peripheral_role_check(advertised.map_err(|e| format!("{e:?}"))),
+    ]
+}
+
+#[cfg(feature = "ble")]
+fn gatt_check(registered: Result<(), String>) -> Check {
+    match registered {
+        Ok(()) => Check::pass("GATT registration", "allowed"),
+        Err(e) if e.contains("AccessDenied") => Check::new(
+            "GATT registration",
+            Status::Fail,
+            "denied by the D-Bus policy",
+            Some(BLUEZ_POLICY_HINT),
+        ),
+        Err(e) => Check::new(
+            "GATT registration",
+            Status::Fail,
+            e,
+            Some("journalctl -u bluetooth tells why BlueZ refused the application"),
+        ),
+    }
+}
+
+#[cfg(feature = "ble")]
+fn peripheral_role_check(advertised: Result<(), String>) -> Check {
+    match advertised {
+        Ok(()) => Check::pass("peripheral role", "the adapter advertises"),
+        Err(e) if e.contains("AccessDenied") => Check::new(
+            "peripheral role",
+            Status::Fail,
+            "advertising denied by the D-Bus policy",
+            Some(BLUEZ_POLICY_HINT),
+        ),
+        // BlueZ's answer once every advertising instance is in use
+        Err(e) if e.contains("Maximum advertisements reached") => Check::new(
+            "peripheral role",
+            Status::Warn,
+            "every advertising slot of the adapter is taken",
+            Some("stop the other beatble session or advertiser and run the doctor again"),
+        ),
+        Err(e) => Check::new(
+            "peripheral role",
+            Status::Fail,
+            e,
+            Some("the adapter must support Bluetooth LE advertising, with BlueZ 5.43 or later"),
+        ),
+    }
+}
+
+/// SharedKeyInput relies on native atomics to never block the reader
+pub fn probe_atomics() -> Check {
+    let native = cfg!(target_has_atomic = "32") && cfg!(target_has_atomic = "64");
+    let detail = format!(
+        "32 and 64 bit atomics {} on {}",
+        if native { "native" } else { "emulated" },
+        std::env::consts::ARCH
+    );
+    if native {
+        Check::pass("atomics", detail)
+    } else {
+        Check::new("atomics", Status::Warn, detail, None)
+    }
+}
+
+/// how late the notifier's sleep wakes up on this machine
+pub async fn probe_timer() -> Check {
+    let mut overshoot = Duration::ZERO;
+    for _ in 0..TIMER_SAMPLES {
+        let started_at = Instant::now();
+        tokio::time::sleep(TIMER_SLEEP).await;
+        overshoot += started_at.elapsed().saturating_sub(TIMER_SLEEP);
+    }
+    timer_check(overshoot / TIMER_SAMPLES)
+}
+
+fn timer_check(overshoot: Duration) -> Check {
+    let detail = format!("a {TIMER_SLEEP:?} sleep wakes up {overshoot:?} late on average");
+    if overshoot <= TIMER_OVERSHOOT_LIMIT {
+        Check::pass("timer", detail)
+    } else {
+        Check::new(
+            "timer",
+            Status::Warn,
+            detail,
+            Some("--busy-poll keeps the interval precise at the cost of a core"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::*;
+
+    /// an empty directory of its own for each test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("beatble-doctor-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("a temp dir");
+        dir
+    }
+
+    fn write(path: PathBuf, contents: &str) {
+        fs::create_dir_all(path.parent().expect("a parent")).expect("a temp dir");
+        fs::write(path, contents).expect("writable");
+    }
+
+    #[test]
+    fn hints_only_show_for_failed_and_warned_checks() {
+        let hint = Some("do this");
+        assert_eq!(
+            Check::new("timer", Status::Pass, "fine", hint).to_string(),
+            "[ ok ] timer: fine"
+        );
+        assert_eq!(
+            Check::new("timer", Status::Warn, "late", hint).to_string(),
+            "[warn] timer: late\n       do this"
+        );
+        assert_eq!(
+            Check::new("rfkill", Status::Fail, "blocked", None).to_string(),
+            "[FAIL] rfkill: blocked"
+        );
+    }
+
+    #[test]
+    fn a_missing_device_fails_with_a_hint() {
+        let check = probe_device("/nonexistent/js0");
+        assert_eq!(check.status, Status::Fail);
+        assert_eq!(check.name, "input device /nonexistent/js0");
+        assert_eq!(check.hint, Some("check the path with `beatble list`"));
+        assert!(probe_evdev("/nonexistent/js0").is_empty());
+    }
+
+    #[test]
+    fn bluetoothd_is_found_by_its_comm() {
+        let proc = scratch_dir("proc");
+        write(proc.join("1/comm"), "systemd\n");
+        assert_eq!(bluetoothd_in(&proc).status, Status::Fail);
+        write(proc.join("412/comm"), "bluetoothd\n");
+        assert_eq!(bluetoothd_in(&proc).status, Status::Pass);
+        fs::remove_dir_all(proc).expect("removable");
+    }
+
+    #[test]
+    fn rfkill_blocks_fail_per_bluetooth_switch() {
+        let class = scratch_dir("rfkill");
+        let switch = |name: &str, kind: &str, hard: &str, soft: &str| {
+            let dir = class.join(name);
+            write(dir.join("name"), &format!("{name}\n"));
+            write(dir.join("type"), &format!("{kind}\n"));
+            write(dir.join("hard"), &format!("{hard}\n"));
+            write(dir.join("soft"), &format!("{soft}\n"));
+        };
+        switch("hci0", "bluetooth", "0", "0");
+        switch("hci1", "bluetooth", "0", "1");
+        switch("hci2", "bluetooth", "1", "1");
+        switch("phy0", "wlan", "0", "1");
+
+        let mut checks: Vec<(String, Status, String)> = rfkill_in(&class)
+            .into_iter()
+            .map(|check| (check.name, check.status, check.detail))
+            .collect();
+        checks.sort_by(|a, b| a.0.cmp(&b.0));
+        let expected = [
+            ("rfkill hci0", Status::Pass, "unblocked"),
+            ("rfkill hci1", Status::Fail, "soft blocked"),
+            ("rfkill hci2", Status::Fail, "hard blocked"),
+        ];
+        assert_eq!(checks.len(), expected.len());
+        for ((name, status, detail), expected) in checks.iter().zip(expected) {
+            assert_eq!((name.as_str(), *status, detail.as_str()), expected);
+        }
+        assert!(rfkill_in(&class.join("missing")).is_empty());
+        fs::remove_dir_all(class).expect("removable");
+    }
+
+    #[cfg(feature = "ble")]
+    #[test]
+    fn a_refused_registration_names_the_d_bus_policy() {
+        assert_eq!(gatt_check(Ok(())).status, Status::Pass);
+        let denied = gatt_check(Err(
+            "Error { name: \"org.freedesktop.DBus.Error.AccessDenied\" }".to_string(),
+        ));
+        assert_eq!(denied.status, Status::Fail);
+        assert_eq!(denied.hint, Some(BLUEZ_POLICY_HINT));
+        let failed = gatt_check(Err("org.bluez.Error.Failed".to_string()));
+        assert_eq!(failed.status, Status::Fail);
+        assert_eq!(failed.detail, "org.bluez.Error.Failed");
+    }
+
+    #[cfg(feature = "ble")]
+    #[test]
+    fn an_adapter_that_cant_advertise_fails_the_peripheral_role() {
+        assert_eq!(peripheral_role_check(Ok(())).status, Status::Pass);
+        let taken = peripheral_role_check(Err(
+            "org.bluez.Error.NotPermitted: Maximum advertisements reached".to_string(),
+        ));
+        assert_eq!(taken.status, Status::Warn);
+        let unsupported = peripheral_role_check(Err(
+            "org.freedesktop.DBus.Error.UnknownMethod: No such interface \"org.bluez.LEAdvertisingManager1\""
+                .to_string(),
+        ));
+        assert_eq!(unsupported.status, Status::Fail);
+        assert!(unsupported
+            .hint
+            .is_some_and(|hint| hint.contains("LE advertising")));
+    }
+
+    #[test]
+    fn a_late_timer_warns() {
+        assert_eq!(timer_check(TIMER_OVERSHOOT_LIMIT).status, Status::Pass);
+        let late = timer_check(TIMER_OVERSHOOT_LIMIT + Duration::from_micros(1));
+        assert_eq!(late.status, Status::Warn);
+        assert!(late.hint.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_paused_clock_never_wakes_late() {
+        assert_eq!(probe_timer().await.status, Status::Pass);
+    }
+
+    #[test]
+    fn atomics_are_native_here() {
+        assert_eq!(probe_atomics().status, Status::Pass);
+    }
+}
@@ -0,0 +1,80 @@
+// --dry-run: the notifier runs as usual, but its frames are printed to stdout
+// instead of being sent to a subscriber.
+
+use std::io::{self, Write};
+
+use beatble::ble::{spawn_local_notifier, NotifyConfig, NotifyContext};
+use beatble::emulation::Emulation;
+use beatble_protocol::payload::{decode_scratch_velocity, sdvx, DecodeError, Frame, ScratchMode};
+use beatble_protocol::KeyInput;
+use eyre::Result;
+use futures::StreamExt;
+
+pub async fn run(
+    context: NotifyContext,
+    notify_config: NotifyConfig,
+    changes_only: bool,
+) -> Result<()> {
+    let mut frames = spawn_local_notifier(context, notify_config);
+    let mut last = None;
+    loop {
+        let bytes = tokio::select! {
+            frame = frames.next() => match frame {
+                Some(bytes) => bytes,
+                None => break,
+            },
+            _ = tokio::signal::ctrl_c() => break,
+        };
+
+        let decoded = decode(&bytes, notify_config);
+        if changes_only {
+            let key_input = decoded.as_ref().ok().map(|&(key_input, _)| key_input);
+            if key_input.is_some() && key_input == last {
+                continue;
+            }
+            last = key_input;
+        }
+
+        let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+        writeln!(io::stdout(), "{hex}  {}", describe(&bytes, notify_config))?;
+    }
+    Ok(())
+}
+
+/// the counter and key input of a frame, or why it doesn't decode
+pub fn describe(bytes: &[u8], notify_config: NotifyConfig) -> String {
+    match decode(bytes, notify_config) {
+        Ok((key_input, counter)) => format!(
+            "{counter:3} {}",
+            summarize(key_input, notify_config.scratch_mode)
+        ),
+        Err(e) => format!("  ? {e}"),
+    }
+}
+
+fn decode(bytes: &[u8], notify_config: NotifyConfig) -> Result<(KeyInput, u8), DecodeError> {
+    match notify_config.emulation {
+        Emulation::Iidx => {
+            Frame::decode(bytes, notify_config.layout).map(|frame| (frame.first, frame.counter))
+        }
+        Emulation::Sdvx => sdvx::Frame::decode(bytes).map(|frame| (frame.key_input, frame.counter)),
+    }
+}
+
+fn summarize(key_input: KeyInput, scratch_mode: ScratchMode) -> String {
+    let mut normal_button = String::new();
+    let mut option_button = String::new();
+    // writing into a String can't fail
+    let _ = bitflags::parser::to_writer(&key_input.normal_button, &mut normal_button);
+    let _ = bitflags::parser::to_writer(&key_input.option_button, &mut option_button);
+    let scratch = match scratch_mode {
+        ScratchMode::Position => format!(
+            "scratch={:#04x} analog={:#04x}",
+            key_input.scratch, key_input.analog
+        ),
+        ScratchMode::Velocity => {
+            format!("velocity={:+}", decode_scratch_velocity(key_input.scratch))
+        }
+    };
+    format!("{scratch} normal=[{normal_button}] option=[{option_button}]")
+}
@@ -0,0 +1,250 @@
+// Payload dump file, one JSON object per line:
+//
+//   {"beatble":"0.1.0","payload_format":"v1",...}     header
+//   {"t":12345678,"counter":4,"bytes":"00..."}         one per sent frame
+//
+// t is nanoseconds on the process clock (see clock::now). The header's clock
+// is the same clock when the file was created and started_at the matching
+// wall-clock time in unix milliseconds.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use beatble_protocol::payload::Payload;
+use eyre::Result;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+use crate::ble::NotifyConfig;
+use crate::clock;
+
+// about 8 seconds of frames at the default rate
+const CHANNEL_CAPACITY: usize = 1024;
+
+struct Record {
+    at: u64,
+    counter: u8,
+    payload: Payload,
+}
+
+/// Why a dump file couldn't be started.
+#[derive(Debug, Error)]
+pub enum DumpError {
+    #[error("failed to create {}", path.display())]
+    Create {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Writes every sent frame to a file from a separate thread.
+pub struct PayloadDump {
+    sender: SyncSender<Record>,
+    dropped: AtomicU64,
+}
+
+impl PayloadDump {
+    pub fn create(path: &Path, notify_config: &NotifyConfig) -> Result<Self, DumpError> {
+        let file = File::create(path).map_err(|source| DumpError::Create {
+            path: path.to_owned(),
+            source,
+        })?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "{}", header(notify_config))?;
+        writer.flush()?;
+
+        let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+        let path = path.to_owned();
+        thread::spawn(move || {
+            if let Err(e) = write_records(writer, receiver) {
+                error!("payload dump to {} failed: {e}", path.display());
+            }
+        });
+
+        Ok(Self {
+            sender,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// never blocks; frames are dropped and counted when the writer falls behind
+    #[inline]
+    pub fn record(&self, counter: u8, payload: Payload) {
+        let record = Record {
+            at: clock::now(),
+            counter,
+            payload,
+        };
+        match self.sender.try_send(record) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn log_summary(&self) {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("payload dump dropped {} frames", dropped);
+        } else {
+            info!("payload dump complete");
+        }
+    }
+}
+
+fn header(notify_config: &NotifyConfig) -> String {
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!(
+        concat!(
+            r#"{{"beatble":"{}","payload_format":"{}","scratch_hires":{},"single_report":{},"#,
+            r#""emulation":"{}","interval_us":{},"counter_start":{},"started_at":{},"clock":{}}}"#
+        ),
+        env!("VERSION"),
+        notify_config.layout.format,
+        notify_config.layout.scratch_hires,
+        notify_config.layout.single_report,
+        notify_config.emulation,
+        notify_config.interval.as_micros(),
+        notify_config.counter_start,
+        started_at,
+        clock::now(),
+    )
+}
+
+fn write_records(mut writer: impl Write, receiver: Receiver<Record>) -> Result<()> {
+    let mut line = String::new();
+    loop {
+        let record = match receiver.try_recv() {
+            Ok(record) => record,
+            Err(TryRecvError::Empty) => {
+                // flush only when idle so bursts are written in one go
+                writer.flush()?;
+                match receiver.recv() {
+                    Ok(record) => record,
+                    Err(_) => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+
+        line.clear();
+        write!(
+            line,
+            r#"{{"t":{},"counter":{},"bytes":""#,
+            record.at, record.counter
+        )?;
+        for byte in record.payload.as_bytes() {
+            write!(line, "{byte:02x}")?;
+        }
+        line.push_str("\"}");
+        writeln!(writer, "{line}")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use beatble_protocol::payload::{Frame, Layout, ScratchMode};
+    use beatble_protocol::KeyInput;
+    use serde_json::Value;
+    use tokio::time::Duration;
+
+    use super::*;
+    use crate::ble::{NotifyMode, RepeatSpacing};
+    use crate::emulation::Emulation;
+
+    fn config() -> NotifyConfig {
+        NotifyConfig {
+            interval: Duration::from_millis(8),
+            mode: NotifyMode::Periodic,
+            warmup_frames: 0,
+            counter_start: 7,
+            frame_repeat: 1,
+            repeat_spacing: RepeatSpacing::BackToBack,
+            busy_poll: false,
+            layout: Layout::default(),
+            emulation: Emulation::Iidx,
+            scratch_mode: ScratchMode::Position,
+            scratch_predict: None,
+        }
+    }
+
+    #[test]
+    fn a_file_that_cant_be_created_is_named() {
+        let path = Path::new("/nonexistent/beatble/dump.jsonl");
+        let error = PayloadDump::create(path, &config())
+            .err()
+            .expect("an error");
+        assert_eq!(
+            error.to_string(),
+            "failed to create /nonexistent/beatble/dump.jsonl"
+        );
+        assert!(matches!(error, DumpError::Create { ref source, .. }
+            if source.kind() == io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn header_has_the_notify_settings() {
+        let header: Value = serde_json::from_str(&header(&config())).expect("a JSON header");
+        assert_eq!(header["beatble"], env!("VERSION"));
+        assert_eq!(header["payload_format"], "v1");
+        assert_eq!(header["scratch_hires"], false);
+        assert_eq!(header["single_report"], false);
+        assert_eq!(header["emulation"], "iidx");
+        assert_eq!(header["interval_us"], 8000);
+        assert_eq!(header["counter_start"], 7);
+        assert!(header["started_at"].as_u64().is_some());
+        assert!(header["clock"].as_u64().is_some());
+    }
+
+    #[test]
+    fn records_round_trip_and_overflow_is_counted() {
+        // no writer thread yet, so the channel fills up
+        let (sender, receiver) = sync_channel(2);
+        let dump = PayloadDump {
+            sender,
+            dropped: AtomicU64::new(0),
+        };
+        let payloads: Vec<Payload> = (0..5u8)
+            .map(|counter| Frame::repeated(KeyInput::init(), counter * 2).encode(Layout::default()))
+            .collect();
+        for (counter, payload) in (0..).step_by(2).zip(&payloads) {
+            dump.record(counter, *payload);
+        }
+        assert_eq!(dump.dropped.load(Ordering::Relaxed), 3);
+        drop(dump);
+
+        let mut written = Vec::new();
+        write_records(&mut written, receiver).expect("written");
+        let lines: Vec<Value> = String::from_utf8(written)
+            .expect("UTF-8")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("a JSON record"))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        for (line, (counter, payload)) in lines.iter().zip((0..).step_by(2).zip(&payloads)) {
+            assert_eq!(line["counter"], counter);
+            assert!(line["t"].as_u64().is_some());
+            let hex: String = payload
+                .as_bytes()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            assert_eq!(line["bytes"], hex);
+        }
+    }
+}
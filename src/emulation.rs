@@ -0,0 +1,42 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Which controller the peripheral pretends to be.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Emulation {
+    /// beatmania IIDX Entry model
+    #[default]
+    Iidx,
+    /// SOUND VOLTEX Entry model
+    Sdvx,
+}
+
+impl Emulation {
+    pub fn advertising_name(self) -> &'static str {
+        match self {
+            Emulation::Iidx => "IIDX Entry model",
+            Emulation::Sdvx => "SDVX Entry model",
+        }
+    }
+}
+
+impl FromStr for Emulation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "iidx" => Ok(Emulation::Iidx),
+            "sdvx" => Ok(Emulation::Sdvx),
+            _ => Err(format!("unknown emulation: {s} (expected iidx or sdvx)")),
+        }
+    }
+}
+
+impl fmt::Display for Emulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Emulation::Iidx => write!(f, "iidx"),
+            Emulation::Sdvx => write!(f, "sdvx"),
+        }
+    }
+}
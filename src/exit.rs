@@ -0,0 +1,92 @@
+// Exit codes, so scripts wrapping beatble can tell failures apart:
+//
+//   1  anything not listed below
+//   2  the input device can't be opened or read
+//   3  bluetooth setup failed before advertising
+//   4  invalid flags, environment variables or config file
+//   5  the input device failed while running
+//   6  bluetooth failed while advertising
+//
+// Errors are classified by the ErrorKind they were wrapped with, if any.
+
+use std::process::ExitCode;
+
+use eyre::Report;
+
+/// What failed, attached to an error with `wrap_err`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ErrorKind {
+    #[error("input device error")]
+    InputDevice,
+    #[error("bluetooth setup failed")]
+    BleSetup,
+    #[error("invalid configuration")]
+    Config,
+    #[error("input device failed")]
+    InputRuntime,
+    #[error("bluetooth failed")]
+    BleRuntime,
+}
+
+impl ErrorKind {
+    pub fn code(self) -> u8 {
+        match self {
+            ErrorKind::InputDevice => 2,
+            ErrorKind::BleSetup => 3,
+            ErrorKind::Config => 4,
+            ErrorKind::InputRuntime => 5,
+            ErrorKind::BleRuntime => 6,
+        }
+    }
+
+    pub fn exit_code(self) -> ExitCode {
+        ExitCode::from(self.code())
+    }
+}
+
+/// the code of the ErrorKind the error was wrapped with, 1 without one
+pub fn code(error: &Report) -> u8 {
+    match error.downcast_ref::<ErrorKind>() {
+        Some(kind) => kind.code(),
+        None => 1,
+    }
+}
+
+pub fn exit_code(error: &Report) -> ExitCode {
+    ExitCode::from(code(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::{eyre, WrapErr};
+
+    use super::*;
+
+    #[test]
+    fn each_kind_has_the_documented_code() {
+        for (kind, expected) in [
+            (ErrorKind::InputDevice, 2),
+            (ErrorKind::BleSetup, 3),
+            (ErrorKind::Config, 4),
+            (ErrorKind::InputRuntime, 5),
+            (ErrorKind::BleRuntime, 6),
+        ] {
+            assert_eq!(kind.code(), expected, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn errors_are_classified_by_their_kind() {
+        let missing: Result<(), _> = Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+        let e = missing.wrap_err(ErrorKind::InputDevice).unwrap_err();
+        assert_eq!(code(&e), 2);
+
+        let invalid: Result<(), _> = Err(eyre!("invalid config /etc/beatble/config.toml"));
+        let e = invalid.wrap_err(ErrorKind::Config).unwrap_err();
+        assert_eq!(code(&e), 4);
+        // context added on the way up keeps the kind
+        assert_eq!(code(&e.wrap_err("failed to start")), 4);
+
+        assert_eq!(code(&eyre!("something else")), 1);
+    }
+}
@@ -0,0 +1,108 @@
+// Just enough HTTP/1.1 for the local endpoints: GET only, one request per
+// connection, the whole response built before it is written.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use eyre::{Result, WrapErr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+use tracing::debug;
+
+// a GET from curl or a scraper is a few hundred bytes
+const MAX_REQUEST: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    pub fn new(status: &'static str, content_type: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn ok(content_type: &'static str, body: String) -> Self {
+        Self::new("200 OK", content_type, body)
+    }
+
+    pub fn not_found() -> Self {
+        Self::new("404 Not Found", "text/plain", "not found\n".to_string())
+    }
+}
+
+/// Binds right away, so a taken address fails the session at startup.
+/// `handler` gets the request path and must not block.
+pub async fn serve(
+    address: SocketAddr,
+    handler: impl Fn(&str) -> Response + Send + Sync + 'static,
+) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .await
+        .wrap_err_with(|| format!("failed to listen on {address}"))?;
+    let handler = Arc::new(handler);
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    debug!("accept on {address} failed: {e}");
+                    continue;
+                }
+            };
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                match timeout(REQUEST_TIMEOUT, respond(stream, &*handler)).await {
+                    Ok(Err(e)) => debug!("request to {address} failed: {e}"),
+                    Err(_) => debug!("request to {address} timed out"),
+                    Ok(Ok(())) => {}
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn respond(mut stream: TcpStream, handler: &impl Fn(&str) -> Response) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request_line = request.split(|&byte| byte == b'\r').next().unwrap_or(&[]);
+    let request_line = String::from_utf8_lossy(request_line);
+    let mut parts = request_line.split(' ');
+    let response = match (parts.next(), parts.next()) {
+        // the query string doesn't select anything
+        (Some("GET"), Some(target)) => handler(target.split('?').next().unwrap_or(target)),
+        _ => Response::new(
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".to_string(),
+        ),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,242 @@
+// `beatble init`: a first-run walk through the pieces other subcommands
+// already provide, ending in a user config file and optionally a systemd user
+// unit. Every question can be skipped with an empty answer, and end of input
+// skips everything that is left, so the wizard never blocks a script.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use beatble::emulation::Emulation;
+use beatble::input::{create_input_handler, device_info, list_devices};
+use beatble_protocol::KeyInput;
+use eyre::{eyre, Result, WrapErr};
+use tokio::time::{interval, Duration, Instant};
+
+use crate::config;
+use crate::doctor::{self, Status};
+
+const TURNTABLE_TEST: Duration = Duration::from_secs(3);
+const TURNTABLE_SAMPLE: Duration = Duration::from_millis(5);
+// a sixteenth of a turn, anything less is noise or a nudge
+const TURNTABLE_MIN_TRAVEL: i64 = 4096;
+const UNIT_NAME: &str = "beatble.service";
+
+pub async fn run() -> Result<()> {
+    println!("This sets up beatble for the current user. Press Enter to skip any step.\n");
+
+    let device = choose_device()?;
+    if let Some(device) = &device {
+        test_turntable(device).await?;
+    }
+    let advertising_name = choose_advertising_name()?;
+    check_bluetooth().await?;
+
+    let path = config::user_config_path()
+        .ok_or_else(|| eyre!("neither XDG_CONFIG_HOME nor HOME is set"))?;
+    let written = write_config(&path, device, advertising_name)?;
+    if written && confirm("Install a systemd user unit?", false)? {
+        write_unit()?;
+    }
+    Ok(())
+}
+
+/// one trimmed line, or None at end of input
+fn ask(question: &str) -> Result<Option<String>> {
+    print!("{question} ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        println!();
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
+
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    let choices = if default { "[Y/n]" } else { "[y/N]" };
+    loop {
+        match ask(&format!("{question} {choices}"))?.as_deref() {
+            None | Some("") => return Ok(default),
+            Some("y" | "Y" | "yes") => return Ok(true),
+            Some("n" | "N" | "no") => return Ok(false),
+            Some(_) => println!("Please answer y or n."),
+        }
+    }
+}
+
+fn choose_device() -> Result<Option<String>> {
+    let devices = list_devices().unwrap_or_default();
+    if devices.is_empty() {
+        println!("No joystick device found; plug in the controller and run `beatble init` again,");
+        println!("or set the device in the config file later.\n");
+        return Ok(None);
+    }
+    println!("Joystick devices:");
+    for (i, device) in devices.iter().enumerate() {
+        match device_info(device) {
+            Ok(info) => println!("  {}) {device}  {info}", i + 1),
+            Err(e) => println!("  {}) {device}  ({e})", i + 1),
+        }
+    }
+    loop {
+        let Some(answer) = ask(&format!("Controller [1-{}]:", devices.len()))? else {
+            return Ok(None);
+        };
+        if answer.is_empty() {
+            println!();
+            return Ok(None);
+        }
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=devices.len()).contains(&n) => {
+                println!();
+                return Ok(Some(devices[n - 1].clone()));
+            }
+            _ => println!("Please enter a number between 1 and {}.", devices.len()),
+        }
+    }
+}
+
+/// Reports which way the scratch position moves for a clockwise turn.
+async fn test_turntable(device: &str) -> Result<()> {
+    if !confirm("Test the turntable direction?", true)? {
+        println!();
+        return Ok(());
+    }
+    let (key_input, _) =
+        match create_input_handler(device, Emulation::Iidx.into(), false, false, Arc::default()) {
+            Ok(handler) => handler,
+            Err(e) => {
+                println!("Can't read {device}: {:#}\n", eyre::Report::new(e));
+                return Ok(());
+            }
+        };
+    if ask("Press Enter, then turn the turntable clockwise for a few seconds.")?.is_none() {
+        return Ok(());
+    }
+
+    let position = |key_input: KeyInput| u16::from_be_bytes([key_input.scratch, key_input.analog]);
+    let mut last = position(key_input.load());
+    let mut travel = 0i64;
+    let mut ticker = interval(TURNTABLE_SAMPLE);
+    let started_at = Instant::now();
+    while started_at.elapsed() < TURNTABLE_TEST {
+        ticker.tick().await;
+        let current = position(key_input.load());
+        // the shorter way round, like the scratch travel counter
+        travel += i64::from(current.wrapping_sub(last) as i16);
+        last = current;
+    }
+    // the reader stops at its next event
+    key_input.claim();
+
+    match travel {
+        travel if travel.abs() < TURNTABLE_MIN_TRAVEL => {
+            println!("The turntable barely moved; check it with `beatble test {device}`.\n")
+        }
+        travel if travel > 0 => println!("Clockwise turns count up.\n"),
+        _ => println!(
+            "Clockwise turns count down. If the game shows the turntable reversed, use the \
+             controller's direction switch;\nbeatble has no setting to invert it yet.\n"
+        ),
+    }
+    Ok(())
+}
+
+fn choose_advertising_name() -> Result<Option<String>> {
+    let default = Emulation::Iidx.advertising_name();
+    let name = ask(&format!("Advertising name [{default}]:"))?.filter(|name| !name.is_empty());
+    println!();
+    Ok(name)
+}
+
+/// the bluetooth checks of `beatble doctor`
+async fn check_bluetooth() -> Result<()> {
+    if !confirm("Check the bluetooth setup?", true)? {
+        println!();
+        return Ok(());
+    }
+    let mut checks = vec![doctor::probe_bluetoothd()];
+    checks.extend(doctor::probe_rfkill());
+    #[cfg(feature = "ble")]
+    checks.push(doctor::probe_adapter().await);
+    #[cfg(feature = "ble")]
+    checks.extend(doctor::probe_peripheral().await);
+    for check in &checks {
+        println!("{check}");
+    }
+    if checks.iter().any(|check| check.status == Status::Fail) {
+        println!(
+            "Fix the failed checks before starting beatble; `beatble doctor` runs them again."
+        );
+    }
+    println!();
+    Ok(())
+}
+
+/// returns whether the config was written
+fn write_config(
+    path: &Path,
+    device: Option<String>,
+    advertising_name: Option<String>,
+) -> Result<bool> {
+    if device.is_none() && advertising_name.is_none() {
+        println!("Nothing to write, {} is left alone.", path.display());
+        return Ok(false);
+    }
+    let contents = config::generate(device, advertising_name)?;
+    println!("{}:\n{contents}", path.display());
+    let confirmed = if path.exists() {
+        confirm(
+            &format!("{} already exists. Overwrite it?", path.display()),
+            false,
+        )?
+    } else {
+        confirm("Write it?", true)?
+    };
+    if !confirmed {
+        return Ok(false);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+    }
+    fs::write(path, contents).wrap_err_with(|| format!("failed to write {}", path.display()))?;
+    println!("Wrote {}; `beatble` now starts with it.\n", path.display());
+    Ok(true)
+}
+
+fn write_unit() -> Result<()> {
+    let exe = std::env::current_exe().wrap_err("failed to locate the beatble binary")?;
+    let dir = config::user_config_path()
+        .and_then(|path| Some(path.parent()?.parent()?.join("systemd").join("user")))
+        .ok_or_else(|| eyre!("neither XDG_CONFIG_HOME nor HOME is set"))?;
+    let path = dir.join(UNIT_NAME);
+    if path.exists()
+        && !confirm(
+            &format!("{} already exists. Overwrite it?", path.display()),
+            false,
+        )?
+    {
+        return Ok(());
+    }
+    let unit = format!(
+        "[Unit]\n\
+         Description=beatble, generated by `beatble init`\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={} run\n\
+         ExecReload=/bin/kill -HUP $MAINPID\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        exe.display()
+    );
+    fs::create_dir_all(&dir).wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+    fs::write(&path, unit).wrap_err_with(|| format!("failed to write {}", path.display()))?;
+    println!("Wrote {}; start it with", path.display());
+    println!("  systemctl --user daemon-reload && systemctl --user enable --now beatble");
+    Ok(())
+}
@@ -1,6 +1,47 @@
-pub use self::ble::KeyInput;
-pub use self::gamepad::create_input_handler;
+#[cfg(feature = "input")]
+pub use self::error::InputError;
+#[cfg(feature = "input")]
+pub use self::gamepad::{
+    attach_input_handler, convert_scratch, create_input_handler, device_guid, device_id,
+    device_info, evdev_siblings, list_devices, select_device, update_key_input, InputHandler,
+    Replayer,
+};
+#[cfg(feature = "input")]
+pub use self::hotplug::Hotplug;
+pub use self::long_press::{LongPress, LongPressButton, LongPresses, DEFAULT_LONG_PRESS_AFTER};
+pub use self::mapping::{
+    ActiveLow, Backend, InputMapping, Keymap, Profile, PROFILES, SCRATCH_SENSITIVITY,
+};
+#[cfg(feature = "input")]
+pub use self::mirror::{Mirror, MIRROR_NAME};
+#[cfg(feature = "input")]
+pub use self::platform::linux::{is_grabbed, Event, EventDevice, OpenError, TimedEvent};
+#[cfg(feature = "input")]
+pub use self::relay::{attach_event_stream, open_relay_source, RelaySource};
+pub use self::scratch::{AntiWobble, Chord, Recenter, RecenterFilter, WobbleFilter};
+pub use self::sdl::{sdl_guid, SdlBinding, SdlControls, SdlMapping};
+pub use self::select::{DeviceId, DeviceSelector};
+pub use self::shared::{KeyInputDp, SharedKeyInput, Side};
+pub use self::tap::{add_tap, remove_tap, Tap};
 
-mod ble;
+#[cfg(feature = "input")]
+mod error;
+#[cfg(feature = "input")]
 mod gamepad;
+#[cfg(feature = "input")]
+mod hotplug;
+#[cfg(feature = "input")]
+mod lock;
+mod long_press;
+mod mapping;
+#[cfg(feature = "input")]
+mod mirror;
+#[cfg(feature = "input")]
 mod platform;
+#[cfg(feature = "input")]
+mod relay;
+mod scratch;
+mod sdl;
+mod select;
+mod shared;
+mod tap;
@@ -0,0 +1,139 @@
+use std::io;
+use std::path::PathBuf;
+use std::string::FromUtf8Error;
+
+use nix::errno::Errno;
+use thiserror::Error;
+
+use super::platform::linux::OpenError;
+
+/// Why an input device couldn't be opened or read.
+#[derive(Debug, Error)]
+pub enum InputError {
+    #[error("no gamepad found: {path}")]
+    Open {
+        path: String,
+        #[source]
+        source: OpenError,
+    },
+    #[error("failed to open /dev/uinput, is the uinput module loaded and the device writable?")]
+    Uinput {
+        #[source]
+        source: Errno,
+    },
+    #[error("{path} has no evdev node")]
+    NoEvdev { path: String },
+    #[error("{path} is the mirror of --mirror-uinput, not a controller")]
+    Mirror { path: String },
+    #[error("{path} is already locked by pid {owner}; stop it or pass --force")]
+    InUse { path: String, owner: String },
+    #[error("failed to resolve device path {path}")]
+    Resolve {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to lock {}", path.display())]
+    Lock {
+        path: PathBuf,
+        #[source]
+        source: Errno,
+    },
+    #[error("failed to read {path}")]
+    ReadDir {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{path} is not a hex id: {value}")]
+    Id { path: String, value: String },
+    #[error("device name is not UTF-8")]
+    Name(#[from] FromUtf8Error),
+    #[error("short read of {0} bytes")]
+    ShortRead(usize),
+    #[error("short write of {0} bytes")]
+    ShortWrite(usize),
+    #[error("controller disconnected")]
+    Disconnected,
+    #[error("unknown error: {0}")]
+    Read(String),
+    #[error("input reader panicked")]
+    Panicked,
+    #[error(transparent)]
+    Sys(#[from] Errno),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn messages_name_the_device_and_keep_the_cause() {
+        let error = InputError::Open {
+            path: "/dev/input/js9".to_string(),
+            source: OpenError::DeviceFileNotFound("/dev/input/js9".to_string()),
+        };
+        assert_eq!(error.to_string(), "no gamepad found: /dev/input/js9");
+        assert_eq!(
+            error.source().map(ToString::to_string).as_deref(),
+            Some("DeviceFileNotFound: /dev/input/js9")
+        );
+
+        let error = InputError::Lock {
+            path: PathBuf::from("/run/lock/beatble-js0.lock"),
+            source: Errno::EWOULDBLOCK,
+        };
+        assert_eq!(
+            error.to_string(),
+            "failed to lock /run/lock/beatble-js0.lock"
+        );
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn each_error_reads_as_a_message() {
+        let errors = [
+            (
+                InputError::InUse {
+                    path: "/dev/input/js0".to_string(),
+                    owner: "1234".to_string(),
+                },
+                "/dev/input/js0 is already locked by pid 1234; stop it or pass --force",
+            ),
+            (
+                InputError::Mirror {
+                    path: "/dev/input/js1".to_string(),
+                },
+                "/dev/input/js1 is the mirror of --mirror-uinput, not a controller",
+            ),
+            (
+                InputError::NoEvdev {
+                    path: "/dev/input/js0".to_string(),
+                },
+                "/dev/input/js0 has no evdev node",
+            ),
+            (
+                InputError::Id {
+                    path: "idVendor".to_string(),
+                    value: "zz".to_string(),
+                },
+                "idVendor is not a hex id: zz",
+            ),
+            (InputError::ShortRead(3), "short read of 3 bytes"),
+            (InputError::Disconnected, "controller disconnected"),
+            (InputError::Panicked, "input reader panicked"),
+            (InputError::Sys(Errno::ENODEV), "ENODEV: No such device"),
+            (
+                InputError::Io(io::Error::other("read failed")),
+                "read failed",
+            ),
+        ];
+        for (error, message) in errors {
+            assert_eq!(error.to_string(), message);
+        }
+    }
+}
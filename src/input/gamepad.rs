@@ -1,117 +1,764 @@
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::thread;
 
-use crossbeam::atomic::AtomicCell;
-use eyre::{Result, WrapErr};
-use log::{debug, error, info, trace};
+use beatble_protocol::KeyInput;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{info, info_span, trace, Span};
 
-use super::ble::{KeyInput, NormalButton, OptionButton};
-use super::platform::linux::{Device, Event};
+use super::error::InputError;
+use super::lock::DeviceLock;
+use super::long_press::LongPresses;
+use super::mapping::{Backend, InputMapping};
+use super::mirror::MIRROR_NAME;
+use super::platform::linux::{Device, DeviceInfo, EvdevDevice, Event};
+use super::scratch::{RecenterFilter, WobbleFilter};
+use super::sdl::sdl_guid;
+use super::select::{DeviceId, DeviceSelector};
+use super::shared::SharedKeyInput;
+use crate::chaos::{self, Chaos};
+use crate::clock;
+use crate::emulation::Emulation;
+use crate::recent::{self, InputEvent};
+use crate::stats::Stats;
 
-trait CodeExt {
-    fn normal_button(self) -> Option<NormalButton>;
-    fn option_button(self) -> Option<OptionButton>;
+/// Scales an axis value into a turntable position.
+#[inline]
+pub fn convert_scratch(value: i16, sensitivity: u8) -> u16 {
+    // wrap over the whole range so a full rotation has no seam
+    (value as u16).wrapping_mul(sensitivity as u16)
 }
 
-impl CodeExt for u8 {
-    #[inline]
-    fn normal_button(self) -> Option<NormalButton> {
-        match self {
-            0 => Some(NormalButton::B1),
-            1 => Some(NormalButton::B2),
-            2 => Some(NormalButton::B3),
-            3 => Some(NormalButton::B4),
-            4 => Some(NormalButton::B5),
-            5 => Some(NormalButton::B6),
-            6 => Some(NormalButton::B7),
-            _ => None,
+const INPUT_DIR: &str = "/dev/input";
+
+/// joystick device paths, sorted, without the mirror of --mirror-uinput
+pub fn list_devices() -> Result<Vec<String>, InputError> {
+    let mut paths = Vec::new();
+    let entries = std::fs::read_dir(INPUT_DIR).map_err(|source| InputError::ReadDir {
+        path: INPUT_DIR.to_owned(),
+        source,
+    })?;
+    for entry in entries {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("js") && !is_mirror(&name) {
+            paths.push(format!("{INPUT_DIR}/{name}"));
         }
     }
+    // js10 after js9
+    paths.sort_by_key(|path| (path.len(), path.clone()));
+    Ok(paths)
+}
 
-    #[inline]
-    fn option_button(self) -> Option<OptionButton> {
-        match self {
-            8 => Some(OptionButton::E1),
-            9 => Some(OptionButton::E2),
-            10 => Some(OptionButton::E3),
-            11 => Some(OptionButton::E4),
-            _ => None,
+/// whether the joystick node called name is a Mirror, by its name in sysfs
+fn is_mirror(name: &str) -> bool {
+    std::fs::read_to_string(format!("/sys/class/input/{name}/device/name"))
+        .is_ok_and(|device| device.trim_end() == MIRROR_NAME)
+}
+
+/// evdev nodes of the same input device as the joystick node
+pub fn evdev_siblings(input: &str) -> Result<Vec<String>, InputError> {
+    let node = std::fs::canonicalize(input)?;
+    let name = node.file_name().unwrap_or_default().to_string_lossy();
+    let parent = format!("/sys/class/input/{name}/device");
+    let mut paths = Vec::new();
+    let entries = std::fs::read_dir(&parent).map_err(|source| InputError::ReadDir {
+        path: parent.clone(),
+        source,
+    })?;
+    for entry in entries {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("event") {
+            paths.push(format!("{INPUT_DIR}/{name}"));
         }
     }
+    paths.sort();
+    Ok(paths)
 }
 
-#[inline]
-fn convert_scratch(value: i16) -> u8 {
-    // sensitivity is doubled
-    (((((value >> 8) as u8) as u16) * 2) % 0xFF) as u8
+/// The SDL GUID of the device, from its input ids in sysfs.
+pub fn device_guid(input: &str) -> Result<String, InputError> {
+    let id = |attr: &str| input_id(input, attr);
+    Ok(sdl_guid(
+        id("bustype")?,
+        id("vendor")?,
+        id("product")?,
+        id("version")?,
+    ))
+}
+
+/// The device's vendor and product id, from sysfs.
+pub fn device_id(input: &str) -> Result<DeviceId, InputError> {
+    Ok(DeviceId {
+        vendor: input_id(input, "vendor")?,
+        product: input_id(input, "product")?,
+    })
+}
+
+/// one of the device's input ids in sysfs, e.g. vendor
+fn input_id(input: &str, attr: &str) -> Result<u16, InputError> {
+    let node = std::fs::canonicalize(input)?;
+    let name = node.file_name().unwrap_or_default().to_string_lossy();
+    let path = format!("/sys/class/input/{name}/device/id/{attr}");
+    let hex = std::fs::read_to_string(&path).map_err(|source| InputError::ReadDir {
+        path: path.clone(),
+        source,
+    })?;
+    u16::from_str_radix(hex.trim(), 16).map_err(|_| InputError::Id {
+        path,
+        value: hex.trim().to_owned(),
+    })
+}
+
+/// The first joystick device that matches selector, by list_devices order.
+/// Devices that can't be opened are skipped, as they couldn't be read anyway.
+pub fn select_device(selector: &DeviceSelector) -> Result<Option<String>, InputError> {
+    for path in list_devices()? {
+        let Ok(info) = device_info(&path) else {
+            continue;
+        };
+        if selector.matches(&info.name, device_id(&path).ok()) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Opens the device just to read its name and axis and button counts.
+pub fn device_info(input: &str) -> Result<DeviceInfo, InputError> {
+    let device = open(input)?;
+    device.info()
+}
+
+/// Resolves once the reader thread fails or hands the input over.
+pub type InputHandler = JoinHandle<Result<(), InputError>>;
+
+/// The returned handle only resolves once the reader thread fails.
+pub fn create_input_handler(
+    input: &str,
+    mapping: InputMapping,
+    busy_poll: bool,
+    force: bool,
+    stats: Arc<Stats>,
+) -> Result<(Arc<SharedKeyInput>, InputHandler), InputError> {
+    let shared_key_input = Arc::new(SharedKeyInput::new());
+    let handler = attach_input_handler(
+        input,
+        Arc::clone(&shared_key_input),
+        mapping,
+        busy_poll,
+        force,
+        stats,
+    )?;
+    Ok((shared_key_input, handler))
 }
 
-pub fn create_input_handler(input: &str) -> Result<Arc<AtomicCell<KeyInput>>> {
-    debug!(
-        "AtomicCell::<KeyInput>::is_lock_free: {}",
-        AtomicCell::<KeyInput>::is_lock_free()
-    );
-    let atomic_key_input = Arc::new(AtomicCell::new(KeyInput::init()));
+/// Reads `input` into an existing SharedKeyInput, taking over from the reader
+/// that fed it so far, whose handle then resolves to Ok.
+pub fn attach_input_handler(
+    input: &str,
+    shared_key_input: Arc<SharedKeyInput>,
+    mapping: InputMapping,
+    busy_poll: bool,
+    force: bool,
+    stats: Arc<Stats>,
+) -> Result<InputHandler, InputError> {
+    let span = info_span!("input", device = input);
+    let _entered = span.enter();
 
-    let mut device = Device::open(input).context(format!("no gamepad found: {input}"))?;
-    info!("connected to {} at {}", device.info()?, input);
+    if mapping.backend == Backend::Evdev {
+        let node = evdev_node(input)?;
+        let device = EvdevDevice::open(&node).map_err(|source| InputError::Open {
+            path: node.clone(),
+            source,
+        })?;
+        let info = device.info()?;
+        check_mirror(input, &info)?;
+        let lock = DeviceLock::acquire(&device, input, force)?;
+        info!("connected to {} at {} through {}", info, input, node);
+        device.set_nonblocking(busy_poll)?;
+        return spawn_reader(span.clone(), lock, device, shared_key_input, mapping, stats);
+    }
+    let device = open(input)?;
+    let info = device.info()?;
+    check_mirror(input, &info)?;
+    let lock = DeviceLock::acquire(&device, input, force)?;
+    info!("connected to {} at {}", info, input);
     device.disable_correction()?;
+    // keep the reader thread spinning on the fd instead of waiting for a wakeup
+    device.set_nonblocking(busy_poll)?;
+
+    spawn_reader(span.clone(), lock, device, shared_key_input, mapping, stats)
+}
 
+/// reading it back would feed every change to itself
+fn check_mirror(input: &str, info: &DeviceInfo) -> Result<(), InputError> {
+    if info.name == MIRROR_NAME {
+        return Err(InputError::Mirror {
+            path: input.to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// input if it is an evdev node, else the first evdev node of its device
+fn evdev_node(input: &str) -> Result<String, InputError> {
+    let node = std::fs::canonicalize(input).map_err(|source| InputError::Resolve {
+        path: input.to_owned(),
+        source,
+    })?;
+    if node
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with("event"))
     {
-        let atomic_key_input = Arc::clone(&atomic_key_input);
-        tokio::task::spawn_blocking(move || {
-            info!("input handler watching input event");
-            let mut key_input = KeyInput::init();
-            'e: loop {
-                for event in device.by_ref() {
-                    match event {
-                        Event::Disconnected => {
-                            error!("controller disconnected");
-                            break 'e;
-                        }
-                        Event::Error(e) => {
-                            error!("unknown error: {e}");
-                            break 'e;
-                        }
-                        Event::ButtonPressed(_)
-                        | Event::ButtonReleased(_)
-                        | Event::AxisChanged(_, _) => {
-                            trace!("event: {event:?}");
-                            update_key_input(&mut key_input, &event);
-                            trace!("key_input: {key_input:?}");
-                            atomic_key_input.store(key_input);
-                        }
-                    }
+        return Ok(input.to_owned());
+    }
+    evdev_siblings(input)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| InputError::NoEvdev {
+            path: input.to_owned(),
+        })
+}
+
+/// Claims shared_key_input and reads events into it on a thread of its own,
+/// holding guard until it returns.
+pub(super) fn spawn_reader<G>(
+    span: Span,
+    guard: G,
+    events: impl Iterator<Item = Event> + Send + 'static,
+    shared_key_input: Arc<SharedKeyInput>,
+    mut mapping: InputMapping,
+    stats: Arc<Stats>,
+) -> Result<InputHandler, InputError>
+where
+    G: Send + 'static,
+{
+    // only once the device is usable, so a failed swap keeps the old reader
+    let reader = shared_key_input.claim();
+    let remaps = shared_key_input.remaps();
+    // a reopened or swapped device keeps a mapping reloaded meanwhile
+    if let Some(reloaded) = &*remaps.borrow() {
+        mapping.remap(reloaded);
+    }
+    let mapper = Mapper::new(mapping);
+    // a thread of its own rather than the blocking pool, so a single-threaded
+    // runtime has nothing to share with it
+    let (result, handler) = oneshot::channel();
+    thread::Builder::new()
+        .name("input".to_owned())
+        .spawn(move || {
+            let _entered = span.entered();
+            // held for as long as events are read
+            let _guard = guard;
+            let read = match chaos::get() {
+                Some(chaos) => {
+                    let events = Faulty { events, chaos };
+                    read_events(events, &shared_key_input, reader, remaps, mapper, &stats)
                 }
+                None => read_events(events, &shared_key_input, reader, remaps, mapper, &stats),
+            };
+            // nobody left to tell once the session is gone
+            let _ = result.send(read);
+        })?;
+    let handler = tokio::spawn(async move { handler.await.unwrap_or(Err(InputError::Panicked)) });
+
+    Ok(handler)
+}
+
+pub(super) fn open(input: &str) -> Result<Device, InputError> {
+    Device::open(input).map_err(|source| InputError::Open {
+        path: input.to_owned(),
+        source,
+    })
+}
+
+/// events with the faults of a Chaos mixed in
+struct Faulty<I> {
+    events: I,
+    chaos: &'static Chaos,
+}
+
+impl<I: Iterator<Item = Event>> Iterator for Faulty<I> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        if chaos::roll(self.chaos.eagain) {
+            return None;
+        }
+        if chaos::roll(self.chaos.eio) {
+            return Some(Event::Error("injected EIO".to_owned()));
+        }
+        loop {
+            let event = self.events.next()?;
+            if !chaos::roll(self.chaos.drop_events) {
+                return Some(event);
             }
-            panic!("input handler exiting");
-        });
-    };
+            trace!("dropping {event:?}");
+        }
+    }
+}
 
-    Ok(atomic_key_input)
+/// An InputMapping with the state a reader keeps for it.
+struct Mapper {
+    mapping: InputMapping,
+    wobble: Option<WobbleFilter>,
+    recenter: Option<RecenterFilter>,
+    long_presses: Option<LongPresses>,
 }
 
+impl Mapper {
+    fn new(mapping: InputMapping) -> Self {
+        Self {
+            wobble: mapping.anti_wobble.map(WobbleFilter::new),
+            recenter: mapping.recenter.clone().map(RecenterFilter::new),
+            long_presses: mapping.long_press.clone().map(LongPresses::new),
+            mapping,
+        }
+    }
+
+    /// the filters of reloaded settings start over, the others keep their state
+    fn remap(&mut self, reloaded: &InputMapping) {
+        self.mapping.remap(reloaded);
+        self.wobble = self.mapping.anti_wobble.map(WobbleFilter::new);
+        info!("input mapping reloaded");
+    }
+
+    #[inline]
+    fn apply(
+        &mut self,
+        key_input: &mut KeyInput,
+        event: &Event,
+        shared_key_input: &Arc<SharedKeyInput>,
+        reader: u64,
+    ) {
+        if let (Some(recenter), &Event::ButtonPressed(button) | &Event::ButtonReleased(button)) =
+            (&mut self.recenter, event)
+        {
+            let pressed = matches!(event, Event::ButtonPressed(_));
+            if let Some(reference) = recenter.button(button, pressed) {
+                info!("turntable re-zeroed at position {reference}");
+                shared_key_input.set_scratch_reference(reference);
+                key_input.set_scratch_position(0);
+            }
+        }
+        if let Some(long_presses) = &mut self.long_presses {
+            long_presses.catch_up(key_input);
+            let long_press = match *event {
+                Event::ButtonPressed(button) => {
+                    long_presses.apply(key_input, button, true, shared_key_input, reader)
+                }
+                Event::ButtonReleased(button) => {
+                    long_presses.apply(key_input, button, false, shared_key_input, reader)
+                }
+                _ => false,
+            };
+            if long_press {
+                return;
+            }
+        }
+        update_key_input(key_input, event, &self.mapping);
+        // the filters only apply to the turntable
+        let &Event::AxisChanged(axis, _) = event else {
+            return;
+        };
+        if self.mapping.emulation != Emulation::Iidx || !self.mapping.keymap.is_turntable(axis) {
+            return;
+        }
+        if let Some(recenter) = &mut self.recenter {
+            let first = recenter.reference().is_none();
+            let position = recenter.filter(key_input.scratch_position());
+            if let (true, Some(reference)) = (first, recenter.reference()) {
+                info!("turntable zero captured at position {reference}");
+                shared_key_input.set_scratch_reference(reference);
+            }
+            key_input.set_scratch_position(position);
+        }
+        // charge scratches only exist on the turntable
+        if let Some(wobble) = &mut self.wobble {
+            let position = wobble.filter(key_input.scratch_position(), clock::now());
+            key_input.set_scratch_position(position);
+        }
+    }
+
+    /// the event with the buttons of mapping.active_low turned around
+    #[inline]
+    fn logical(&self, event: Event) -> Event {
+        let Some(active_low) = &self.mapping.active_low else {
+            return event;
+        };
+        match event {
+            Event::ButtonPressed(button) if active_low.contains(button) => {
+                Event::ButtonReleased(button)
+            }
+            Event::ButtonReleased(button) if active_low.contains(button) => {
+                Event::ButtonPressed(button)
+            }
+            event => event,
+        }
+    }
+
+    /// see LongPresses::catch_up
+    #[inline]
+    fn catch_up(&mut self, key_input: &mut KeyInput) -> bool {
+        self.long_presses
+            .as_mut()
+            .is_some_and(|long_presses| long_presses.catch_up(key_input))
+    }
+}
+
+/// Applies events to the input the way a reader does, but on the caller's
+/// thread whenever the caller likes, for replaying recorded events.
+///
+/// The long press and anti-wobble timers still run on the process clock.
+pub struct Replayer {
+    shared_key_input: Arc<SharedKeyInput>,
+    reader: u64,
+    key_input: KeyInput,
+    mapper: Mapper,
+}
+
+impl Replayer {
+    /// claims the input like a new reader
+    pub fn new(shared_key_input: Arc<SharedKeyInput>, mapping: InputMapping) -> Self {
+        Self {
+            reader: shared_key_input.claim(),
+            shared_key_input,
+            key_input: KeyInput::init(),
+            mapper: Mapper::new(mapping),
+        }
+    }
+
+    pub fn apply(&mut self, event: Event) {
+        let event = self.mapper.logical(event);
+        self.mapper.apply(
+            &mut self.key_input,
+            &event,
+            &self.shared_key_input,
+            self.reader,
+        );
+        self.shared_key_input.store(self.key_input);
+        if self.mapper.catch_up(&mut self.key_input) {
+            self.shared_key_input.store(self.key_input);
+        }
+    }
+}
+
+/// Returns Ok once another reader claims the input.
+fn read_events(
+    mut events: impl Iterator<Item = Event>,
+    shared_key_input: &Arc<SharedKeyInput>,
+    reader: u64,
+    mut remaps: watch::Receiver<Option<InputMapping>>,
+    mut mapper: Mapper,
+    stats: &Stats,
+) -> Result<(), InputError> {
+    info!("input handler watching input event");
+    let mut key_input = KeyInput::init();
+    let mut axes = [None; AXES];
+    loop {
+        for event in events.by_ref() {
+            if !shared_key_input.is_claimed_by(reader) {
+                info!("input handed over to another device");
+                return Ok(());
+            }
+            // a version check, the value is only read after a reload
+            if remaps.has_changed().unwrap_or(false) {
+                if let Some(reloaded) = &*remaps.borrow_and_update() {
+                    mapper.remap(reloaded);
+                    // a held button would be released under its new mapping
+                    key_input = KeyInput::init();
+                    shared_key_input.store(key_input);
+                }
+            }
+            match event {
+                Event::Disconnected => return Err(InputError::Disconnected),
+                Event::Error(e) => return Err(InputError::Read(e)),
+                Event::ButtonPressed(_) | Event::ButtonReleased(_) | Event::AxisChanged(_, _) => {
+                    trace!("event: {event:?}");
+                    // everything from here on sees the press, not the level
+                    let event = mapper.logical(event);
+                    record_event(stats, &event, &mut axes);
+                    mapper.apply(&mut key_input, &event, shared_key_input, reader);
+                    trace!("key_input: {key_input:?}");
+                    shared_key_input.store(key_input);
+                    // a long press whose timer went off while this event was handled
+                    if mapper.catch_up(&mut key_input) {
+                        shared_key_input.store(key_input);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// axis numbers are a u8
+const AXES: usize = 256;
+
+/// lock-free counters and rings only, the reader never waits on a metrics
+/// scrape or a crash report
 #[inline]
-fn update_key_input(key_input: &mut KeyInput, event: &Event) {
+fn record_event(stats: &Stats, event: &Event, axes: &mut [Option<i16>; AXES]) {
     match *event {
         Event::ButtonPressed(button) => {
-            if let Some(button) = button.normal_button() {
-                key_input.normal_button.insert(button);
+            recent::record_event(InputEvent::Pressed(button));
+            stats.buttons_pressed.fetch_add(1, Ordering::Relaxed);
+            if let Some(presses) = stats.presses.get(button as usize) {
+                presses.fetch_add(1, Ordering::Relaxed);
             }
-            if let Some(button) = button.option_button() {
-                key_input.option_button.insert(button);
+        }
+        Event::ButtonReleased(button) => {
+            recent::record_event(InputEvent::Released(button));
+            stats.buttons_released.fetch_add(1, Ordering::Relaxed);
+        }
+        Event::AxisChanged(axis, value) => {
+            recent::record_event(InputEvent::Axis(axis, value));
+            stats.axis_changes.fetch_add(1, Ordering::Relaxed);
+            // the turntable wraps, so the shorter way round is the movement
+            if let Some(previous) = axes[axis as usize].replace(value) {
+                let travel = value.wrapping_sub(previous).unsigned_abs();
+                stats
+                    .scratch_travel
+                    .fetch_add(travel as u64, Ordering::Relaxed);
             }
         }
+        Event::Disconnected | Event::Error(_) => {}
+    }
+}
+
+/// Applies one event to the key input the way the reader does.
+#[inline]
+pub fn update_key_input(key_input: &mut KeyInput, event: &Event, mapping: &InputMapping) {
+    let keymap = &mapping.keymap;
+    match *event {
+        Event::ButtonPressed(button) => {
+            key_input.normal_button |= keymap.normal_button(button);
+            key_input.option_button |= keymap.option_button(button);
+        }
         Event::ButtonReleased(button) => {
-            if let Some(button) = button.normal_button() {
-                key_input.normal_button.remove(button);
+            key_input.normal_button -= keymap.normal_button(button);
+            key_input.option_button -= keymap.option_button(button);
+        }
+        Event::AxisChanged(axis, value) => match (mapping.emulation, axis) {
+            (Emulation::Iidx, axis) if keymap.is_turntable(axis) => {
+                let position = convert_scratch(value, mapping.turntable_sensitivity);
+                key_input.set_scratch_position(if mapping.reverse_turntable {
+                    position.wrapping_neg()
+                } else {
+                    position
+                });
             }
-            if let Some(button) = button.option_button() {
-                key_input.option_button.remove(button);
+            (Emulation::Iidx, _) => {}
+            (Emulation::Sdvx, 0) => key_input.scratch = (convert_scratch(value, 1) >> 8) as u8,
+            (Emulation::Sdvx, 1) => key_input.analog = (convert_scratch(value, 1) >> 8) as u8,
+            (Emulation::Sdvx, _) => {}
+        },
+        // the reader stops on these before they get here
+        Event::Disconnected | Event::Error(_) => {}
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use beatble_protocol::NormalButton;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::input::ActiveLow;
+
+    #[test]
+    fn a_reloaded_mapping_applies_from_the_next_event() {
+        let shared = Arc::new(SharedKeyInput::new());
+        let reader = shared.claim();
+        let remaps = shared.remaps();
+        let mapper = Mapper::new(InputMapping::from(Emulation::Iidx));
+        let mut reloaded = InputMapping::from(Emulation::Iidx);
+        reloaded.keymap.keys.swap(0, 1);
+        reloaded.emulation = Emulation::Sdvx;
+
+        let mut pressed = Vec::new();
+        let mut step = 0;
+        let events = std::iter::from_fn(|| {
+            step += 1;
+            match step {
+                1 | 3 => Some(Event::ButtonPressed(0)),
+                2 => {
+                    pressed.push(shared.load().normal_button);
+                    shared.remap(reloaded.clone());
+                    Some(Event::ButtonReleased(0))
+                }
+                _ => {
+                    pressed.push(shared.load().normal_button);
+                    // ends the reader at this event
+                    shared.claim();
+                    Some(Event::ButtonReleased(0))
+                }
+            }
+        });
+        read_events(events, &shared, reader, remaps, mapper, &Stats::new()).expect("handed over");
+        assert_eq!(pressed, [NormalButton::B1, NormalButton::B2]);
+
+        // and a reader started later maps with it too
+        let mut mapping = InputMapping::from(Emulation::Iidx);
+        mapping.remap(shared.remaps().borrow().as_ref().expect("reloaded"));
+        assert_eq!(mapping.keymap, reloaded.keymap);
+        assert_eq!(mapping.emulation, Emulation::Iidx);
+    }
+
+    /// a js_event as the joystick node reports it
+    fn js_event(typ: u8, number: u8, value: i16) -> [u8; 8] {
+        let mut buf = [0; 8];
+        buf[4..6].copy_from_slice(&value.to_ne_bytes());
+        buf[6] = typ;
+        buf[7] = number;
+        buf
+    }
+
+    #[test]
+    fn active_low_buttons_start_from_their_initial_state() {
+        const BUTTON: u8 = 0x01;
+        const AXIS: u8 = 0x02;
+        const INIT: u8 = 0x80;
+        // buttons 0 and 2 report 1 when pressed, 1 and 3 report 0
+        let mut mapping = InputMapping::from(Emulation::Iidx);
+        mapping.active_low = Some(ActiveLow::Buttons(vec![1, 3]));
+        let shared = Arc::new(SharedKeyInput::new());
+        let mut replayer = Replayer::new(Arc::clone(&shared), mapping);
+        let mut read = |buf| {
+            if let Some(event) = Event::parse(buf) {
+                replayer.apply(event);
             }
+            shared.load().normal_button
+        };
+
+        // at startup 0 and 1 are released and 2 and 3 are held
+        read(js_event(INIT | BUTTON, 0, 0));
+        read(js_event(INIT | BUTTON, 1, 1));
+        read(js_event(INIT | BUTTON, 2, 1));
+        let initial = read(js_event(INIT | BUTTON, 3, 0));
+        assert_eq!(initial, NormalButton::B3 | NormalButton::B4);
+        assert!(Event::parse(js_event(INIT | AXIS, 0, 0x40)).is_none());
+
+        assert_eq!(
+            read(js_event(BUTTON, 1, 0)),
+            NormalButton::B2 | NormalButton::B3 | NormalButton::B4
+        );
+        assert_eq!(
+            read(js_event(BUTTON, 3, 1)),
+            NormalButton::B2 | NormalButton::B3
+        );
+        assert_eq!(read(js_event(BUTTON, 2, 0)), NormalButton::B2);
+        assert_eq!(
+            read(js_event(BUTTON, 0, 1)),
+            NormalButton::B1 | NormalButton::B2
+        );
+        assert_eq!(read(js_event(BUTTON, 1, 1)), NormalButton::B1);
+        assert_eq!(read(js_event(BUTTON, 0, 0)), NormalButton::empty());
+    }
+
+    // one turn of the axis, from its lowest value up
+    fn turn() -> impl Iterator<Item = i16> {
+        i16::MIN..=i16::MAX
+    }
+
+    #[test]
+    fn convert_scratch_is_a_bijection_at_sensitivity_1() {
+        let mut seen = vec![false; 1 << 16];
+        for value in turn() {
+            let position = convert_scratch(value, 1);
+            assert!(!seen[position as usize], "{position} twice");
+            seen[position as usize] = true;
         }
-        Event::AxisChanged(_axis, value) => {
-            key_input.scratch = convert_scratch(value);
+        assert!(seen.iter().all(|&seen| seen));
+    }
+
+    #[test]
+    fn convert_scratch_has_no_seam() {
+        for sensitivity in [1, 2] {
+            // including the wrap from the end of the turn back to its start
+            let positions: Vec<u16> = turn()
+                .chain([i16::MIN])
+                .map(|value| convert_scratch(value, sensitivity))
+                .collect();
+            for pair in positions.windows(2) {
+                assert_eq!(pair[1].wrapping_sub(pair[0]), u16::from(sensitivity));
+            }
+            // the scratch byte on the wire moves by at most one, mod 256
+            for pair in positions.windows(2) {
+                let [from, to] = [pair[0].to_be_bytes()[0], pair[1].to_be_bytes()[0]];
+                assert!(to.wrapping_sub(from) <= 1, "{from} -> {to}");
+            }
         }
-        Event::Disconnected | Event::Error(_) => unreachable!(),
-    };
+    }
+
+    #[test]
+    fn convert_scratch_covers_twice_at_sensitivity_2() {
+        let mut hits = vec![0u8; 1 << 16];
+        for value in turn() {
+            hits[convert_scratch(value, 2) as usize] += 1;
+        }
+        for (position, &count) in hits.iter().enumerate() {
+            let expected = if position % 2 == 0 { 2 } else { 0 };
+            assert_eq!(count, expected, "position {position}");
+        }
+    }
+
+    fn any_event() -> impl Strategy<Value = Event> {
+        // past the buttons any keymap uses, and axes beyond the turntable
+        prop_oneof![
+            (0..64u8).prop_map(Event::ButtonPressed),
+            (0..64u8).prop_map(Event::ButtonReleased),
+            (0..8u8, any::<i16>()).prop_map(|(axis, value)| Event::AxisChanged(axis, value)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn update_key_input_releases_and_ignores(
+            events in proptest::collection::vec(any_event(), 1..200),
+            sdvx in any::<bool>(),
+        ) {
+            let emulation = if sdvx { Emulation::Sdvx } else { Emulation::Iidx };
+            let mapping = InputMapping::from(emulation);
+            let keymap = &mapping.keymap;
+            let mut key_input = KeyInput::init();
+            for event in &events {
+                let before = key_input;
+                update_key_input(&mut key_input, event, &mapping);
+                match *event {
+                    Event::ButtonReleased(button) => {
+                        let normal = keymap.normal_button(button);
+                        let option = keymap.option_button(button);
+                        prop_assert!(!key_input.normal_button.intersects(normal));
+                        prop_assert!(!key_input.option_button.intersects(option));
+                    }
+                    Event::ButtonPressed(button)
+                        if keymap.normal_button(button).is_empty()
+                            && keymap.option_button(button).is_empty() =>
+                    {
+                        prop_assert_eq!(key_input, before);
+                    }
+                    _ => {}
+                }
+                if let Event::ButtonPressed(button) | Event::ButtonReleased(button) = *event {
+                    // a button only ever touches the bits it is mapped to
+                    let normal = keymap.normal_button(button);
+                    let option = keymap.option_button(button);
+                    let mut unmapped = key_input;
+                    unmapped.normal_button -= normal;
+                    unmapped.option_button -= option;
+                    prop_assert_eq!(unmapped.normal_button, before.normal_button - normal);
+                    prop_assert_eq!(unmapped.option_button, before.option_button - option);
+                    prop_assert_eq!(key_input.scratch, before.scratch);
+                    prop_assert_eq!(key_input.analog, before.analog);
+                }
+                if let Event::AxisChanged(..) = *event {
+                    prop_assert_eq!(key_input.normal_button, before.normal_button);
+                    prop_assert_eq!(key_input.option_button, before.option_button);
+                }
+            }
+        }
+    }
 }
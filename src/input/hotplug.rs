@@ -0,0 +1,128 @@
+// Input devices turning up, from the uevents the kernel and udev broadcast on
+// netlink, so a reader waiting to reopen an unplugged controller can try as
+// soon as it is back instead of at its next retry.
+//
+// udev's copy of an event comes after its rules ran, when the node has its
+// permissions and symlinks; the kernel's comes first, and is all there is
+// without a udev daemon, e.g. in a container. Both are listened to, and a
+// waiter only takes a hint from either: it still has to open the device.
+// https://github.com/systemd/systemd/blob/v255/src/libsystemd/sd-device/device-monitor.c
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::Arc;
+use std::thread;
+
+use nix::libc;
+use tokio::sync::Notify;
+use tracing::{debug, trace};
+
+const NETLINK_KOBJECT_UEVENT: libc::c_int = 15;
+// the multicast groups of the kernel's and udev's events
+const GROUP_KERNEL: u32 = 1;
+const GROUP_UDEV: u32 = 2;
+// udev's events start with this and a header, see monitor_netlink_header
+const UDEV_PREFIX: &[u8] = b"libudev\0";
+const UDEV_PROPERTIES_OFFSET: usize = 16;
+
+/// Wakes whoever waits for an input device to be added.
+pub struct Hotplug {
+    added: Notify,
+}
+
+impl Hotplug {
+    /// Listens from a thread of its own, for as long as the process runs.
+    pub fn spawn() -> io::Result<Arc<Self>> {
+        let socket = open_socket()?;
+        let hotplug = Arc::new(Self {
+            added: Notify::new(),
+        });
+        let listener = Arc::clone(&hotplug);
+        thread::Builder::new()
+            .name("hotplug".to_owned())
+            .spawn(move || {
+                if let Err(e) = listener.listen(socket) {
+                    debug!("stopped watching for input devices: {e}");
+                }
+            })?;
+        Ok(hotplug)
+    }
+
+    /// Resolves on the next input device added after the call.
+    pub async fn added(&self) {
+        self.added.notified().await;
+    }
+
+    fn listen(&self, mut socket: File) -> io::Result<()> {
+        let mut buf = vec![0u8; 8192];
+        loop {
+            let len = socket.read(&mut buf)?;
+            if let Some(devname) = added_input(&buf[..len]) {
+                trace!("input device added: /dev/{devname}");
+                self.added.notify_waiters();
+            }
+        }
+    }
+}
+
+fn open_socket() -> io::Result<File> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            NETLINK_KOBJECT_UEVENT,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    address.nl_groups = GROUP_KERNEL | GROUP_UDEV;
+    let res = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+            size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(File::from(fd))
+}
+
+/// The DEVNAME of an input node an event adds, e.g. input/js0; None for any
+/// other event.
+fn added_input(message: &[u8]) -> Option<&str> {
+    // both kinds list NUL-terminated KEY=VALUE properties; the kernel's
+    // after an ACTION@DEVPATH line
+    let properties = if message.starts_with(UDEV_PREFIX) {
+        let offset = message.get(UDEV_PROPERTIES_OFFSET..UDEV_PROPERTIES_OFFSET + 4)?;
+        let offset = u32::from_ne_bytes(offset.try_into().ok()?) as usize;
+        message.get(offset..)?
+    } else {
+        let start = message.iter().position(|&b| b == 0)? + 1;
+        message.get(start..)?
+    };
+    let mut action = None;
+    let mut subsystem = None;
+    let mut devname = None;
+    for property in properties.split(|&b| b == 0) {
+        let Ok(property) = std::str::from_utf8(property) else {
+            continue;
+        };
+        match property.split_once('=') {
+            Some(("ACTION", value)) => action = Some(value),
+            Some(("SUBSYSTEM", value)) => subsystem = Some(value),
+            Some(("DEVNAME", value)) => devname = Some(value),
+            _ => {}
+        }
+    }
+    // the input device itself has no node, only its js and event children
+    let devname = devname?.trim_start_matches("/dev/");
+    (action? == "add" && subsystem? == "input" && devname.starts_with("input/")).then_some(devname)
+}
@@ -0,0 +1,119 @@
+// Advisory lock per input device. Two readers of one joystick each get only
+// part of its events, so a second instance refuses to start instead. The
+// flock is taken on the device's own open file, so there is no lock file to
+// create or trust, and it goes away with the process, however it exits.
+
+use std::fs;
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::path::PathBuf;
+
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use nix::sys::stat::{fstat, major, minor};
+use tracing::{debug, warn};
+
+use super::error::InputError;
+
+pub struct DeviceLock {
+    _fd: Flock<OwnedFd>,
+}
+
+impl DeviceLock {
+    /// with force a device held by another instance is used anyway, unlocked
+    pub fn acquire(
+        device: &impl AsFd,
+        input: &str,
+        force: bool,
+    ) -> Result<Option<Self>, InputError> {
+        debug!("locking {}", input);
+        // a duplicate shares the open file the lock belongs to
+        let fd = device.as_fd().try_clone_to_owned()?;
+        match Flock::lock(fd, FlockArg::LockExclusiveNonblock) {
+            Ok(fd) => Ok(Some(Self { _fd: fd })),
+            Err((fd, Errno::EWOULDBLOCK)) => {
+                let owner = owner(&fd).unwrap_or_else(|| "unknown".to_owned());
+                if !force {
+                    return Err(InputError::InUse {
+                        path: input.to_owned(),
+                        owner,
+                    });
+                }
+                warn!("{input} is also in use by pid {owner}, both will miss events");
+                Ok(None)
+            }
+            Err((_, source)) => Err(InputError::Lock {
+                path: PathBuf::from(input),
+                source,
+            }),
+        }
+    }
+}
+
+/// the pid holding a flock on the file behind fd, as /proc/locks lists it
+fn owner(fd: &impl AsFd) -> Option<String> {
+    let stat = fstat(fd.as_fd().as_raw_fd()).ok()?;
+    let id = format!(
+        "{:02x}:{:02x}:{}",
+        major(stat.st_dev),
+        minor(stat.st_dev),
+        stat.st_ino
+    );
+    owner_in(&fs::read_to_string("/proc/locks").ok()?, &id)
+}
+
+/// lines look like `1: FLOCK  ADVISORY  WRITE 1234 00:05:318 0 EOF`
+fn owner_in(locks: &str, id: &str) -> Option<String> {
+    locks.lines().find_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        match fields.as_slice() {
+            [_, "FLOCK", _, _, pid, file, ..] if *file == id => Some((*pid).to_owned()),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::File;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn a_second_open_file_is_refused_unless_forced() {
+        let path = env::temp_dir().join(format!("beatble-lock-test-{}", process::id()));
+        let first = File::create(&path).expect("a temp file");
+        let second = File::open(&path).expect("a temp file");
+        fs::remove_file(&path).expect("removable");
+
+        let lock = DeviceLock::acquire(&first, "js0", false).expect("unlocked");
+        assert!(lock.is_some());
+        match DeviceLock::acquire(&second, "js0", false) {
+            Err(InputError::InUse { path, owner }) => {
+                assert_eq!(path, "js0");
+                assert_eq!(owner, process::id().to_string());
+            }
+            other => panic!("expected InUse, got {:?}", other.map(|lock| lock.is_some())),
+        }
+        assert!(DeviceLock::acquire(&second, "js0", true)
+            .expect("forced")
+            .is_none());
+
+        drop(lock);
+        assert!(DeviceLock::acquire(&second, "js0", false)
+            .expect("released")
+            .is_some());
+    }
+
+    #[test]
+    fn owner_is_read_from_the_matching_flock() {
+        let locks = "\
+1: POSIX  ADVISORY  WRITE 100 00:05:318 0 EOF
+2: FLOCK  ADVISORY  WRITE 200 00:05:319 0 EOF
+3: FLOCK  ADVISORY  WRITE 300 00:05:318 0 EOF
+";
+        assert_eq!(owner_in(locks, "00:05:318").as_deref(), Some("300"));
+        assert_eq!(owner_in(locks, "00:05:320"), None);
+    }
+}
@@ -0,0 +1,379 @@
+// Long-press alternate mapping, for controllers with fewer extra buttons than
+// E1 to E4: a joystick button gets a short and a long action. A short press
+// sends its action once the button is released, as a tap the latch keeps for a
+// frame. Held past the threshold, the button holds its long action until it is
+// released.
+//
+// The reader only wakes up for events, so each press starts a timer thread that
+// asserts the long action when the threshold passes without one; the reader
+// takes it over at its next store.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use beatble_protocol::{KeyInput, OptionButton};
+use tracing::warn;
+
+use super::shared::SharedKeyInput;
+use crate::clock;
+
+/// how long a button is held before its long action, unless set per button
+pub const DEFAULT_LONG_PRESS_AFTER: Duration = Duration::from_millis(400);
+// a threshold beyond this is a typo rather than a press
+const MAX_LONG_PRESS_AFTER: Duration = Duration::from_secs(10);
+
+/// How one joystick button maps to two option buttons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LongPressButton {
+    /// the joystick's button number, counted from 0 as jstest does
+    pub button: u8,
+    pub short: OptionButton,
+    pub long: OptionButton,
+    pub after: Duration,
+}
+
+/// The buttons with a long action, e.g. `8=E1/E3,9=E2/E4@600`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LongPress {
+    pub buttons: Vec<LongPressButton>,
+}
+
+impl LongPress {
+    fn get(&self, button: u8) -> Option<&LongPressButton> {
+        self.buttons.iter().find(|mapped| mapped.button == button)
+    }
+}
+
+impl FromStr for LongPress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut long_press = LongPress::default();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let button = entry.parse::<LongPressButton>()?;
+            if long_press.get(button.button).is_some() {
+                return Err(format!("button {} is mapped twice", button.button));
+            }
+            long_press.buttons.push(button);
+        }
+        Ok(long_press)
+    }
+}
+
+impl FromStr for LongPressButton {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = || format!("expected BUTTON=SHORT/LONG[@MS], e.g. 8=E1/E3: {s}");
+        let (button, actions) = s.split_once('=').ok_or_else(expected)?;
+        let (actions, after) = match actions.split_once('@') {
+            Some((actions, ms)) => {
+                let ms = ms
+                    .parse()
+                    .map_err(|_| format!("not a number of milliseconds: {ms}"))?;
+                (actions, Duration::from_millis(ms))
+            }
+            None => (actions, DEFAULT_LONG_PRESS_AFTER),
+        };
+        let (short, long) = actions.split_once('/').ok_or_else(expected)?;
+        let option_button = |name: &str| {
+            OptionButton::from_name(name)
+                .ok_or_else(|| format!("unknown option button: {name} (expected E1 to E4)"))
+        };
+        let mapped = LongPressButton {
+            button: button
+                .parse()
+                .map_err(|_| format!("not a button number: {button}"))?,
+            short: option_button(short)?,
+            long: option_button(long)?,
+            after,
+        };
+        if mapped.short == mapped.long {
+            return Err(format!("short and long press are both {short}: {s}"));
+        }
+        if after.is_zero() || after > MAX_LONG_PRESS_AFTER {
+            return Err(format!(
+                "long press threshold must be more than 0 and at most {MAX_LONG_PRESS_AFTER:?}: {s}"
+            ));
+        }
+        Ok(mapped)
+    }
+}
+
+impl fmt::Display for LongPress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, mapped) in self.buttons.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}=", mapped.button)?;
+            bitflags::parser::to_writer(&mapped.short, &mut *f)?;
+            write!(f, "/")?;
+            bitflags::parser::to_writer(&mapped.long, &mut *f)?;
+            if mapped.after != DEFAULT_LONG_PRESS_AFTER {
+                write!(f, "@{}", mapped.after.as_millis())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// a press as its timer sees it
+const HELD: u8 = 0;
+// the timer is storing the long action
+const ASSERTING: u8 = 1;
+const LONG: u8 = 2;
+const RELEASED: u8 = 3;
+
+struct Press {
+    mapped: LongPressButton,
+    // clock::now() of the press
+    at: u64,
+    // shared with the timer of this press only, so a late timer can't act on
+    // a later press
+    state: Arc<AtomicU8>,
+}
+
+/// The presses of one reader's long-press buttons.
+pub struct LongPresses {
+    config: LongPress,
+    pressed: Vec<Press>,
+}
+
+impl LongPresses {
+    pub fn new(config: LongPress) -> Self {
+        Self {
+            config,
+            pressed: Vec::new(),
+        }
+    }
+
+    /// Applies a press or release of button to key_input, storing a short press
+    /// into shared_key_input as a tap. False for a button without a long action.
+    pub fn apply(
+        &mut self,
+        key_input: &mut KeyInput,
+        button: u8,
+        pressed: bool,
+        shared_key_input: &Arc<SharedKeyInput>,
+        reader: u64,
+    ) -> bool {
+        let Some(&mapped) = self.config.get(button) else {
+            return false;
+        };
+        let now = clock::now();
+        let index = self
+            .pressed
+            .iter()
+            .position(|press| press.mapped.button == button);
+        match (pressed, index) {
+            (true, None) => {
+                let state = Arc::new(AtomicU8::new(HELD));
+                spawn_timer(
+                    mapped,
+                    Arc::clone(&state),
+                    Arc::clone(shared_key_input),
+                    reader,
+                );
+                self.pressed.push(Press {
+                    mapped,
+                    at: now,
+                    state,
+                });
+            }
+            (false, Some(index)) => {
+                let press = self.pressed.swap_remove(index);
+                match release(&press.state) {
+                    LONG => key_input.option_button.remove(mapped.long),
+                    // held long enough, but released before its timer woke up
+                    _ if now.saturating_sub(press.at) >= mapped.after.as_nanos() as u64 => {
+                        tap(key_input, mapped.long, shared_key_input)
+                    }
+                    _ => tap(key_input, mapped.short, shared_key_input),
+                }
+            }
+            // a repeated press or a release of a press from before this reader
+            _ => {}
+        }
+        true
+    }
+
+    /// Holds the long action of every button held past its threshold in
+    /// key_input; true when that changed key_input.
+    pub fn catch_up(&mut self, key_input: &mut KeyInput) -> bool {
+        let now = clock::now();
+        let mut changed = false;
+        for press in &self.pressed {
+            let due = now.saturating_sub(press.at) >= press.mapped.after.as_nanos() as u64;
+            let long = match press.state.load(Ordering::Relaxed) {
+                ASSERTING | LONG => true,
+                HELD if due => press
+                    .state
+                    .compare_exchange(HELD, LONG, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok(),
+                _ => false,
+            };
+            if long && !key_input.option_button.contains(press.mapped.long) {
+                key_input.option_button.insert(press.mapped.long);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Marks a press released and returns what it was, after waiting out a timer
+/// that is storing the long action, so that can't come after the release.
+fn release(state: &AtomicU8) -> u8 {
+    loop {
+        match state.load(Ordering::Relaxed) {
+            // a few atomic operations away from LONG
+            ASSERTING => thread::yield_now(),
+            previous => {
+                if state
+                    .compare_exchange(previous, RELEASED, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return previous;
+                }
+            }
+        }
+    }
+}
+
+/// stored pressed and then released, so the latch keeps it for a frame
+fn tap(key_input: &mut KeyInput, button: OptionButton, shared_key_input: &SharedKeyInput) {
+    key_input.option_button.insert(button);
+    shared_key_input.store(*key_input);
+    key_input.option_button.remove(button);
+}
+
+fn spawn_timer(
+    mapped: LongPressButton,
+    state: Arc<AtomicU8>,
+    shared_key_input: Arc<SharedKeyInput>,
+    reader: u64,
+) {
+    let timer = thread::Builder::new()
+        .name("long-press".to_owned())
+        .spawn(move || {
+            thread::sleep(mapped.after);
+            let asserting = state
+                .compare_exchange(HELD, ASSERTING, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok();
+            if !asserting {
+                return;
+            }
+            if shared_key_input.is_claimed_by(reader) {
+                shared_key_input.press_option(mapped.long);
+            }
+            state.store(LONG, Ordering::Relaxed);
+        });
+    // the reader still catches up at its next event
+    if let Err(e) = timer {
+        warn!("failed to start the long press timer: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the timers are threads on the real clock, so the presses keep well clear
+    // of the threshold
+    const AFTER: Duration = Duration::from_millis(100);
+
+    fn reader() -> (LongPresses, Arc<SharedKeyInput>, u64) {
+        let config = LongPress {
+            buttons: vec![LongPressButton {
+                button: 8,
+                short: OptionButton::E1,
+                long: OptionButton::E3,
+                after: AFTER,
+            }],
+        };
+        let shared = Arc::new(SharedKeyInput::new());
+        let reader = shared.claim();
+        (LongPresses::new(config), shared, reader)
+    }
+
+    #[test]
+    fn a_release_before_the_threshold_taps_the_short_action() {
+        let (mut long_presses, shared, reader) = reader();
+        let mut key_input = KeyInput::init();
+        assert!(long_presses.apply(&mut key_input, 8, true, &shared, reader));
+        assert!(key_input.option_button.is_empty());
+        thread::sleep(AFTER / 10);
+        assert!(long_presses.apply(&mut key_input, 8, false, &shared, reader));
+        assert!(key_input.option_button.is_empty());
+        // as the reader stores after every event
+        shared.store(key_input);
+        // latched for the next frame
+        assert_eq!(shared.take().option_button, OptionButton::E1);
+
+        // and the timer of the press leaves it alone
+        thread::sleep(AFTER * 2);
+        assert!(!long_presses.catch_up(&mut key_input));
+        assert_eq!(shared.take().option_button, OptionButton::empty());
+    }
+
+    #[test]
+    fn a_button_held_past_the_threshold_holds_the_long_action() {
+        let (mut long_presses, shared, reader) = reader();
+        let mut key_input = KeyInput::init();
+        long_presses.apply(&mut key_input, 8, true, &shared, reader);
+        assert!(!long_presses.catch_up(&mut key_input));
+
+        // the timer presses it without an event from the reader
+        thread::sleep(AFTER * 2);
+        assert_eq!(shared.load().option_button, OptionButton::E3);
+        // which takes it over at its next one
+        assert!(long_presses.catch_up(&mut key_input));
+        assert_eq!(key_input.option_button, OptionButton::E3);
+        assert!(!long_presses.catch_up(&mut key_input));
+
+        long_presses.apply(&mut key_input, 8, false, &shared, reader);
+        assert!(key_input.option_button.is_empty());
+        shared.store(key_input);
+        // no short action on the way out
+        assert_eq!(shared.take().option_button, OptionButton::E3);
+    }
+
+    #[test]
+    fn other_buttons_are_left_to_the_keymap() {
+        let (mut long_presses, shared, reader) = reader();
+        let mut key_input = KeyInput::init();
+        assert!(!long_presses.apply(&mut key_input, 9, true, &shared, reader));
+        assert!(!long_presses.apply(&mut key_input, 9, false, &shared, reader));
+    }
+
+    #[test]
+    fn settings_round_trip_through_text() {
+        let long_press: LongPress = "8=E1/E3, 9=E2/E4@600".parse().unwrap();
+        assert_eq!(long_press.buttons[1].after, Duration::from_millis(600));
+        assert_eq!(long_press.to_string(), "8=E1/E3,9=E2/E4@600");
+        for (invalid, error) in [
+            ("8=E1/E3,8=E2/E4", "button 8 is mapped twice"),
+            ("8=E1/E1", "short and long press are both E1: 8=E1/E1"),
+            (
+                "8=E1",
+                "expected BUTTON=SHORT/LONG[@MS], e.g. 8=E1/E3: 8=E1",
+            ),
+            ("8=E1/E5", "unknown option button: E5 (expected E1 to E4)"),
+            (
+                "8=E1/E3@0",
+                "long press threshold must be more than 0 and at most 10s: 8=E1/E3@0",
+            ),
+        ] {
+            assert_eq!(invalid.parse::<LongPress>().unwrap_err(), error);
+        }
+    }
+}
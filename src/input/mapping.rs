@@ -0,0 +1,582 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use beatble_protocol::{NormalButton, OptionButton};
+use bitflags::Flags;
+use serde::Deserialize;
+
+use crate::emulation::Emulation;
+
+use super::long_press::LongPress;
+use super::scratch::{AntiWobble, Recenter};
+
+/// The turntable sensitivity in IIDX mode, doubled.
+pub const SCRATCH_SENSITIVITY: u8 = 2;
+
+const KEYS: [NormalButton; 7] = [
+    NormalButton::B1,
+    NormalButton::B2,
+    NormalButton::B3,
+    NormalButton::B4,
+    NormalButton::B5,
+    NormalButton::B6,
+    NormalButton::B7,
+];
+const OPTIONS: [OptionButton; 4] = [
+    OptionButton::E1,
+    OptionButton::E2,
+    OptionButton::E3,
+    OptionButton::E4,
+];
+
+/// How a reader turns a device's events into key input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputMapping {
+    /// which node of the device a reader reads
+    pub backend: Backend,
+    pub emulation: Emulation,
+    pub keymap: Keymap,
+    /// turntable position units per axis unit, see convert_scratch
+    pub turntable_sensitivity: u8,
+    pub reverse_turntable: bool,
+    /// buttons read as pressed when they report 0
+    pub active_low: Option<ActiveLow>,
+    pub anti_wobble: Option<AntiWobble>,
+    /// only for the position scratch mode, see RecenterFilter
+    pub recenter: Option<Recenter>,
+    pub long_press: Option<LongPress>,
+}
+
+impl InputMapping {
+    /// Takes the settings a config reload changes from reloaded, keeping the
+    /// backend and emulation the device was opened with.
+    pub fn remap(&mut self, reloaded: &InputMapping) {
+        self.keymap = reloaded.keymap;
+        self.turntable_sensitivity = reloaded.turntable_sensitivity;
+        self.anti_wobble = reloaded.anti_wobble;
+    }
+}
+
+impl From<Emulation> for InputMapping {
+    /// the buttons and axes as they are, without filters
+    fn from(emulation: Emulation) -> Self {
+        Self {
+            backend: Backend::default(),
+            emulation,
+            keymap: Keymap::DEFAULT,
+            turntable_sensitivity: SCRATCH_SENSITIVITY,
+            reverse_turntable: false,
+            active_low: None,
+            anti_wobble: None,
+            recenter: None,
+            long_press: None,
+        }
+    }
+}
+
+/// Which kernel interface a device is read through: joydev, the joystick
+/// node, or evdev, the event node of the same device, which works where
+/// joydev isn't loaded. Either gives the same button and axis numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Joydev,
+    Evdev,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "joydev" => Ok(Backend::Joydev),
+            "evdev" => Ok(Backend::Evdev),
+            _ => Err(format!("unknown backend: {s} (expected joydev or evdev)")),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Backend::Joydev => write!(f, "joydev"),
+            Backend::Evdev => write!(f, "evdev"),
+        }
+    }
+}
+
+/// Which joystick buttons are the keys and option buttons, and which axis is
+/// the turntable, e.g. `B1=0,B2=1,E1=8,TT=0`. Buttons and axes are counted
+/// from 0 as jstest does; a control left out is never pressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    pub keys: [Option<u8>; 7],
+    pub options: [Option<u8>; 4],
+    /// any axis drives the turntable when None
+    pub turntable: Option<u8>,
+}
+
+impl Keymap {
+    /// B1 to B7 on buttons 0 to 6, E1 to E4 on buttons 8 to 11
+    pub const DEFAULT: Self = Self {
+        keys: [
+            Some(0),
+            Some(1),
+            Some(2),
+            Some(3),
+            Some(4),
+            Some(5),
+            Some(6),
+        ],
+        options: [Some(8), Some(9), Some(10), Some(11)],
+        turntable: None,
+    };
+
+    /// nothing pressed, every axis the turntable
+    const EMPTY: Self = Self {
+        keys: [None; 7],
+        options: [None; 4],
+        turntable: None,
+    };
+
+    /// the keys on button, empty for none
+    #[inline]
+    pub fn normal_button(&self, button: u8) -> NormalButton {
+        mapped(&self.keys, &KEYS, button)
+    }
+
+    /// the option buttons on button, empty for none
+    #[inline]
+    pub fn option_button(&self, button: u8) -> OptionButton {
+        mapped(&self.options, &OPTIONS, button)
+    }
+
+    #[inline]
+    pub fn is_turntable(&self, axis: u8) -> bool {
+        self.turntable.is_none_or(|turntable| turntable == axis)
+    }
+
+    /// The keymap of a mapping file, which names the control on each button
+    /// and axis instead. Buttons it leaves out are ignored, and without an
+    /// axis for the scratch (or TT, as in --keymap) any axis turns the
+    /// turntable:
+    ///
+    /// ```toml
+    /// [buttons]
+    /// 1 = "B1"
+    /// 12 = "E1"
+    ///
+    /// [axes]
+    /// 0 = "scratch"
+    /// ```
+    pub fn from_mapping_file(contents: &str) -> Result<Self, String> {
+        let file: MappingFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+        let mut keymap = Self::EMPTY;
+        let sources = file
+            .buttons
+            .iter()
+            .map(|entry| ("button", entry))
+            .chain(file.axes.iter().map(|entry| ("axis", entry)));
+        for (kind, (number, control)) in sources {
+            let (article, expected) = if kind == "axis" {
+                ("an", "scratch")
+            } else {
+                ("a", "B1 to B7 or E1 to E4")
+            };
+            let number: u8 = number
+                .parse()
+                .map_err(|_| format!("not {article} {kind} number: {number}"))?;
+            let name = match control.as_str() {
+                "scratch" => "TT",
+                name => name,
+            };
+            // only an axis turns the turntable
+            let slot = keymap
+                .slot(name)
+                .filter(|_| (name == "TT") == (kind == "axis"))
+                .ok_or_else(|| {
+                    format!("unknown control on {kind} {number}: {control} (expected {expected})")
+                })?;
+            if let Some(previous) = slot.replace(number) {
+                return Err(format!(
+                    "{control} is mapped from both {kind} {previous} and {kind} {number}"
+                ));
+            }
+        }
+        Ok(keymap)
+    }
+
+    /// where the number of a control by its name goes
+    fn slot(&mut self, control: &str) -> Option<&mut Option<u8>> {
+        if control == "TT" {
+            Some(&mut self.turntable)
+        } else if let Some(key) = NormalButton::from_name(control) {
+            Some(&mut self.keys[key.bits().trailing_zeros() as usize])
+        } else {
+            OptionButton::from_name(control)
+                .map(|option| &mut self.options[option.bits().trailing_zeros() as usize])
+        }
+    }
+}
+
+/// the contents of a mapping file, see Keymap::from_mapping_file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MappingFile {
+    #[serde(default)]
+    buttons: BTreeMap<String, String>,
+    #[serde(default)]
+    axes: BTreeMap<String, String>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[inline]
+fn mapped<F: Flags + Copy>(buttons: &[Option<u8>], flags: &[F], button: u8) -> F {
+    buttons
+        .iter()
+        .zip(flags)
+        .filter(|(mapped, _)| **mapped == Some(button))
+        .fold(F::empty(), |all, (_, &flag)| all.union(flag))
+}
+
+impl FromStr for Keymap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keymap = Keymap::EMPTY;
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (control, number) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected CONTROL=NUMBER, e.g. B1=0: {entry}"))?;
+            let number = number
+                .parse()
+                .map_err(|_| format!("not a button or axis number: {number}"))?;
+            let slot = keymap.slot(control).ok_or_else(|| {
+                format!("unknown control: {control} (expected B1 to B7, E1 to E4 or TT)")
+            })?;
+            if slot.replace(number).is_some() {
+                return Err(format!("{control} is mapped twice"));
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+impl fmt::Display for Keymap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys = (1..).zip(self.keys).map(|(n, key)| (format!("B{n}"), key));
+        let options = (1..)
+            .zip(self.options)
+            .map(|(n, option)| (format!("E{n}"), option));
+        let turntable = [("TT".to_owned(), self.turntable)];
+        let mut separator = "";
+        for (name, number) in keys.chain(options).chain(turntable) {
+            if let Some(number) = number {
+                write!(f, "{separator}{name}={number}")?;
+                separator = ",";
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Buttons wired the other way round, reporting 0 when pressed: a list of
+/// button numbers, e.g. `3,7`, or `all` for a device that reports every button
+/// that way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ActiveLow {
+    All,
+    Buttons(Vec<u8>),
+}
+
+impl ActiveLow {
+    #[inline]
+    pub fn contains(&self, button: u8) -> bool {
+        match self {
+            ActiveLow::All => true,
+            ActiveLow::Buttons(buttons) => buttons.contains(&button),
+        }
+    }
+}
+
+impl FromStr for ActiveLow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "all" {
+            return Ok(ActiveLow::All);
+        }
+        let mut buttons = Vec::new();
+        for button in s
+            .split(',')
+            .map(str::trim)
+            .filter(|button| !button.is_empty())
+        {
+            let button = button
+                .parse()
+                .map_err(|_| format!("not a button number: {button} (expected e.g. 3,7 or all)"))?;
+            if !buttons.contains(&button) {
+                buttons.push(button);
+            }
+        }
+        if buttons.is_empty() {
+            return Err("no buttons given (expected e.g. 3,7 or all)".to_string());
+        }
+        Ok(ActiveLow::Buttons(buttons))
+    }
+}
+
+impl fmt::Display for ActiveLow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ActiveLow::All => write!(f, "all"),
+            ActiveLow::Buttons(buttons) => {
+                let buttons: Vec<_> = buttons.iter().map(u8::to_string).collect();
+                write!(f, "{}", buttons.join(","))
+            }
+        }
+    }
+}
+
+/// A complete keymap and turntable setup that ships with beatble, to start
+/// from with `--profile`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Profile {
+    pub name: &'static str,
+    /// one line on what it is for
+    pub about: &'static str,
+    pub keymap: Keymap,
+    pub turntable_sensitivity: u8,
+    pub reverse_turntable: bool,
+    /// in degrees, see AntiWobble
+    pub anti_wobble: Option<f64>,
+    pub anti_wobble_grace: Duration,
+}
+
+pub const PROFILES: [Profile; 5] = [
+    Profile {
+        name: "default",
+        about: "the built-in defaults: keys on buttons 0 to 6, E1 to E4 on 8 to 11",
+        keymap: Keymap::DEFAULT,
+        turntable_sensitivity: SCRATCH_SENSITIVITY,
+        reverse_turntable: false,
+        anti_wobble: None,
+        anti_wobble_grace: Duration::from_millis(200),
+    },
+    Profile {
+        name: "dao",
+        about: "DAO boards: E1 to E4 right after the keys, anti-wobble for a jittery turntable",
+        keymap: Keymap {
+            keys: Keymap::DEFAULT.keys,
+            options: [Some(7), Some(8), Some(9), Some(10)],
+            turntable: Some(0),
+        },
+        turntable_sensitivity: SCRATCH_SENSITIVITY,
+        reverse_turntable: false,
+        anti_wobble: Some(1.5),
+        anti_wobble_grace: Duration::from_millis(150),
+    },
+    Profile {
+        name: "phoenixwan",
+        about: "PHOENIXWAN: a turntable that sweeps the whole axis range in one turn",
+        keymap: Keymap {
+            turntable: Some(0),
+            ..Keymap::DEFAULT
+        },
+        turntable_sensitivity: 1,
+        reverse_turntable: false,
+        anti_wobble: None,
+        anti_wobble_grace: Duration::from_millis(200),
+    },
+    Profile {
+        name: "konami-entry",
+        about: "the official entry model: only axis 0 drives the turntable",
+        keymap: Keymap {
+            turntable: Some(0),
+            ..Keymap::DEFAULT
+        },
+        turntable_sensitivity: SCRATCH_SENSITIVITY,
+        reverse_turntable: false,
+        anti_wobble: None,
+        anti_wobble_grace: Duration::from_millis(200),
+    },
+    Profile {
+        name: "mirror-2p",
+        about: "a 1P controller played as 2P: keys in reverse order, the turntable reversed",
+        keymap: Keymap {
+            keys: [
+                Some(6),
+                Some(5),
+                Some(4),
+                Some(3),
+                Some(2),
+                Some(1),
+                Some(0),
+            ],
+            ..Keymap::DEFAULT
+        },
+        turntable_sensitivity: SCRATCH_SENSITIVITY,
+        reverse_turntable: true,
+        anti_wobble: None,
+        anti_wobble_grace: Duration::from_millis(200),
+    },
+];
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PROFILES
+            .iter()
+            .find(|profile| profile.name == s)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = PROFILES.iter().map(|profile| profile.name).collect();
+                format!("unknown profile: {s} (available: {})", names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the DAO clone of the request: keys on 1 to 7, Start and Select on 12 and 13
+    const DAO_CLONE: &str = r#"
+[buttons]
+1 = "B1"
+2 = "B2"
+3 = "B3"
+4 = "B4"
+5 = "B5"
+6 = "B6"
+7 = "B7"
+12 = "E1"
+13 = "E2"
+
+[axes]
+0 = "scratch"
+"#;
+
+    #[test]
+    fn a_mapping_file_names_the_control_on_each_code() {
+        let keymap = Keymap::from_mapping_file(DAO_CLONE).unwrap();
+        assert_eq!(
+            keymap.to_string(),
+            "B1=1,B2=2,B3=3,B4=4,B5=5,B6=6,B7=7,E1=12,E2=13,TT=0"
+        );
+        assert_eq!(keymap.normal_button(1), NormalButton::B1);
+        assert_eq!(keymap.normal_button(7), NormalButton::B7);
+        assert_eq!(keymap.option_button(13), OptionButton::E2);
+        assert!(keymap.is_turntable(0));
+        assert!(!keymap.is_turntable(1));
+
+        // TT as in --keymap
+        let aliased = DAO_CLONE.replace(r#"0 = "scratch""#, r#"0 = "TT""#);
+        assert_eq!(Keymap::from_mapping_file(&aliased).unwrap(), keymap);
+    }
+
+    #[test]
+    fn codes_a_mapping_file_leaves_out_are_ignored() {
+        let keymap = Keymap::from_mapping_file(DAO_CLONE).unwrap();
+        for button in [0, 8, 11, 14, u8::MAX] {
+            assert!(keymap.normal_button(button).is_empty(), "button {button}");
+            assert!(keymap.option_button(button).is_empty(), "button {button}");
+        }
+
+        // without an axis any axis is the turntable
+        let keymap = Keymap::from_mapping_file("[buttons]\n0 = \"B1\"").unwrap();
+        assert!(keymap.is_turntable(0) && keymap.is_turntable(5));
+        assert!(keymap.option_button(8).is_empty());
+        assert_eq!(Keymap::from_mapping_file("").unwrap(), Keymap::EMPTY);
+    }
+
+    #[test]
+    fn two_codes_on_one_control_are_rejected() {
+        for (contents, error) in [
+            (
+                "[buttons]\n1 = \"B3\"\n2 = \"B3\"",
+                "B3 is mapped from both button 1 and button 2",
+            ),
+            (
+                "[buttons]\n12 = \"E1\"\n13 = \"E1\"",
+                "E1 is mapped from both button 12 and button 13",
+            ),
+            (
+                "[axes]\n0 = \"scratch\"\n1 = \"TT\"",
+                "TT is mapped from both axis 0 and axis 1",
+            ),
+        ] {
+            assert_eq!(
+                Keymap::from_mapping_file(contents).unwrap_err(),
+                error,
+                "{contents:?}"
+            );
+        }
+        // one code on two controls is fine
+        let keymap = Keymap::from_mapping_file("[buttons]\n1 = \"B1\"\n\"01\" = \"B2\"").unwrap();
+        assert_eq!(keymap.normal_button(1), NormalButton::B1 | NormalButton::B2);
+    }
+
+    #[test]
+    fn unknown_codes_and_controls_are_rejected() {
+        for (contents, error) in [
+            ("[buttons]\nx = \"B1\"", "not a button number: x"),
+            ("[buttons]\n256 = \"B1\"", "not a button number: 256"),
+            ("[axes]\n-1 = \"scratch\"", "not an axis number: -1"),
+            (
+                "[buttons]\n1 = \"B8\"",
+                "unknown control on button 1: B8 (expected B1 to B7 or E1 to E4)",
+            ),
+            (
+                "[buttons]\n1 = \"scratch\"",
+                "unknown control on button 1: scratch (expected B1 to B7 or E1 to E4)",
+            ),
+            (
+                "[axes]\n0 = \"B1\"",
+                "unknown control on axis 0: B1 (expected scratch)",
+            ),
+        ] {
+            assert_eq!(
+                Keymap::from_mapping_file(contents).unwrap_err(),
+                error,
+                "{contents:?}"
+            );
+        }
+        assert!(Keymap::from_mapping_file("[hats]\n0 = \"B1\"").is_err());
+        assert!(Keymap::from_mapping_file("[buttons]\n1 = 3").is_err());
+    }
+
+    #[test]
+    fn without_a_file_the_default_keymap_applies() {
+        let mapping = InputMapping::from(Emulation::Iidx);
+        assert_eq!(mapping.keymap, Keymap::DEFAULT);
+        assert_eq!(Keymap::default(), Keymap::DEFAULT);
+        for (button, key) in (0..).zip(KEYS) {
+            assert_eq!(mapping.keymap.normal_button(button), key);
+        }
+        for (button, option) in (8..).zip(OPTIONS) {
+            assert_eq!(mapping.keymap.option_button(button), option);
+        }
+        assert!(mapping.keymap.normal_button(7).is_empty());
+        assert!(mapping.keymap.option_button(7).is_empty());
+        assert!((0..=u8::MAX).all(|axis| mapping.keymap.is_turntable(axis)));
+    }
+}
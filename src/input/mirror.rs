@@ -0,0 +1,112 @@
+// --mirror-uinput: the controller as a uinput gamepad, for software on this
+// machine while beatble holds the real one. On its joystick node B1 to B7 are
+// buttons 0 to 6 and E1 to E4 buttons 7 to 10, as with the dao profile; the
+// turntable, or VOL-L and VOL-R, are axes 0 and 1 from 0 to 255.
+
+use beatble_protocol::{KeyInput, NormalButton, OptionButton};
+
+use super::error::InputError;
+use super::platform::linux::{UinputDevice, EV_ABS, EV_KEY};
+use crate::emulation::Emulation;
+
+/// What the mirror calls itself, so nothing reads it as a controller.
+pub const MIRROR_NAME: &str = "beatble mirror";
+
+// linux/input-event-codes.h
+const BTN_TRIGGER: u16 = 0x120;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+
+const KEYS: [NormalButton; 7] = [
+    NormalButton::B1,
+    NormalButton::B2,
+    NormalButton::B3,
+    NormalButton::B4,
+    NormalButton::B5,
+    NormalButton::B6,
+    NormalButton::B7,
+];
+const OPTIONS: [OptionButton; 4] = [
+    OptionButton::E1,
+    OptionButton::E2,
+    OptionButton::E3,
+    OptionButton::E4,
+];
+
+/// A uinput gamepad that shows the key input written to it, removed when
+/// dropped.
+pub struct Mirror {
+    device: UinputDevice,
+    emulation: Emulation,
+    // what the device shows
+    shown: KeyInput,
+}
+
+impl Mirror {
+    pub fn create(emulation: Emulation) -> Result<Self, InputError> {
+        Self::create_named(MIRROR_NAME, emulation)
+    }
+
+    /// A gamepad like a Mirror under another name, which beatble may read.
+    pub fn create_named(name: &str, emulation: Emulation) -> Result<Self, InputError> {
+        let buttons: Vec<u16> = (0..(KEYS.len() + OPTIONS.len()) as u16)
+            .map(|n| BTN_TRIGGER + n)
+            .collect();
+        let axes: &[(u16, i32)] = match emulation {
+            Emulation::Iidx => &[(ABS_X, 0xff)],
+            Emulation::Sdvx => &[(ABS_X, 0xff), (ABS_Y, 0xff)],
+        };
+        let device = UinputDevice::create(name, &buttons, axes)?;
+        Ok(Self {
+            device,
+            emulation,
+            shown: KeyInput::init(),
+        })
+    }
+
+    /// The joystick node of the device, once the kernel made one; udev may
+    /// still be creating the file.
+    pub fn joystick(&self) -> Result<Option<String>, InputError> {
+        let sysfs = format!("/sys/class/input/{}", self.device.sysname()?);
+        let entries = std::fs::read_dir(&sysfs).map_err(|source| InputError::ReadDir {
+            path: sysfs.clone(),
+            source,
+        })?;
+        for entry in entries {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with("js") {
+                return Ok(Some(format!("/dev/input/{name}")));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Shows key_input, in one report with whatever changed since the last.
+    pub fn update(&mut self, key_input: KeyInput) -> Result<(), InputError> {
+        let shown = self.shown;
+        let keys = KEYS.iter().map(|&key| {
+            let (before, now) = (shown.normal_button, key_input.normal_button);
+            (before.contains(key), now.contains(key))
+        });
+        let options = OPTIONS.iter().map(|&option| {
+            let (before, now) = (shown.option_button, key_input.option_button);
+            (before.contains(option), now.contains(option))
+        });
+        let mut changes: Vec<(u16, u16, i32)> = (BTN_TRIGGER..)
+            .zip(keys.chain(options))
+            .filter(|(_, (before, now))| before != now)
+            .map(|(code, (_, now))| (EV_KEY, code, now.into()))
+            .collect();
+        if shown.scratch != key_input.scratch {
+            changes.push((EV_ABS, ABS_X, key_input.scratch.into()));
+        }
+        // the low byte of the turntable position in IIDX mode
+        if self.emulation == Emulation::Sdvx && shown.analog != key_input.analog {
+            changes.push((EV_ABS, ABS_Y, key_input.analog.into()));
+        }
+        self.device.report(&changes)?;
+        self.shown = key_input;
+        Ok(())
+    }
+}
@@ -1,16 +1,22 @@
 // https://www.kernel.org/doc/Documentation/input/joystick-api.txt
 // https://github.com/torvalds/linux/blob/v5.10/include/uapi/linux/joystick.h
+// https://github.com/torvalds/linux/blob/v5.10/drivers/input/joydev.c
 
-use std::os::unix::io::RawFd;
+use std::mem::size_of;
+use std::os::unix::io::{AsFd, BorrowedFd, RawFd};
+use std::time::Duration;
 
 use crate::input::platform::linux::ioctl::CorrectionType;
+use crate::input::InputError;
 use bitflags::bitflags;
-use eyre::Result;
 use nix::errno::Errno;
-use nix::{fcntl, unistd};
+use nix::{fcntl, libc, unistd};
 use thiserror::Error;
 
+/// A joystick event.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Event {
     ButtonPressed(u8),
     ButtonReleased(u8),
@@ -19,6 +25,7 @@ pub enum Event {
     Error(String),
 }
 
+/// Why a device couldn't be opened, for hints on what to fix.
 #[derive(Debug, Error)]
 pub enum OpenError {
     #[error("DeviceFileNotFound: {0}")]
@@ -28,14 +35,13 @@ pub enum OpenError {
     #[error("InvalidPath: {0}")]
     InvalidPath(String),
     #[error("Unknown: {0}")]
-    Unknown(eyre::Report),
+    Unknown(Errno),
 }
 
-#[allow(dead_code)]
 pub struct DeviceInfo {
-    axes: u8,
-    buttons: u8,
-    name: String,
+    pub axes: u8,
+    pub buttons: u8,
+    pub name: String,
 }
 
 impl std::fmt::Display for DeviceInfo {
@@ -65,7 +71,10 @@ mod ioctl {
     use std::mem::size_of;
 
     use nix::errno::Errno;
-    use nix::{ioctl_read, ioctl_read_buf, libc, request_code_read, request_code_write};
+    use nix::{
+        ioctl_none, ioctl_read, ioctl_read_buf, ioctl_write_int, ioctl_write_ptr, libc,
+        request_code_read, request_code_write,
+    };
 
     #[repr(u16)]
     #[derive(Debug, Clone, Default)]
@@ -95,6 +104,31 @@ mod ioctl {
     ioctl_read!(js_get_buttons, JS_IOC_MAGIC, JS_IOC_TYPE_GET_BUTTONS, u8);
     ioctl_read_buf!(js_get_name, JS_IOC_MAGIC, JS_IOC_TYPE_GET_NAME, u8);
 
+    // linux/input.h
+    const EV_IOC_MAGIC: u8 = b'E';
+    const EV_IOC_TYPE_GET_NAME: u8 = 0x06;
+    // 0x20 + the event type
+    const EV_IOC_TYPE_GET_KEY_BITS: u8 = 0x21;
+    const EV_IOC_TYPE_GET_ABS_BITS: u8 = 0x23;
+    const EV_IOC_TYPE_GRAB: u8 = 0x90;
+
+    ioctl_read_buf!(ev_get_name, EV_IOC_MAGIC, EV_IOC_TYPE_GET_NAME, u8);
+    ioctl_read_buf!(ev_get_key_bits, EV_IOC_MAGIC, EV_IOC_TYPE_GET_KEY_BITS, u8);
+    ioctl_read_buf!(ev_get_abs_bits, EV_IOC_MAGIC, EV_IOC_TYPE_GET_ABS_BITS, u8);
+    ioctl_write_int!(ev_grab, EV_IOC_MAGIC, EV_IOC_TYPE_GRAB);
+
+    // linux/uinput.h
+    const UI_IOC_MAGIC: u8 = b'U';
+
+    ioctl_none!(ui_dev_create, UI_IOC_MAGIC, 1);
+    ioctl_none!(ui_dev_destroy, UI_IOC_MAGIC, 2);
+    ioctl_write_ptr!(ui_dev_setup, UI_IOC_MAGIC, 3, libc::uinput_setup);
+    ioctl_write_ptr!(ui_abs_setup, UI_IOC_MAGIC, 4, libc::uinput_abs_setup);
+    ioctl_write_int!(ui_set_evbit, UI_IOC_MAGIC, 100);
+    ioctl_write_int!(ui_set_keybit, UI_IOC_MAGIC, 101);
+    ioctl_write_int!(ui_set_absbit, UI_IOC_MAGIC, 103);
+    ioctl_read_buf!(ui_get_sysname, UI_IOC_MAGIC, 44, u8);
+
     const REQ_SET_CORRECTION: libc::c_ulong = request_code_write!(
         JS_IOC_MAGIC,
         JS_IOC_TYPE_SET_CORRECTION,
@@ -126,11 +160,12 @@ mod ioctl {
 impl From<RawEvent> for Option<Event> {
     #[inline]
     fn from(ev: RawEvent) -> Self {
-        if ev.typ.contains(EventType::INIT) {
-            // ignore init event
-            return None;
-        }
-        match ev.typ {
+        // the initial state of a button reads like a change, so an active-low
+        // button is turned around from the start, but the turntable only
+        // starts where it is first moved
+        let init = ev.typ.contains(EventType::INIT);
+        match ev.typ.difference(EventType::INIT) {
+            EventType::AXIS if init => None,
             EventType::BUTTON => {
                 if ev.value == 0 {
                     Some(Event::ButtonReleased(ev.number))
@@ -143,33 +178,64 @@ impl From<RawEvent> for Option<Event> {
                 let value = ev.value << 8;
                 Some(Event::AxisChanged(ev.number, value))
             }
-            _ => unreachable!(),
+            // a type newer than this reader, nothing to map it to
+            _ => None,
         }
     }
 }
 
+impl Event {
+    /// Parses one `js_event` as read from a joystick node, None for the
+    /// initial axis positions and types it doesn't know.
+    #[inline]
+    pub fn parse(buf: [u8; 8]) -> Option<Event> {
+        let raw_ev = unsafe { std::mem::transmute::<[u8; 8], RawEvent>(buf) };
+        raw_ev.into()
+    }
+
+    /// Like parse, with the event's timestamp: milliseconds on a clock of the
+    /// kernel's, only good for the time between events.
+    pub fn parse_timed(buf: [u8; 8]) -> Option<(Duration, Event)> {
+        let raw_ev = unsafe { std::mem::transmute::<[u8; 8], RawEvent>(buf) };
+        let time = Duration::from_millis(raw_ev.time.into());
+        Option::<Event>::from(raw_ev).map(|event| (time, event))
+    }
+}
+
+fn open_input(path: &str) -> Result<RawFd, OpenError> {
+    // mode is dummy
+    fcntl::open(path, fcntl::OFlag::O_RDONLY, nix::sys::stat::Mode::S_IRUSR).map_err(|err| {
+        use OpenError::*;
+
+        match err {
+            Errno::ENOENT => DeviceFileNotFound(path.to_owned()),
+            Errno::EPERM | Errno::EACCES => PermissionDenied(path.to_owned()),
+            Errno::EINVAL => InvalidPath(path.to_owned()),
+            e => Unknown(e),
+        }
+    })
+}
+
+fn set_nonblocking(fd: RawFd, nonblocking: bool) -> Result<(), InputError> {
+    let flags = fcntl::fcntl(fd, fcntl::FcntlArg::F_GETFL)?;
+    let mut flags = fcntl::OFlag::from_bits_truncate(flags);
+    flags.set(fcntl::OFlag::O_NONBLOCK, nonblocking);
+    fcntl::fcntl(fd, fcntl::FcntlArg::F_SETFL(flags))?;
+    Ok(())
+}
+
 pub struct Device(RawFd);
 
 impl Device {
-    pub fn open(path: &str) -> Result<Self> {
-        // mode is dummy
-        let fd = fcntl::open(path, fcntl::OFlag::O_RDONLY, nix::sys::stat::Mode::S_IRUSR).map_err(
-            |err| {
-                use OpenError::*;
-
-                match err {
-                    Errno::ENOENT => DeviceFileNotFound(path.to_owned()),
-                    Errno::EPERM => PermissionDenied(path.to_owned()),
-                    Errno::EINVAL => InvalidPath(path.to_owned()),
-                    e => Unknown(e.into()),
-                }
-            },
-        )?;
+    pub fn open(path: &str) -> Result<Self, OpenError> {
+        Ok(Self(open_input(path)?))
+    }
 
-        Ok(Self(fd))
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), InputError> {
+        set_nonblocking(self.0, nonblocking)
     }
 
-    pub fn disable_correction(&self) -> Result<()> {
+    pub fn disable_correction(&self) -> Result<(), InputError> {
         let corr = unsafe {
             let mut axes = 0u8;
             ioctl::js_get_axes(self.0, &mut axes)?;
@@ -194,7 +260,7 @@ impl Device {
         Ok(())
     }
 
-    pub fn info(&self) -> Result<DeviceInfo> {
+    pub fn info(&self) -> Result<DeviceInfo, InputError> {
         let mut axes = 0u8;
         let mut buttons = 0u8;
         let mut name = [0u8; 128];
@@ -214,11 +280,320 @@ impl Device {
             name,
         })
     }
+
+    /// the next event as the driver reports it, blocking
+    pub fn read_raw(&self) -> Result<[u8; 8], InputError> {
+        let mut buf = [0u8; 8];
+        match unistd::read(self.0, &mut buf) {
+            Ok(8) => Ok(buf),
+            Ok(n) => Err(InputError::ShortRead(n)),
+            Err(Errno::ENODEV) => Err(InputError::Disconnected),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// whether another process has an exclusive grab on an evdev node, which
+/// keeps its events from ever reaching the joystick node
+pub fn is_grabbed(evdev: &str) -> Result<bool, InputError> {
+    let fd = fcntl::open(evdev, fcntl::OFlag::O_RDONLY, nix::sys::stat::Mode::empty())?;
+    let grabbed = match unsafe { ioctl::ev_grab(fd, 1) } {
+        Ok(_) => unsafe { ioctl::ev_grab(fd, 0) }.map(|_| false),
+        Err(Errno::EBUSY) => Ok(true),
+        Err(e) => Err(e),
+    };
+    unistd::close(fd)?;
+    Ok(grabbed?)
+}
+
+/// An evdev event with the time the kernel queued it.
+pub struct TimedEvent {
+    pub kind: u16,
+    pub code: u16,
+    /// since the epoch on CLOCK_REALTIME, the evdev default
+    pub time: Duration,
+}
+
+impl TimedEvent {
+    /// the end of one report from the device
+    pub fn is_report(&self) -> bool {
+        self.kind == EV_SYN && self.code == SYN_REPORT
+    }
+}
+
+const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0;
+const BTN_MISC: u16 = 0x100;
+pub const BTN_JOYSTICK: u16 = 0x120;
+const KEY_CNT: usize = 0x300;
+const ABS_CNT: usize = 0x40;
+// an autorepeat, as opposed to a press (1) or release (0)
+const KEY_REPEAT: i32 = 2;
+const BUS_VIRTUAL: u16 = 0x06;
+const UINPUT: &str = "/dev/uinput";
+
+/// An evdev node, read for its microsecond timestamps; the joystick node only
+/// has milliseconds.
+pub struct EventDevice(RawFd);
+
+impl EventDevice {
+    pub fn open(path: &str) -> Result<Self, InputError> {
+        let fd = fcntl::open(path, fcntl::OFlag::O_RDONLY, nix::sys::stat::Mode::empty())?;
+        Ok(Self(fd))
+    }
+
+    /// blocks until the next event
+    pub fn read(&self) -> Result<TimedEvent, InputError> {
+        let mut buf = [0u8; size_of::<libc::input_event>()];
+        let n = unistd::read(self.0, &mut buf)?;
+        if n != buf.len() {
+            return Err(InputError::ShortRead(n));
+        }
+        let event = unsafe { std::ptr::read_unaligned(buf.as_ptr().cast::<libc::input_event>()) };
+        Ok(TimedEvent {
+            kind: event.type_,
+            code: event.code,
+            time: Duration::new(event.time.tv_sec as u64, event.time.tv_usec as u32 * 1000),
+        })
+    }
+}
+
+/// An evdev node read in place of the joystick node, with the events the
+/// joystick node would have: buttons and axes are numbered as joydev numbers
+/// them, so a keymap means the same with either, and an axis has the value
+/// the joystick node reports without correction.
+pub struct EvdevDevice {
+    fd: RawFd,
+    /// button number by key code
+    buttons: Vec<Option<u8>>,
+    /// axis number by axis code
+    axes: [Option<u8>; ABS_CNT],
+}
+
+impl EvdevDevice {
+    pub fn open(path: &str) -> Result<Self, OpenError> {
+        let fd = open_input(path)?;
+        let mut device = Self {
+            fd,
+            buttons: vec![None; KEY_CNT],
+            axes: [None; ABS_CNT],
+        };
+        device.number().map_err(OpenError::Unknown)?;
+        Ok(device)
+    }
+
+    /// Numbers the device's keys and axes as joydev_connect does: the joystick
+    /// buttons from BTN_JOYSTICK up, then the BTN_MISC ones below them, and
+    /// the axes from ABS_X up. joydev leaves out keys below BTN_MISC, which
+    /// come last here, after every number joydev uses.
+    fn number(&mut self) -> nix::Result<()> {
+        let mut keys = [0u8; KEY_CNT / 8];
+        let mut abs = [0u8; ABS_CNT / 8];
+        unsafe {
+            ioctl::ev_get_key_bits(self.fd, &mut keys)?;
+            ioctl::ev_get_abs_bits(self.fd, &mut abs)?;
+        }
+        let has = |bits: &[u8], code: usize| bits[code / 8] & (1 << (code % 8)) != 0;
+        let codes = (BTN_JOYSTICK as usize..KEY_CNT)
+            .chain(BTN_MISC as usize..BTN_JOYSTICK as usize)
+            .chain(0..BTN_MISC as usize);
+        for (number, code) in (0..=u8::MAX).zip(codes.filter(|&code| has(&keys, code))) {
+            self.buttons[code] = Some(number);
+        }
+        for (number, code) in (0..=u8::MAX).zip((0..ABS_CNT).filter(|&code| has(&abs, code))) {
+            self.axes[code] = Some(number);
+        }
+        Ok(())
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), InputError> {
+        set_nonblocking(self.fd, nonblocking)
+    }
+
+    pub fn info(&self) -> Result<DeviceInfo, InputError> {
+        let mut name = [0u8; 128];
+        unsafe { ioctl::ev_get_name(self.fd, &mut name)? };
+        let name = name.iter().copied().take_while(|&c| c != 0).collect();
+        let count = |numbers: &[Option<u8>]| numbers.iter().flatten().count() as u8;
+        Ok(DeviceInfo {
+            axes: count(&self.axes),
+            buttons: count(&self.buttons),
+            name: String::from_utf8(name)?,
+        })
+    }
+
+    /// the event for an input_event, None for ones the joystick node has no
+    /// event for
+    #[inline]
+    fn event(&self, event: &libc::input_event) -> Option<Event> {
+        match event.type_ {
+            EV_KEY if event.value != KEY_REPEAT => {
+                let button = (*self.buttons.get(event.code as usize)?)?;
+                Some(if event.value == 0 {
+                    Event::ButtonReleased(button)
+                } else {
+                    Event::ButtonPressed(button)
+                })
+            }
+            EV_ABS => {
+                let axis = (*self.axes.get(event.code as usize)?)?;
+                // as joydev clamps it, then the same scaling as a js_event
+                let value = event.value.clamp(-32767, 32767) as i16;
+                Some(Event::AxisChanged(axis, value << 8))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Iterator for EvdevDevice {
+    type Item = Event;
+
+    #[inline]
+    fn next(&mut self) -> Option<Event> {
+        let mut buf = [0u8; size_of::<libc::input_event>()];
+        match unistd::read(self.fd, &mut buf) {
+            Ok(n) if n == buf.len() => {
+                let event =
+                    unsafe { std::ptr::read_unaligned(buf.as_ptr().cast::<libc::input_event>()) };
+                self.event(&event)
+            }
+            Ok(n) => Some(Event::Error(format!("short read of {n} bytes"))),
+            // nothing to read yet in non-blocking mode
+            Err(Errno::EAGAIN) => None,
+            Err(Errno::ENODEV) => Some(Event::Disconnected),
+            Err(e) => Some(Event::Error(format!("read error: {e}"))),
+        }
+    }
+}
+
+impl AsFd for EvdevDevice {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // SAFETY: the fd stays open until the device is dropped
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+impl Drop for EvdevDevice {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.fd);
+    }
+}
+
+/// A device made up through /dev/uinput, which exists until it is dropped.
+pub struct UinputDevice(RawFd);
+
+impl UinputDevice {
+    /// A device called name with these key codes, and these absolute axes
+    /// from 0 to max.
+    pub fn create(name: &str, keys: &[u16], axes: &[(u16, i32)]) -> Result<Self, InputError> {
+        let flags = fcntl::OFlag::O_WRONLY | fcntl::OFlag::O_NONBLOCK | fcntl::OFlag::O_CLOEXEC;
+        let fd = fcntl::open(UINPUT, flags, nix::sys::stat::Mode::empty())
+            .map_err(|source| InputError::Uinput { source })?;
+        // closes fd if any step fails
+        let device = Self(fd);
+
+        let mut setup: libc::uinput_setup = unsafe { std::mem::zeroed() };
+        setup.id.bustype = BUS_VIRTUAL;
+        // one byte short of the buffer so it stays NUL-terminated
+        for (c, &b) in setup
+            .name
+            .iter_mut()
+            .zip(name.as_bytes())
+            .take(libc::UINPUT_MAX_NAME_SIZE - 1)
+        {
+            *c = b as libc::c_char;
+        }
+        unsafe {
+            ioctl::ui_set_evbit(fd, EV_KEY.into())?;
+            for &key in keys {
+                ioctl::ui_set_keybit(fd, key.into())?;
+            }
+            if !axes.is_empty() {
+                ioctl::ui_set_evbit(fd, EV_ABS.into())?;
+            }
+            for &(code, max) in axes {
+                ioctl::ui_set_absbit(fd, code.into())?;
+                let mut abs: libc::uinput_abs_setup = std::mem::zeroed();
+                abs.code = code;
+                abs.absinfo.maximum = max;
+                ioctl::ui_abs_setup(fd, &abs)?;
+            }
+            ioctl::ui_dev_setup(fd, &setup)?;
+            ioctl::ui_dev_create(fd)?;
+        }
+        Ok(device)
+    }
+
+    /// the name of the input device in /sys/class/input, e.g. input42
+    pub fn sysname(&self) -> Result<String, InputError> {
+        let mut name = [0u8; 64];
+        unsafe { ioctl::ui_get_sysname(self.0, &mut name)? };
+        let name = name.iter().copied().take_while(|&c| c != 0).collect();
+        Ok(String::from_utf8(name)?)
+    }
+
+    /// Writes key and axis changes as one report; nothing when there are none.
+    pub fn report(&self, changes: &[(u16, u16, i32)]) -> Result<(), InputError> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let event = |kind: u16, code: u16, value: i32| libc::input_event {
+            time: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            type_: kind,
+            code,
+            value,
+        };
+        let events: Vec<libc::input_event> = changes
+            .iter()
+            .map(|&(kind, code, value)| event(kind, code, value))
+            .chain([event(EV_SYN, SYN_REPORT, 0)])
+            .collect();
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                events.as_ptr().cast::<u8>(),
+                events.len() * size_of::<libc::input_event>(),
+            )
+        };
+        let fd = unsafe { BorrowedFd::borrow_raw(self.0) };
+        let n = unistd::write(fd, bytes)?;
+        if n != bytes.len() {
+            return Err(InputError::ShortWrite(n));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UinputDevice {
+    fn drop(&mut self) {
+        // closing would remove the node too, but only once every copy of
+        // the fd is gone
+        let _ = unsafe { ioctl::ui_dev_destroy(self.0) };
+        let _ = unistd::close(self.0);
+    }
+}
+
+impl Drop for EventDevice {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.0);
+    }
+}
+
+impl AsFd for Device {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // SAFETY: the fd stays open until the device is dropped
+        unsafe { BorrowedFd::borrow_raw(self.0) }
+    }
 }
 
 impl Drop for Device {
     fn drop(&mut self) {
-        unistd::close(self.0).unwrap();
+        let _ = unistd::close(self.0);
     }
 }
 
@@ -229,12 +604,48 @@ impl Iterator for Device {
     fn next(&mut self) -> Option<Event> {
         let mut buf = [0u8; 8];
         match unistd::read(self.0, &mut buf) {
-            Ok(_) => {
-                let raw_ev = unsafe { std::mem::transmute::<[u8; 8], RawEvent>(buf) };
-                raw_ev.into()
-            }
+            Ok(_) => Event::parse(buf),
+            // nothing to read yet in non-blocking mode
+            Err(Errno::EAGAIN) => None,
             Err(Errno::ENODEV) => Some(Event::Disconnected),
             Err(e) => Some(Event::Error(format!("read error: {e}"))),
         }
     }
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    // the format released with the feature, which saved files depend on
+    const PINNED: &str = r#"[{"button_pressed":3},{"button_released":3},{"axis_changed":[0,-32767]},"disconnected",{"error":"read error"}]"#;
+
+    fn events() -> Vec<Event> {
+        vec![
+            Event::ButtonPressed(3),
+            Event::ButtonReleased(3),
+            Event::AxisChanged(0, -32767),
+            Event::Disconnected,
+            Event::Error("read error".to_string()),
+        ]
+    }
+
+    // Event has no PartialEq, as an error's text isn't worth comparing elsewhere
+    fn debug(events: &[Event]) -> String {
+        format!("{events:?}")
+    }
+
+    #[test]
+    fn events_round_trip_through_serde() {
+        let json = serde_json::to_string(&events()).unwrap();
+        let decoded: Vec<Event> = serde_json::from_str(&json).unwrap();
+        assert_eq!(debug(&decoded), debug(&events()));
+    }
+
+    #[test]
+    fn events_keep_the_pinned_format() {
+        assert_eq!(serde_json::to_string(&events()).unwrap(), PINNED);
+        let decoded: Vec<Event> = serde_json::from_str(PINNED).unwrap();
+        assert_eq!(debug(&decoded), debug(&events()));
+    }
+}
@@ -0,0 +1,329 @@
+// Reading a joystick in one process for a session in another: the relay
+// writes the raw js_event of every read, 8 bytes each, and the session parses
+// them like the events of a device of its own.
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use tracing::info_span;
+
+use super::error::InputError;
+use super::gamepad::{open, spawn_reader, InputHandler};
+use super::lock::DeviceLock;
+use super::mapping::InputMapping;
+use super::platform::linux::{Device, DeviceInfo, Event};
+use super::shared::SharedKeyInput;
+use crate::stats::Stats;
+
+/// A joystick opened and locked for relaying, e.g. before dropping the
+/// privileges it took to open it.
+pub struct RelaySource {
+    device: Device,
+    info: DeviceInfo,
+    _lock: Option<DeviceLock>,
+}
+
+/// Opens input the way attach_input_handler does, to relay its events instead.
+pub fn open_relay_source(input: &str, force: bool) -> Result<RelaySource, InputError> {
+    let device = open(input)?;
+    let lock = DeviceLock::acquire(&device, input, force)?;
+    let info = device.info()?;
+    device.disable_correction()?;
+    Ok(RelaySource {
+        device,
+        info,
+        _lock: lock,
+    })
+}
+
+impl RelaySource {
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    /// Copies events to out as they are read, until the device or out fails.
+    pub fn relay(self, mut out: impl Write) -> Result<(), InputError> {
+        loop {
+            let event = self.device.read_raw()?;
+            out.write_all(&event)?;
+            out.flush()?;
+        }
+    }
+}
+
+/// Like attach_input_handler, for the events a RelaySource writes to events.
+/// The stream ending fails the reader like a disconnected device.
+pub fn attach_event_stream(
+    name: &str,
+    events: impl Read + Send + 'static,
+    shared_key_input: Arc<SharedKeyInput>,
+    mapping: InputMapping,
+    stats: Arc<Stats>,
+) -> Result<InputHandler, InputError> {
+    let span = info_span!("input", device = name);
+    spawn_reader(
+        span,
+        (),
+        EventStream(events),
+        shared_key_input,
+        mapping,
+        stats,
+    )
+}
+
+struct EventStream<R>(R);
+
+impl<R: Read> Iterator for EventStream<R> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        let mut buf = [0u8; 8];
+        match self.0.read_exact(&mut buf) {
+            Ok(()) => Event::parse(buf),
+            // the relay is gone, with its device or on its own
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Some(Event::Disconnected),
+            Err(e) => Some(Event::Error(format!("read error: {e}"))),
+        }
+    }
+}
+
+// The whole pipeline: events through a reader thread and the mapping, the
+// key input characteristic with a mock central subscribed, and the frames it
+// is notified of. The frames are normalized to the distinct consecutive
+// sub-reports, as how many frames each state lasts depends on the timing.
+#[cfg(all(test, feature = "ble"))]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    use beatble_protocol::payload::{Frame, Layout, ScratchMode};
+    use bluster::gatt::event::{Event as GattEvent, NotifySubscribe};
+    use futures::channel::mpsc::{channel, Receiver};
+    use futures::{SinkExt, StreamExt};
+
+    use self::Step::{Axis, Button, Hold};
+    use super::*;
+    use crate::ble::{
+        create_key_input_with_events, NotifyConfig, NotifyContext, NotifyMode, RepeatSpacing,
+    };
+    use crate::control::Control;
+    use crate::emulation::Emulation;
+    use crate::latency::Latency;
+
+    const GOLDEN: &str = include_str!("../../assets/pipeline/golden.txt");
+    const INTERVAL: Duration = Duration::from_millis(8);
+    // several frames per state, so none is missed however the clocks line up
+    const HOLD: Duration = Duration::from_millis(40);
+    const JS_EVENT_BUTTON: u8 = 0x01;
+    const JS_EVENT_AXIS: u8 = 0x02;
+
+    #[derive(Clone, Copy)]
+    enum Step {
+        Button(u8, bool),
+        Axis(u8, i16),
+        Hold,
+    }
+
+    /// taps, a chord, the turntable across the wrap of the axis, an option
+    /// button; axis values are a byte, as joysticks of the kind report them
+    const SCRIPT: &[Step] = &[
+        Hold,
+        Button(0, true),
+        Hold,
+        Button(0, false),
+        Hold,
+        Button(2, true),
+        Hold,
+        Button(4, true),
+        Hold,
+        Button(2, false),
+        Hold,
+        Button(4, false),
+        Hold,
+        Axis(0, 0x7e),
+        Hold,
+        Axis(0, 0x7f),
+        Hold,
+        Axis(0, 0x80),
+        Hold,
+        Axis(0, 0x81),
+        Hold,
+        Button(8, true),
+        Hold,
+        Button(8, false),
+        Hold,
+    ];
+
+    fn js_event(step: Step) -> [u8; 8] {
+        let (value, kind, number) = match step {
+            Button(number, pressed) => (i16::from(pressed), JS_EVENT_BUTTON, number),
+            Axis(number, value) => (value, JS_EVENT_AXIS, number),
+            Hold => unreachable!("not an event"),
+        };
+        let mut buf = [0u8; 8];
+        buf[4..6].copy_from_slice(&value.to_ne_bytes());
+        buf[6] = kind;
+        buf[7] = number;
+        buf
+    }
+
+    fn notify_config() -> NotifyConfig {
+        NotifyConfig {
+            interval: INTERVAL,
+            mode: NotifyMode::Periodic,
+            warmup_frames: 0,
+            counter_start: 0,
+            frame_repeat: 1,
+            repeat_spacing: RepeatSpacing::BackToBack,
+            busy_poll: false,
+            layout: Layout::default(),
+            emulation: Emulation::Iidx,
+            scratch_mode: ScratchMode::Position,
+            scratch_predict: None,
+        }
+    }
+
+    /// Subscribes a mock central to a key input service of key_input, plays
+    /// into its reader and takes the frames until a while after play returns.
+    async fn capture(
+        key_input: Arc<SharedKeyInput>,
+        play: impl FnOnce() + Send + 'static,
+    ) -> Vec<Frame> {
+        let notify_config = notify_config();
+        let context = NotifyContext {
+            key_input,
+            control: Arc::new(Control::new(notify_config.interval, notify_config.mode)),
+            latency: Arc::new(Latency::new()),
+            stats: Arc::new(Stats::new()),
+            dump: None,
+        };
+        let (_service, mut events) = create_key_input_with_events(context, notify_config);
+        let (notification, mut notifications) = channel(64);
+        events
+            .send(GattEvent::NotifySubscribe(NotifySubscribe { notification }))
+            .await
+            .expect("the characteristic takes events");
+
+        let player = thread::spawn(play);
+        let mut frames = Vec::new();
+        while !player.is_finished() {
+            frames.push(next_frame(&mut notifications).await);
+        }
+        player.join().expect("the player finished");
+        for _ in 0..HOLD.as_millis() / INTERVAL.as_millis() {
+            frames.push(next_frame(&mut notifications).await);
+        }
+        frames
+    }
+
+    async fn next_frame(notifications: &mut Receiver<Vec<u8>>) -> Frame {
+        let payload = notifications.next().await.expect("the notifier stopped");
+        Frame::decode(&payload, Layout::default()).expect("a valid frame")
+    }
+
+    /// the states the frames went through, one line each as in the golden file
+    fn normalize(frames: &[Frame]) -> Vec<String> {
+        let mut states: Vec<String> = frames
+            .iter()
+            .flat_map(|frame| [frame.first, frame.second])
+            .map(|key_input| {
+                format!(
+                    "{:02x} {:07b} {:04b}",
+                    key_input.scratch,
+                    key_input.normal_button.bits(),
+                    key_input.option_button.bits()
+                )
+            })
+            .collect();
+        states.dedup();
+        states
+    }
+
+    fn golden() -> Vec<&'static str> {
+        GOLDEN
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.is_empty())
+            .collect()
+    }
+
+    fn assert_counters_continue(frames: &[Frame]) {
+        let step = Layout::default().counter_step();
+        for pair in frames.windows(2) {
+            assert_eq!(
+                pair[1].counter,
+                pair[0].counter.wrapping_add(step),
+                "a frame was skipped or repeated"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn events_through_the_pipeline_match_the_golden_frames() {
+        let key_input = Arc::new(SharedKeyInput::new());
+        let (mut relay, stream) = UnixStream::pair().expect("a socket pair");
+        let mapping = InputMapping::from(Emulation::Iidx);
+        let stats = Arc::new(Stats::new());
+        let _reader =
+            attach_event_stream("pipeline", stream, Arc::clone(&key_input), mapping, stats)
+                .expect("a reader");
+        let frames = capture(key_input, move || {
+            for &step in SCRIPT {
+                match step {
+                    Hold => thread::sleep(HOLD),
+                    event => relay.write_all(&js_event(event)).expect("writable"),
+                }
+            }
+        })
+        .await;
+        assert_counters_continue(&frames);
+        assert_eq!(normalize(&frames), golden());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs write access to /dev/uinput"]
+    async fn a_virtual_joystick_gives_the_same_frames() {
+        use super::super::platform::linux::{UinputDevice, BTN_JOYSTICK, EV_ABS, EV_KEY};
+
+        let buttons: Vec<u16> = (0..12).map(|button| BTN_JOYSTICK + button).collect();
+        // ABS_X, which joydev numbers axis 0
+        let device = UinputDevice::create("beatble pipeline test", &buttons, &[(0, 0xff)])
+            .expect("a uinput device");
+        let sysfs = format!("/sys/class/input/{}", device.sysname().expect("a sysname"));
+        // udev may still be creating the node
+        thread::sleep(Duration::from_millis(500));
+        let joystick = std::fs::read_dir(&sysfs)
+            .expect("a sysfs entry")
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .find(|name| name.starts_with("js"))
+            .expect("a joystick node");
+
+        let key_input = Arc::new(SharedKeyInput::new());
+        let _reader = super::super::gamepad::attach_input_handler(
+            &format!("/dev/input/{joystick}"),
+            Arc::clone(&key_input),
+            InputMapping::from(Emulation::Iidx),
+            false,
+            false,
+            Arc::new(Stats::new()),
+        )
+        .expect("a reader");
+        let frames = capture(key_input, move || {
+            for &step in SCRIPT {
+                match step {
+                    Hold => thread::sleep(HOLD),
+                    Button(button, pressed) => device
+                        .report(&[(EV_KEY, BTN_JOYSTICK + u16::from(button), pressed.into())])
+                        .expect("reported"),
+                    Axis(axis, value) => device
+                        .report(&[(EV_ABS, axis.into(), value.into())])
+                        .expect("reported"),
+                }
+            }
+        })
+        .await;
+        assert_counters_continue(&frames);
+        assert_eq!(normalize(&frames), golden());
+    }
+}
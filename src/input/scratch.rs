@@ -0,0 +1,259 @@
+// Anti-wobble for charge scratches: a hand holding the turntable still jitters
+// it back and forth by a few units, and the game may drop a charge on every
+// flip of direction. Once the turntable has been turning one way, a movement
+// back by less than the threshold is held until the grace period has passed
+// since the last movement forward; a larger one is a reversal and passes at
+// once.
+//
+// Recentering, for turntables with no fixed zero: the first position a reader
+// sees becomes the reference and positions are reported relative to it, so the
+// turntable starts at 0 wherever it was left. Holding a chord of buttons
+// re-zeroes it on the current position.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// turntable position units per full turn, see convert_scratch
+const UNITS_PER_TURN: f64 = 65536.0;
+
+/// Settings of the anti-wobble filter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AntiWobble {
+    /// in turntable position units, 65536 per turn
+    pub threshold: u16,
+    pub grace: Duration,
+}
+
+impl AntiWobble {
+    pub fn new(degrees: f64, grace: Duration) -> Self {
+        let units = (degrees / 360.0 * UNITS_PER_TURN).round();
+        Self {
+            threshold: units.clamp(0.0, u16::MAX as f64) as u16,
+            grace,
+        }
+    }
+}
+
+/// Directional hysteresis over the positions of one turntable.
+#[derive(Clone, Debug)]
+pub struct WobbleFilter {
+    config: AntiWobble,
+    // the last position passed on
+    position: Option<u16>,
+    // -1 or 1 once the turntable has moved
+    direction: i16,
+    // clock::now() of the last movement passed on
+    moved_at: u64,
+}
+
+impl WobbleFilter {
+    pub fn new(config: AntiWobble) -> Self {
+        Self {
+            config,
+            position: None,
+            direction: 0,
+            moved_at: 0,
+        }
+    }
+
+    /// The position to report for position, read at now in clock::now() nanoseconds.
+    pub fn filter(&mut self, position: u16, now: u64) -> u16 {
+        let Some(last) = self.position else {
+            self.position = Some(position);
+            self.moved_at = now;
+            return position;
+        };
+        // the turntable wraps, so the shorter way round is the movement
+        let delta = position.wrapping_sub(last) as i16;
+        if delta == 0 {
+            return last;
+        }
+        let back = self.direction != 0 && delta.signum() != self.direction;
+        let small = delta.unsigned_abs() < self.config.threshold;
+        let in_grace = now.saturating_sub(self.moved_at) < self.config.grace.as_nanos() as u64;
+        if back && small && in_grace {
+            return last;
+        }
+        self.position = Some(position);
+        self.direction = delta.signum();
+        self.moved_at = now;
+        position
+    }
+}
+
+/// Joystick buttons held together, e.g. `8+9`. Buttons are counted from 0 as
+/// jstest does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chord(Vec<u8>);
+
+impl Chord {
+    pub fn buttons(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut buttons = Vec::new();
+        for button in s.split('+') {
+            let button = button.trim().parse::<u8>().map_err(|_| {
+                format!("invalid chord button: {button} (expected button numbers joined by +, e.g. 8+9)")
+            })?;
+            if buttons.contains(&button) {
+                return Err(format!("button {button} is in the chord twice"));
+            }
+            buttons.push(button);
+        }
+        Ok(Self(buttons))
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, button) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "+")?;
+            }
+            write!(f, "{button}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings of turntable recentering.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recenter {
+    /// re-zeroes the turntable when held, if any
+    pub chord: Option<Chord>,
+}
+
+/// Positions of one turntable relative to a reference zero.
+#[derive(Clone, Debug)]
+pub struct RecenterFilter {
+    config: Recenter,
+    reference: Option<u16>,
+    // the last position as read, before recentering
+    raw: Option<u16>,
+    // the chord's buttons held down
+    held: Vec<u8>,
+}
+
+impl RecenterFilter {
+    pub fn new(config: Recenter) -> Self {
+        Self {
+            config,
+            reference: None,
+            raw: None,
+            held: Vec::new(),
+        }
+    }
+
+    /// the position as read that is reported as 0, once one was read
+    pub fn reference(&self) -> Option<u16> {
+        self.reference
+    }
+
+    /// The position to report for position; the first one becomes the reference.
+    pub fn filter(&mut self, position: u16) -> u16 {
+        self.raw = Some(position);
+        position.wrapping_sub(*self.reference.get_or_insert(position))
+    }
+
+    /// Tracks a button of the chord. Returns the new reference when this press
+    /// completes the chord and a position was read to re-zero on.
+    pub fn button(&mut self, button: u8, pressed: bool) -> Option<u16> {
+        let chord = self.config.chord.as_ref()?;
+        if !chord.buttons().contains(&button) {
+            return None;
+        }
+        if !pressed {
+            self.held.retain(|&held| held != button);
+            return None;
+        }
+        if self.held.contains(&button) {
+            return None;
+        }
+        self.held.push(button);
+        if self.held.len() < chord.buttons().len() {
+            return None;
+        }
+        self.reference = self.raw;
+        self.reference
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    /// what the filter reports for a trace of (milliseconds, position)
+    fn play(config: AntiWobble, trace: &[(u64, u16)]) -> Vec<u16> {
+        let mut filter = WobbleFilter::new(config);
+        trace
+            .iter()
+            .map(|&(at, position)| filter.filter(position, at * MS))
+            .collect()
+    }
+
+    // 2 degrees is 364 units
+    fn config() -> AntiWobble {
+        AntiWobble::new(2.0, Duration::from_millis(200))
+    }
+
+    #[test]
+    fn degrees_become_position_units() {
+        assert_eq!(config().threshold, 364);
+        assert_eq!(AntiWobble::new(360.0, Duration::ZERO).threshold, u16::MAX);
+    }
+
+    #[test]
+    fn jitter_while_holding_a_charge_is_held() {
+        let trace = [
+            (0, 1000),
+            (10, 1400),
+            (20, 1800),
+            // the hand holds still and the turntable jitters
+            (30, 1750),
+            (40, 1810),
+            (50, 1700),
+            (60, 1790),
+            (70, 1800),
+        ];
+        assert_eq!(
+            play(config(), &trace),
+            [1000, 1400, 1800, 1800, 1810, 1810, 1810, 1810]
+        );
+    }
+
+    #[test]
+    fn a_real_reversal_passes_at_once() {
+        let trace = [(0, 1000), (10, 1800), (20, 1300), (30, 900)];
+        assert_eq!(play(config(), &trace), [1000, 1800, 1300, 900]);
+    }
+
+    #[test]
+    fn a_small_movement_back_passes_after_the_grace_period() {
+        let trace = [
+            (0, 1000),
+            (10, 1800),
+            (100, 1700),
+            (209, 1700),
+            // 200ms after the last movement forward
+            (210, 1700),
+            (220, 1650),
+        ];
+        assert_eq!(play(config(), &trace), [1000, 1800, 1800, 1800, 1700, 1650]);
+    }
+
+    #[test]
+    fn the_shorter_way_round_is_the_direction() {
+        // forward across the wrap, then jitter back across it
+        let trace = [(0, 65000), (10, 65400), (20, 200), (30, 65500), (40, 300)];
+        assert_eq!(play(config(), &trace), [65000, 65400, 200, 200, 300]);
+    }
+}
@@ -0,0 +1,442 @@
+// SDL game controller mappings, as collected in the community's
+// gamecontrollerdb.txt: one device per line,
+//
+//   GUID,NAME,CONTROL:BINDING,...,platform:Linux,
+//
+// e.g. `a:b0,leftx:a0,dpup:h0.1`. Only buttons and axes are used; hats, half
+// axes and inversion are read but don't make a key or turntable. Lines for
+// other platforms never match a joystick node.
+//
+// On Linux the GUID is built from the device's input ids, each a
+// little-endian u16 padded to 32 bits:
+//
+//   bustype crc16 | vendor 0 | product 0 | version 0
+
+use std::fmt;
+use std::str::FromStr;
+
+use tracing::warn;
+
+use super::mapping::Keymap;
+
+const PLATFORM: &str = "Linux";
+
+/// What one SDL control is bound to on the joystick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdlBinding {
+    Button(u8),
+    Axis(u8),
+    /// hat and direction bit
+    Hat(u8, u8),
+}
+
+impl FromStr for SdlBinding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // half axes (+a1, -a1) and inverted ones (a1~) are still the axis
+        let binding = s.trim_start_matches(['+', '-']).trim_end_matches('~');
+        let number = |n: &str| {
+            n.parse()
+                .map_err(|_| format!("not a button, axis or hat: {s}"))
+        };
+        match binding.split_at_checked(1) {
+            Some(("b", n)) => Ok(Self::Button(number(n)?)),
+            Some(("a", n)) => Ok(Self::Axis(number(n)?)),
+            Some(("h", n)) => {
+                let (hat, direction) = n
+                    .split_once('.')
+                    .ok_or_else(|| format!("expected hN.M: {s}"))?;
+                Ok(Self::Hat(number(hat)?, number(direction)?))
+            }
+            _ => Err(format!("not a button, axis or hat: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for SdlBinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Button(button) => write!(f, "button {button}"),
+            Self::Axis(axis) => write!(f, "axis {axis}"),
+            Self::Hat(hat, direction) => write!(f, "hat {hat}.{direction}"),
+        }
+    }
+}
+
+/// One line of gamecontrollerdb.txt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SdlMapping {
+    pub guid: String,
+    pub name: String,
+    pub platform: Option<String>,
+    /// by SDL control name without a half-axis sign, e.g. leftx
+    pub bindings: Vec<(String, SdlBinding)>,
+}
+
+impl SdlMapping {
+    /// The first entry in a gamecontrollerdb.txt that fits guid, as SDL picks
+    /// them: the same ids, ignoring the CRC, or any version for an entry
+    /// without one.
+    pub fn find(db: &str, guid: &str) -> Result<Option<Self>, String> {
+        let mut any_version = None;
+        for (number, line) in db.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            // only lines of this device are parsed, so one written for a
+            // newer SDL can't break the rest
+            let entry = line.split(',').next().unwrap_or_default();
+            let exact = same_ids(entry, guid, false);
+            if !exact && (any_version.is_some() || !same_ids(entry, guid, true)) {
+                continue;
+            }
+            let mapping = line
+                .parse::<Self>()
+                .map_err(|e| format!("line {}: {e}", number + 1))?;
+            if mapping.platform.as_deref().is_some_and(|p| p != PLATFORM) {
+                continue;
+            }
+            if exact {
+                return Ok(Some(mapping));
+            }
+            any_version = Some(mapping);
+        }
+        Ok(any_version)
+    }
+
+    pub fn binding(&self, control: &str) -> Option<SdlBinding> {
+        self.bindings
+            .iter()
+            .find(|(name, _)| name == control)
+            .map(|&(_, binding)| binding)
+    }
+
+    /// The keymap with the SDL controls of controls. A key bound to an axis or
+    /// hat, or to nothing on this device, is left out; a turntable without an
+    /// axis is any axis.
+    pub fn keymap(&self, controls: &SdlControls) -> Keymap {
+        let button = |control: &Option<String>| {
+            let control = control.as_deref()?;
+            match self.binding(control) {
+                Some(SdlBinding::Button(button)) => Some(button),
+                Some(binding) => {
+                    warn!("{control} of {} is {binding}, not a button", self.name);
+                    None
+                }
+                None => None,
+            }
+        };
+        let turntable =
+            controls
+                .turntable
+                .as_deref()
+                .and_then(|control| match self.binding(control) {
+                    Some(SdlBinding::Axis(axis)) => Some(axis),
+                    binding => {
+                        let bound = binding.map_or("unbound".to_owned(), |b| b.to_string());
+                        warn!(
+                            "{control} of {} is {bound}, not an axis; any axis turns the turntable",
+                            self.name
+                        );
+                        None
+                    }
+                });
+        Keymap {
+            keys: controls.keys.each_ref().map(button),
+            options: controls.options.each_ref().map(button),
+            turntable,
+        }
+    }
+}
+
+impl FromStr for SdlMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split(',');
+        let guid = fields.next().unwrap_or_default().trim();
+        if guid.len() != 32 || !guid.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("not an SDL GUID: {guid}"));
+        }
+        let name = fields
+            .next()
+            .ok_or_else(|| "expected GUID,NAME,CONTROL:BINDING,...".to_owned())?;
+        let mut mapping = Self {
+            guid: guid.to_ascii_lowercase(),
+            name: name.trim().to_owned(),
+            platform: None,
+            bindings: Vec::new(),
+        };
+        for field in fields.map(str::trim).filter(|field| !field.is_empty()) {
+            let (control, binding) = field
+                .split_once(':')
+                .ok_or_else(|| format!("expected CONTROL:BINDING: {field}"))?;
+            match control {
+                "platform" => mapping.platform = Some(binding.to_owned()),
+                // SDL versions the entry applies to, not a control
+                "crc" | "hint" | "sdk>=" | "sdk<=" => {}
+                _ => {
+                    let control = control.trim_start_matches(['+', '-']);
+                    mapping
+                        .bindings
+                        .push((control.to_owned(), binding.parse()?));
+                }
+            }
+        }
+        Ok(mapping)
+    }
+}
+
+/// The SDL GUID of a device with these input ids.
+pub fn sdl_guid(bustype: u16, vendor: u16, product: u16, version: u16) -> String {
+    let le = |id: u16| {
+        let [low, high] = id.to_le_bytes();
+        format!("{low:02x}{high:02x}0000")
+    };
+    [bustype, vendor, product, version].map(le).concat()
+}
+
+/// the same device, ignoring the CRC and, with any_version, an entry's
+/// version when it has none
+fn same_ids(entry: &str, guid: &str, any_version: bool) -> bool {
+    let ids = |guid: &str| {
+        let guid = guid.to_ascii_lowercase();
+        // bustype, vendor, product, version
+        [0..4, 8..12, 16..20, 24..28].map(|range| guid.get(range).unwrap_or_default().to_owned())
+    };
+    let (entry, guid) = (ids(entry), ids(guid));
+    entry[..3] == guid[..3] && (entry[3] == guid[3] || any_version && entry[3] == "0000")
+}
+
+/// Which SDL controls are the keys, option buttons and turntable, e.g.
+/// `B1=a,B2=b,E1=back,TT=leftx`; a control left out is never pressed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SdlControls {
+    pub keys: [Option<String>; 7],
+    pub options: [Option<String>; 4],
+    pub turntable: Option<String>,
+}
+
+impl Default for SdlControls {
+    /// the face buttons and shoulders as keys, as on a pad-style controller
+    fn default() -> Self {
+        let some = |control: &str| Some(control.to_owned());
+        Self {
+            keys: [
+                "a",
+                "b",
+                "x",
+                "y",
+                "leftshoulder",
+                "rightshoulder",
+                "lefttrigger",
+            ]
+            .map(some),
+            options: ["back", "start", "guide", "misc1"].map(some),
+            turntable: some("leftx"),
+        }
+    }
+}
+
+impl FromStr for SdlControls {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut controls = Self {
+            keys: Default::default(),
+            options: Default::default(),
+            turntable: None,
+        };
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (control, sdl) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected CONTROL=SDL_CONTROL, e.g. B1=a: {entry}"))?;
+            // the same names as a keymap
+            let index = |prefix: &str, count: usize| {
+                control
+                    .strip_prefix(prefix)
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .filter(|n| (1..=count).contains(n))
+                    .map(|n| n - 1)
+            };
+            let slot = if control == "TT" {
+                &mut controls.turntable
+            } else if let Some(i) = index("B", 7) {
+                &mut controls.keys[i]
+            } else if let Some(i) = index("E", 4) {
+                &mut controls.options[i]
+            } else {
+                return Err(format!(
+                    "unknown control: {control} (expected B1 to B7, E1 to E4 or TT)"
+                ));
+            };
+            if slot.replace(sdl.to_owned()).is_some() {
+                return Err(format!("{control} is mapped twice"));
+            }
+        }
+        Ok(controls)
+    }
+}
+
+impl fmt::Display for SdlControls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys = (1..).zip(&self.keys).map(|(n, key)| (format!("B{n}"), key));
+        let options = (1..)
+            .zip(&self.options)
+            .map(|(n, option)| (format!("E{n}"), option));
+        let turntable = [("TT".to_owned(), &self.turntable)];
+        let mut separator = "";
+        for (name, control) in keys.chain(options).chain(turntable) {
+            if let Some(control) = control {
+                write!(f, "{separator}{name}={control}")?;
+                separator = ",";
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // as in gamecontrollerdb.txt
+    const XBOX_360: &str = "030000005e0400008e02000010010000,Xbox 360 Controller,a:b0,b:b1,back:b6,dpdown:h0.4,dpleft:h0.8,dpright:h0.2,dpup:h0.1,guide:b8,leftshoulder:b4,leftstick:b9,lefttrigger:a2,leftx:a0,lefty:a1,rightshoulder:b5,rightstick:b10,righttrigger:a5,rightx:a3,righty:a4,start:b7,x:b2,y:b3,platform:Linux,";
+    const PS4: &str = "030000004c050000c405000011010000,PS4 Controller,a:b0,b:b1,back:b8,dpdown:h0.4,dpleft:h0.8,dpright:h0.2,dpup:h0.1,guide:b10,leftshoulder:b4,leftstick:b11,lefttrigger:a2,leftx:a0,lefty:a1,rightshoulder:b5,rightstick:b12,righttrigger:a5,rightx:a3,righty:a4,start:b9,x:b3,y:b2,platform:Linux,";
+
+    #[test]
+    fn a_gamecontrollerdb_line_parses() {
+        let mapping: SdlMapping = XBOX_360.parse().unwrap();
+        assert_eq!(mapping.guid, "030000005e0400008e02000010010000");
+        assert_eq!(mapping.name, "Xbox 360 Controller");
+        assert_eq!(mapping.platform.as_deref(), Some("Linux"));
+        assert_eq!(mapping.bindings.len(), 21);
+        assert_eq!(mapping.binding("a"), Some(SdlBinding::Button(0)));
+        assert_eq!(mapping.binding("start"), Some(SdlBinding::Button(7)));
+        assert_eq!(mapping.binding("lefttrigger"), Some(SdlBinding::Axis(2)));
+        assert_eq!(mapping.binding("dpleft"), Some(SdlBinding::Hat(0, 8)));
+        assert_eq!(mapping.binding("misc1"), None);
+    }
+
+    #[test]
+    fn half_axes_inversion_and_crc_are_read() {
+        let line = "03008fe54c050000c405000011010000,PS4 Controller,crc:e58f,a:b0,+lefty:+a1,-leftx:-a0,righty:a4~,hint:!SDL_GAMECONTROLLER_USE_BUTTON_LABELS:=1,platform:Linux,";
+        let mapping: SdlMapping = line.parse().unwrap();
+        assert_eq!(
+            mapping.bindings,
+            [
+                ("a".to_owned(), SdlBinding::Button(0)),
+                ("lefty".to_owned(), SdlBinding::Axis(1)),
+                ("leftx".to_owned(), SdlBinding::Axis(0)),
+                ("righty".to_owned(), SdlBinding::Axis(4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_refused() {
+        for line in [
+            "",
+            "030000005e04,Short GUID,a:b0",
+            "030000005e0400008e0200001001000g,Not hex,a:b0",
+            "030000005e0400008e02000010010000",
+            "030000005e0400008e02000010010000,Xbox,a",
+            "030000005e0400008e02000010010000,Xbox,a:x0",
+            "030000005e0400008e02000010010000,Xbox,a:b256",
+            "030000005e0400008e02000010010000,Xbox,dpup:h0",
+        ] {
+            assert!(line.parse::<SdlMapping>().is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn guids_are_built_from_the_input_ids() {
+        assert_eq!(
+            sdl_guid(0x0003, 0x045e, 0x028e, 0x0110),
+            "030000005e0400008e02000010010000"
+        );
+        assert_eq!(
+            sdl_guid(0x0003, 0x054c, 0x05c4, 0x0111),
+            "030000004c050000c405000011010000"
+        );
+    }
+
+    #[test]
+    fn find_picks_the_device_like_sdl() {
+        let mac = "030000005e0400008e02000010010000,Xbox 360 Controller,a:b1,platform:Mac OS X,";
+        let any_version = "030000005e0400008e02000000000000,Xbox 360 (any),a:b2,platform:Linux,";
+        let db = [
+            "# Linux",
+            "030000004c050000c405000011010000,Broken,a:z9,",
+            mac,
+            any_version,
+            XBOX_360,
+            PS4,
+        ]
+        .join("\n");
+
+        let xbox = SdlMapping::find(&db, &sdl_guid(0x0003, 0x045e, 0x028e, 0x0110)).unwrap();
+        // the exact version wins over the earlier entry for any version
+        assert_eq!(xbox.unwrap().name, "Xbox 360 Controller");
+        let other_version = SdlMapping::find(&db, &sdl_guid(0x0003, 0x045e, 0x028e, 0x0114));
+        assert_eq!(other_version.unwrap().unwrap().name, "Xbox 360 (any)");
+        // the CRC in the bustype's upper half is ignored
+        let crc = SdlMapping::find(&db, "0300e58f5e0400008e02000010010000").unwrap();
+        assert_eq!(crc.unwrap().name, "Xbox 360 Controller");
+        assert_eq!(
+            SdlMapping::find(&db, &sdl_guid(0x0005, 0x045e, 0x028e, 0x0110)),
+            Ok(None)
+        );
+        // only the lines of the device are parsed
+        assert_eq!(
+            SdlMapping::find(&db, &sdl_guid(0x0003, 0x054c, 0x05c4, 0x0111)),
+            Err("line 2: not a button, axis or hat: z9".to_owned())
+        );
+    }
+
+    #[test]
+    fn keymap_takes_buttons_and_an_axis() {
+        let mapping: SdlMapping = PS4.parse().unwrap();
+        let keymap = mapping.keymap(&SdlControls::default());
+        // the left trigger is an axis, misc1 isn't on the controller
+        assert_eq!(
+            keymap.keys,
+            [Some(0), Some(1), Some(3), Some(2), Some(4), Some(5), None]
+        );
+        assert_eq!(keymap.options, [Some(8), Some(9), Some(10), None]);
+        assert_eq!(keymap.turntable, Some(0));
+
+        let controls: SdlControls = "B1=a,TT=dpup".parse().unwrap();
+        let keymap = mapping.keymap(&controls);
+        assert_eq!(keymap.keys[0], Some(0));
+        assert_eq!(keymap.keys[1..], [None; 6]);
+        // a hat can't turn the turntable, any axis does
+        assert_eq!(keymap.turntable, None);
+    }
+
+    #[test]
+    fn controls_parse_and_display() {
+        let controls: SdlControls = "B1=a, B7=lefttrigger,E2=start,TT=rightx".parse().unwrap();
+        assert_eq!(controls.keys[0].as_deref(), Some("a"));
+        assert_eq!(controls.keys[6].as_deref(), Some("lefttrigger"));
+        assert_eq!(controls.options[1].as_deref(), Some("start"));
+        assert_eq!(controls.turntable.as_deref(), Some("rightx"));
+        assert_eq!(
+            controls.to_string(),
+            "B1=a,B7=lefttrigger,E2=start,TT=rightx"
+        );
+        assert_eq!(
+            SdlControls::default().to_string().parse::<SdlControls>(),
+            Ok(SdlControls::default())
+        );
+        for spec in ["B8=a", "E0=back", "B1", "B1=a,B1=b"] {
+            assert!(spec.parse::<SdlControls>().is_err(), "{spec}");
+        }
+    }
+}
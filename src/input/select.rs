@@ -0,0 +1,70 @@
+// Picking a device by what it is rather than where its node is, since the
+// numbering of /dev/input/jsN depends on the order devices turn up in.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A device's USB vendor and product id, e.g. `1ccf:1234` as lsusb shows it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceId {
+    pub vendor: u16,
+    pub product: u16,
+}
+
+impl FromStr for DeviceId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = |part: &str| u16::from_str_radix(part, 16).ok();
+        s.split_once(':')
+            .and_then(|(vendor, product)| {
+                Some(DeviceId {
+                    vendor: hex(vendor)?,
+                    product: hex(product)?,
+                })
+            })
+            .ok_or_else(|| {
+                format!("not a device id: {s} (expected VENDOR:PRODUCT, e.g. 1ccf:1234)")
+            })
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor, self.product)
+    }
+}
+
+/// What a device has to match to be picked; every part that is set has to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceSelector {
+    /// part of the device name, in any case
+    pub name: Option<String>,
+    pub id: Option<DeviceId>,
+}
+
+impl DeviceSelector {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.id.is_none()
+    }
+
+    pub fn matches(&self, name: &str, id: Option<DeviceId>) -> bool {
+        let name_matches = self
+            .name
+            .as_ref()
+            .is_none_or(|wanted| name.to_lowercase().contains(&wanted.to_lowercase()));
+        let id_matches = self.id.is_none_or(|wanted| id == Some(wanted));
+        name_matches && id_matches
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.name, self.id) {
+            (Some(name), Some(id)) => write!(f, "name {name:?} and id {id}"),
+            (Some(name), None) => write!(f, "name {name:?}"),
+            (None, Some(id)) => write!(f, "id {id}"),
+            (None, None) => write!(f, "anything"),
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use std::fmt;
+use std::mem::{align_of, size_of};
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+
+use beatble_protocol::{KeyInput, NormalButton, OptionButton};
+use tokio::sync::watch;
+
+use super::mapping::InputMapping;
+use super::tap;
+use crate::clock;
+
+/// Latest input state shared between the input handler and the notifier.
+///
+/// Presses are latched until the next frame samples them so that a tap
+/// shorter than one notification period still shows up in a frame.
+// aligned to a cache line of its own, see the assertions below
+#[repr(align(64))]
+pub struct SharedKeyInput {
+    // packed, AtomicU32 is lock-free on every supported target
+    key_input: AtomicU32,
+    latched_normal: AtomicU8,
+    latched_option: AtomicU8,
+    // turntable movement in position units, until a notifier takes it
+    movement: AtomicI32,
+    // the turntable position reported as 0 when recentering, NO_REFERENCE
+    // before one is captured
+    scratch_reference: AtomicU32,
+    updated_at: AtomicU64,
+    // the reader allowed to store, see claim
+    reader: AtomicU64,
+    // bumped whenever a store changes the input
+    changes: watch::Sender<u64>,
+    // the mapping of the last config reload, for the reader
+    remaps: watch::Sender<Option<InputMapping>>,
+}
+
+const NO_REFERENCE: u32 = u32::MAX;
+
+// the notifier touches every field each frame, keep them in one cache line;
+// the alignment alone rounds the size up to a multiple of 64
+#[cfg(target_pointer_width = "64")]
+const _: () = assert!(size_of::<SharedKeyInput>() == 64 && align_of::<SharedKeyInput>() == 64);
+#[cfg(target_pointer_width = "64")]
+const _: () = assert!(size_of::<AtomicU32>() == size_of::<KeyInput>());
+
+impl SharedKeyInput {
+    pub fn new() -> Self {
+        Self {
+            key_input: AtomicU32::new(KeyInput::init().pack()),
+            latched_normal: AtomicU8::new(0),
+            latched_option: AtomicU8::new(0),
+            movement: AtomicI32::new(0),
+            scratch_reference: AtomicU32::new(NO_REFERENCE),
+            updated_at: AtomicU64::new(clock::now()),
+            reader: AtomicU64::new(0),
+            changes: watch::Sender::new(0),
+            remaps: watch::Sender::new(None),
+        }
+    }
+
+    #[inline]
+    pub fn store(&self, key_input: KeyInput) {
+        self.latched_normal
+            .fetch_or(key_input.normal_button.bits(), Ordering::Relaxed);
+        self.latched_option
+            .fetch_or(key_input.option_button.bits(), Ordering::Relaxed);
+        let packed = key_input.pack();
+        let previous = self.key_input.swap(packed, Ordering::Relaxed);
+        self.updated_at.store(clock::now(), Ordering::Relaxed);
+        // the shorter way round, as the position wraps
+        let moved = key_input
+            .scratch_position()
+            .wrapping_sub(KeyInput::unpack(previous).scratch_position()) as i16;
+        if moved != 0 {
+            self.movement.fetch_add(moved.into(), Ordering::Relaxed);
+        }
+        if previous != packed {
+            self.changes.send_modify(|changes| *changes += 1);
+        }
+        tap::stored(self, key_input);
+    }
+
+    /// Presses an option button on top of the stored input, latched like a
+    /// store, without racing the reader's stores.
+    pub fn press_option(&self, button: OptionButton) {
+        self.latched_option
+            .fetch_or(button.bits(), Ordering::Relaxed);
+        let press = |packed| {
+            let mut key_input = KeyInput::unpack(packed);
+            key_input.option_button.insert(button);
+            Some(key_input.pack())
+        };
+        let (Ok(previous) | Err(previous)) =
+            self.key_input
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, press);
+        self.updated_at.store(clock::now(), Ordering::Relaxed);
+        if !KeyInput::unpack(previous).option_button.contains(button) {
+            self.changes.send_modify(|changes| *changes += 1);
+        }
+        let mut key_input = KeyInput::unpack(previous);
+        key_input.option_button.insert(button);
+        tap::stored(self, key_input);
+    }
+
+    /// resolves on the next store that changes the input
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    /// Hands a reloaded mapping to the reader, which applies it from its next
+    /// event on, and to readers started later.
+    pub fn remap(&self, mapping: InputMapping) {
+        self.remaps.send_replace(Some(mapping));
+    }
+
+    /// the mappings passed to remap, the latest one already seen
+    pub fn remaps(&self) -> watch::Receiver<Option<InputMapping>> {
+        self.remaps.subscribe()
+    }
+
+    /// Hands the input over to a new reader and clears it. The previous reader
+    /// stops at its next event.
+    pub fn claim(&self) -> u64 {
+        let reader = self.reader.fetch_add(1, Ordering::Relaxed) + 1;
+        self.store(KeyInput::init());
+        // the turntable didn't move back to where a new reader starts
+        self.movement.store(0, Ordering::Relaxed);
+        // and recenters on a reference of its own
+        self.scratch_reference
+            .store(NO_REFERENCE, Ordering::Relaxed);
+        reader
+    }
+
+    /// the reader allowed to store, as returned by its claim
+    #[inline]
+    pub fn reader(&self) -> u64 {
+        self.reader.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn is_claimed_by(&self, reader: u64) -> bool {
+        self.reader.load(Ordering::Relaxed) == reader
+    }
+
+    /// time of the last store on the process-wide monotonic clock
+    #[inline]
+    pub fn updated_at(&self) -> u64 {
+        self.updated_at.load(Ordering::Relaxed)
+    }
+
+    /// current state without the latched presses, leaving the latch to the notifier
+    #[inline]
+    pub fn load(&self) -> KeyInput {
+        KeyInput::unpack(self.key_input.load(Ordering::Relaxed))
+    }
+
+    /// presses waiting for the next frame, leaving them latched
+    pub fn latched(&self) -> (NormalButton, OptionButton) {
+        (
+            NormalButton::from_bits_truncate(self.latched_normal.load(Ordering::Relaxed)),
+            OptionButton::from_bits_truncate(self.latched_option.load(Ordering::Relaxed)),
+        )
+    }
+
+    /// Turntable movement since the previous call in position units, 65536 per
+    /// turn; the notifier of the velocity scratch mode owns it.
+    #[inline]
+    pub fn take_movement(&self) -> i32 {
+        self.movement.swap(0, Ordering::Relaxed)
+    }
+
+    /// The turntable position as read that the reader reports as 0, when it
+    /// recenters; for debugging only.
+    pub fn scratch_reference(&self) -> Option<u16> {
+        match self.scratch_reference.load(Ordering::Relaxed) {
+            NO_REFERENCE => None,
+            reference => Some(reference as u16),
+        }
+    }
+
+    pub fn set_scratch_reference(&self, reference: u16) {
+        self.scratch_reference
+            .store(reference.into(), Ordering::Relaxed);
+    }
+
+    /// current state with the latched presses merged in, clearing the latch
+    #[inline]
+    pub fn take(&self) -> KeyInput {
+        let latched_normal = self.latched_normal.swap(0, Ordering::Relaxed);
+        let latched_option = self.latched_option.swap(0, Ordering::Relaxed);
+        let mut key_input = KeyInput::unpack(self.key_input.load(Ordering::Relaxed));
+        key_input.normal_button |= NormalButton::from_bits_truncate(latched_normal);
+        key_input.option_button |= OptionButton::from_bits_truncate(latched_option);
+        key_input
+    }
+}
+
+impl Default for SharedKeyInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A player side in double play.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    P1,
+    P2,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Side::P1 => write!(f, "1P"),
+            Side::P2 => write!(f, "2P"),
+        }
+    }
+}
+
+/// Double play: an independent input state per side, each fed by its own device.
+#[derive(Clone)]
+pub struct KeyInputDp {
+    pub p1: Arc<SharedKeyInput>,
+    pub p2: Arc<SharedKeyInput>,
+}
+
+impl KeyInputDp {
+    /// the input state of one side
+    pub fn side(&self, side: Side) -> &Arc<SharedKeyInput> {
+        match side {
+            Side::P1 => &self.p1,
+            Side::P2 => &self.p2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn stores_come_back_whole() {
+        let shared = SharedKeyInput::new();
+        let key_input = KeyInput {
+            scratch: 0x9c,
+            normal_button: NormalButton::B2 | NormalButton::B6,
+            option_button: OptionButton::E3,
+            analog: 0x11,
+        };
+        shared.store(key_input);
+        assert_eq!(shared.load(), key_input);
+        assert_eq!(shared.take(), key_input);
+    }
+
+    #[test]
+    fn a_tap_is_latched_until_taken() {
+        let shared = SharedKeyInput::new();
+        let mut key_input = KeyInput::init();
+        key_input.normal_button = NormalButton::B4;
+        shared.store(key_input);
+        shared.store(KeyInput::init());
+        assert_eq!(shared.load(), KeyInput::init());
+        assert_eq!(shared.take().normal_button, NormalButton::B4);
+        assert_eq!(shared.take(), KeyInput::init());
+    }
+
+    /// A reader storing random transitions and a notifier taking frames on a
+    /// clock, both on threads of their own. Each records a ticket from one
+    /// counter with every store or take: the reader after storing, the
+    /// notifier before taking, so a frame sees at least every store with a
+    /// lower ticket.
+    fn stress(duration: Duration, period: Duration) {
+        let shared = Arc::new(SharedKeyInput::new());
+        let tickets = Arc::new(AtomicU64::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let reader = {
+            let (shared, tickets, done) = (shared.clone(), tickets.clone(), done.clone());
+            thread::spawn(move || {
+                let mut random = 0x9e37_79b9_7f4a_7c15_u64;
+                let mut key_input = KeyInput::init();
+                let mut stores = Vec::new();
+                while !done.load(Ordering::Relaxed) {
+                    // xorshift, the same run every time
+                    random ^= random << 13;
+                    random ^= random >> 7;
+                    random ^= random << 17;
+                    match random % 13 {
+                        bit @ 0..=6 => key_input
+                            .normal_button
+                            .toggle(NormalButton::from_bits_truncate(1 << bit)),
+                        bit @ 7..=10 => key_input
+                            .option_button
+                            .toggle(OptionButton::from_bits_truncate(1 << (bit - 7))),
+                        _ => key_input.scratch = (random >> 32) as u8,
+                    }
+                    shared.store(key_input);
+                    stores.push((tickets.fetch_add(1, Ordering::SeqCst), key_input));
+                    // taps far shorter than a period as well as holds over several
+                    let pause =
+                        Duration::from_micros((random >> 40) % (period.as_micros() as u64 * 3));
+                    thread::sleep(pause);
+                }
+                stores
+            })
+        };
+        let notifier = {
+            let (shared, tickets, done) = (shared.clone(), tickets.clone(), done.clone());
+            thread::spawn(move || {
+                let mut frames = Vec::new();
+                while !done.load(Ordering::Relaxed) {
+                    let ticket = tickets.fetch_add(1, Ordering::SeqCst);
+                    frames.push((ticket, shared.take()));
+                    thread::sleep(period);
+                }
+                frames
+            })
+        };
+        thread::sleep(duration);
+        done.store(true, Ordering::Relaxed);
+        let stores = reader.join().expect("the reader finished");
+        let frames = notifier.join().expect("the notifier finished");
+        assert!(stores.len() > 100 && frames.len() > 10, "too few to tell");
+
+        // every press shows up in the first frame after it, however short
+        let mut previous = KeyInput::init();
+        for &(ticket, key_input) in &stores {
+            let pressed = key_input.normal_button - previous.normal_button;
+            let pressed_option = key_input.option_button - previous.option_button;
+            previous = key_input;
+            let Some(&(_, frame)) = frames.iter().find(|&&(taken, _)| taken > ticket) else {
+                break;
+            };
+            assert!(
+                frame.normal_button.contains(pressed)
+                    && frame.option_button.contains(pressed_option),
+                "press {pressed:?} {pressed_option:?} at {ticket} missing from {frame:?}"
+            );
+        }
+
+        // and a frame holds nothing that wasn't stored around it, give or
+        // take one frame for the stores that raced the take
+        for (i, &(ticket, frame)) in frames.iter().enumerate() {
+            let from = i.checked_sub(1).map_or(0, |i| frames[i].0);
+            let to = frames.get(i + 1).map_or(u64::MAX, |&(next, _)| next);
+            let first = stores.partition_point(|&(stored, _)| stored < from);
+            // with the one store that may have raced the next take
+            let last = stores.partition_point(|&(stored, _)| stored < to) + 1;
+            let before = first
+                .checked_sub(1)
+                .map_or(KeyInput::init(), |i| stores[i].1);
+            let around: Vec<KeyInput> = std::iter::once(before)
+                .chain(
+                    stores[first..last.min(stores.len())]
+                        .iter()
+                        .map(|&(_, k)| k),
+                )
+                .collect();
+            let normal = around
+                .iter()
+                .fold(NormalButton::empty(), |all, k| all | k.normal_button);
+            let option = around
+                .iter()
+                .fold(OptionButton::empty(), |all, k| all | k.option_button);
+            assert!(
+                normal.contains(frame.normal_button) && option.contains(frame.option_button),
+                "frame {ticket} {frame:?} has a button never pressed around it"
+            );
+            assert!(
+                around.iter().any(|k| k.scratch == frame.scratch),
+                "frame {ticket} {frame:?} has a scratch never stored around it"
+            );
+        }
+    }
+
+    #[test]
+    fn movement_goes_the_shorter_way_round_the_wrap() {
+        let shared = SharedKeyInput::new();
+        let mut key_input = KeyInput::init();
+        for position in [65500, 100, 65436, 65436] {
+            key_input.set_scratch_position(position);
+            shared.store(key_input);
+        }
+        // from 0: -36, +136, -200 and nothing
+        assert_eq!(shared.take_movement(), -100);
+        assert_eq!(shared.take_movement(), 0);
+        // a new reader starting elsewhere isn't movement
+        key_input.set_scratch_position(30000);
+        shared.store(key_input);
+        shared.claim();
+        assert_eq!(shared.take_movement(), 0);
+    }
+
+    /// an input whose fields all follow from n, to tell a torn one apart
+    fn whole(n: u8) -> KeyInput {
+        KeyInput {
+            scratch: n,
+            normal_button: NormalButton::from_bits_truncate(n),
+            option_button: OptionButton::from_bits_truncate(n >> 4),
+            analog: !n,
+        }
+    }
+
+    #[test]
+    fn loads_racing_stores_are_never_torn() {
+        let shared = Arc::new(SharedKeyInput::new());
+        shared.store(whole(0));
+        let done = Arc::new(AtomicBool::new(false));
+        let writers: Vec<_> = [1u8, 2]
+            .into_iter()
+            .map(|step| {
+                let (shared, done) = (shared.clone(), done.clone());
+                thread::spawn(move || {
+                    let mut n = 0u8;
+                    while !done.load(Ordering::Relaxed) {
+                        n = n.wrapping_add(step);
+                        shared.store(whole(n));
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let (shared, done) = (shared.clone(), done.clone());
+                thread::spawn(move || {
+                    let mut loads = 0u64;
+                    while !done.load(Ordering::Relaxed) {
+                        let key_input = shared.load();
+                        assert_eq!(key_input, whole(key_input.scratch), "a torn load");
+                        loads += 1;
+                    }
+                    loads
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(300));
+        done.store(true, Ordering::Relaxed);
+        for writer in writers {
+            writer.join().expect("a writer");
+        }
+        for reader in readers {
+            assert!(reader.join().expect("a reader") > 1000, "too few to tell");
+        }
+    }
+
+    #[test]
+    fn frames_keep_every_press_and_invent_nothing() {
+        stress(Duration::from_millis(300), Duration::from_millis(1));
+    }
+
+    #[test]
+    #[ignore = "runs for a minute"]
+    fn frames_keep_every_press_and_invent_nothing_for_a_minute() {
+        stress(Duration::from_secs(60), Duration::from_millis(1));
+    }
+}
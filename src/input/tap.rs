@@ -0,0 +1,43 @@
+// Outputs that follow the input state as it is stored rather than as frames
+// sample it, e.g. a MIDI port. A tap runs on the thread that stores, right
+// after the event the state comes from, so it must be quick; with no tap
+// added a store pays for one atomic load.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use beatble_protocol::KeyInput;
+
+use super::shared::SharedKeyInput;
+
+static ANY: AtomicBool = AtomicBool::new(false);
+static TAPS: RwLock<Vec<Arc<dyn Tap>>> = RwLock::new(Vec::new());
+
+/// Sees every input state stored into any SharedKeyInput of the process.
+pub trait Tap: Send + Sync {
+    /// key_input was just stored into input; it may be the same as before
+    fn stored(&self, input: &SharedKeyInput, key_input: KeyInput);
+}
+
+/// Adds a tap for the rest of the process.
+pub fn add_tap(tap: Arc<dyn Tap>) {
+    TAPS.write().unwrap_or_else(|e| e.into_inner()).push(tap);
+    ANY.store(true, Ordering::Release);
+}
+
+/// Removes a tap added with add_tap, once no store is running it.
+pub fn remove_tap(tap: &Arc<dyn Tap>) {
+    let mut taps = TAPS.write().unwrap_or_else(|e| e.into_inner());
+    taps.retain(|added| !Arc::ptr_eq(added, tap));
+    ANY.store(!taps.is_empty(), Ordering::Release);
+}
+
+#[inline]
+pub(super) fn stored(input: &SharedKeyInput, key_input: KeyInput) {
+    if !ANY.load(Ordering::Acquire) {
+        return;
+    }
+    for tap in TAPS.read().unwrap_or_else(|e| e.into_inner()).iter() {
+        tap.stored(input, key_input);
+    }
+}
@@ -0,0 +1,30 @@
+use tracing::info;
+
+pub use self::histogram::{Histogram, Summary};
+
+mod histogram;
+
+/// Timing of the notification pipeline, recorded in microseconds.
+#[derive(Default)]
+pub struct Latency {
+    /// time between two sent frames
+    pub frame_interval: Histogram,
+    /// time from the input event to the frame carrying it
+    pub data_age: Histogram,
+}
+
+impl Latency {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&self) {
+        self.frame_interval.reset();
+        self.data_age.reset();
+    }
+
+    pub fn log_summary(&self) {
+        info!("frame interval: {}", self.frame_interval.summary());
+        info!("data age: {}", self.data_age.summary());
+    }
+}
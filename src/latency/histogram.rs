@@ -0,0 +1,220 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+// log-linear buckets: exact below SUB_BUCKETS, then SUB_BUCKETS buckets per power of two
+// (at most 12.5% relative error), HDR-style without the allocation
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// Lock-free histogram of u64 values; recording is four relaxed atomic operations.
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Summary {
+    pub count: u64,
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+/// Counts below a series of bucket boundaries, the shape Prometheus expects.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Debug, Default)]
+pub struct Cumulative {
+    /// (bound, number of values below it), ascending
+    pub buckets: Vec<(u64, u64)>,
+    pub count: u64,
+    pub sum: u64,
+}
+
+#[inline]
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let sub_bucket = (value >> (exponent - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    (exponent - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS + sub_bucket
+}
+
+/// smallest value that falls into the bucket
+fn bucket_value(index: usize) -> u64 {
+    if index >= BUCKETS {
+        return u64::MAX;
+    }
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let exponent = (index / SUB_BUCKETS) as u32 + SUB_BUCKET_BITS - 1;
+    let sub_bucket = (index % SUB_BUCKETS) as u64;
+    (SUB_BUCKETS as u64 + sub_bucket) << (exponent - SUB_BUCKET_BITS)
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn record(&self, value: u64) {
+        self.buckets[bucket_index(value)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+
+    /// per-bucket counts from the lowest to the highest non-empty bucket
+    pub fn counts(&self) -> Vec<u64> {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let first = counts.iter().position(|&count| count > 0).unwrap_or(0);
+        let last = counts.iter().rposition(|&count| count > 0).unwrap_or(0);
+        counts[first..=last].to_vec()
+    }
+
+    /// (lowest value, count) per bucket from the lowest to the highest
+    /// non-empty bucket
+    pub fn buckets(&self) -> Vec<(u64, u64)> {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let first = counts.iter().position(|&count| count > 0).unwrap_or(0);
+        let last = counts.iter().rposition(|&count| count > 0).unwrap_or(0);
+        (first..=last)
+            .map(|index| (bucket_value(index), counts[index]))
+            .collect()
+    }
+
+    /// Every `stride`-th bucket boundary from 2^low to 2^high. Powers of two
+    /// always start a bucket, so the counts are exact as long as `stride`
+    /// divides SUB_BUCKETS.
+    #[cfg(feature = "metrics")]
+    pub fn cumulative(&self, low: u32, high: u32, stride: usize) -> Cumulative {
+        debug_assert!(SUB_BUCKETS.is_multiple_of(stride) && low <= high);
+        let counts = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let first = bucket_index(1 << low);
+        let last = bucket_index(1 << high);
+
+        let mut seen = counts[..first].iter().sum::<u64>();
+        let mut buckets = Vec::new();
+        for index in (first..=last).step_by(stride) {
+            buckets.push((bucket_value(index), seen));
+            seen += counts[index..(index + stride).min(BUCKETS)]
+                .iter()
+                .sum::<u64>();
+        }
+        Cumulative {
+            buckets,
+            count: counts.iter().sum(),
+            sum: self.sum.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn summary(&self) -> Summary {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        // recomputed from the buckets so percentiles stay consistent with each other
+        let count = counts.iter().sum::<u64>();
+        let max = self.max.load(Ordering::Relaxed);
+
+        let percentile = |quantile: f64| {
+            let rank = ((count as f64 * quantile).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (index, bucket_count) in counts.iter().enumerate() {
+                seen += bucket_count;
+                if seen >= rank {
+                    // highest value of the bucket, like HDR's highest equivalent value
+                    return (bucket_value(index + 1) - 1).min(max);
+                }
+            }
+            max
+        };
+
+        if count == 0 {
+            return Summary::default();
+        }
+        Summary {
+            count,
+            p50: percentile(0.50),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max,
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "p50={}us p95={}us p99={}us max={}us (n={})",
+            self.p50, self.p95, self.p99, self.max, self.count
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_takes_every_percentile_from_the_same_buckets() {
+        let histogram = Histogram::new();
+        for value in 1..=1000 {
+            histogram.record(value);
+        }
+        let summary = histogram.summary();
+        assert_eq!(summary.count, 1000);
+        assert_eq!(summary.max, 1000);
+        // within a bucket's 12.5% of the exact value
+        for (percentile, exact) in [(summary.p50, 500), (summary.p95, 950), (summary.p99, 990)] {
+            assert!(
+                percentile >= exact && percentile <= exact + exact / 8,
+                "{percentile}"
+            );
+        }
+        assert!(summary.p50 <= summary.p95 && summary.p95 <= summary.p99);
+        assert!(summary.p99 <= summary.max);
+    }
+}
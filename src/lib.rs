@@ -0,0 +1,38 @@
+//! beatble's pipeline as a library: read a joystick into a [`SharedKeyInput`],
+//! notify it from a GATT service and advertise that service as a controller.
+//! The `beatble` binary is the command line around these pieces; see
+//! `examples/custom_pipeline.rs` for a minimal pipeline of your own.
+//!
+//! [`SharedKeyInput`]: input::SharedKeyInput
+
+/// The key input GATT service and the notifiers behind it.
+pub mod ble;
+/// Faults injected on purpose, to test how the pipeline recovers.
+pub mod chaos;
+/// A process-wide monotonic clock in nanoseconds, cheap to store in atomics.
+pub mod clock;
+/// Pausing and retiming the notifiers while they run.
+pub mod control;
+/// Recording sent payloads to a file.
+pub mod dump;
+/// The controllers beatble can pretend to be.
+pub mod emulation;
+/// Error kinds and the exit codes they map to.
+pub mod exit;
+/// Joystick devices and the input state they feed.
+pub mod input;
+/// Frame interval and data age histograms.
+pub mod latency;
+/// Advertising services as a bluetooth peripheral.
+#[cfg(feature = "ble")]
+pub mod peripheral;
+/// The last input events and sent frames, for crash reports.
+pub mod recent;
+/// Diagnostic counters.
+pub mod stats;
+/// sd_notify(3) readiness and status, a no-op outside a systemd unit.
+pub mod systemd;
+
+/// Encoding of key input frames.
+pub use beatble_protocol::payload;
+pub use beatble_protocol::{KeyInput, NormalButton, OptionButton};
@@ -0,0 +1,130 @@
+// `beatble test`: runs only the input pipeline and redraws the sampled
+// KeyInput on one terminal line, without touching bluetooth.
+
+#[cfg(feature = "input")]
+use std::io::{self, Write};
+#[cfg(feature = "input")]
+use std::sync::Arc;
+
+use beatble::emulation::Emulation;
+#[cfg(feature = "input")]
+use beatble::exit::ErrorKind;
+#[cfg(feature = "input")]
+use beatble::input::create_input_handler;
+use beatble_protocol::{KeyInput, NormalButton, OptionButton};
+#[cfg(feature = "input")]
+use eyre::{Result, WrapErr};
+#[cfg(feature = "input")]
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+#[cfg(feature = "input")]
+use crate::cli::RunArgs;
+
+// ~30 fps
+#[cfg(feature = "input")]
+const REFRESH_INTERVAL: Duration = Duration::from_millis(33);
+const BAR_WIDTH: usize = 24;
+
+const IIDX_LANES: [(NormalButton, &str); 7] = [
+    (NormalButton::B1, "1"),
+    (NormalButton::B2, "2"),
+    (NormalButton::B3, "3"),
+    (NormalButton::B4, "4"),
+    (NormalButton::B5, "5"),
+    (NormalButton::B6, "6"),
+    (NormalButton::B7, "7"),
+];
+const SDVX_BUTTONS: [(NormalButton, &str); 7] = [
+    (NormalButton::B1, "A"),
+    (NormalButton::B2, "B"),
+    (NormalButton::B3, "C"),
+    (NormalButton::B4, "D"),
+    (NormalButton::B5, "L"),
+    (NormalButton::B6, "R"),
+    (NormalButton::B7, "S"),
+];
+const OPTIONS: [(OptionButton, &str); 4] = [
+    (OptionButton::E1, "E1"),
+    (OptionButton::E2, "E2"),
+    (OptionButton::E3, "E3"),
+    (OptionButton::E4, "E4"),
+];
+
+#[cfg(feature = "input")]
+pub async fn run(args: RunArgs) -> Result<()> {
+    let input = args.input().wrap_err(ErrorKind::Config)?;
+    let (key_input, mut input_handler) = create_input_handler(
+        input,
+        crate::sdl::input_mapping(&args)?,
+        false,
+        args.force,
+        Arc::default(),
+    )
+    .wrap_err(ErrorKind::InputDevice)?;
+
+    let mut refresh = interval(REFRESH_INTERVAL);
+    refresh.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut stdout = io::stdout();
+    loop {
+        tokio::select! {
+            _ = refresh.tick() => {
+                // take() like the notifier does, so taps shorter than a refresh still show
+                let line = render(key_input.take(), args.emulate);
+                write!(stdout, "\r\x1b[2K{line}")?;
+                stdout.flush()?;
+            }
+            result = &mut input_handler => {
+                writeln!(stdout)?;
+                return result?.wrap_err(ErrorKind::InputRuntime);
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    writeln!(stdout)?;
+    Ok(())
+}
+
+pub fn render(key_input: KeyInput, emulation: Emulation) -> String {
+    match emulation {
+        Emulation::Iidx => {
+            let position = u16::from_be_bytes([key_input.scratch, key_input.analog]);
+            format!(
+                "{}  {}  scratch {} {:5.1}° ({:#06x})",
+                buttons(&IIDX_LANES, key_input.normal_button),
+                buttons(&OPTIONS, key_input.option_button),
+                bar(position),
+                position as f64 * 360.0 / 65536.0,
+                position,
+            )
+        }
+        Emulation::Sdvx => format!(
+            "{}  VOL-L {} {:3}  VOL-R {} {:3}",
+            buttons(&SDVX_BUTTONS, key_input.normal_button),
+            bar(u16::from_be_bytes([key_input.scratch, 0])),
+            key_input.scratch,
+            bar(u16::from_be_bytes([key_input.analog, 0])),
+            key_input.analog,
+        ),
+    }
+}
+
+fn buttons<B>(labels: &[(B, &str)], pressed: B) -> String
+where
+    B: bitflags::Flags + Copy,
+{
+    labels
+        .iter()
+        .map(|&(button, label)| {
+            if pressed.contains(button) {
+                format!("[{label}]")
+            } else {
+                format!("[{}]", " ".repeat(label.len()))
+            }
+        })
+        .collect()
+}
+
+fn bar(position: u16) -> String {
+    let filled = position as usize * BAR_WIDTH / (u16::MAX as usize + 1);
+    format!("|{}{}|", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled))
+}
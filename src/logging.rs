@@ -0,0 +1,170 @@
+// Subscriber setup. RUST_LOG takes EnvFilter directives; without it, -v/-q
+// pick the filter, and without those --log-level, which a config reload can
+// change through a reload handle.
+// The text format is tracing-subscriber's default; the json format writes one
+// object per event with the message and fields under "fields" and the
+// enclosing spans under "spans":
+//
+//   {"timestamp":"...","level":"INFO","fields":{"message":"connected to ..."},
+//    "target":"beatble::input::gamepad","span":{"device":"/dev/input/js0","name":"input"},
+//    "spans":[{"device":"/dev/input/js0","name":"input"}]}
+//
+// Either format goes to stderr, or to --log-file instead. The last lines are
+// also kept in memory, for the dashboard and crash reports.
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::panic;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use eyre::{eyre, Result, WrapErr};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+use self::file::LogFile;
+use crate::cli::{GlobalArgs, RunArgs};
+use crate::tui::LogTail;
+
+mod file;
+
+const MIB: u64 = 1024 * 1024;
+
+static LOG_FILE: OnceLock<LogFile> = OnceLock::new();
+static LOG_TAIL: OnceLock<LogTail> = OnceLock::new();
+// only set while --log-level decides the filter
+static LOG_LEVEL: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format: {s} (expected text or json)")),
+        }
+    }
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// With tui the log goes to the dashboard instead of stderr, and with the
+/// --log-file of run to that file.
+pub fn init(global: &GlobalArgs, tui: bool, run: Option<&RunArgs>) -> Result<Option<LogTail>> {
+    let (filter, reloadable) = match env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.is_empty() => (
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy(),
+            false,
+        ),
+        _ => (
+            EnvFilter::new(global.log_filter()),
+            global.verbose == 0 && global.quiet == 0,
+        ),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    if reloadable {
+        let _ = LOG_LEVEL.set(handle);
+    }
+    let log_tail = LOG_TAIL.get_or_init(LogTail::new).clone();
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(log_tail.clone());
+
+    if tui {
+        registry.init();
+        return Ok(Some(log_tail));
+    }
+    let log_file = match run {
+        Some(args) => open_log_file(args)?,
+        None => None,
+    };
+    match (global.log_format, log_file) {
+        (LogFormat::Text, Some(log_file)) => registry
+            .with(fmt::layer().with_ansi(false).with_writer(log_file))
+            .init(),
+        (LogFormat::Json, Some(log_file)) => registry
+            .with(fmt::layer().json().with_writer(log_file))
+            .init(),
+        (LogFormat::Text, None) => registry
+            .with(
+                fmt::layer()
+                    .with_ansi(io::stderr().is_terminal())
+                    .with_writer(io::stderr),
+            )
+            .init(),
+        (LogFormat::Json, None) => registry
+            .with(fmt::layer().json().with_writer(io::stderr))
+            .init(),
+    }
+    Ok(None)
+}
+
+/// Replaces the filter with the --log-level directives, or the default
+/// without them. Returns false when RUST_LOG or -v/-q picked the filter,
+/// which is left alone then.
+pub fn set_level(level: Option<&str>) -> Result<bool> {
+    let Some(handle) = LOG_LEVEL.get() else {
+        return Ok(false);
+    };
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level)?,
+        // what no -v/-q gives
+        None => EnvFilter::new("info"),
+    };
+    handle.reload(filter)?;
+    Ok(true)
+}
+
+/// up to count of the newest log lines, oldest first
+pub fn recent_lines(count: usize) -> Vec<String> {
+    LOG_TAIL
+        .get()
+        .map(|log_tail| log_tail.last(count))
+        .unwrap_or_default()
+}
+
+/// Writes out what --log-file still buffers; called on the way out.
+pub fn flush() {
+    if let Some(log_file) = LOG_FILE.get() {
+        log_file.flush();
+    }
+}
+
+fn open_log_file(args: &RunArgs) -> Result<Option<&'static LogFile>> {
+    let Some(path) = &args.log_file else {
+        return Ok(None);
+    };
+    if args.log_file_size == 0 {
+        return Err(eyre!("--log-file-size must be at least 1 MiB"));
+    }
+    let log_file = LogFile::open(path, args.log_file_size * MIB, args.log_file_keep)
+        .wrap_err_with(|| format!("failed to open log file {}", path.display()))?;
+    let log_file = LOG_FILE.get_or_init(|| log_file);
+    // the lines leading up to a panic matter the most
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Some(log_file) = LOG_FILE.get() {
+            log_file.try_flush();
+        }
+        hook(info);
+    }));
+    Ok(Some(log_file))
+}
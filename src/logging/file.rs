@@ -0,0 +1,267 @@
+// --log-file: the formatted lines of either log format go to a file that is
+// rotated by size, FILE to FILE.1, FILE.1 to FILE.2 and so on, dropping the
+// oldest. Each event is kept whole until it is appended, so a rotation only
+// ever happens between lines.
+//
+// Lines are buffered and written out when the buffer fills, with the first
+// line after a second without a write, with every warning or error, and on
+// shutdown and panic. There is no flushing thread; it wouldn't survive
+// --daemonize forking after the logging is set up.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+const BUFFER_CAPACITY: usize = 8 * 1024;
+const FLUSH_AFTER: Duration = Duration::from_secs(1);
+
+/// A log file that rotates once it would grow past max_size bytes, keeping
+/// keep rotated files next to it.
+pub struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    file: File,
+    // bytes in the file, including the buffered ones
+    size: u64,
+    buffer: Vec<u8>,
+    flushed_at: Instant,
+}
+
+impl LogFile {
+    pub fn open(path: &Path, max_size: u64, keep: usize) -> io::Result<Self> {
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_size,
+            keep,
+            state: Mutex::new(State {
+                file,
+                size,
+                buffer: Vec::with_capacity(BUFFER_CAPACITY),
+                flushed_at: Instant::now(),
+            }),
+        })
+    }
+
+    /// writes out the buffered lines
+    pub fn flush(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.write_out();
+        }
+    }
+
+    /// like flush, but gives up instead of waiting for another writer, e.g.
+    /// one that panicked halfway through
+    pub fn try_flush(&self) {
+        if let Ok(mut state) = self.state.try_lock() {
+            state.write_out();
+        }
+    }
+
+    fn append(&self, line: &[u8], urgent: bool) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if state.size > 0 && state.size + line.len() as u64 > self.max_size {
+            state.write_out();
+            self.rotate(&mut state);
+        }
+        state.buffer.extend_from_slice(line);
+        state.size += line.len() as u64;
+        if urgent
+            || state.buffer.len() >= BUFFER_CAPACITY
+            || state.flushed_at.elapsed() >= FLUSH_AFTER
+        {
+            state.write_out();
+        }
+    }
+
+    fn rotate(&self, state: &mut State) {
+        for n in (1..self.keep).rev() {
+            // the older files may not exist yet
+            let _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+        }
+        let _ = match self.keep {
+            0 => fs::remove_file(&self.path),
+            _ => fs::rename(&self.path, self.rotated(1)),
+        };
+        // if that failed, the file keeps growing until the next try
+        if let Ok(file) = open_append(&self.path) {
+            state.file = file;
+        }
+        state.size = 0;
+    }
+
+    /// FILE.n
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{n}"));
+        path.into()
+    }
+}
+
+impl State {
+    fn write_out(&mut self) {
+        // there is nowhere left to report a failed write to; the lines are
+        // dropped rather than piling up
+        let _ = self.file.write_all(&self.buffer);
+        self.buffer.clear();
+        self.flushed_at = Instant::now();
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// One event as the fmt layer writes it, appended when it is dropped.
+pub struct Line<'a> {
+    log_file: &'a LogFile,
+    line: Vec<u8>,
+    urgent: bool,
+}
+
+impl Write for Line<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Line<'_> {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.log_file.append(&self.line, self.urgent);
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for &'static LogFile {
+    type Writer = Line<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        Line {
+            log_file: self,
+            line: Vec::new(),
+            urgent: false,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        Line {
+            log_file: self,
+            line: Vec::new(),
+            urgent: *meta.level() <= Level::WARN,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    /// an empty directory of its own for each test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("beatble-log-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("a temp dir");
+        dir
+    }
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap_or_default()
+    }
+
+    // 10 bytes each
+    fn line(n: usize) -> String {
+        format!("line {n:04}\n")
+    }
+
+    #[test]
+    fn files_roll_over_between_lines_and_the_oldest_are_dropped() {
+        let dir = scratch_dir("rotate");
+        let path = dir.join("beatble.log");
+        let log = LogFile::open(&path, 25, 2).unwrap();
+        for n in 0..9 {
+            log.append(line(n).as_bytes(), false);
+        }
+        log.flush();
+
+        // two lines to a file, as a third would make 30 bytes
+        assert_eq!(read(path.clone()), line(8));
+        assert_eq!(read(log.rotated(1)), line(6) + &line(7));
+        assert_eq!(read(log.rotated(2)), line(4) + &line(5));
+        assert!(!log.rotated(3).exists());
+        let mut files = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 3);
+
+        // without any to keep the file just starts over
+        let log = LogFile::open(&path, 25, 0).unwrap();
+        log.append(line(9).as_bytes(), false);
+        log.append(line(10).as_bytes(), true);
+        assert_eq!(read(path.clone()), line(10));
+        files = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn an_existing_file_counts_against_the_size() {
+        let dir = scratch_dir("existing");
+        let path = dir.join("beatble.log");
+        fs::write(&path, line(0) + &line(1)).unwrap();
+        let log = LogFile::open(&path, 25, 1).unwrap();
+        log.append(line(2).as_bytes(), true);
+        assert_eq!(read(path.clone()), line(2));
+        assert_eq!(read(log.rotated(1)), line(0) + &line(1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_line_longer_than_the_size_gets_a_file_of_its_own() {
+        let dir = scratch_dir("long");
+        let path = dir.join("beatble.log");
+        let log = LogFile::open(&path, 25, 1).unwrap();
+        let long = "x".repeat(40) + "\n";
+        log.append(long.as_bytes(), true);
+        assert_eq!(read(path.clone()), long);
+        log.append(line(0).as_bytes(), true);
+        assert_eq!(read(path.clone()), line(0));
+        assert_eq!(read(log.rotated(1)), long);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lines_wait_for_a_warning_or_a_flush() {
+        let dir = scratch_dir("buffer");
+        let path = dir.join("beatble.log");
+        let log = LogFile::open(&path, 1024, 1).unwrap();
+        log.append(line(0).as_bytes(), false);
+        assert_eq!(read(path.clone()), "");
+        log.append(line(1).as_bytes(), true);
+        assert_eq!(read(path.clone()), line(0) + &line(1));
+        log.append(line(2).as_bytes(), false);
+        log.try_flush();
+        assert_eq!(read(path.clone()), line(0) + &line(1) + &line(2));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
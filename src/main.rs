@@ -1,72 +1,382 @@
-use std::sync::Arc;
+use std::io;
+use std::process::ExitCode;
 
-use bluster::Peripheral;
-use clap::Parser;
-use crossbeam::atomic::AtomicCell;
-use eyre::Result;
-use log::{debug, info};
+use beatble::exit::{self, ErrorKind};
+#[cfg(feature = "input")]
+use beatble::input::{device_id, device_info, list_devices};
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use eyre::{eyre, Result, WrapErr};
 
-use crate::input::{create_input_handler, KeyInput};
+use crate::build_info::BUILD_INFO;
+use crate::cli::{Cli, Command, ConfigCommand, RunArgs};
+use crate::config::Reloader;
+use crate::runtime::Flavor;
+#[cfg(feature = "ble")]
+use crate::script::Script;
+use crate::tui::LogTail;
 
-use self::ble::create_key_input;
+#[cfg(feature = "input")]
+mod bench;
+#[cfg(feature = "dbus")]
+mod bonds;
+mod build_info;
+mod cli;
+mod config;
+mod crash;
+mod ctl;
+#[cfg(feature = "ble")]
+mod daemon;
+#[cfg(feature = "dbus")]
+mod dbus;
+#[cfg(feature = "input")]
+mod doctor;
+#[cfg(feature = "ble")]
+mod dry_run;
+#[cfg(feature = "ble")]
+mod http;
+#[cfg(feature = "input")]
+mod init;
+#[cfg(any(feature = "ble", feature = "input"))]
+mod live_view;
+mod logging;
+#[cfg(all(feature = "ble", feature = "input"))]
+mod measure;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "midi")]
+mod midi;
+#[cfg(all(feature = "ble", feature = "input"))]
+mod mirror;
+#[cfg(feature = "overlay")]
+mod overlay;
+#[cfg(feature = "dbus")]
+mod pairing;
+#[cfg(all(feature = "ble", feature = "input"))]
+mod regress;
+mod runtime;
+#[cfg(feature = "sandbox")]
+mod sandbox;
+#[cfg(feature = "ble")]
+mod script;
+#[cfg(feature = "input")]
+mod sdl;
+#[cfg(feature = "ble")]
+mod session;
+#[cfg(feature = "ble")]
+mod snapshot;
+#[cfg(feature = "ble")]
+mod soak;
+#[cfg(all(feature = "ble", feature = "input"))]
+mod split;
+#[cfg(feature = "ble")]
+mod status;
+mod supervisor;
+#[cfg(feature = "ble")]
+mod suspend;
+mod tui;
+#[cfg(feature = "input")]
+mod udev;
+#[cfg(feature = "verify")]
+mod verify;
 
-mod ble;
-mod input;
+fn main() -> ExitCode {
+    let matches = match Cli::command().try_get_matches() {
+        Ok(matches) => matches,
+        Err(e) => return clap_exit(e),
+    };
+    let cli = match Cli::from_arg_matches(&matches) {
+        Ok(cli) => cli,
+        Err(e) => return clap_exit(e),
+    };
 
-#[derive(Parser)]
-#[clap(name = "beatble")]
-#[clap(version = env!("VERSION"))]
-struct Args {
-    /// input device path
-    #[arg(value_name = "DEVICE")]
-    input: String,
+    // not clap's version flag, which exits before --verbose is parsed
+    if cli.version {
+        match cli.global.verbose {
+            0 => println!("beatble {}", BUILD_INFO.version),
+            _ => println!("{BUILD_INFO}"),
+        }
+        return ExitCode::SUCCESS;
+    }
 
-    /// sleep duration in ms
-    // 8 = 1000 / 120
-    #[arg(long, value_name = "DURATION", default_value_t = 8)]
-    sleep_duration: u64,
-}
+    let tui = match &cli.command {
+        None => cli.run.tui,
+        #[cfg(all(feature = "ble", feature = "input"))]
+        Some(Command::Run(args)) => args.tui,
+        #[cfg(feature = "ble")]
+        Some(Command::Simulate(args)) => args.run.tui,
+        Some(_) => false,
+    };
+    let log_tail = match logging::init(&cli.global, tui, run_args(&cli)) {
+        Ok(log_tail) => log_tail,
+        Err(e) => {
+            let e = e.wrap_err(ErrorKind::Config);
+            eprintln!("Error: {e:?}");
+            return exit::exit_code(&e);
+        }
+    };
 
-const ADVERTISING_NAME: &str = "IIDX Entry model";
+    let result = start(cli, &matches, log_tail);
+    // before the error, which a daemon writes into the log file too
+    logging::flush();
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // what eyre prints when main returns the error
+            eprintln!("Error: {e:?}");
+            crash::report_error(&e);
+            exit::exit_code(&e)
+        }
+    }
+}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+/// the options of a command that reads a device or plays a script
+fn run_args(cli: &Cli) -> Option<&RunArgs> {
+    match &cli.command {
+        None => Some(&cli.run),
+        #[cfg(all(feature = "ble", feature = "input"))]
+        Some(Command::Run(args)) => Some(args),
+        #[cfg(feature = "input")]
+        Some(Command::Test(args)) => Some(args),
+        #[cfg(feature = "ble")]
+        Some(Command::Simulate(args)) => Some(&args.run),
+        _ => None,
+    }
+}
 
-    let args = Args::parse();
+/// usage errors count as configuration errors; --help and --version exit 0
+fn clap_exit(e: clap::Error) -> ExitCode {
+    // nothing to report to if stderr is gone
+    let _ = e.print();
+    if e.use_stderr() {
+        ErrorKind::Config.exit_code()
+    } else {
+        ExitCode::SUCCESS
+    }
+}
 
-    debug!("input: {}", args.input);
-    debug!("sleep_duration: {}", args.sleep_duration);
+/// --daemonize has to fork before the runtime starts its threads
+fn start(mut cli: Cli, matches: &ArgMatches, log_tail: Option<LogTail>) -> Result<()> {
+    let reloader = config::apply(&mut cli, matches).wrap_err(ErrorKind::Config)?;
+    if let Some(args) = run_args(&cli) {
+        crash::install(config::show_redacted(args)?);
+        // the config file's too, which isn't merged in yet when logging starts
+        logging::set_level(args.log_level.as_deref()).wrap_err(ErrorKind::Config)?;
+    }
+    #[cfg(feature = "input")]
+    if matches!(&cli.command, Some(Command::Test(args)) if args.daemonize) {
+        return Err(eyre!("--daemonize is only available for run and simulate"))
+            .wrap_err(ErrorKind::Config);
+    }
+    #[cfg(feature = "ble")]
+    let _pidfile = match &cli.command {
+        #[cfg(feature = "input")]
+        None => daemon::start(&cli.run)?,
+        #[cfg(feature = "input")]
+        Some(Command::Run(args)) => daemon::start(args)?,
+        Some(Command::Simulate(args)) => daemon::start(&args.run)?,
+        _ => None,
+    };
+    #[cfg(feature = "sandbox")]
+    {
+        let args = match &cli.command {
+            None => Some(&cli.run),
+            #[cfg(feature = "input")]
+            Some(Command::Run(args)) => Some(args),
+            Some(Command::Simulate(args)) => Some(&args.run),
+            _ => None,
+        };
+        if let Some(args) = args.filter(|args| args.sandbox) {
+            let config = reloader.as_ref().and_then(Reloader::path);
+            sandbox::restrict_paths(args, config).wrap_err("failed to set up the sandbox")?;
+        }
+    }
+    let runtime = match &cli.command {
+        None => cli.run.runtime,
+        #[cfg(all(feature = "ble", feature = "input"))]
+        Some(Command::Run(args)) => args.runtime,
+        #[cfg(feature = "input")]
+        Some(Command::Test(args)) => args.runtime,
+        #[cfg(feature = "ble")]
+        Some(Command::Simulate(args)) => args.run.runtime,
+        _ => Flavor::default(),
+    };
+    runtime
+        .build()?
+        .block_on(run_command(cli, log_tail, reloader))
+}
 
-    let sleep_duration = tokio::time::Duration::from_millis(args.sleep_duration);
+async fn run_command(
+    cli: Cli,
+    log_tail: Option<LogTail>,
+    reloader: Option<Reloader>,
+) -> Result<()> {
+    match cli.command {
+        #[cfg(all(feature = "ble", feature = "input"))]
+        None => session::run(cli.run, None, log_tail, reloader).await,
+        // a reduced build has no default command
+        #[cfg(not(all(feature = "ble", feature = "input")))]
+        None => {
+            let _ = (log_tail, reloader);
+            Err(eyre!(
+                "this build can't run a controller, see --help for its commands"
+            ))
+            .wrap_err(ErrorKind::Config)
+        }
+        #[cfg(all(feature = "ble", feature = "input"))]
+        Some(Command::Run(args)) => session::run(args, None, log_tail, reloader).await,
+        #[cfg(feature = "ble")]
+        Some(Command::Simulate(args)) => {
+            let script = Script::load(&args.script).wrap_err(ErrorKind::Config)?;
+            session::run(args.run, Some((script, args.repeat)), log_tail, reloader).await
+        }
+        #[cfg(feature = "input")]
+        Some(Command::Test(args)) => live_view::run(args).await,
+        #[cfg(feature = "input")]
+        Some(Command::List) => {
+            for path in list_devices().wrap_err(ErrorKind::InputDevice)? {
+                match (device_info(&path), device_id(&path)) {
+                    (Ok(info), Ok(id)) => println!("{path}\t{info}\t{id}"),
+                    (Ok(info), Err(_)) => println!("{path}\t{info}"),
+                    (Err(e), _) => println!("{path}\t({e})"),
+                }
+            }
+            Ok(())
+        }
+        #[cfg(feature = "input")]
+        Some(Command::Info { input }) => {
+            let info = device_info(&input).wrap_err(ErrorKind::InputDevice)?;
+            println!("name: {info}");
+            println!("axes: {}", info.axes);
+            println!("buttons: {}", info.buttons);
+            Ok(())
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Show(args),
+        }) => {
+            print!("{}", config::show(&args).wrap_err(ErrorKind::Config)?);
+            Ok(())
+        }
+        #[cfg(feature = "input")]
+        Some(Command::Bench {
+            device,
+            seconds,
+            json,
+        }) => bench::run(&device, seconds, json).await,
+        #[cfg(all(feature = "ble", feature = "input"))]
+        Some(Command::MeasureLatency {
+            samples,
+            sleep_duration,
+            busy_poll,
+            json,
+        }) => measure::run(samples, sleep_duration, busy_poll, json).await,
+        #[cfg(all(feature = "ble", feature = "input"))]
+        Some(Command::Regress(args)) => {
+            tokio::task::spawn_blocking(move || regress::run(args)).await?
+        }
+        #[cfg(feature = "ble")]
+        Some(Command::Soak {
+            hours,
+            sample_every,
+            report,
+        }) => soak::run(hours, sample_every, report).await,
+        #[cfg(feature = "input")]
+        Some(Command::Init) => init::run().await,
+        #[cfg(feature = "input")]
+        Some(Command::Doctor { device }) => doctor::run(device).await,
+        #[cfg(feature = "input")]
+        Some(Command::SetupUdev {
+            device,
+            group,
+            install,
+        }) => udev::setup(device.as_deref(), &group, install),
+        #[cfg(feature = "dbus")]
+        Some(Command::Bonds { command }) => match command {
+            cli::BondsCommand::List => bonds::list().await,
+            cli::BondsCommand::Forget { address } => bonds::forget(&address).await,
+        },
+        Some(Command::Ctl { socket, command }) => ctl::send(socket, &command).await,
+        Some(Command::Completions { shell }) => {
+            cli::write_completions(shell, &mut io::stdout());
+            Ok(())
+        }
+        #[cfg(feature = "verify")]
+        Some(Command::Verify(args)) => verify::run(args).await,
+        #[cfg(feature = "verify")]
+        Some(Command::Capture(args)) => verify::capture::run(args).await,
+        #[cfg(all(feature = "ble", feature = "input"))]
+        Some(Command::RelayInput {
+            device,
+            uid,
+            gid,
+            force,
+        }) => tokio::task::spawn_blocking(move || split::relay(&device, uid, gid, force)).await?,
+    }
+}
 
-    info!("Preparing input handler");
-    let key_input = create_input_handler(&args.input)?;
+// The exit code of whole commands, each run in a process of its own as a
+// session installs signal handlers and a panic hook.
+#[cfg(test)]
+mod tests {
+    use std::process::{self, Command, Stdio};
+    use std::{env, fs};
 
-    run_peripheral(key_input, sleep_duration).await
-}
+    use super::*;
 
-async fn run_peripheral(
-    key_input: Arc<AtomicCell<KeyInput>>,
-    sleep_duration: tokio::time::Duration,
-) -> Result<()> {
-    info!("Preparing peripheral");
-    let peripheral = Peripheral::new().await?;
-    peripheral.add_service(&create_key_input(key_input, sleep_duration))?;
+    const ARGV: &str = "BEATBLE_EXIT_TEST";
 
-    while !peripheral.is_powered().await? {}
-    info!("Peripheral powered on");
+    /// the exit code of beatble with args, space separated
+    fn exit_code(args: &str) -> Option<i32> {
+        Command::new(env::current_exe().expect("the test binary"))
+            .args(["--exact", "tests::command", "--ignored", "-q"])
+            .env(ARGV, args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .expect("the command to run")
+            .code()
+    }
 
-    peripheral.register_gatt().await?;
-    peripheral.start_advertising(ADVERTISING_NAME, &[]).await?;
+    #[test]
+    #[ignore = "run by the exit code tests"]
+    fn command() {
+        let Ok(args) = env::var(ARGV) else {
+            return;
+        };
+        let argv = ["beatble"].into_iter().chain(args.split(' '));
+        let matches = match Cli::command().try_get_matches_from(argv) {
+            Ok(matches) => matches,
+            Err(_) => process::exit(ErrorKind::Config.code().into()),
+        };
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+        let code = match start(cli, &matches, None) {
+            Ok(()) => 0,
+            Err(e) => exit::code(&e),
+        };
+        process::exit(code.into());
+    }
 
-    while !peripheral.is_advertising().await? {}
-    info!("Peripheral started advertising {}", ADVERTISING_NAME);
+    #[test]
+    fn an_invalid_configuration_exits_with_4() {
+        let config = env::temp_dir().join(format!("beatble-exit-{}.toml", process::id()));
+        fs::write(&config, "sleep-duration = \"fast\"").unwrap();
+        let invalid = format!("--config {} config show", config.display());
+        let code = exit_code(&invalid);
+        fs::remove_file(&config).unwrap();
+        assert_eq!(code, Some(4));
 
-    while peripheral.is_advertising().await? {
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        assert_eq!(
+            exit_code("--config /nonexistent/beatble.toml config show"),
+            Some(4)
+        );
+        assert_eq!(exit_code("config show --sleep-duration 0"), Some(4));
+        assert_eq!(exit_code("config show --no-such-flag"), Some(4));
     }
-    info!("Peripheral stopped advertising {}", ADVERTISING_NAME);
 
-    Ok(())
+    #[cfg(feature = "input")]
+    #[test]
+    fn a_missing_device_exits_with_2() {
+        assert_eq!(exit_code("info /nonexistent/js0"), Some(2));
+        #[cfg(feature = "ble")]
+        assert_eq!(exit_code("run /nonexistent/js0 --dry-run"), Some(2));
+    }
 }
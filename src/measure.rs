@@ -0,0 +1,224 @@
+// `beatble measure-latency`: the whole path from an input event to the
+// notification that carries it, on this machine. A uinput joystick stands in
+// for the controller and the usual reader and notifier run on it; the frames
+// go to a local subscriber as with --dry-run, since bluez can't connect an
+// adapter to itself. Each sample toggles B1 and records
+//
+// - latency: from the write into uinput until a frame shows the new state
+//
+// with the injections spread over the frame period, so a periodic notifier
+// should come out at about half the period plus the input path. The radio
+// and the central's connection interval come on top of this.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use beatble::ble::{spawn_local_notifier, NotifyConfig, NotifyContext, NotifyMode, RepeatSpacing};
+use beatble::control::Control;
+use beatble::emulation::Emulation;
+use beatble::exit::ErrorKind;
+use beatble::input::{create_input_handler, Mirror};
+use beatble::latency::{Histogram, Latency, Summary};
+use beatble::payload::{Frame, Layout, ScratchMode};
+use beatble::stats::Stats;
+use beatble_protocol::{KeyInput, NormalButton};
+use eyre::{bail, eyre, Result, WrapErr};
+use futures::StreamExt;
+use serde_json::json;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout, Instant};
+use tracing::warn;
+
+const PROBE_NAME: &str = "beatble latency probe";
+// how long udev may take to create the joystick node
+const NODE_TIMEOUT: Duration = Duration::from_secs(3);
+// a toggle that takes longer than this is lost
+const SAMPLE_TIMEOUT: Duration = Duration::from_secs(1);
+// the widest bar of the histogram
+const BAR_WIDTH: u64 = 50;
+
+pub async fn run(samples: u32, sleep_duration: u64, busy_poll: bool, json: bool) -> Result<()> {
+    if samples == 0 || sleep_duration == 0 {
+        return Err(eyre!("samples and sleep-duration must be at least 1"))
+            .wrap_err(ErrorKind::Config);
+    }
+    let period = Duration::from_millis(sleep_duration);
+    let mut probe = Mirror::create_named(PROBE_NAME, Emulation::Iidx)
+        .wrap_err("failed to create the uinput joystick")
+        .wrap_err(ErrorKind::InputDevice)?;
+    let device = joystick(&probe).await.wrap_err(ErrorKind::InputDevice)?;
+
+    let stats = Arc::new(Stats::new());
+    let (key_input, mut reader) = create_input_handler(
+        &device,
+        Emulation::Iidx.into(),
+        busy_poll,
+        false,
+        Arc::clone(&stats),
+    )
+    .wrap_err(ErrorKind::InputDevice)?;
+    let notify_config = NotifyConfig {
+        interval: period,
+        mode: NotifyMode::Periodic,
+        warmup_frames: 0,
+        counter_start: 0,
+        frame_repeat: 1,
+        repeat_spacing: RepeatSpacing::BackToBack,
+        busy_poll,
+        layout: Layout::default(),
+        emulation: Emulation::Iidx,
+        scratch_mode: ScratchMode::Position,
+        scratch_predict: None,
+    };
+    let context = NotifyContext {
+        key_input,
+        control: Arc::new(Control::new(notify_config.interval, notify_config.mode)),
+        latency: Arc::new(Latency::new()),
+        stats,
+        dump: None,
+    };
+    let data_age = Arc::clone(&context.latency);
+    let mut frames = spawn_local_notifier(context, notify_config);
+    // stamped as they arrive, so a slow check here doesn't add to the latency
+    let (sender, mut received) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(bytes) = frames.next().await {
+            let pressed = Frame::decode(&bytes, notify_config.layout)
+                .map(|frame| frame.first.normal_button.contains(NormalButton::B1));
+            if sender.send((Instant::now(), pressed)).is_err() {
+                break;
+            }
+        }
+    });
+
+    if !json {
+        eprintln!(
+            "Injecting {samples} toggles of B1 into {device} at a {}ms frame period",
+            period.as_millis()
+        );
+    }
+    let latency = Histogram::new();
+    let mut lost = 0;
+    let mut key_input = KeyInput::init();
+    // start from a frame of the current state
+    sleep(period * 2).await;
+    for sample in 0..samples {
+        // a different phase of the frame clock every time
+        let phase = period.mul_f64(f64::from(sample % 97) / 97.0);
+        sleep(period + phase).await;
+        while received.try_recv().is_ok() {}
+
+        key_input.normal_button.toggle(NormalButton::B1);
+        let pressed = key_input.normal_button.contains(NormalButton::B1);
+        let injected_at = Instant::now();
+        probe
+            .update(key_input)
+            .wrap_err("failed to write to the uinput joystick")
+            .wrap_err(ErrorKind::InputRuntime)?;
+        let shown: Result<Result<Instant>, _> = timeout(SAMPLE_TIMEOUT, async {
+            loop {
+                tokio::select! {
+                    frame = received.recv() => match frame {
+                        Some((at, Ok(state))) if state == pressed => return Ok(at),
+                        Some(_) => {}
+                        None => return Err(eyre!("the notifier stopped")),
+                    },
+                    result = &mut reader => {
+                        result??;
+                        return Err(eyre!("the input reader stopped"));
+                    }
+                }
+            }
+        })
+        .await;
+        match shown {
+            Ok(at) => latency.record(at?.duration_since(injected_at).as_micros() as u64),
+            Err(_) => {
+                warn!("sample {sample}: no frame within {SAMPLE_TIMEOUT:?}");
+                lost += 1;
+            }
+        }
+    }
+    // removing the device ends the reader
+    drop(probe);
+
+    let summary = latency.summary();
+    let data_age = data_age.data_age.summary();
+    if summary.count == 0 {
+        bail!("no toggle ever reached a frame");
+    }
+    let period_us = period.as_micros() as u64;
+    if json {
+        let buckets: Vec<_> = latency
+            .buckets()
+            .into_iter()
+            .map(|(from_us, count)| json!({ "from_us": from_us, "count": count }))
+            .collect();
+        let result = json!({
+            "device": device,
+            "samples": samples,
+            "lost": lost,
+            "frame_period_us": period_us,
+            "busy_poll": busy_poll,
+            "latency_us": summary,
+            "data_age_us": data_age,
+            "histogram": buckets,
+        });
+        println!("{result:#}");
+        return Ok(());
+    }
+
+    println!(
+        "{samples} toggles of B1 through {device}, {lost} lost, {}ms frame period",
+        period.as_millis()
+    );
+    println!();
+    println!(
+        "{:<18}{:>8}{:>8}{:>8}{:>8}{:>8}",
+        "", "count", "p50", "p95", "p99", "max"
+    );
+    print_row("latency (us)", summary);
+    print_row("data age (us)", data_age);
+    println!();
+    print_histogram(&latency);
+    println!();
+    println!(
+        "p50 is {:.2} frame periods; a periodic notifier alone accounts for about 0.5.",
+        summary.p50 as f64 / period_us as f64
+    );
+    Ok(())
+}
+
+/// the probe's joystick node, once udev created it
+async fn joystick(probe: &Mirror) -> Result<String> {
+    let deadline = Instant::now() + NODE_TIMEOUT;
+    loop {
+        if let Some(node) = probe.joystick()? {
+            if std::path::Path::new(&node).exists() {
+                return Ok(node);
+            }
+        }
+        if Instant::now() > deadline {
+            bail!(
+                "no joystick node for the uinput device after {NODE_TIMEOUT:?}, is joydev loaded?"
+            );
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+fn print_row(name: &str, summary: Summary) {
+    println!(
+        "{name:<18}{:>8}{:>8}{:>8}{:>8}{:>8}",
+        summary.count, summary.p50, summary.p95, summary.p99, summary.max
+    );
+}
+
+fn print_histogram(histogram: &Histogram) {
+    let buckets = histogram.buckets();
+    let most = buckets.iter().map(|&(_, count)| count).max().unwrap_or(1);
+    for (from_us, count) in buckets {
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(most) as usize);
+        println!("{from_us:>8}us {count:>6} {bar}");
+    }
+}
@@ -0,0 +1,231 @@
+// `--metrics-listen`: Stats and Latency in the Prometheus text format. A
+// scrape only loads atomics, so it never holds up the input reader or the
+// notifiers.
+
+use std::fmt::{self, Write as _};
+use std::net::SocketAddr;
+
+use beatble::ble::NotifyContext;
+use beatble::latency::Histogram;
+use beatble::stats::BUTTON_NAMES;
+use eyre::Result;
+use tracing::info;
+
+use crate::http::{self, Response};
+
+// 128us to ~2.1s, two buckets per power of two
+const HISTOGRAM_LOW: u32 = 7;
+const HISTOGRAM_HIGH: u32 = 21;
+const HISTOGRAM_STRIDE: usize = 4;
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub async fn spawn(address: SocketAddr, context: NotifyContext) -> Result<()> {
+    http::serve(address, move |path| match path {
+        "/metrics" => Response::ok(
+            CONTENT_TYPE,
+            render(&context).expect("writing to a String never fails"),
+        ),
+        _ => Response::not_found(),
+    })
+    .await?;
+    info!("Serving metrics on http://{address}/metrics");
+    Ok(())
+}
+
+fn render(context: &NotifyContext) -> Result<String, fmt::Error> {
+    let stats = context.stats.snapshot();
+    let latency = &context.latency;
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "beatble_input_events_total",
+        "counter",
+        "input events read from the device",
+    )?;
+    for (kind, counter) in [
+        ("button_pressed", stats.buttons_pressed),
+        ("button_released", stats.buttons_released),
+        ("axis_changed", stats.axis_changes),
+    ] {
+        sample(
+            &mut out,
+            "beatble_input_events_total",
+            &format!("type=\"{kind}\""),
+            counter,
+        )?;
+    }
+
+    header(
+        &mut out,
+        "beatble_button_presses_total",
+        "counter",
+        "presses by button",
+    )?;
+    for (name, counter) in BUTTON_NAMES.iter().zip(&stats.presses) {
+        if let Some(name) = name {
+            sample(
+                &mut out,
+                "beatble_button_presses_total",
+                &format!("button=\"{name}\""),
+                counter,
+            )?;
+        }
+    }
+
+    for (name, help, counter) in [
+        (
+            "beatble_scratch_travel_total",
+            "scratch movement in raw axis units, 65536 per turn",
+            stats.scratch_travel,
+        ),
+        (
+            "beatble_notifications_sent_total",
+            "frames handed to a subscriber",
+            stats.sent_frames,
+        ),
+        (
+            "beatble_notifications_repeated_total",
+            "--frame-repeat copies handed to a subscriber on top of the frames",
+            stats.repeated_frames,
+        ),
+        (
+            "beatble_notifications_dropped_total",
+            "frames that didn't fit into the notification channel",
+            stats.congested_frames,
+        ),
+        (
+            "beatble_subscriptions_total",
+            "notify subscriptions, more than one means a central reconnected",
+            stats.subscriptions,
+        ),
+        (
+            "beatble_unsubscriptions_total",
+            "subscriptions ended by an unsubscribe request",
+            stats.unsubscriptions,
+        ),
+        (
+            "beatble_connections_total",
+            "times a central subscribed while none was",
+            stats.connections,
+        ),
+        (
+            "beatble_disconnections_total",
+            "times the last subscriber went away",
+            stats.disconnections,
+        ),
+        (
+            "beatble_readvertisements_total",
+            "times advertising started over after stopping without a subscriber",
+            stats.readvertisements,
+        ),
+        (
+            "beatble_bluez_losses_total",
+            "times bluetoothd went away and the peripheral was set up again",
+            stats.bluez_losses,
+        ),
+        (
+            "beatble_pairings_total",
+            "centrals that finished pairing through the --pairing agent",
+            stats.pairings,
+        ),
+        (
+            "beatble_failed_pairings_total",
+            "pairings the --pairing agent rejected or BlueZ cancelled",
+            stats.failed_pairings,
+        ),
+        (
+            "beatble_pings_total",
+            "tokens echoed by the ping characteristic",
+            stats.pings,
+        ),
+        (
+            "beatble_throttled_pings_total",
+            "pings not echoed: too soon after the last, too long or unsubscribed",
+            stats.throttled_pings,
+        ),
+        (
+            "beatble_notifier_stalls_total",
+            "notifier tasks that stopped making progress and were respawned",
+            stats.stalls,
+        ),
+    ] {
+        header(&mut out, name, "counter", help)?;
+        sample(&mut out, name, "", counter)?;
+    }
+
+    header(
+        &mut out,
+        "beatble_subscribers",
+        "gauge",
+        "subscriptions currently being notified",
+    )?;
+    sample(&mut out, "beatble_subscribers", "", stats.subscribers)?;
+
+    header(
+        &mut out,
+        "beatble_connected_seconds_total",
+        "counter",
+        "time a central was subscribed",
+    )?;
+    sample(
+        &mut out,
+        "beatble_connected_seconds_total",
+        "",
+        stats.connected_ms as f64 / 1000.0,
+    )?;
+    header(
+        &mut out,
+        "beatble_last_connection_seconds",
+        "gauge",
+        "how long the latest ended connection lasted",
+    )?;
+    sample(
+        &mut out,
+        "beatble_last_connection_seconds",
+        "",
+        stats.last_connection_ms as f64 / 1000.0,
+    )?;
+
+    histogram(
+        &mut out,
+        "beatble_frame_interval_seconds",
+        "time between two sent frames",
+        &latency.frame_interval,
+    )?;
+    histogram(
+        &mut out,
+        "beatble_data_age_seconds",
+        "time from the input event to the frame carrying it",
+        &latency.data_age,
+    )?;
+    Ok(out)
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} {kind}")
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl fmt::Display) -> fmt::Result {
+    if labels.is_empty() {
+        writeln!(out, "{name} {value}")
+    } else {
+        writeln!(out, "{name}{{{labels}}} {value}")
+    }
+}
+
+/// microsecond histogram in seconds; SIGUSR2 resets it like a counter reset
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) -> fmt::Result {
+    let cumulative = histogram.cumulative(HISTOGRAM_LOW, HISTOGRAM_HIGH, HISTOGRAM_STRIDE);
+    header(out, name, "histogram", help)?;
+    let bucket = format!("{name}_bucket");
+    // bounds are exclusive by a microsecond, close enough for an le label
+    for (bound, count) in &cumulative.buckets {
+        let le = *bound as f64 / 1e6;
+        sample(out, &bucket, &format!("le=\"{le}\""), count)?;
+    }
+    sample(out, &bucket, "le=\"+Inf\"", cumulative.count)?;
+    sample(out, &format!("{name}_sum"), "", cumulative.sum as f64 / 1e6)?;
+    sample(out, &format!("{name}_count"), "", cumulative.count)
+}
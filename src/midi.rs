@@ -0,0 +1,253 @@
+// --midi: a virtual ALSA sequencer port named "beatble" that plays a note per
+// key and option button while it is held and sends the turntable as a
+// controller, for practice software that takes MIDI. It is fed by an input
+// tap, so every event goes out as the reader stores it instead of waiting for
+// the next frame; with --no-bluetooth nothing else is needed.
+
+use std::ffi::CString;
+use std::fmt;
+use std::ptr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use alsa::seq::{EvCtrl, EvNote, Event, EventType, PortCap, PortType, Seq};
+use beatble::emulation::Emulation;
+use beatble::input::{add_tap, SharedKeyInput, Tap};
+use beatble_protocol::{KeyInput, NormalButton, OptionButton};
+use eyre::{Result, WrapErr};
+use tracing::{info, trace, warn};
+
+use crate::cli::RunArgs;
+
+const PORT_NAME: &str = "beatble";
+const VELOCITY: u8 = 100;
+const MAX_CC: u8 = 119;
+const KEYS: [NormalButton; 7] = [
+    NormalButton::B1,
+    NormalButton::B2,
+    NormalButton::B3,
+    NormalButton::B4,
+    NormalButton::B5,
+    NormalButton::B6,
+    NormalButton::B7,
+];
+const OPTIONS: [OptionButton; 4] = [
+    OptionButton::E1,
+    OptionButton::E2,
+    OptionButton::E3,
+    OptionButton::E4,
+];
+
+/// Notes of B1 to B7 and E1 to E4, e.g. `60,62,64,65,67,69,71,72,74,76,77`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MidiNotes(pub [u8; 11]);
+
+impl Default for MidiNotes {
+    /// the white keys up from middle C
+    fn default() -> Self {
+        Self([60, 62, 64, 65, 67, 69, 71, 72, 74, 76, 77])
+    }
+}
+
+impl FromStr for MidiNotes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let notes = s
+            .split(',')
+            .map(|note| match note.trim().parse() {
+                Ok(note) if note < 128 => Ok(note),
+                _ => Err(format!("not a MIDI note from 0 to 127: {note}")),
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        let notes = notes.try_into().map_err(|notes: Vec<u8>| {
+            format!(
+                "expected 11 notes, for B1 to B7 and E1 to E4, got {}",
+                notes.len()
+            )
+        })?;
+        Ok(Self(notes))
+    }
+}
+
+impl fmt::Display for MidiNotes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, note) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{note}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What goes out on the port.
+#[derive(Clone, Copy, Debug)]
+struct MidiConfig {
+    /// from 0, though shown from 1
+    pub channel: u8,
+    pub notes: MidiNotes,
+    /// the turntable, or VOL-L with VOL-R on the next controller
+    pub scratch_cc: u8,
+    pub emulation: Emulation,
+}
+
+struct Port {
+    seq: Seq,
+    port: i32,
+    // what the port last sent
+    sent: KeyInput,
+}
+
+/// Sends the input stored into one SharedKeyInput.
+struct Bridge {
+    input: Arc<SharedKeyInput>,
+    config: MidiConfig,
+    port: Mutex<Port>,
+}
+
+/// Opens the port and sends key_input to it from now on.
+pub fn spawn(args: &RunArgs, key_input: Arc<SharedKeyInput>) -> Result<()> {
+    if !(1..=16).contains(&args.midi_channel) {
+        eyre::bail!(
+            "midi-channel must be from 1 to 16, got {}",
+            args.midi_channel
+        );
+    }
+    // 120 and up are channel mode messages
+    let last_cc = match args.emulate {
+        Emulation::Iidx => MAX_CC,
+        Emulation::Sdvx => MAX_CC - 1,
+    };
+    if args.midi_scratch_cc > last_cc {
+        eyre::bail!(
+            "midi-scratch-cc must be at most {last_cc} when emulating {}, got {}",
+            args.emulate,
+            args.midi_scratch_cc
+        );
+    }
+    let config = MidiConfig {
+        channel: args.midi_channel - 1,
+        notes: args.midi_notes,
+        scratch_cc: args.midi_scratch_cc,
+        emulation: args.emulate,
+    };
+
+    let seq = Seq::open(None, Some(alsa::Direction::Playback), false)
+        .wrap_err("failed to open the ALSA sequencer")?;
+    let name = CString::new(PORT_NAME)?;
+    seq.set_client_name(&name)?;
+    let port = seq
+        .create_simple_port(
+            &name,
+            PortCap::READ | PortCap::SUBS_READ,
+            PortType::MIDI_GENERIC | PortType::APPLICATION,
+        )
+        .wrap_err("failed to create the MIDI port")?;
+    let client = seq.client_id()?;
+    info!(
+        "MIDI port {client}:{port} on channel {}, connect it with `aconnect {client}:{port} DEST`",
+        config.channel + 1
+    );
+    add_tap(Arc::new(Bridge {
+        input: key_input,
+        config,
+        port: Mutex::new(Port {
+            seq,
+            port,
+            sent: KeyInput::init(),
+        }),
+    }));
+    Ok(())
+}
+
+impl Tap for Bridge {
+    fn stored(&self, input: &SharedKeyInput, key_input: KeyInput) {
+        // the other side in double play
+        if !ptr::eq(input, &*self.input) {
+            return;
+        }
+        let mut port = self.port.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = port.send(&self.config, key_input) {
+            warn!("failed to send MIDI events: {e}");
+        }
+    }
+}
+
+impl Port {
+    fn send(&mut self, config: &MidiConfig, key_input: KeyInput) -> alsa::Result<()> {
+        let sent = self.sent;
+        let keys = KEYS.iter().map(|&key| {
+            let (before, now) = (sent.normal_button, key_input.normal_button);
+            (before.contains(key), now.contains(key))
+        });
+        let options = OPTIONS.iter().map(|&option| {
+            let (before, now) = (sent.option_button, key_input.option_button);
+            (before.contains(option), now.contains(option))
+        });
+        for ((before, now), &note) in keys.chain(options).zip(&config.notes.0) {
+            if before != now {
+                self.note(config.channel, note, now)?;
+            }
+        }
+        match config.emulation {
+            // 7 bits of the turntable angle
+            Emulation::Iidx => {
+                let (before, now) = (
+                    sent.scratch_position() >> 9,
+                    key_input.scratch_position() >> 9,
+                );
+                if before != now {
+                    self.control(config.channel, config.scratch_cc, now as u8)?;
+                }
+            }
+            Emulation::Sdvx => {
+                if sent.scratch >> 1 != key_input.scratch >> 1 {
+                    self.control(config.channel, config.scratch_cc, key_input.scratch >> 1)?;
+                }
+                if sent.analog >> 1 != key_input.analog >> 1 {
+                    let cc = config.scratch_cc + 1;
+                    self.control(config.channel, cc, key_input.analog >> 1)?;
+                }
+            }
+        }
+        self.sent = key_input;
+        Ok(())
+    }
+
+    fn note(&self, channel: u8, note: u8, on: bool) -> alsa::Result<()> {
+        trace!(note, on, "MIDI note");
+        let (event_type, velocity) = if on {
+            (EventType::Noteon, VELOCITY)
+        } else {
+            (EventType::Noteoff, 0)
+        };
+        let data = EvNote {
+            channel,
+            note,
+            velocity,
+            off_velocity: 0,
+            duration: 0,
+        };
+        self.output(Event::new(event_type, &data))
+    }
+
+    fn control(&self, channel: u8, cc: u8, value: u8) -> alsa::Result<()> {
+        let data = EvCtrl {
+            channel,
+            param: cc.into(),
+            value: value.into(),
+        };
+        self.output(Event::new(EventType::Controller, &data))
+    }
+
+    /// straight to the subscribers, without the sequencer's queue
+    fn output(&self, mut event: Event) -> alsa::Result<()> {
+        event.set_source(self.port);
+        event.set_subs();
+        event.set_direct();
+        self.seq.event_output_direct(&mut event)?;
+        Ok(())
+    }
+}
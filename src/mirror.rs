@@ -0,0 +1,58 @@
+// --mirror-uinput: the 1P input as a uinput gamepad named "beatble mirror",
+// for software on this machine, e.g. a simulator to warm up on while the
+// controller is paired. Like --midi it is fed by an input tap, so it follows
+// every event; the device is removed when the session ends.
+
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use beatble::emulation::Emulation;
+use beatble::input::{add_tap, remove_tap, Mirror, SharedKeyInput, Tap, MIRROR_NAME};
+use beatble_protocol::KeyInput;
+use eyre::{Result, WrapErr};
+use tracing::{info, warn};
+
+/// Shows the input stored into one SharedKeyInput.
+struct Output {
+    input: Arc<SharedKeyInput>,
+    mirror: Mutex<Mirror>,
+}
+
+/// Removes the mirror when dropped.
+pub struct MirrorGuard(Arc<dyn Tap>);
+
+/// Creates the mirror and shows key_input on it from now on.
+pub fn spawn(emulation: Emulation, key_input: Arc<SharedKeyInput>) -> Result<MirrorGuard> {
+    let mut mirror = Mirror::create(emulation).wrap_err("failed to create the uinput mirror")?;
+    mirror
+        .update(key_input.load())
+        .wrap_err("failed to write to the uinput mirror")?;
+    info!("mirroring the input to a uinput gamepad named \"{MIRROR_NAME}\"");
+    let tap: Arc<dyn Tap> = Arc::new(Output {
+        input: key_input,
+        mirror: Mutex::new(mirror),
+    });
+    add_tap(Arc::clone(&tap));
+    Ok(MirrorGuard(tap))
+}
+
+impl Tap for Output {
+    fn stored(&self, input: &SharedKeyInput, key_input: KeyInput) {
+        // the other side in double play
+        if !ptr::eq(input, &*self.input) {
+            return;
+        }
+        let mut mirror = self.mirror.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = mirror.update(key_input) {
+            warn!("failed to write to the uinput mirror: {e}");
+        }
+    }
+}
+
+impl Drop for MirrorGuard {
+    fn drop(&mut self) {
+        // the last reference, so the device goes with it
+        remove_tap(&self.0);
+        info!("removed the uinput mirror");
+    }
+}
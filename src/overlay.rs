@@ -0,0 +1,234 @@
+// `--overlay-listen`: a WebSocket that pushes the input state to stream
+// overlays, see examples/overlay.html. Every client gets its own receiver of
+// a broadcast channel; one that falls behind is disconnected instead of
+// holding up the others.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use beatble::emulation::Emulation;
+use beatble::input::SharedKeyInput;
+use beatble_protocol::{KeyInput, NormalButton, OptionButton};
+use eyre::{Result, WrapErr};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{interval, timeout, Duration, Instant, MissedTickBehavior};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tracing::{debug, info, warn};
+
+// fast enough for a 60 fps overlay; a tap shorter than this can be missed,
+// since sampling doesn't consume the notifier's latched presses
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(8);
+const HEARTBEAT: Duration = Duration::from_secs(1);
+// messages a client may fall behind before it is dropped
+const CLIENT_BACKLOG: usize = 64;
+const SEND_TIMEOUT: Duration = Duration::from_secs(1);
+const LANES: [NormalButton; 7] = [
+    NormalButton::B1,
+    NormalButton::B2,
+    NormalButton::B3,
+    NormalButton::B4,
+    NormalButton::B5,
+    NormalButton::B6,
+    NormalButton::B7,
+];
+const OPTIONS: [OptionButton; 4] = [
+    OptionButton::E1,
+    OptionButton::E2,
+    OptionButton::E3,
+    OptionButton::E4,
+];
+
+/// Sent as a JSON text message on every input change, and at least once per
+/// second without one.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    /// "iidx" or "sdvx"
+    pub emulate: String,
+    /// B1 to B7, true while held
+    pub lanes: [bool; 7],
+    /// E1 to E4, true while held
+    pub options: [bool; 4],
+    pub scratch: Scratch,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scratch {
+    /// the scratch and analog bytes as one big-endian value: the turntable
+    /// angle for iidx, 65536 per turn
+    pub position: u16,
+    /// turns per second since the previous message, positive when the
+    /// position grows
+    pub velocity: f64,
+}
+
+impl Message {
+    fn new(emulation: Emulation, key_input: KeyInput, velocity: f64) -> Self {
+        Self {
+            emulate: emulation.to_string(),
+            lanes: LANES.map(|lane| key_input.normal_button.contains(lane)),
+            options: OPTIONS.map(|option| key_input.option_button.contains(option)),
+            scratch: Scratch {
+                position: position(key_input),
+                velocity,
+            },
+        }
+    }
+}
+
+fn position(key_input: KeyInput) -> u16 {
+    u16::from_be_bytes([key_input.scratch, key_input.analog])
+}
+
+/// Binds right away, so a taken address fails the session at startup.
+pub async fn spawn(
+    address: SocketAddr,
+    key_input: Arc<SharedKeyInput>,
+    emulation: Emulation,
+) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .await
+        .wrap_err_with(|| format!("failed to listen on {address}"))?;
+    info!("Serving the input overlay on ws://{address}");
+    let (sender, _) = broadcast::channel(CLIENT_BACKLOG);
+
+    tokio::spawn(sample(key_input, emulation, sender.clone()));
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tokio::spawn(serve(stream, peer, sender.subscribe()));
+                }
+                Err(e) => debug!("overlay accept failed: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn sample(
+    key_input: Arc<SharedKeyInput>,
+    emulation: Emulation,
+    sender: broadcast::Sender<String>,
+) {
+    let mut ticker = interval(SAMPLE_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut last = KeyInput::init();
+    let mut last_sent_at = Instant::now();
+    let mut clients = 0;
+    loop {
+        ticker.tick().await;
+        let current = key_input.load();
+        // a new client gets the current state right away
+        let joined = sender.receiver_count() > clients;
+        clients = sender.receiver_count();
+        if current == last && last_sent_at.elapsed() < HEARTBEAT && !joined {
+            continue;
+        }
+        // the shorter way round, like the scratch travel counter
+        let travel = position(current).wrapping_sub(position(last)) as i16;
+        let velocity = travel as f64 / 65536.0 / last_sent_at.elapsed().as_secs_f64();
+        last = current;
+        last_sent_at = Instant::now();
+        if clients == 0 {
+            continue;
+        }
+        match serde_json::to_string(&Message::new(emulation, current, velocity)) {
+            Ok(message) => {
+                let _ = sender.send(message);
+            }
+            Err(e) => warn!("failed to encode overlay message: {e}"),
+        }
+    }
+}
+
+async fn serve(stream: TcpStream, peer: SocketAddr, mut messages: broadcast::Receiver<String>) {
+    let mut socket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!("overlay handshake with {peer} failed: {e}");
+            return;
+        }
+    };
+    info!("Overlay client {peer} connected");
+    loop {
+        tokio::select! {
+            message = messages.recv() => match message {
+                Ok(message) => {
+                    let sent = timeout(SEND_TIMEOUT, socket.send(WsMessage::text(message))).await;
+                    if !matches!(sent, Ok(Ok(()))) {
+                        warn!("overlay client {peer} is not keeping up, dropping it");
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("overlay client {peer} fell {skipped} messages behind, dropping it");
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // only a close or an error matter, pings are answered by tungstenite
+            incoming = socket.next() => match incoming {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = socket.close(None).await;
+    info!("Overlay client {peer} disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the example in the README, which overlays are written against
+    const README: &str = r#"{"emulate":"iidx","lanes":[false,true,false,false,false,false,false],"options":[false,false,false,false],"scratch":{"position":1000,"velocity":1.78}}"#;
+
+    fn key_input(
+        normal_button: NormalButton,
+        option_button: OptionButton,
+        position: u16,
+    ) -> KeyInput {
+        let [scratch, analog] = position.to_be_bytes();
+        KeyInput {
+            scratch,
+            normal_button,
+            option_button,
+            analog,
+        }
+    }
+
+    #[test]
+    fn messages_round_trip_through_json() {
+        let messages = [
+            Message::new(Emulation::Iidx, KeyInput::init(), 0.0),
+            Message::new(
+                Emulation::Sdvx,
+                key_input(
+                    NormalButton::B1 | NormalButton::B7,
+                    OptionButton::E2,
+                    0xfffe,
+                ),
+                -0.25,
+            ),
+        ];
+        for message in messages {
+            let json = serde_json::to_string(&message).unwrap();
+            assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn the_readme_example_is_what_gets_sent() {
+        let message = Message::new(
+            Emulation::Iidx,
+            key_input(NormalButton::B2, OptionButton::empty(), 1000),
+            1.78,
+        );
+        assert_eq!(serde_json::to_string(&message).unwrap(), README);
+        assert_eq!(serde_json::from_str::<Message>(README).unwrap(), message);
+    }
+}
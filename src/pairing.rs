@@ -0,0 +1,305 @@
+// `--pairing passkey|confirm`: a BlueZ pairing agent for centrals that insist
+// on authenticated pairing, which just works can't give them. The kernel picks
+// the passkey and BlueZ hands it to the agent, which logs it for the player
+// to type on the central (passkey entry) or to compare with the central's
+// (numeric comparison).
+//
+// - passkey registers as DisplayOnly, so the central always types
+// - confirm registers as DisplayYesNo: numeric comparison where the central
+//   has a display, confirmed with E1 or rejected with E2 on the controller,
+//   or confirmed by itself after --pairing-auto-confirm; a central that can
+//   only type gets passkey entry as with passkey
+//
+// Pairing outcomes, from the agent and Device1.Paired, go to the log and stats.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use beatble::input::SharedKeyInput;
+use beatble::stats::Stats;
+use beatble_protocol::OptionButton;
+use eyre::{Result, WrapErr};
+use futures::StreamExt;
+use tokio::sync::Notify;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+use zbus::message::Type;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue};
+use zbus::{interface, Connection, DBusError, MatchRule, MessageStream};
+
+const AGENT_PATH: &str = "/dev/watiko/Beatble1/agent";
+// when the kernel gives up on a pairing that isn't answered
+const SMP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Who authenticates a pairing central.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pairing {
+    /// BlueZ pairs without an agent, unauthenticated
+    #[default]
+    JustWorks,
+    /// the central types the passkey beatble shows
+    Passkey,
+    /// the player confirms the number both sides show
+    Confirm,
+}
+
+impl Pairing {
+    /// the agent's IO capability as BlueZ names it
+    fn capability(self) -> &'static str {
+        match self {
+            Pairing::JustWorks => "NoInputNoOutput",
+            Pairing::Passkey => "DisplayOnly",
+            Pairing::Confirm => "DisplayYesNo",
+        }
+    }
+}
+
+impl FromStr for Pairing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "just-works" => Ok(Pairing::JustWorks),
+            "passkey" => Ok(Pairing::Passkey),
+            "confirm" => Ok(Pairing::Confirm),
+            _ => Err(format!(
+                "unknown pairing: {s} (expected just-works, passkey or confirm)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Pairing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pairing::JustWorks => write!(f, "just-works"),
+            Pairing::Passkey => write!(f, "passkey"),
+            Pairing::Confirm => write!(f, "confirm"),
+        }
+    }
+}
+
+/// The replies BlueZ understands from an agent.
+#[derive(Debug, DBusError)]
+#[zbus(prefix = "org.bluez.Error")]
+enum AgentError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    Rejected(String),
+    Canceled(String),
+}
+
+struct Agent {
+    key_input: Arc<SharedKeyInput>,
+    stats: Arc<Stats>,
+    auto_confirm: Option<Duration>,
+    // BlueZ gave up on the request being answered
+    cancelled: Notify,
+}
+
+#[interface(name = "org.bluez.Agent1")]
+impl Agent {
+    fn release(&self) {
+        warn!("BlueZ released the pairing agent");
+    }
+
+    /// legacy PIN pairing, which a controller has no way to enter
+    fn request_pin_code(&self, device: OwnedObjectPath) -> Result<String, AgentError> {
+        self.failed(&device, "it asked for a PIN code");
+        Err(AgentError::Rejected("no PIN code input".to_owned()))
+    }
+
+    fn display_pin_code(&self, device: OwnedObjectPath, pincode: String) {
+        info!(device = %device, "pairing PIN code: {pincode}, enter it on the central");
+    }
+
+    /// only asked of an agent that can type
+    fn request_passkey(&self, device: OwnedObjectPath) -> Result<u32, AgentError> {
+        self.failed(&device, "it asked for a passkey to type");
+        Err(AgentError::Rejected("no passkey input".to_owned()))
+    }
+
+    /// called again for every digit typed on the central
+    fn display_passkey(&self, device: OwnedObjectPath, passkey: u32, entered: u16) {
+        if entered == 0 {
+            info!(device = %device, "pairing passkey: {passkey:06}, enter it on the central");
+        } else {
+            debug!(device = %device, "{entered} digit(s) of the passkey entered");
+        }
+    }
+
+    async fn request_confirmation(
+        &self,
+        device: OwnedObjectPath,
+        passkey: u32,
+    ) -> Result<(), AgentError> {
+        let auto = match self.auto_confirm {
+            Some(after) => format!(", accepted by itself after {after:?}"),
+            None => String::new(),
+        };
+        info!(
+            device = %device,
+            "pairing code: {passkey:06}, if the central shows the same press E1, otherwise E2{auto}"
+        );
+        let answer = async {
+            match self.auto_confirm {
+                Some(after) => timeout(after, self.button()).await.unwrap_or(Some(true)),
+                None => self.button().await,
+            }
+        };
+        let accepted = tokio::select! {
+            accepted = answer => accepted,
+            _ = self.cancelled.notified() => return Err(AgentError::Canceled("cancelled".to_owned())),
+        };
+        match accepted {
+            Some(true) => {
+                info!(device = %device, "pairing code confirmed");
+                Ok(())
+            }
+            Some(false) => {
+                self.failed(&device, "E2 rejected the code");
+                Err(AgentError::Rejected(
+                    "rejected on the controller".to_owned(),
+                ))
+            }
+            None => {
+                self.failed(&device, "the input is gone");
+                Err(AgentError::Rejected("no input to confirm with".to_owned()))
+            }
+        }
+    }
+
+    /// just works pairing from a central while an agent is registered
+    fn request_authorization(&self, device: OwnedObjectPath) {
+        info!(device = %device, "accepting unauthenticated pairing");
+    }
+
+    fn authorize_service(&self, device: OwnedObjectPath, uuid: String) {
+        debug!(device = %device, "authorizing service {uuid}");
+    }
+
+    fn cancel(&self) {
+        self.cancelled.notify_waiters();
+        warn!("pairing cancelled by BlueZ, e.g. timed out or failed on the central");
+        self.stats.failed_pairings.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Agent {
+    /// Some(true) on the next E1 press, Some(false) on the next E2 press;
+    /// buttons already held don't count
+    async fn button(&self) -> Option<bool> {
+        let mut changes = self.key_input.subscribe();
+        let mut held = self.key_input.load().option_button;
+        loop {
+            changes.changed().await.ok()?;
+            let now = self.key_input.load().option_button;
+            let pressed = now.difference(held);
+            held = now;
+            if pressed.contains(OptionButton::E1) {
+                return Some(true);
+            }
+            if pressed.contains(OptionButton::E2) {
+                return Some(false);
+            }
+        }
+    }
+
+    fn failed(&self, device: &ObjectPath, why: &str) {
+        warn!(device = %device, "pairing rejected: {why}");
+        self.stats.failed_pairings.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Registers the agent as BlueZ's default, so it handles every pairing.
+pub async fn spawn(
+    pairing: Pairing,
+    auto_confirm: Option<Duration>,
+    key_input: Arc<SharedKeyInput>,
+    stats: Arc<Stats>,
+) -> Result<()> {
+    if let Some(after) = auto_confirm {
+        if pairing != Pairing::Confirm {
+            warn!("pairing-auto-confirm only applies to --pairing confirm");
+        } else if after >= SMP_TIMEOUT {
+            eyre::bail!(
+                "pairing-auto-confirm must be less than {}s, when pairing times out",
+                SMP_TIMEOUT.as_secs()
+            );
+        }
+    }
+    let connection = Connection::system()
+        .await
+        .wrap_err("failed to connect to the system bus")?;
+    let agent = Agent {
+        key_input,
+        stats: Arc::clone(&stats),
+        auto_confirm,
+        cancelled: Notify::new(),
+    };
+    connection.object_server().at(AGENT_PATH, agent).await?;
+    let path = ObjectPath::try_from(AGENT_PATH)?;
+    let capability = pairing.capability();
+    call_agent_manager(&connection, "RegisterAgent", &(&path, capability))
+        .await
+        .wrap_err("failed to register the pairing agent")?;
+    call_agent_manager(&connection, "RequestDefaultAgent", &(&path,))
+        .await
+        .wrap_err("failed to make the pairing agent BlueZ's default")?;
+    info!("pairing agent registered as {capability} ({pairing})");
+
+    tokio::spawn(async move {
+        if let Err(e) = watch_paired(&connection, &stats).await {
+            warn!("not watching BlueZ for finished pairings: {e}");
+        }
+        // the agent keeps answering for as long as the connection does
+        std::future::pending::<()>().await;
+    });
+    Ok(())
+}
+
+async fn call_agent_manager<B>(connection: &Connection, method: &str, body: &B) -> zbus::Result<()>
+where
+    B: serde::Serialize + zbus::zvariant::DynamicType,
+{
+    connection
+        .call_method(
+            Some("org.bluez"),
+            "/org/bluez",
+            Some("org.bluez.AgentManager1"),
+            method,
+            body,
+        )
+        .await?;
+    Ok(())
+}
+
+/// counts Device1.Paired turning true
+async fn watch_paired(connection: &Connection, stats: &Stats) -> zbus::Result<()> {
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender("org.bluez")?
+        .interface("org.freedesktop.DBus.Properties")?
+        .member("PropertiesChanged")?
+        .arg(0, "org.bluez.Device1")?
+        .build();
+    let mut signals = MessageStream::for_match_rule(rule, connection, None).await?;
+    while let Some(signal) = signals.next().await {
+        let signal = signal?;
+        let (_, changed, _): (String, HashMap<String, OwnedValue>, Vec<String>) =
+            signal.body().deserialize()?;
+        let paired = changed
+            .get("Paired")
+            .and_then(|value| bool::try_from(value).ok());
+        if paired == Some(true) {
+            let device = signal.header().path().map(ToString::to_string);
+            info!(device, "paired");
+            stats.pairings.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
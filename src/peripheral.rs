@@ -0,0 +1,475 @@
+use std::future::Future;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use bluster::gatt::service::Service;
+use bluster::Peripheral;
+use thiserror::Error;
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+use tracing::{info, instrument, warn};
+
+use crate::ble::NotifyContext;
+use crate::systemd;
+
+// bluster has no events for the adapter and advertising state, so both are
+// polled
+const SETUP_POLL: Duration = Duration::from_millis(20);
+const ADVERTISING_POLL: Duration = Duration::from_millis(100);
+const PROGRESS_EVERY: Duration = Duration::from_secs(5);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+// between attempts to set up again after losing bluetoothd, doubling from the
+// first to the longest
+const REBUILD_DELAY: Duration = Duration::from_secs(1);
+const MAX_REBUILD_DELAY: Duration = Duration::from_secs(30);
+// D-Bus errors of a bluetoothd, or a bus, that went away. bluster passes the
+// D-Bus error on only as text, so they are matched by name
+const BLUEZ_LOST: [&str; 5] = [
+    "org.freedesktop.DBus.Error.ServiceUnknown",
+    "org.freedesktop.DBus.Error.NameHasNoOwner",
+    "org.freedesktop.DBus.Error.NoReply",
+    "org.freedesktop.DBus.Error.Disconnected",
+    "org.freedesktop.DBus.Error.UnknownObject",
+];
+
+/// A setup step the adapter didn't finish in time.
+#[derive(Debug, Error)]
+#[error("timed out after {after:?} waiting for {what}")]
+pub struct Timeout {
+    pub what: &'static str,
+    pub after: Duration,
+}
+
+/// Advertising stopped while no central was subscribed, e.g. after an adapter
+/// reset, rfkill or a bluetoothd restart.
+#[derive(Debug, Error)]
+#[error("advertising stopped without a connected central (adapter powered: {})", describe(*.powered))]
+pub struct AdvertisingStopped {
+    /// None if the adapter couldn't be asked
+    pub powered: Option<bool>,
+}
+
+fn describe(powered: Option<bool>) -> &'static str {
+    match powered {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "unknown",
+    }
+}
+
+/// Why the peripheral couldn't start advertising.
+#[derive(Debug, Error)]
+pub enum BleSetupError {
+    #[error(transparent)]
+    Bluez(#[from] bluster::Error),
+    #[error(transparent)]
+    Timeout(#[from] Timeout),
+}
+
+/// Why [`PeripheralBuilder::run`] failed.
+#[derive(Debug, Error)]
+pub enum BleError {
+    /// before advertising started
+    #[error(transparent)]
+    Setup(#[from] BleSetupError),
+    /// while advertising
+    #[error(transparent)]
+    Bluez(bluster::Error),
+    #[error(transparent)]
+    AdvertisingStopped(#[from] AdvertisingStopped),
+    /// bluetoothd went away and setting up again failed every retry
+    #[error("bluetoothd went away and setting up again failed {attempts} times")]
+    Lost {
+        attempts: u32,
+        #[source]
+        source: BleSetupError,
+    },
+}
+
+/// Registers GATT services and advertises them until advertising stops.
+///
+/// ```no_run
+/// # async fn example(context: beatble::ble::NotifyContext, config: beatble::ble::NotifyConfig) -> eyre::Result<()> {
+/// use beatble::ble::create_key_input;
+/// use beatble::emulation::Emulation;
+/// use beatble::peripheral::PeripheralBuilder;
+///
+/// PeripheralBuilder::new(Emulation::Iidx.advertising_name())
+///     .service(create_key_input(context.clone(), config))
+///     .run(context)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct PeripheralBuilder {
+    advertising_name: String,
+    services: Vec<Service>,
+    power_timeout: Duration,
+    advertising_timeout: Duration,
+    readvertise: bool,
+    rebuild_retries: u32,
+    rebuild: Option<Arc<Notify>>,
+    ready: Option<Arc<Notify>>,
+}
+
+impl PeripheralBuilder {
+    /// A peripheral without services that advertises as advertising_name.
+    pub fn new(advertising_name: impl Into<String>) -> Self {
+        Self {
+            advertising_name: advertising_name.into(),
+            services: Vec::new(),
+            power_timeout: DEFAULT_TIMEOUT,
+            advertising_timeout: DEFAULT_TIMEOUT,
+            readvertise: false,
+            rebuild_retries: 0,
+            rebuild: None,
+            ready: None,
+        }
+    }
+
+    /// Adds a service, e.g. from [`create_key_input`](crate::ble::create_key_input).
+    pub fn service(mut self, service: Service) -> Self {
+        self.services.push(service);
+        self
+    }
+
+    /// Adds several services, e.g. from
+    /// [`create_key_input_dp`](crate::ble::create_key_input_dp).
+    pub fn services(mut self, services: impl IntoIterator<Item = Service>) -> Self {
+        self.services.extend(services);
+        self
+    }
+
+    /// How long to wait for the adapter to be powered, 30s by default.
+    pub fn power_timeout(mut self, timeout: Duration) -> Self {
+        self.power_timeout = timeout;
+        self
+    }
+
+    /// How long to wait for advertising to start, 30s by default.
+    pub fn advertising_timeout(mut self, timeout: Duration) -> Self {
+        self.advertising_timeout = timeout;
+        self
+    }
+
+    /// Whether to set up and advertise again, instead of failing with
+    /// [`AdvertisingStopped`], when advertising stops unexpectedly.
+    pub fn readvertise(mut self, readvertise: bool) -> Self {
+        self.readvertise = readvertise;
+        self
+    }
+
+    /// How often to try setting up again, with a new connection to the
+    /// adapter, when bluetoothd or the system bus goes away while
+    /// advertising, before failing with [`BleError::Lost`]. 0, the default,
+    /// fails with [`BleError::Bluez`] right away.
+    pub fn rebuild_retries(mut self, retries: u32) -> Self {
+        self.rebuild_retries = retries;
+        self
+    }
+
+    /// Sets up again, like after losing bluetoothd, whenever rebuild is
+    /// notified, e.g. once the system woke up from sleep with its connections
+    /// gone.
+    pub fn rebuild_on(mut self, rebuild: Arc<Notify>) -> Self {
+        self.rebuild = Some(rebuild);
+        self
+    }
+
+    /// Notified once advertising first started, i.e. once setup is done.
+    pub fn notify_ready(mut self, ready: Arc<Notify>) -> Self {
+        self.ready = Some(ready);
+        self
+    }
+
+    /// Resolves within 100ms of the adapter stopping to advertise to a
+    /// subscribed central. Failures before advertising started, including a
+    /// [`Timeout`], are [`BleError::Setup`].
+    #[instrument(name = "ble.advertising", skip_all, fields(advertising_name = %self.advertising_name))]
+    pub async fn run(self, context: NotifyContext) -> Result<(), BleError> {
+        let mut ready = false;
+        // failed attempts to set up again since bluetoothd went away
+        let mut rebuilding = None;
+        loop {
+            let peripheral = match (self.advertise().await, rebuilding) {
+                (Ok(peripheral), _) => peripheral,
+                (Err(e), None) => return Err(e.into()),
+                // bluetoothd may still be starting
+                (Err(e), Some(attempts)) => {
+                    warn!("failed to set up again: {e}");
+                    rebuilding = Some(self.wait_to_rebuild(attempts, e).await?);
+                    continue;
+                }
+            };
+            if let Some(attempts) = rebuilding.take() {
+                info!("Peripheral set up again after {} attempt(s)", attempts + 1);
+            }
+            info!("Peripheral started advertising {}", self.advertising_name);
+            if !ready {
+                systemd::ready();
+                if let Some(ready) = &self.ready {
+                    ready.notify_one();
+                }
+                systemd::spawn_health_task(context.clone());
+                ready = true;
+            }
+
+            let stopped = wait_until(
+                || async { Ok(!peripheral.is_advertising().await?) },
+                ADVERTISING_POLL,
+                None,
+            );
+            let rebuild = async {
+                match &self.rebuild {
+                    Some(rebuild) => rebuild.notified().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let stopped = tokio::select! {
+                stopped = stopped => stopped,
+                _ = rebuild => {
+                    info!("Tearing the peripheral down to set it up again");
+                    rebuilding = Some(0);
+                    tokio::time::sleep(REBUILD_DELAY).await;
+                    continue;
+                }
+            };
+            match stopped {
+                Ok(_) => {}
+                Err(e) if self.rebuild_retries > 0 && is_bluez_lost(&e) => {
+                    warn!("lost bluetoothd: {e}; tearing the peripheral down to set it up again");
+                    context.stats.bluez_losses.fetch_add(1, Ordering::Relaxed);
+                    // the peripheral goes, its registrations went with bluetoothd
+                    rebuilding = Some(0);
+                    tokio::time::sleep(REBUILD_DELAY).await;
+                    continue;
+                }
+                Err(e) => return Err(BleError::Bluez(e)),
+            }
+            // the adapter stops advertising once a central connects
+            if context.stats.subscribers.load(Ordering::Relaxed) > 0 {
+                info!("Peripheral stopped advertising {}", self.advertising_name);
+                return Ok(());
+            }
+            let stopped = AdvertisingStopped {
+                powered: peripheral.is_powered().await.ok(),
+            };
+            if !self.readvertise {
+                return Err(stopped.into());
+            }
+            context
+                .stats
+                .readvertisements
+                .fetch_add(1, Ordering::Relaxed);
+            warn!("{stopped}, advertising again");
+        }
+    }
+
+    /// Counts a failed attempt to set up again and waits for the next one;
+    /// fails once the retries are used up.
+    async fn wait_to_rebuild(&self, attempts: u32, error: BleSetupError) -> Result<u32, BleError> {
+        let attempts = attempts + 1;
+        if attempts > self.rebuild_retries {
+            return Err(BleError::Lost {
+                attempts,
+                source: error,
+            });
+        }
+        let delay = REBUILD_DELAY
+            .saturating_mul(1 << attempts.min(5))
+            .min(MAX_REBUILD_DELAY);
+        info!(
+            "setting up again in {delay:?} (retry {attempts} of {})",
+            self.rebuild_retries
+        );
+        tokio::time::sleep(delay).await;
+        Ok(attempts)
+    }
+
+    /// a new connection to the adapter with every service registered
+    async fn advertise(&self) -> Result<Peripheral, BleSetupError> {
+        info!("Preparing peripheral");
+        let peripheral = Peripheral::new().await?;
+        for service in &self.services {
+            peripheral.add_service(service)?;
+        }
+
+        let what = "adapter power";
+        if !wait_until(
+            || peripheral.is_powered(),
+            SETUP_POLL,
+            Some((what, self.power_timeout)),
+        )
+        .await?
+        {
+            return Err(Timeout {
+                what,
+                after: self.power_timeout,
+            }
+            .into());
+        }
+        info!("Peripheral powered on");
+
+        peripheral.register_gatt().await?;
+        peripheral
+            .start_advertising(&self.advertising_name, &[])
+            .await?;
+
+        let what = "advertising to start";
+        if !wait_until(
+            || peripheral.is_advertising(),
+            SETUP_POLL,
+            Some((what, self.advertising_timeout)),
+        )
+        .await?
+        {
+            return Err(Timeout {
+                what,
+                after: self.advertising_timeout,
+            }
+            .into());
+        }
+        Ok(peripheral)
+    }
+}
+
+fn is_bluez_lost(error: &bluster::Error) -> bool {
+    let text = format!("{error} {error:?}");
+    BLUEZ_LOST.iter().any(|name| text.contains(name))
+}
+
+/// Polls condition every period until it holds. With a deadline, logs that it
+/// is still waiting every 5s and gives up with false once it passes.
+async fn wait_until<F, Fut>(
+    mut condition: F,
+    period: Duration,
+    deadline: Option<(&'static str, Duration)>,
+) -> Result<bool, bluster::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<bool, bluster::Error>>,
+{
+    let started_at = Instant::now();
+    let mut progress_at = started_at + PROGRESS_EVERY;
+    let mut poll = tokio::time::interval(period);
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        poll.tick().await;
+        if condition().await? {
+            return Ok(true);
+        }
+        let Some((what, timeout)) = deadline else {
+            continue;
+        };
+        let now = Instant::now();
+        let waited = now - started_at;
+        if waited >= timeout {
+            return Ok(false);
+        }
+        if now >= progress_at {
+            info!("still waiting for {what} ({}s)", waited.as_secs());
+            progress_at += PROGRESS_EVERY;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    const DEADLINE: Option<(&str, Duration)> = Some(("the test", Duration::from_secs(2)));
+
+    /// waits on a condition that holds from ready_at on, returning the result,
+    /// how long it took and how often the condition was polled
+    async fn wait(
+        ready_at: Duration,
+        deadline: Option<(&'static str, Duration)>,
+    ) -> (bool, Duration, u32) {
+        let started_at = Instant::now();
+        let polls = Cell::new(0);
+        let met = wait_until(
+            || {
+                polls.set(polls.get() + 1);
+                async move { Ok(started_at.elapsed() >= ready_at) }
+            },
+            SETUP_POLL,
+            deadline,
+        )
+        .await
+        .unwrap();
+        (met, started_at.elapsed(), polls.get())
+    }
+
+    #[test]
+    fn errors_say_what_failed() {
+        let timeout = || Timeout {
+            what: "adapter power",
+            after: Duration::from_secs(30),
+        };
+        assert_eq!(
+            timeout().to_string(),
+            "timed out after 30s waiting for adapter power"
+        );
+        // setup errors show as their cause
+        assert_eq!(
+            BleError::Setup(timeout().into()).to_string(),
+            timeout().to_string()
+        );
+        let error = BleError::Lost {
+            attempts: 3,
+            source: timeout().into(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "bluetoothd went away and setting up again failed 3 times"
+        );
+        assert_eq!(
+            std::error::Error::source(&error).map(ToString::to_string),
+            Some(timeout().to_string())
+        );
+    }
+
+    #[test]
+    fn a_stopped_advertisement_says_whether_the_adapter_is_powered() {
+        let messages = [Some(true), Some(false), None]
+            .map(|powered| BleError::from(AdvertisingStopped { powered }).to_string());
+        assert_eq!(
+            messages,
+            ["yes", "no", "unknown"].map(|powered| format!(
+                "advertising stopped without a connected central (adapter powered: {powered})"
+            ))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_condition_that_already_holds_returns_at_once() {
+        assert_eq!(
+            wait(Duration::ZERO, DEADLINE).await,
+            (true, Duration::ZERO, 1)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_condition_met_before_the_deadline_returns_on_the_next_poll() {
+        let (met, after, polls) = wait(Duration::from_millis(1010), DEADLINE).await;
+        assert!(met);
+        assert_eq!(after, Duration::from_millis(1020));
+        assert_eq!(polls, 52);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_condition_never_met_times_out_at_the_deadline() {
+        let (met, after, _) = wait(Duration::MAX, DEADLINE).await;
+        assert!(!met);
+        assert_eq!(after, Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn without_a_deadline_it_waits_as_long_as_it_takes() {
+        let (met, after, _) = wait(Duration::from_secs(60), None).await;
+        assert!(met);
+        assert_eq!(after, Duration::from_secs(60));
+    }
+}
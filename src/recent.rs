@@ -0,0 +1,169 @@
+// The last input events and sent frames, kept for crash reports. A writer
+// claims a slot with one atomic add and fills it with a few stores, so
+// neither the readers nor the notifiers ever wait on a lock. A slot that is
+// being written while a report reads it is left out of the report.
+
+use std::fmt;
+use std::sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering};
+
+use beatble_protocol::{KeyInput, NormalButton, OptionButton};
+
+use crate::clock;
+
+/// how many input events are kept
+pub const EVENTS: usize = 200;
+/// how many sent frames are kept
+pub const FRAMES: usize = 50;
+
+static INPUT_EVENTS: Ring<EVENTS> = Ring::new();
+static SENT_FRAMES: Ring<FRAMES> = Ring::new();
+
+/// An input event as a reader applied it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    Pressed(u8),
+    Released(u8),
+    Axis(u8, i16),
+}
+
+/// A frame as a notifier sent it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SentFrame {
+    pub counter: u8,
+    pub key_input: KeyInput,
+}
+
+#[inline]
+pub fn record_event(event: InputEvent) {
+    let data = match event {
+        InputEvent::Pressed(button) => u64::from(button) << 16,
+        InputEvent::Released(button) => 1 << 24 | u64::from(button) << 16,
+        InputEvent::Axis(axis, value) => 2 << 24 | u64::from(axis) << 16 | u64::from(value as u16),
+    };
+    INPUT_EVENTS.push(data);
+}
+
+#[inline]
+pub fn record_frame(counter: u8, key_input: KeyInput) {
+    let data = u64::from(counter) << 32
+        | u64::from(key_input.scratch) << 24
+        | u64::from(key_input.normal_button.bits()) << 16
+        | u64::from(key_input.option_button.bits()) << 8
+        | u64::from(key_input.analog);
+    SENT_FRAMES.push(data);
+}
+
+/// The kept input events, oldest first, with the clock::now() they were
+/// recorded at.
+pub fn events() -> Vec<(u64, InputEvent)> {
+    INPUT_EVENTS
+        .read()
+        .into_iter()
+        .map(|(at, data)| {
+            let number = (data >> 16) as u8;
+            let event = match data >> 24 {
+                0 => InputEvent::Pressed(number),
+                1 => InputEvent::Released(number),
+                _ => InputEvent::Axis(number, data as u16 as i16),
+            };
+            (at, event)
+        })
+        .collect()
+}
+
+/// The kept frames, oldest first, with the clock::now() they were sent at.
+pub fn frames() -> Vec<(u64, SentFrame)> {
+    SENT_FRAMES
+        .read()
+        .into_iter()
+        .map(|(at, data)| {
+            let key_input = KeyInput {
+                scratch: (data >> 24) as u8,
+                normal_button: NormalButton::from_bits_retain((data >> 16) as u8),
+                option_button: OptionButton::from_bits_retain((data >> 8) as u8),
+                analog: data as u8,
+            };
+            let frame = SentFrame {
+                counter: (data >> 32) as u8,
+                key_input,
+            };
+            (at, frame)
+        })
+        .collect()
+}
+
+impl fmt::Display for InputEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputEvent::Pressed(button) => write!(f, "button {button} pressed"),
+            InputEvent::Released(button) => write!(f, "button {button} released"),
+            InputEvent::Axis(axis, value) => write!(f, "axis {axis} at {value}"),
+        }
+    }
+}
+
+impl fmt::Display for SentFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let key_input = &self.key_input;
+        write!(
+            f,
+            "counter {:3} buttons {:#04x} options {:#03x} scratch {:#04x} analog {:#04x}",
+            self.counter,
+            key_input.normal_button.bits(),
+            key_input.option_button.bits(),
+            key_input.scratch,
+            key_input.analog
+        )
+    }
+}
+
+struct Ring<const N: usize> {
+    next: AtomicUsize,
+    slots: [Slot; N],
+}
+
+struct Slot {
+    // clock::now() of the write, 0 while it is written or before the first
+    at: AtomicU64,
+    data: AtomicU64,
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Self {
+        Self {
+            next: AtomicUsize::new(0),
+            slots: [const {
+                Slot {
+                    at: AtomicU64::new(0),
+                    data: AtomicU64::new(0),
+                }
+            }; N],
+        }
+    }
+
+    #[inline]
+    fn push(&self, data: u64) {
+        let slot = &self.slots[self.next.fetch_add(1, Ordering::Relaxed) % N];
+        slot.at.store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+        slot.data.store(data, Ordering::Relaxed);
+        slot.at.store(clock::now().max(1), Ordering::Release);
+    }
+
+    /// the written slots as (at, data), oldest first
+    fn read(&self) -> Vec<(u64, u64)> {
+        let mut entries: Vec<(u64, u64)> = self
+            .slots
+            .iter()
+            .filter_map(|slot| {
+                let at = slot.at.load(Ordering::Acquire);
+                let data = slot.data.load(Ordering::Relaxed);
+                fence(Ordering::Acquire);
+                // rewritten meanwhile
+                (at != 0 && slot.at.load(Ordering::Relaxed) == at).then_some((at, data))
+            })
+            .collect();
+        entries.sort_unstable_by_key(|&(at, _)| at);
+        entries
+    }
+}
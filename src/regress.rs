@@ -0,0 +1,388 @@
+// `beatble regress`: recorded joystick events go through the usual mapping and
+// notifier on a virtual clock, and the frames that come out are compared with
+// a golden file, so a change to the mapping or payload path shows as a diff
+// instead of needing a play test.
+//
+// The events file holds js_event records, 8 bytes each, as read from a
+// joystick node: `cat /dev/input/js0 > events.bin` records some. They are
+// replayed on their own timestamps, half a millisecond off the frame clock so
+// that no event ties with a frame. The golden file holds every frame sent
+// until a few frames after the last event, each as a length byte and the
+// payload. Both clocks are tokio's, paused, so a run takes no real time and
+// always comes out the same; the mapping is the emulation's defaults, with
+// no filter that runs on the process clock.
+//
+// assets/regress has a few recorded scenarios, each an events.bin with the
+// frames.bin it should produce:
+//
+//   for scenario in assets/regress/*/; do
+//       beatble regress --input replay:${scenario}events.bin --golden ${scenario}frames.bin
+//   done
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use beatble::ble::{spawn_local_notifier, NotifyConfig, NotifyContext, NotifyMode, RepeatSpacing};
+use beatble::control::Control;
+use beatble::emulation::Emulation;
+use beatble::input::{Event, Replayer, SharedKeyInput};
+use beatble::latency::Latency;
+use beatble::payload::{Layout, PayloadFormat, ScratchMode};
+use beatble::stats::Stats;
+use clap::{Args, ValueHint};
+use eyre::{bail, eyre, Result, WrapErr};
+use futures::StreamExt;
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::dry_run::describe;
+
+// see the top of the file
+const EVENT_OFFSET: Duration = Duration::from_micros(500);
+// frames after the last event, for releases to show
+const TAIL_FRAMES: u32 = 8;
+// frames listed before the first difference
+const CONTEXT_FRAMES: usize = 3;
+
+#[derive(Args)]
+pub struct RegressArgs {
+    /// where the events come from: replay:FILE, a file of js_event records
+    #[arg(long, value_name = "SOURCE")]
+    input: String,
+
+    /// frames the events should produce
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    golden: PathBuf,
+
+    /// write the frames to the golden file instead of comparing them
+    #[arg(long)]
+    update: bool,
+
+    /// which controller the frames are for
+    #[arg(long, value_name = "MODEL", default_value_t = Emulation::Iidx)]
+    emulate: Emulation,
+
+    /// payload layout of the frames
+    #[arg(long, value_name = "FORMAT", default_value_t = PayloadFormat::V1)]
+    payload_format: PayloadFormat,
+
+    /// frame period in ms, as with run
+    #[arg(long, value_name = "DURATION", default_value_t = 8)]
+    sleep_duration: u64,
+}
+
+pub fn run(args: RegressArgs) -> Result<()> {
+    let path = args
+        .input
+        .strip_prefix("replay:")
+        .ok_or_else(|| eyre!("unknown input: {} (expected replay:FILE)", args.input))?;
+    let events = load_events(Path::new(path))?;
+    if args.sleep_duration == 0 {
+        bail!("sleep-duration must be at least 1");
+    }
+    let notify_config = NotifyConfig {
+        interval: Duration::from_millis(args.sleep_duration),
+        mode: NotifyMode::Periodic,
+        warmup_frames: 0,
+        counter_start: 0,
+        frame_repeat: 1,
+        repeat_spacing: RepeatSpacing::BackToBack,
+        busy_poll: false,
+        layout: Layout {
+            format: args.payload_format,
+            ..Layout::default()
+        },
+        emulation: args.emulate,
+        scratch_mode: ScratchMode::Position,
+        scratch_predict: None,
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()?;
+    let frames = runtime.block_on(replay(events, notify_config));
+
+    if args.update {
+        fs::write(&args.golden, encode(&frames))
+            .wrap_err_with(|| format!("failed to write {}", args.golden.display()))?;
+        println!("wrote {} frames to {}", frames.len(), args.golden.display());
+        return Ok(());
+    }
+    let golden = fs::read(&args.golden)
+        .wrap_err_with(|| format!("failed to read {}", args.golden.display()))?;
+    let golden = decode(&golden).ok_or_else(|| eyre!("{} is truncated", args.golden.display()))?;
+    match compare(&golden, &frames, notify_config) {
+        None => {
+            println!("{} frames match {}", frames.len(), args.golden.display());
+            Ok(())
+        }
+        Some(difference) => {
+            println!("{difference}");
+            bail!("frames differ from {}", args.golden.display())
+        }
+    }
+}
+
+/// the events of a file and when they happened, from the first one
+fn load_events(path: &Path) -> Result<Vec<(Duration, Event)>> {
+    let mut file =
+        fs::File::open(path).wrap_err_with(|| format!("failed to open {}", path.display()))?;
+    let mut events = Vec::new();
+    let mut buf = [0u8; 8];
+    loop {
+        match file.read_exact(&mut buf) {
+            Ok(()) => events.extend(Event::parse_timed(buf)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e).wrap_err_with(|| format!("failed to read {}", path.display())),
+        }
+    }
+    let Some(&(start, _)) = events.first() else {
+        bail!("{} has no events", path.display());
+    };
+    Ok(events
+        .into_iter()
+        .map(|(time, event)| (time.saturating_sub(start), event))
+        .collect())
+}
+
+/// the frames a notifier sends for events, on the paused clock
+async fn replay(events: Vec<(Duration, Event)>, notify_config: NotifyConfig) -> Vec<Vec<u8>> {
+    let end = events.last().map_or(Duration::ZERO, |&(time, _)| time);
+    let count = (end.as_nanos() / notify_config.interval.as_nanos()) as u32 + 1 + TAIL_FRAMES;
+
+    let key_input = Arc::new(SharedKeyInput::new());
+    let mut replayer = Replayer::new(Arc::clone(&key_input), notify_config.emulation.into());
+    let context = NotifyContext {
+        key_input,
+        control: Arc::new(Control::new(notify_config.interval, notify_config.mode)),
+        latency: Arc::new(Latency::new()),
+        stats: Arc::new(Stats::new()),
+        dump: None,
+    };
+    let start = Instant::now();
+    let mut notifications = spawn_local_notifier(context, notify_config);
+    tokio::spawn(async move {
+        for (time, event) in events {
+            sleep_until(start + time + EVENT_OFFSET).await;
+            replayer.apply(event);
+        }
+    });
+    notifications.by_ref().take(count as usize).collect().await
+}
+
+fn encode(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for frame in frames {
+        encoded.push(frame.len() as u8);
+        encoded.extend_from_slice(frame);
+    }
+    encoded
+}
+
+/// None when a frame is cut short
+fn decode(mut encoded: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut frames = Vec::new();
+    while let Some((&len, rest)) = encoded.split_first() {
+        let frame = rest.get(..len as usize)?;
+        frames.push(frame.to_vec());
+        encoded = &rest[len as usize..];
+    }
+    Some(frames)
+}
+
+/// the first frame that differs, with the frames before it, or None
+fn compare(golden: &[Vec<u8>], frames: &[Vec<u8>], notify_config: NotifyConfig) -> Option<String> {
+    let at = (0..golden.len().max(frames.len())).find(|&i| golden.get(i) != frames.get(i))?;
+    let period = notify_config.interval.as_millis() as usize;
+    let line = |frame: Option<&Vec<u8>>| match frame {
+        Some(bytes) => {
+            let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+            format!("{hex}  {}", describe(bytes, notify_config))
+        }
+        None => "(none)".to_string(),
+    };
+    let mut lines = vec![format!(
+        "first difference at frame {at} ({}ms), of {} golden and {} replayed frames:",
+        at * period,
+        golden.len(),
+        frames.len()
+    )];
+    for i in at.saturating_sub(CONTEXT_FRAMES)..at {
+        lines.push(format!("  frame {i:5}  {}", line(frames.get(i))));
+    }
+    lines.push(format!("  golden {at:4}  {}", line(golden.get(at))));
+    lines.push(format!("  replay {at:4}  {}", line(frames.get(at))));
+    Some(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    fn scenarios() -> Vec<PathBuf> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/regress");
+        let mut scenarios: Vec<PathBuf> = fs::read_dir(dir)
+            .expect("the recorded scenarios")
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        scenarios.sort();
+        scenarios
+    }
+
+    fn args(events: &Path, golden: &Path) -> RegressArgs {
+        RegressArgs {
+            input: format!("replay:{}", events.display()),
+            golden: golden.to_owned(),
+            update: false,
+            emulate: Emulation::Iidx,
+            payload_format: PayloadFormat::V1,
+            sleep_duration: 8,
+        }
+    }
+
+    fn temp(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("beatble-regress-{}-{name}", process::id()))
+    }
+
+    #[test]
+    fn recorded_scenarios_match_their_golden_frames() {
+        let scenarios = scenarios();
+        assert!(scenarios.len() >= 3);
+        for scenario in scenarios {
+            let args = args(&scenario.join("events.bin"), &scenario.join("frames.bin"));
+            run(args).unwrap_or_else(|e| panic!("{}: {e:?}", scenario.display()));
+        }
+    }
+
+    #[test]
+    fn a_different_payload_path_fails_the_comparison() {
+        let scenario = &scenarios()[0];
+        let args = RegressArgs {
+            payload_format: PayloadFormat::V2,
+            ..args(&scenario.join("events.bin"), &scenario.join("frames.bin"))
+        };
+        assert!(run(args).is_err());
+    }
+
+    #[test]
+    fn update_writes_a_golden_file_that_matches() {
+        let scenario = &scenarios()[0];
+        let golden = temp("update");
+        let events = scenario.join("events.bin");
+        run(RegressArgs {
+            update: true,
+            ..args(&events, &golden)
+        })
+        .unwrap();
+        let written = fs::read(&golden).unwrap();
+        let result = run(args(&events, &golden));
+        fs::remove_file(&golden).unwrap();
+        result.unwrap();
+        assert_eq!(written, fs::read(scenario.join("frames.bin")).unwrap());
+    }
+
+    #[test]
+    fn bad_sources_and_periods_are_refused() {
+        let scenario = &scenarios()[0];
+        let golden = scenario.join("frames.bin");
+        let args = |input: &str, sleep_duration| RegressArgs {
+            input: input.to_owned(),
+            sleep_duration,
+            ..args(Path::new(""), &golden)
+        };
+        let events = format!("replay:{}", scenario.join("events.bin").display());
+        assert!(run(args("/dev/input/js0", 8)).is_err());
+        assert!(run(args(&events, 0)).is_err());
+
+        // a trailing partial record is dropped, no complete one is an error
+        let partial = temp("partial");
+        fs::write(&partial, [0u8; 7]).unwrap();
+        let loaded = load_events(&partial);
+        fs::remove_file(&partial).unwrap();
+        assert!(loaded.is_err());
+    }
+
+    #[test]
+    fn events_start_at_zero() {
+        let path = temp("events");
+        // js_event: time in ms, value, type (button), number
+        let record = |ms: u32, value: i16| {
+            let mut record = ms.to_le_bytes().to_vec();
+            record.extend(value.to_le_bytes());
+            record.extend([0x01, 0x00]);
+            record
+        };
+        fs::write(&path, [record(1000, 1), record(1016, 0)].concat()).unwrap();
+        let events = load_events(&path);
+        fs::remove_file(&path).unwrap();
+        let times: Vec<Duration> = events.unwrap().iter().map(|&(time, _)| time).collect();
+        assert_eq!(times, [Duration::ZERO, Duration::from_millis(16)]);
+    }
+
+    #[test]
+    fn golden_files_round_trip() {
+        let frames = vec![vec![1, 2, 3], vec![], vec![0xff; 10]];
+        assert_eq!(decode(&encode(&frames)), Some(frames));
+        // the length byte says 3, 2 follow
+        assert_eq!(decode(&[3, 1, 2]), None);
+    }
+
+    #[test]
+    fn the_first_difference_is_shown_with_the_frames_before_it() {
+        let notify_config = NotifyConfig {
+            interval: Duration::from_millis(8),
+            mode: NotifyMode::Periodic,
+            warmup_frames: 0,
+            counter_start: 0,
+            frame_repeat: 1,
+            repeat_spacing: RepeatSpacing::BackToBack,
+            busy_poll: false,
+            layout: Layout::default(),
+            emulation: Emulation::Iidx,
+            scratch_mode: ScratchMode::Position,
+            scratch_predict: None,
+        };
+        let frame = |counter: u8| {
+            beatble::payload::Frame::repeated(beatble::KeyInput::init(), counter)
+                .encode(Layout::default())
+                .as_bytes()
+                .to_vec()
+        };
+        let golden: Vec<Vec<u8>> = (0..6).map(|i| frame(i * 2)).collect();
+        assert_eq!(compare(&golden, &golden, notify_config), None);
+
+        let mut replayed = golden.clone();
+        replayed[4] = frame(0x40);
+        let difference = compare(&golden, &replayed, notify_config).unwrap();
+        let lines: Vec<&str> = difference.lines().collect();
+        assert_eq!(
+            lines[0],
+            "first difference at frame 4 (32ms), of 6 golden and 6 replayed frames:"
+        );
+        assert_eq!(lines.len(), 1 + CONTEXT_FRAMES + 2);
+        assert!(
+            lines[1].starts_with("  frame     1  0000000002"),
+            "{}",
+            lines[1]
+        );
+        assert!(
+            lines[4].starts_with("  golden    4  0000000008"),
+            "{}",
+            lines[4]
+        );
+        assert!(
+            lines[5].starts_with("  replay    4  0000000040"),
+            "{}",
+            lines[5]
+        );
+
+        let short = &golden[..5];
+        let difference = compare(&golden, short, notify_config).unwrap();
+        assert!(difference.ends_with("replay    5  (none)"), "{difference}");
+    }
+}
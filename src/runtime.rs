@@ -0,0 +1,48 @@
+// The tokio runtime a command runs on. Input readers are threads of their
+// own either way, so a current-thread runtime only runs the notifiers, timers
+// and sockets, without worker threads to schedule between.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use tokio::runtime::{Builder, Runtime};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Flavor {
+    CurrentThread,
+    #[default]
+    Multi,
+}
+
+impl Flavor {
+    pub fn build(self) -> io::Result<Runtime> {
+        match self {
+            Flavor::CurrentThread => Builder::new_current_thread().enable_all().build(),
+            Flavor::Multi => Builder::new_multi_thread().enable_all().build(),
+        }
+    }
+}
+
+impl FromStr for Flavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "current-thread" => Ok(Flavor::CurrentThread),
+            "multi" => Ok(Flavor::Multi),
+            _ => Err(format!(
+                "unknown runtime: {s} (expected current-thread or multi)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Flavor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Flavor::CurrentThread => write!(f, "current-thread"),
+            Flavor::Multi => write!(f, "multi"),
+        }
+    }
+}
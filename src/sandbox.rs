@@ -0,0 +1,494 @@
+// --sandbox: a session that is set up only needs the files it has open and a
+// few paths it may reopen. Landlock only confines the calling thread and the
+// threads it starts afterwards, so the paths are restricted before the runtime
+// starts; the syscall allow-list follows once advertising started, synced to
+// every thread.
+//
+// A syscall outside the list kills the whole process with SIGSYS, which the
+// kernel logs with the syscall number (audit or dmesg). A path outside the
+// ruleset fails with EACCES like any other permission error.
+//
+// With --split-privileges, the input process is started after both, and
+// inherits them: it may only run this executable and its libraries, and gets
+// the few more syscalls it needs to start and to drop root.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{bail, Result};
+use landlock::{
+    Access, AccessFs, BitFlags, PathBeneath, PathFd, Ruleset, RulesetAttr, RulesetCreatedAttr,
+    RulesetStatus, ABI,
+};
+use nix::libc;
+use seccompiler::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule,
+};
+use tracing::{debug, info, warn};
+
+use crate::cli::RunArgs;
+use crate::{crash, ctl};
+
+// written by --conn-interval-min and --conn-interval-max
+const DEBUGFS_BLUETOOTH: &str = "/sys/kernel/debug/bluetooth";
+// where the loader of the input process looks up libraries
+const LD_SO_CACHE: &str = "/etc/ld.so.cache";
+
+/// Denies every path a session of args has no use for, in this thread and
+/// the threads it starts. config is re-read on SIGHUP.
+pub fn restrict_paths(args: &RunArgs, config: Option<&Path>) -> Result<()> {
+    let abi = ABI::V3;
+    let read = AccessFs::from_read(abi);
+    let read_file = BitFlags::from(AccessFs::ReadFile);
+    let write = AccessFs::from_all(abi);
+    let mut rules: Vec<(PathBuf, BitFlags<AccessFs>)> = vec![
+        // reopened devices and `beatble ctl device`
+        ("/dev/input".into(), read),
+        // device names, connection intervals and the doctor checks logged
+        // when bluetooth fails
+        ("/sys".into(), read),
+        ("/proc".into(), read),
+    ];
+    let devices = args.input.iter().chain(&args.dp_device);
+    rules.extend(devices.map(|device| (device.into(), read_file)));
+    // editors replace the file rather than write it
+    if let Some(dir) = config.and_then(parent) {
+        rules.push((dir, read));
+    }
+    // the control socket unless it is given
+    let socket = args
+        .control_socket
+        .clone()
+        .unwrap_or_else(ctl::default_path);
+    rules.extend(parent(&socket).map(|dir| (dir, write)));
+    rules.extend(parent(&ctl::default_path()).map(|dir| (dir, write)));
+    if let Some(dump) = &args.dump_payloads {
+        rules.extend(parent(dump).map(|dir| (dir, write)));
+    }
+    // crash reports; it can't be created once restricted
+    let crash_dir = crash::dir();
+    if fs::create_dir_all(&crash_dir).is_ok() {
+        rules.push((crash_dir, write));
+    }
+    // rotation renames and creates files next to the log file
+    if let Some(log_file) = &args.log_file {
+        rules.extend(parent(log_file).map(|dir| (dir, write)));
+    }
+    if args.conn_interval_min.is_some() {
+        rules.push((DEBUGFS_BLUETOOTH.into(), write));
+    }
+    if args.mirror_uinput {
+        rules.push(("/dev/uinput".into(), read_file | AccessFs::WriteFile));
+    }
+    if args.tui {
+        rules.push(("/dev/tty".into(), AccessFs::ReadFile | AccessFs::WriteFile));
+    }
+    // the input process, also when a device is reopened
+    if args.split_privileges {
+        let exe = env::current_exe()?;
+        rules.push((exe, read_file | AccessFs::Execute));
+        rules.extend(
+            mapped_executables()?
+                .into_iter()
+                .map(|path| (path, read_file | AccessFs::Execute)),
+        );
+        rules.push((LD_SO_CACHE.into(), read_file));
+    }
+
+    let mut ruleset = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))?
+        .create()?;
+    for (path, access) in rules {
+        match PathFd::new(&path) {
+            Ok(fd) => {
+                debug!("allowing {access:?} beneath {}", path.display());
+                ruleset = ruleset.add_rule(PathBeneath::new(fd, access))?;
+            }
+            Err(e) => debug!("not allowing {}: {e}", path.display()),
+        }
+    }
+    match ruleset.restrict_self()?.ruleset {
+        RulesetStatus::FullyEnforced => info!("filesystem access restricted"),
+        RulesetStatus::PartiallyEnforced => {
+            warn!("filesystem access only partly restricted, the kernel's Landlock is older than ABI 3")
+        }
+        RulesetStatus::NotEnforced => bail!("the kernel doesn't support Landlock"),
+    }
+    Ok(())
+}
+
+/// The files mapped executable into this process: the executable, the
+/// loader and the libraries, which the input process loads the same way.
+fn mapped_executables() -> Result<BTreeSet<PathBuf>> {
+    let maps = fs::read_to_string("/proc/self/maps")?;
+    Ok(maps
+        .lines()
+        .filter_map(|line| {
+            // address perms offset dev inode path
+            let mut fields = line.split_whitespace();
+            let perms = fields.nth(1)?;
+            let path = fields.nth(3)?;
+            (perms.contains('x') && path.starts_with('/')).then(|| path.into())
+        })
+        .collect())
+}
+
+/// a relative path's parent is the working directory
+fn parent(path: &Path) -> Option<PathBuf> {
+    match path.parent()? {
+        dir if dir.as_os_str().is_empty() => env::current_dir().ok(),
+        dir => Some(dir.to_owned()),
+    }
+}
+
+/// Kills the process on any syscall a running session of args doesn't make,
+/// in every thread.
+pub fn restrict_syscalls(args: &RunArgs) -> Result<()> {
+    let split_privileges = if args.split_privileges {
+        SPLIT_PRIVILEGES
+    } else {
+        &[]
+    };
+    let mut rules: BTreeMap<i64, Vec<SeccompRule>> = ALLOWED
+        .iter()
+        .chain(split_privileges)
+        .map(|&call| (call, vec![]))
+        .collect();
+    // new connections to bluez and systemd, but no network sockets
+    let unix = SeccompCondition::new(
+        0,
+        SeccompCmpArgLen::Dword,
+        SeccompCmpOp::Eq,
+        libc::AF_UNIX as u64,
+    )?;
+    rules.insert(libc::SYS_socket, vec![SeccompRule::new(vec![unix])?]);
+
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::KillProcess,
+        SeccompAction::Allow,
+        env::consts::ARCH.try_into()?,
+    )?;
+    let program: BpfProgram = filter.try_into()?;
+    seccompiler::apply_filter_all_threads(&program)?;
+    info!("syscalls restricted, any other kills the process");
+    Ok(())
+}
+
+const ALLOWED: &[i64] = &[
+    // files and devices already open, reopened devices and their locks
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_pread64,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_pwrite64,
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_statx,
+    libc::SYS_newfstatat,
+    libc::SYS_readlinkat,
+    libc::SYS_getcwd,
+    libc::SYS_getdents64,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_flock,
+    libc::SYS_ftruncate,
+    libc::SYS_fsync,
+    libc::SYS_unlinkat,
+    // log file rotation
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+    libc::SYS_dup,
+    libc::SYS_dup3,
+    libc::SYS_pipe2,
+    // memory
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    // threads, the runtime and its timers
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_prctl,
+    libc::SYS_futex,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2,
+    libc::SYS_ppoll,
+    libc::SYS_pselect6,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_gettimeofday,
+    libc::SYS_getrandom,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_restart_syscall,
+    // signals
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_tgkill,
+    // d-bus, systemd, the control socket and the http listeners
+    libc::SYS_connect,
+    libc::SYS_accept4,
+    libc::SYS_recvfrom,
+    libc::SYS_recvmsg,
+    libc::SYS_sendto,
+    libc::SYS_sendmsg,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_shutdown,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_uname,
+    // their older variants, still used on x86_64
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_lstat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_readlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_unlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rename,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+];
+
+// starting the input process, which inherits the filter, and what it makes
+// before it reads the device
+const SPLIT_PRIVILEGES: &[i64] = &[
+    libc::SYS_execve,
+    libc::SYS_wait4,
+    libc::SYS_kill,
+    // the loader and the runtime
+    libc::SYS_set_tid_address,
+    libc::SYS_prlimit64,
+    libc::SYS_faccessat,
+    libc::SYS_faccessat2,
+    // dropping root
+    libc::SYS_setgroups,
+    libc::SYS_setgid,
+    libc::SYS_setuid,
+    libc::SYS_setresgid,
+    libc::SYS_setresuid,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_arch_prctl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_access,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_dup2,
+];
+
+#[cfg(all(test, feature = "ble", feature = "input"))]
+mod tests {
+    use std::io::Read;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{self, Command, Stdio};
+
+    use clap::{CommandFactory, FromArgMatches, Parser};
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::split;
+
+    // the test binary plays the session and the input process, each in a
+    // process of its own, as neither sandbox can be lifted again
+    const ROLE: &str = "BEATBLE_SANDBOX_TEST";
+    const NOBODY: u32 = 65534;
+
+    fn device() -> PathBuf {
+        env::temp_dir().join(format!("beatble-sandbox-{}", process::id()))
+    }
+
+    /// runs the test named role in a new process of this test binary
+    fn play(role: &str, device: &Path) -> Command {
+        let mut command = Command::new(env::current_exe().expect("the test binary"));
+        command
+            .args(["--exact", &format!("sandbox::tests::{role}")])
+            .args(["--ignored", "--test-threads=1", "--nocapture", "-q"])
+            .env(ROLE, role)
+            .env("DEVICE", device);
+        command
+    }
+
+    fn playing(role: &str) -> Option<PathBuf> {
+        (env::var(ROLE).ok()? == role).then(|| env::var_os("DEVICE").expect("a device").into())
+    }
+
+    /// why restrict_paths can't run here, if it can't
+    fn without_landlock() -> Option<&'static str> {
+        // the ABI version, or -1 without Landlock
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<libc::c_void>(),
+                0usize,
+                1u32,
+            )
+        };
+        (abi < 1).then_some("the kernel doesn't support Landlock")
+    }
+
+    #[test]
+    fn sandbox_and_split_privileges_go_together() {
+        let cli = Cli::try_parse_from(["beatble", "--sandbox", "--split-privileges", "/dev/null"])
+            .expect("valid flags");
+        assert!(cli.run.sandbox && cli.run.split_privileges);
+    }
+
+    #[test]
+    fn a_sandboxed_session_starts_its_input_process() {
+        let skipped = without_landlock().or_else(|| {
+            (!nix::unistd::Uid::effective().is_root())
+                .then_some("only root can drop privileges to the input process")
+        });
+        if let Some(why) = skipped {
+            eprintln!("skipped: {why}");
+            return;
+        }
+        let device = device();
+        fs::write(&device, []).unwrap();
+        let status = play("session", &device).status().unwrap();
+        fs::remove_file(&device).unwrap();
+        assert!(status.success(), "the sandboxed session failed: {status}");
+    }
+
+    #[test]
+    #[ignore = "played by a_sandboxed_session_starts_its_input_process"]
+    fn session() {
+        let Some(device) = playing("session") else {
+            return;
+        };
+        let cli = Cli::try_parse_from([
+            "beatble".as_ref(),
+            "--sandbox".as_ref(),
+            "--split-privileges".as_ref(),
+            device.as_os_str(),
+        ])
+        .unwrap();
+        restrict_paths(&cli.run, None).unwrap();
+        restrict_syscalls(&cli.run).unwrap();
+
+        let mut child = play("relay", &device)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("the input process to start");
+        let mut output = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        let status = child.wait().unwrap();
+        assert!(status.success(), "the input process failed: {status}");
+        assert!(output.contains("input process ready"), "{output}");
+    }
+
+    #[test]
+    #[ignore = "played by a_sandboxed_session_starts_its_input_process"]
+    fn relay() {
+        let Some(device) = playing("relay") else {
+            return;
+        };
+        let _device = fs::File::open(device).expect("the device to open");
+        split::drop_privileges(NOBODY, NOBODY).unwrap();
+        println!("input process ready");
+    }
+
+    #[test]
+    fn a_syscall_outside_the_list_kills_the_process() {
+        let status = play("forbidden", Path::new("")).status().unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGSYS), "{status}");
+    }
+
+    #[test]
+    #[ignore = "played by a_syscall_outside_the_list_kills_the_process"]
+    fn forbidden() {
+        if playing("forbidden").is_none() {
+            return;
+        }
+        let cli = Cli::try_parse_from(["beatble", "--sandbox", "/dev/null"]).unwrap();
+        restrict_syscalls(&cli.run).unwrap();
+        // no session asks for its parent
+        unsafe { libc::getppid() };
+        unreachable!("getppid was allowed");
+    }
+
+    #[test]
+    fn a_sandboxed_simulation_completes() {
+        if let Some(why) = without_landlock() {
+            eprintln!("skipped: {why}");
+            return;
+        }
+        let script = device();
+        fs::write(
+            &script,
+            "0.0 press B1\n0.1 release B1\n0.2 scratch +90deg over 0.1\n",
+        )
+        .unwrap();
+        let output = play("simulation", &script)
+            .stderr(Stdio::inherit())
+            .output()
+            .unwrap();
+        fs::remove_file(&script).unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "the sandboxed simulation failed: {}",
+            output.status
+        );
+        // the key input of the script, printed by --dry-run
+        assert!(stdout.contains("normal=[B1]"), "{stdout}");
+    }
+
+    #[test]
+    #[ignore = "played by a_sandboxed_simulation_completes"]
+    fn simulation() {
+        let Some(script) = playing("simulation") else {
+            return;
+        };
+        let argv = [
+            "beatble".as_ref(),
+            "simulate".as_ref(),
+            "--script".as_ref(),
+            script.as_os_str(),
+            "--dry-run".as_ref(),
+            "--sandbox".as_ref(),
+        ];
+        let matches = Cli::command().try_get_matches_from(argv).unwrap();
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+        crate::start(cli, &matches, None).unwrap();
+        // the session ran with the filter, not just set up
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+        assert!(status.contains("Seccomp:\t2"), "{status}");
+    }
+}
@@ -0,0 +1,392 @@
+// `beatble simulate`: a script of timed actions drives the key input instead
+// of a controller, so a console-side report can be replayed exactly:
+//
+//   # hold E1 for ten seconds while tapping B1
+//   0.0   press E1
+//   0.5   press B1
+//   0.55  release B1
+//   1.0   scratch +90deg over 0.25
+//   10.0  release E1
+//
+// Times are seconds since the start of the script and never go back. Buttons
+// are B1 to B7 and E1 to E4. A scratch moves the turntable position by an
+// angle, up when positive, at once or evenly over the given seconds while
+// later actions go on.
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use beatble::input::SharedKeyInput;
+use beatble_protocol::{KeyInput, NormalButton, OptionButton};
+use eyre::{Result, WrapErr};
+use thiserror::Error;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Duration, Instant};
+use tracing::{debug, info};
+
+// position units per full turn, as the input handler maps the turntable
+const UNITS_PER_TURN: f64 = 65536.0;
+// how often a gradual scratch moves, a little faster than the fastest notifier
+const SCRATCH_TICK: Duration = Duration::from_millis(1);
+// far enough for any replay, near enough that the player's deadlines can't
+// overflow
+const MAX_SECONDS: f64 = 24.0 * 60.0 * 60.0;
+
+/// Where and why a script is invalid; line and column count from 1.
+#[derive(Debug, Error)]
+#[error("line {line}, column {column}: {message}")]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Button {
+    Normal(NormalButton),
+    Option(OptionButton),
+}
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(button) = NormalButton::from_name(s) {
+            return Ok(Button::Normal(button));
+        }
+        if let Some(button) = OptionButton::from_name(s) {
+            return Ok(Button::Option(button));
+        }
+        Err(format!(
+            "unknown button: {s} (expected B1 to B7 or E1 to E4)"
+        ))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Press(Button),
+    Release(Button),
+    Scratch { degrees: f64, over: Duration },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Step {
+    at: Duration,
+    action: Action,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read script {}", path.display()))?;
+        source
+            .parse()
+            .wrap_err_with(|| format!("invalid script {}", path.display()))
+    }
+
+    /// until the last action, including a gradual scratch, is done
+    fn duration(&self) -> Duration {
+        self.steps
+            .iter()
+            .map(|step| match step.action {
+                Action::Scratch { over, .. } => step.at + over,
+                _ => step.at,
+            })
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+impl FromStr for Script {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut steps: Vec<Step> = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let words = words(line);
+            if words.is_empty() {
+                continue;
+            }
+            let error = |(word, message): (usize, String)| ParseError {
+                line: i + 1,
+                column: line[..words[word].0].chars().count() + 1,
+                message,
+            };
+            let step = parse_step(&words).map_err(error)?;
+            if let Some(last) = steps.last() {
+                if step.at < last.at {
+                    return Err(error((
+                        0,
+                        format!(
+                            "{}s is before the previous action at {}s",
+                            step.at.as_secs_f64(),
+                            last.at.as_secs_f64()
+                        ),
+                    )));
+                }
+            }
+            steps.push(step);
+        }
+        if steps.is_empty() {
+            return Err(ParseError {
+                line: source.lines().count(),
+                column: 1,
+                message: "no actions".to_string(),
+            });
+        }
+        Ok(Self { steps })
+    }
+}
+
+/// the words of line with their byte offsets
+fn words(line: &str) -> Vec<(usize, &str)> {
+    line.split_whitespace()
+        // every word is a slice of line
+        .map(|word| (word.as_ptr() as usize - line.as_ptr() as usize, word))
+        .collect()
+}
+
+/// fails with the index of the word at fault
+fn parse_step(words: &[(usize, &str)]) -> Result<Step, (usize, String)> {
+    let at = parse_seconds(words[0].1).map_err(|e| (0, e))?;
+    let names = words.iter().map(|&(_, word)| word).collect::<Vec<_>>();
+    let action = match names[1..] {
+        ["press", button] => Action::Press(button.parse().map_err(|e| (2, e))?),
+        ["release", button] => Action::Release(button.parse().map_err(|e| (2, e))?),
+        ["scratch", angle] => Action::Scratch {
+            degrees: parse_degrees(angle).map_err(|e| (2, e))?,
+            over: Duration::ZERO,
+        },
+        ["scratch", angle, "over", seconds] => Action::Scratch {
+            degrees: parse_degrees(angle).map_err(|e| (2, e))?,
+            over: parse_seconds(seconds).map_err(|e| (4, e))?,
+        },
+        [] => return Err((0, format!("no action after {}", names[0]))),
+        _ => {
+            return Err((
+                1,
+                format!(
+                    "unknown action: {} (expected press BUTTON, release BUTTON or \
+                     scratch ANGLE [over SECONDS])",
+                    names[1..].join(" ")
+                ),
+            ))
+        }
+    };
+    Ok(Step { at, action })
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds = s
+        .parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("invalid time: {s} (expected seconds, e.g. 0.5)"))?;
+    if seconds.as_secs_f64() > MAX_SECONDS {
+        return Err(format!("{s}s is more than a day"));
+    }
+    Ok(seconds)
+}
+
+fn parse_degrees(s: &str) -> Result<f64, String> {
+    s.strip_suffix("deg")
+        .and_then(|degrees| degrees.parse::<f64>().ok())
+        .filter(|degrees| degrees.is_finite())
+        .ok_or_else(|| format!("invalid angle: {s} (expected degrees, e.g. +90deg or -45deg)"))
+}
+
+/// a scratch that is still turning
+struct Turning {
+    started_at: Instant,
+    over: Duration,
+    units: i64,
+    applied: i64,
+}
+
+/// Plays the script into key_input and then ends the session through quit,
+/// unless it repeats.
+pub fn spawn(
+    script: Script,
+    key_input: Arc<SharedKeyInput>,
+    repeat: bool,
+    quit: Arc<Notify>,
+) -> JoinHandle<Result<()>> {
+    tokio::spawn(async move {
+        let mut state = KeyInput::init();
+        let mut position = 0u16;
+        loop {
+            info!("Playing script ({:?})", script.duration());
+            let started_at = Instant::now();
+            let mut steps = script.steps.iter().peekable();
+            let mut turning: Vec<Turning> = Vec::new();
+            while steps.peek().is_some() || !turning.is_empty() {
+                let next_step = steps.peek().map(|step| started_at + step.at);
+                let wake_at = match (next_step, turning.is_empty()) {
+                    (Some(at), true) => at,
+                    (Some(at), false) => at.min(Instant::now() + SCRATCH_TICK),
+                    (None, _) => Instant::now() + SCRATCH_TICK,
+                };
+                sleep_until(wake_at).await;
+
+                let now = Instant::now();
+                while let Some(step) = steps.next_if(|step| started_at + step.at <= now) {
+                    debug!("script: {:?}", step.action);
+                    match step.action {
+                        Action::Press(Button::Normal(button)) => state.normal_button.insert(button),
+                        Action::Press(Button::Option(button)) => state.option_button.insert(button),
+                        Action::Release(Button::Normal(button)) => {
+                            state.normal_button.remove(button)
+                        }
+                        Action::Release(Button::Option(button)) => {
+                            state.option_button.remove(button)
+                        }
+                        Action::Scratch { degrees, over } => turning.push(Turning {
+                            started_at: started_at + step.at,
+                            over,
+                            units: (degrees / 360.0 * UNITS_PER_TURN).round() as i64,
+                            applied: 0,
+                        }),
+                    }
+                    // one store per action, so a press released in the same
+                    // tick is still latched
+                    key_input.store(state);
+                }
+                if turning.is_empty() {
+                    continue;
+                }
+                for turn in &mut turning {
+                    let elapsed = now.saturating_duration_since(turn.started_at);
+                    let done = if turn.over.is_zero() {
+                        1.0
+                    } else {
+                        (elapsed.as_secs_f64() / turn.over.as_secs_f64()).min(1.0)
+                    };
+                    let target = (turn.units as f64 * done).round() as i64;
+                    // the position wraps like the turntable does
+                    position = position.wrapping_add((target - turn.applied) as u16);
+                    turn.applied = target;
+                }
+                turning.retain(|turn| turn.applied != turn.units);
+                state.set_scratch_position(position);
+                key_input.store(state);
+            }
+            if !repeat {
+                info!("Script finished");
+                quit.notify_one();
+                return Ok(());
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(seconds: f64, action: Action) -> Step {
+        Step {
+            at: Duration::from_secs_f64(seconds),
+            action,
+        }
+    }
+
+    /// the line, column and message of the error for source
+    fn error(source: &str) -> (usize, usize, String) {
+        let e = source.parse::<Script>().expect_err("an invalid script");
+        (e.line, e.column, e.message)
+    }
+
+    #[test]
+    fn the_documented_example_parses() {
+        let source = "\
+            # hold E1 for ten seconds while tapping B1\n\
+            0.0   press E1\n\
+            0.5   press B1\n\
+            0.55  release B1\n\
+            1.0   scratch +90deg over 0.25\n\
+            10.0  release E1\n";
+        let script: Script = source.parse().unwrap();
+        assert_eq!(
+            script.steps,
+            [
+                step(0.0, Action::Press(Button::Option(OptionButton::E1))),
+                step(0.5, Action::Press(Button::Normal(NormalButton::B1))),
+                step(0.55, Action::Release(Button::Normal(NormalButton::B1))),
+                step(
+                    1.0,
+                    Action::Scratch {
+                        degrees: 90.0,
+                        over: Duration::from_millis(250)
+                    }
+                ),
+                step(10.0, Action::Release(Button::Option(OptionButton::E1))),
+            ]
+        );
+        assert_eq!(script.duration(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn a_gradual_scratch_can_outlast_the_last_action() {
+        let script: Script = "0 press B1\n1 scratch -45deg over 2.5 # back\n2 release B1"
+            .parse()
+            .unwrap();
+        assert_eq!(script.duration(), Duration::from_secs_f64(3.5));
+    }
+
+    #[test]
+    fn errors_point_at_the_word_at_fault() {
+        assert_eq!(
+            error("0 press B1\n\n  0.5 press B8"),
+            (
+                3,
+                13,
+                "unknown button: B8 (expected B1 to B7 or E1 to E4)".to_string()
+            )
+        );
+        assert_eq!(
+            error("# comment\nsoon press B1"),
+            (
+                2,
+                1,
+                "invalid time: soon (expected seconds, e.g. 0.5)".to_string()
+            )
+        );
+        assert_eq!(
+            error("0 scratch 90"),
+            (
+                1,
+                11,
+                "invalid angle: 90 (expected degrees, e.g. +90deg or -45deg)".to_string()
+            )
+        );
+        assert_eq!(error("0 scratch 90deg over -1").1, 22);
+        assert_eq!(error("0 tap B1").1, 3);
+        assert_eq!(error("0 # press B1").2, "no action after 0");
+        assert_eq!(error("0 press B1\n100000 release B1").0, 2);
+    }
+
+    #[test]
+    fn time_never_goes_back() {
+        assert_eq!(
+            error("1 press B1\n0.5 release B1"),
+            (2, 1, "0.5s is before the previous action at 1s".to_string())
+        );
+        // the same time twice is fine
+        assert!("1 press B1\n1 release B1".parse::<Script>().is_ok());
+    }
+
+    #[test]
+    fn a_script_without_actions_is_an_error() {
+        assert_eq!(error("# nothing yet\n\n"), (2, 1, "no actions".to_string()));
+    }
+}
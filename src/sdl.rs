@@ -0,0 +1,78 @@
+// --sdl-mapping and --sdl-mapping-file: the keymap from an SDL game
+// controller mapping, the entry for the input device's GUID in a
+// gamecontrollerdb.txt or a line given as is. It is resolved for the 1P
+// device and applies to every device the session reads, except that a device
+// `beatble ctl device` swaps in gets its own entry from the file.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use beatble::exit::ErrorKind;
+use beatble::input::{device_guid, InputMapping, Keymap, SdlControls, SdlMapping};
+use eyre::{eyre, Result, WrapErr};
+use tracing::{debug, info};
+
+use crate::cli::RunArgs;
+
+/// RunArgs::input_mapping() with the keymap of the SDL mapping, if one is given.
+pub fn input_mapping(args: &RunArgs) -> Result<InputMapping> {
+    let mut mapping = args.input_mapping();
+    let sdl = match (&args.sdl_mapping, &args.sdl_mapping_file) {
+        (Some(line), _) => line
+            .parse::<SdlMapping>()
+            .map_err(|e| eyre!("invalid sdl-mapping: {e}"))
+            .wrap_err(ErrorKind::Config)?,
+        (None, Some(path)) => find(path, args.input().wrap_err(ErrorKind::Config)?)?,
+        (None, None) => return Ok(mapping),
+    };
+    info!("SDL mapping: {} ({})", sdl.name, sdl.guid);
+    mapping.keymap = sdl.keymap(&args.sdl_controls);
+    debug!("keymap: {}", mapping.keymap);
+    Ok(mapping)
+}
+
+/// --sdl-mapping-file, for the entry of a device swapped in later.
+// only a session with bluetooth swaps devices
+#[cfg_attr(not(feature = "ble"), allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct SdlLookup {
+    path: PathBuf,
+    controls: SdlControls,
+}
+
+#[cfg_attr(not(feature = "ble"), allow(dead_code))]
+impl SdlLookup {
+    /// None unless the keymap comes from --sdl-mapping-file
+    pub fn new(args: &RunArgs) -> Option<Self> {
+        if args.sdl_mapping.is_some() {
+            return None;
+        }
+        Some(Self {
+            path: args.sdl_mapping_file.clone()?,
+            controls: args.sdl_controls.clone(),
+        })
+    }
+
+    /// the keymap of input's entry, read from the file again
+    pub fn keymap(&self, input: &str) -> Result<Keymap> {
+        let sdl = find(&self.path, input)?;
+        info!("SDL mapping for {input}: {} ({})", sdl.name, sdl.guid);
+        let keymap = sdl.keymap(&self.controls);
+        debug!("keymap: {keymap}");
+        Ok(keymap)
+    }
+}
+
+/// the entry for input's GUID in the file at path
+fn find(path: &Path, input: &str) -> Result<SdlMapping> {
+    let guid = device_guid(input)
+        .wrap_err_with(|| format!("failed to read the SDL GUID of {input}"))
+        .wrap_err(ErrorKind::InputDevice)?;
+    let db = fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read {}", path.display()))
+        .wrap_err(ErrorKind::Config)?;
+    SdlMapping::find(&db, &guid)
+        .map_err(|e| eyre!("invalid {}: {e}", path.display()))
+        .and_then(|sdl| sdl.ok_or_else(|| eyre!("no SDL mapping for {guid} in {}", path.display())))
+        .wrap_err(ErrorKind::Config)
+}
@@ -0,0 +1,821 @@
+// One emulation session as configured on the command line: the input
+// readers, the notifiers and everything that observes them, until the
+// peripheral stops, an input reader fails or a shutdown is requested.
+
+use std::sync::Arc;
+
+use beatble::ble::{
+    align_to_conn_interval, create_key_input, create_key_input_dp, read_conn_interval,
+    request_conn_interval, watch_conn_params, ConnInterval, Connection, NotifyConfig,
+    NotifyContext, NotifyMode, RepeatSpacing,
+};
+use beatble::chaos;
+use beatble::control::{check_interval, Command as ControlCommand, Control};
+use beatble::dump::PayloadDump;
+use beatble::emulation::Emulation;
+use beatble::exit::ErrorKind;
+#[cfg(feature = "input")]
+use beatble::input::{attach_input_handler, Hotplug};
+use beatble::input::{InputMapping, KeyInputDp, SharedKeyInput};
+use beatble::latency::Latency;
+use beatble::payload::{Layout, ScratchMode};
+use beatble::peripheral::{BleError, PeripheralBuilder};
+use beatble::stats::{self, Stats};
+use beatble::systemd;
+use eyre::{eyre, Result, WrapErr};
+use futures::future::OptionFuture;
+use futures::TryFutureExt;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, Notify};
+use tokio::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use crate::cli::RunArgs;
+use crate::config::{self, Change, Reloader};
+use crate::ctl;
+#[cfg(feature = "input")]
+use crate::doctor::{self, Status};
+use crate::logging;
+use crate::script::{self, Script};
+use crate::snapshot::Snapshot;
+#[cfg(feature = "input")]
+use crate::split;
+#[cfg(feature = "input")]
+use crate::supervisor::HOTPLUG_SETTLE;
+use crate::supervisor::{ActiveDevice, Reopen, RestartPolicy, Supervisor};
+use crate::suspend::{self, Sleep};
+use crate::tui::{Dashboard, LogTail};
+use crate::{dry_run, status};
+
+// --wait-for-device looks for the device this often, and says it still waits
+#[cfg(feature = "input")]
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+#[cfg(feature = "input")]
+const WAIT_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// With a script, and whether it repeats, the script stands in for the 1P
+/// input device.
+pub async fn run(
+    // --wait-for-device sets the input once the device turns up
+    #[cfg_attr(not(feature = "input"), allow(unused_mut))] mut args: RunArgs,
+    script: Option<(Script, bool)>,
+    log_tail: Option<LogTail>,
+    reloader: Option<Reloader>,
+) -> Result<()> {
+    match &script {
+        Some(_) => debug!("input: script"),
+        None if args.input.is_none() && args.wait_for_device => {
+            debug!("input: waiting for {}", args.device_selector())
+        }
+        None => debug!("input: {}", args.input().wrap_err(ErrorKind::Config)?),
+    }
+    debug!("dp_device: {:?}", args.dp_device);
+    debug!("sleep_duration: {}", args.sleep_duration);
+    debug!("notify_on_change: {}", args.notify_on_change);
+    debug!("keep_alive: {}", args.keep_alive);
+    debug!("warmup_frames: {}", args.warmup_frames);
+    debug!("counter_start: {}", args.counter_start);
+    debug!("frame_repeat: {}", args.frame_repeat);
+    debug!("frame_repeat_spacing: {}", args.frame_repeat_spacing);
+    debug!("busy_poll: {}", args.busy_poll);
+    debug!("runtime: {}", args.runtime);
+    debug!("on_input_error: {}", args.on_input_error);
+    debug!("on_advertising_stop: {}", args.on_advertising_stop);
+    debug!("on_bluetooth_loss: {}", args.on_bluetooth_loss);
+    info!("emulating: {}", args.emulate);
+    info!("payload format: {}", args.payload_format);
+    if let Some(profile) = args.profile {
+        info!("profile: {profile}");
+    }
+    if args.scratch_hires {
+        warn!("hi-res scratch enabled, payloads are not console compatible");
+    }
+    if args.single_report {
+        warn!("single report payloads enabled, payloads are not console compatible");
+    }
+    if args.scratch_mode != ScratchMode::Position {
+        warn!(
+            "{} scratch mode enabled, payloads are not console compatible",
+            args.scratch_mode
+        );
+    }
+    if (args.scratch_recenter || args.scratch_recenter_chord.is_some())
+        && args.input_mapping().recenter.is_none()
+    {
+        warn!(
+            "--scratch-recenter does nothing in the {} scratch mode or when emulating {}",
+            args.scratch_mode, args.emulate
+        );
+    }
+    if let Some(ms) = args.scratch_predict {
+        warn!("experimental scratch prediction enabled, {ms} ms ahead");
+    }
+    if let Some(faults) = &args.chaos {
+        warn!("injecting faults: {faults}");
+        chaos::install(faults.clone());
+    }
+
+    if let (Some(min), Some(max)) = (args.conn_interval_min, args.conn_interval_max) {
+        let conn_interval = ConnInterval {
+            min: from_millis_f64(min),
+            max: from_millis_f64(max),
+        };
+        match request_conn_interval(conn_interval) {
+            Ok(()) => info!("Requested connection interval {:?}", conn_interval),
+            Err(e) => warn!(
+                "failed to request connection interval: {:#}",
+                eyre::Report::new(e)
+            ),
+        }
+    }
+
+    let notify_config = NotifyConfig {
+        interval: notify_interval(&args),
+        mode: notify_mode(&args),
+        warmup_frames: args.warmup_frames,
+        counter_start: args.counter_start,
+        frame_repeat: args.frame_repeat,
+        repeat_spacing: args.frame_repeat_spacing,
+        busy_poll: args.busy_poll,
+        layout: Layout {
+            format: args.payload_format,
+            scratch_hires: args.scratch_hires,
+            single_report: args.single_report,
+        },
+        emulation: args.emulate,
+        scratch_mode: args.scratch_mode,
+        scratch_predict: args.scratch_predict.map(from_millis_f64),
+    };
+
+    // rejected intervals didn't pass config::validate
+    if let Ok(Some(why)) =
+        check_interval(Duration::from_millis(args.sleep_duration), args.busy_poll)
+    {
+        warn!("--sleep-duration {}: {why}", args.sleep_duration);
+    }
+
+    if args.stats == Some(0) {
+        return Err(eyre!("--stats needs a period of at least one second"))
+            .wrap_err(ErrorKind::Config);
+    }
+
+    if script.is_some() && args.dp_device.is_some() {
+        return Err(eyre!(
+            "a script only plays 1P, --dp-device can't be used with it"
+        ))
+        .wrap_err(ErrorKind::Config);
+    }
+
+    if args.dp_device.is_some() && args.emulate != Emulation::Iidx {
+        return Err(eyre!("double play is only available when emulating iidx"))
+            .wrap_err(ErrorKind::Config);
+    }
+
+    if args.scratch_predict.is_some() && args.emulate != Emulation::Iidx {
+        return Err(eyre!(
+            "scratch prediction is only available when emulating iidx"
+        ))
+        .wrap_err(ErrorKind::Config);
+    }
+
+    if args.frame_repeat > 1 && args.frame_repeat_spacing == RepeatSpacing::Ticks {
+        info!(
+            "sending every frame on {} ticks, new input waits up to {} more",
+            args.frame_repeat,
+            args.frame_repeat - 1
+        );
+    }
+
+    if args.scratch_mode == ScratchMode::Velocity {
+        if args.emulate != Emulation::Iidx {
+            return Err(eyre!(
+                "the velocity scratch mode is only available when emulating iidx"
+            ))
+            .wrap_err(ErrorKind::Config);
+        }
+        if args.scratch_predict.is_some() || args.scratch_hires {
+            return Err(eyre!(
+                "the velocity scratch mode sends no position, so it can't be used with \
+                 --scratch-predict or --scratch-hires"
+            ))
+            .wrap_err(ErrorKind::Config);
+        }
+    }
+
+    info!("Preparing input handler");
+    systemd::status("waiting for input device");
+    // a reopen still happens on its timer without it
+    #[cfg(feature = "input")]
+    let hotplug = Hotplug::spawn()
+        .inspect_err(|e| debug!("not watching for input devices: {e}"))
+        .ok();
+    #[cfg(feature = "input")]
+    if script.is_none() && args.wait_for_device {
+        wait_for_device(&mut args, hotplug.as_deref()).await?;
+    }
+    #[cfg(feature = "input")]
+    let mapping = match script {
+        Some(_) => args.input_mapping(),
+        None => crate::sdl::input_mapping(&args)?,
+    };
+    #[cfg(not(feature = "input"))]
+    let mapping = args.input_mapping();
+    let stats = Arc::new(Stats::new());
+    let quit = Arc::new(Notify::new());
+    let key_input = Arc::new(SharedKeyInput::new());
+    // ahead of the 2P reader, so a reload reaches it too
+    let p2 = Arc::new(SharedKeyInput::new());
+    let control = Arc::new(Control::new(notify_config.interval, notify_config.mode));
+    let sleep = Arc::new(Sleep::new(Arc::clone(&control)));
+    let (readers, swapped_readers) = mpsc::unbounded_channel();
+    let active = Arc::new(ActiveDevice::default());
+    let mut supervisor = Supervisor::new(
+        Reopen {
+            policy: args.on_input_error,
+            sleep: Arc::clone(&sleep),
+            #[cfg(feature = "input")]
+            mapping: mapping.clone(),
+            #[cfg(feature = "input")]
+            busy_poll: args.busy_poll,
+            #[cfg(feature = "input")]
+            force: args.force,
+            #[cfg(feature = "input")]
+            split_privileges: args.split_privileges,
+            #[cfg(feature = "input")]
+            tui: args.tui,
+            #[cfg(feature = "input")]
+            stats: Arc::clone(&stats),
+            #[cfg(feature = "input")]
+            hotplug,
+        },
+        &key_input,
+        Arc::clone(&active),
+        swapped_readers,
+    );
+    match script {
+        Some((script, repeat)) => {
+            let input_handler =
+                script::spawn(script, Arc::clone(&key_input), repeat, Arc::clone(&quit));
+            supervisor.watch(None, &key_input, input_handler);
+        }
+        None => {
+            let device = args.input().wrap_err(ErrorKind::Config)?;
+            watch_input(&mut supervisor, device, &key_input, &mapping, &args, &stats).await?;
+        }
+    }
+
+    let context = NotifyContext {
+        key_input,
+        control,
+        latency: Arc::new(Latency::new()),
+        stats,
+        dump: match &args.dump_payloads {
+            Some(path) => {
+                info!("Dumping payloads to {}", path.display());
+                Some(Arc::new(PayloadDump::create(path, &notify_config)?))
+            }
+            None => None,
+        },
+    };
+    spawn_signal_handlers(context.clone(), Snapshot::new(&args))?;
+    // the peripheral is set up again on every resume
+    let rebuild = Arc::new(Notify::new());
+    suspend::spawn(sleep, Arc::clone(&rebuild));
+    if let Some(period) = args.stats {
+        stats::spawn_log(Duration::from_secs(period), context.clone());
+    }
+    ctl::spawn(
+        &args
+            .control_socket
+            .clone()
+            .unwrap_or_else(ctl::default_path),
+        ctl::Session {
+            context: context.clone(),
+            mapping: mapping.clone(),
+            #[cfg(feature = "input")]
+            sdl: crate::sdl::SdlLookup::new(&args),
+            active,
+            busy_poll: args.busy_poll,
+            force: args.force,
+            #[cfg(feature = "input")]
+            split_privileges: args.split_privileges,
+            readers,
+            quit: Arc::clone(&quit),
+        },
+    );
+    if let Some(address) = args.status_listen {
+        status::spawn(address, &args, context.clone())
+            .await
+            .wrap_err(ErrorKind::Config)?;
+    }
+    #[cfg(feature = "dbus")]
+    if let Some(bus) = args.dbus {
+        crate::dbus::spawn(bus, context.clone(), args.emulate).await?;
+    }
+    #[cfg(feature = "dbus")]
+    if !args.dry_run && !args.no_bluetooth {
+        // a session without the record still works, just without the warnings
+        if let Err(e) = crate::bonds::spawn().await {
+            warn!("not keeping a record of bonds: {e:#}");
+        }
+    }
+    #[cfg(feature = "dbus")]
+    if args.pairing != crate::pairing::Pairing::JustWorks && !args.dry_run && !args.no_bluetooth {
+        crate::pairing::spawn(
+            args.pairing,
+            args.pairing_auto_confirm.map(Duration::from_secs),
+            Arc::clone(&context.key_input),
+            Arc::clone(&context.stats),
+        )
+        .await
+        .wrap_err(ErrorKind::BleSetup)?;
+    }
+    #[cfg(feature = "overlay")]
+    if let Some(address) = args.overlay_listen {
+        crate::overlay::spawn(address, Arc::clone(&context.key_input), args.emulate)
+            .await
+            .wrap_err(ErrorKind::Config)?;
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(address) = args.metrics_listen {
+        crate::metrics::spawn(address, context.clone())
+            .await
+            .wrap_err(ErrorKind::Config)?;
+    }
+    #[cfg(feature = "midi")]
+    if args.midi {
+        crate::midi::spawn(&args, Arc::clone(&context.key_input)).wrap_err(ErrorKind::Config)?;
+    }
+    // removed once the session returns
+    #[cfg(feature = "input")]
+    let _mirror = if args.mirror_uinput {
+        let mirror = crate::mirror::spawn(args.emulate, Arc::clone(&context.key_input))
+            .wrap_err(ErrorKind::InputDevice)?;
+        Some(mirror)
+    } else {
+        None
+    };
+    #[cfg(not(feature = "input"))]
+    if args.mirror_uinput {
+        return Err(eyre!("this build can't create the uinput mirror")).wrap_err(ErrorKind::Config);
+    }
+    if let Some(reloader) = reloader {
+        let key_inputs = [Arc::clone(&context.key_input), Arc::clone(&p2)];
+        spawn_reload_handler(
+            reloader,
+            args.clone(),
+            Arc::clone(&context.control),
+            key_inputs,
+        )?;
+    }
+
+    // setup is done once advertising started, or right away without bluetooth
+    let ready = Arc::new(Notify::new());
+    let result = if args.dry_run {
+        if args.dp_device.is_some() {
+            warn!("dry run only prints the 1P frames");
+        }
+        ready.notify_one();
+        tokio::select! {
+            result = dry_run::run(context.clone(), notify_config, args.changes_only) => result,
+            result = supervisor.run() => result,
+            result = shutdown_signal(&quit) => result,
+            result = sandbox(&ready, &args) => result,
+        }
+    } else if args.no_bluetooth {
+        if args.dp_device.is_some() {
+            warn!("without bluetooth only the 1P device is read");
+        }
+        ready.notify_one();
+        tokio::select! {
+            result = supervisor.run() => result,
+            result = shutdown_signal(&quit) => result,
+            result = sandbox(&ready, &args) => result,
+        }
+    } else {
+        let services = match &args.dp_device {
+            Some(dp_device) => {
+                info!("Preparing 2P input handler");
+                watch_input(
+                    &mut supervisor,
+                    dp_device,
+                    &p2,
+                    &mapping,
+                    &args,
+                    &context.stats,
+                )
+                .await?;
+                let key_input = KeyInputDp {
+                    p1: Arc::clone(&context.key_input),
+                    p2,
+                };
+                create_key_input_dp(context.clone(), &key_input, notify_config)
+            }
+            None => vec![create_key_input(context.clone(), notify_config)],
+        };
+
+        #[cfg(feature = "dbus")]
+        crate::dbus::spawn_disconnect_reasons(Arc::clone(&context.stats));
+        spawn_conn_params(&args, Arc::clone(&context.stats));
+        let advertising_name = args
+            .advertising_name
+            .as_deref()
+            .unwrap_or(args.emulate.advertising_name());
+        systemd::status("starting bluetooth");
+        let peripheral = PeripheralBuilder::new(advertising_name)
+            .services(services)
+            .power_timeout(Duration::from_secs(args.power_timeout))
+            .advertising_timeout(Duration::from_secs(args.advertising_timeout))
+            .readvertise(args.on_advertising_stop == RestartPolicy::Restart)
+            .rebuild_retries(match args.on_bluetooth_loss {
+                RestartPolicy::Restart => args.bluetooth_retries,
+                RestartPolicy::Exit => 0,
+            })
+            .rebuild_on(rebuild)
+            .notify_ready(Arc::clone(&ready))
+            .run(context.clone())
+            .map_err(|e| {
+                let kind = match e {
+                    BleError::Setup(_) => ErrorKind::BleSetup,
+                    _ => ErrorKind::BleRuntime,
+                };
+                eyre::Report::new(e).wrap_err(kind)
+            });
+        let dashboard: OptionFuture<_> = log_tail
+            .map(|log_tail| Dashboard::new(context.clone(), args.emulate, log_tail).spawn())
+            .into();
+        // whichever ends first ends the session, dropping the others
+        tokio::select! {
+            result = peripheral => result,
+            result = supervisor.run() => result,
+            Some(result) = dashboard => result?,
+            result = shutdown_signal(&quit) => result,
+            result = sandbox(&ready, &args) => result,
+        }
+    };
+    #[cfg(feature = "input")]
+    if let Err(e) = &result {
+        if let Some(ErrorKind::BleSetup | ErrorKind::BleRuntime) = e.downcast_ref::<ErrorKind>() {
+            log_bluetooth_checks();
+        }
+    }
+    context.latency.log_summary();
+    context.stats.log_summary();
+    if let Some(dump) = &context.dump {
+        dump.log_summary();
+    }
+    result
+}
+
+/// the failed bluetooth checks of `beatble doctor`, for why bluetooth failed
+#[cfg(feature = "input")]
+fn log_bluetooth_checks() {
+    let mut checks = vec![doctor::probe_bluetoothd()];
+    checks.extend(doctor::probe_rfkill());
+    for check in checks.iter().filter(|check| check.status == Status::Fail) {
+        error!("{check}");
+    }
+}
+
+/// Reads path into key_input with the session's settings, watched by
+/// supervisor.
+/// --wait-for-device: returns once the device is there, with args.input set
+/// to the one --device-name or --device-id picked.
+#[cfg(feature = "input")]
+async fn wait_for_device(args: &mut RunArgs, hotplug: Option<&Hotplug>) -> Result<()> {
+    let selector = args.device_selector();
+    let present = |args: &mut RunArgs| -> Result<bool> {
+        if let Some(input) = &args.input {
+            return Ok(std::path::Path::new(input).exists());
+        }
+        let path = beatble::input::select_device(&selector).wrap_err(ErrorKind::InputDevice)?;
+        args.input = path;
+        Ok(args.input.is_some())
+    };
+    if present(args)? {
+        return Ok(());
+    }
+    let wanted = match &args.input {
+        Some(input) => input.clone(),
+        None => format!("a joystick device with {selector}"),
+    };
+    info!("Waiting for {wanted} to be plugged in");
+    let started = tokio::time::Instant::now();
+    let mut reported = started;
+    loop {
+        // a hotplug event only makes it sooner, one may come before the wait
+        let added = async {
+            match hotplug {
+                Some(hotplug) => hotplug.added().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(DEVICE_POLL_INTERVAL) => {}
+            _ = added => {}
+        }
+        if present(args)? {
+            break;
+        }
+        if reported.elapsed() >= WAIT_REPORT_INTERVAL {
+            info!(
+                "still waiting for {wanted}, {}s so far",
+                started.elapsed().as_secs()
+            );
+            reported = tokio::time::Instant::now();
+        }
+    }
+    info!("{} plugged in", args.input().wrap_err(ErrorKind::Config)?);
+    tokio::time::sleep(HOTPLUG_SETTLE).await;
+    Ok(())
+}
+
+#[cfg(feature = "input")]
+async fn watch_input(
+    supervisor: &mut Supervisor,
+    path: &str,
+    key_input: &Arc<SharedKeyInput>,
+    mapping: &InputMapping,
+    args: &RunArgs,
+    stats: &Arc<Stats>,
+) -> Result<()> {
+    if args.split_privileges {
+        let handler = split::attach(
+            path,
+            Arc::clone(key_input),
+            mapping.clone(),
+            args.force,
+            args.tui,
+            Arc::clone(stats),
+        )
+        .await
+        .wrap_err(ErrorKind::InputDevice)?;
+        supervisor.watch(Some(path), key_input, handler);
+        return Ok(());
+    }
+    let handler = attach_input_handler(
+        path,
+        Arc::clone(key_input),
+        mapping.clone(),
+        args.busy_poll,
+        args.force,
+        Arc::clone(stats),
+    )
+    .wrap_err(ErrorKind::InputDevice)?;
+    supervisor.watch(Some(path), key_input, handler);
+    Ok(())
+}
+
+/// only simulate starts a session in a build without the input feature
+#[cfg(not(feature = "input"))]
+async fn watch_input(
+    _supervisor: &mut Supervisor,
+    _path: &str,
+    _key_input: &Arc<SharedKeyInput>,
+    _mapping: &InputMapping,
+    _args: &RunArgs,
+    _stats: &Arc<Stats>,
+) -> Result<()> {
+    Err(eyre!("this build can't read input devices")).wrap_err(ErrorKind::Config)
+}
+
+/// with --sandbox, restricts syscalls once ready; only resolves if that fails
+#[cfg_attr(not(feature = "sandbox"), allow(unused_variables))]
+async fn sandbox(ready: &Notify, args: &RunArgs) -> Result<()> {
+    #[cfg(feature = "sandbox")]
+    if args.sandbox {
+        ready.notified().await;
+        crate::sandbox::restrict_syscalls(args).wrap_err("failed to set up the sandbox")?;
+    }
+    std::future::pending().await
+}
+
+/// SIGINT, SIGTERM or `beatble ctl quit` end the session the same way a
+/// stopped advertisement does
+async fn shutdown_signal(quit: &Notify) -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+        _ = quit.notified() => {}
+    }
+    info!("Shutting down");
+    Ok(())
+}
+
+fn spawn_signal_handlers(context: NotifyContext, snapshot: Snapshot) -> Result<()> {
+    let mut user_defined1 = signal(SignalKind::user_defined1())?;
+    let control = Arc::clone(&context.control);
+    tokio::spawn(async move {
+        while user_defined1.recv().await.is_some() {
+            control.apply(ControlCommand::TogglePause);
+        }
+    });
+
+    let mut user_defined2 = signal(SignalKind::user_defined2())?;
+    tokio::spawn(async move {
+        while user_defined2.recv().await.is_some() {
+            snapshot.log(&context);
+            context.latency.reset();
+            info!("Latency histograms reset");
+        }
+    });
+
+    Ok(())
+}
+
+fn spawn_reload_handler(
+    reloader: Reloader,
+    mut args: RunArgs,
+    control: Arc<Control>,
+    key_inputs: [Arc<SharedKeyInput>; 2],
+) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("Reloading config");
+            match reloader.reload() {
+                Ok(new_args) => reload(&mut args, new_args, &control, &key_inputs),
+                Err(e) => error!("failed to reload config, keeping the running one: {e:#}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// applies the reloadable settings of new_args to the running notifiers and
+/// input readers
+fn reload(
+    args: &mut RunArgs,
+    new_args: RunArgs,
+    control: &Control,
+    key_inputs: &[Arc<SharedKeyInput>],
+) {
+    let changes = match config::diff(args, &new_args) {
+        Ok(changes) => changes,
+        Err(e) => {
+            error!("failed to compare configs, keeping the running one: {e:#}");
+            return;
+        }
+    };
+    let (reloadable, fixed): (Vec<Change>, Vec<Change>) =
+        changes.into_iter().partition(Change::is_reloadable);
+    if reloadable.is_empty() && fixed.is_empty() {
+        info!("Config unchanged");
+        return;
+    }
+    for change in &reloadable {
+        info!("Config changed {change}");
+    }
+    if !fixed.is_empty() {
+        let fixed: Vec<String> = fixed.iter().map(ToString::to_string).collect();
+        warn!("restart to apply {}", fixed.join(", "));
+    }
+    if reloadable.is_empty() {
+        return;
+    }
+
+    let interval = Duration::from_millis(new_args.sleep_duration);
+    match check_interval(interval, args.busy_poll) {
+        Ok(Some(why)) => warn!("sleep_duration {}: {why}", new_args.sleep_duration),
+        Ok(None) => {}
+        Err(e) => {
+            error!("invalid sleep_duration, keeping the running config: {e}");
+            return;
+        }
+    }
+    if reloadable.iter().any(Change::is_mapping) {
+        // the keymap an SDL mapping gives the device wins, as at startup
+        #[cfg(feature = "input")]
+        let mapping = crate::sdl::input_mapping(&new_args);
+        #[cfg(not(feature = "input"))]
+        let mapping: Result<InputMapping> = Ok(new_args.input_mapping());
+        match mapping {
+            Ok(mapping) => {
+                for key_input in key_inputs {
+                    key_input.remap(mapping.clone());
+                }
+            }
+            Err(e) => {
+                error!("failed to build the input mapping, keeping the running config: {e:#}");
+                return;
+            }
+        }
+        args.keymap = new_args.keymap;
+        args.mapping = new_args.mapping.clone();
+        args.turntable_sensitivity = new_args.turntable_sensitivity;
+        args.anti_wobble = new_args.anti_wobble;
+        args.anti_wobble_grace = new_args.anti_wobble_grace;
+    }
+    if reloadable.iter().any(|change| change.key == "log-level") {
+        match logging::set_level(new_args.log_level.as_deref()) {
+            Ok(true) => {}
+            Ok(false) => warn!("log-level is left alone, RUST_LOG or -v/-q set the log filter"),
+            Err(e) => error!("failed to change the log filter: {e:#}"),
+        }
+        args.log_level = new_args.log_level.clone();
+    }
+    args.sleep_duration = new_args.sleep_duration;
+    args.notify_on_change = new_args.notify_on_change;
+    args.keep_alive = new_args.keep_alive;
+    control.apply(ControlCommand::SetInterval(notify_interval(args)));
+    control.apply(ControlCommand::SetMode(notify_mode(args)));
+}
+
+/// Logs what the central picks for every connection and keeps it in stats,
+/// warning about an interval over --conn-interval-warn.
+fn spawn_conn_params(args: &RunArgs, stats: Arc<Stats>) {
+    match read_conn_interval() {
+        Ok(range) => info!(
+            "Adapter prefers connection intervals {:?} to {:?}",
+            range.min, range.max
+        ),
+        Err(e) => debug!(
+            "failed to read the preferred connection intervals: {:#}",
+            eyre::Report::new(e)
+        ),
+    }
+    let mut params = match watch_conn_params() {
+        Ok(params) => params,
+        Err(e) => {
+            info!("not watching connection parameters, the HCI monitor needs CAP_NET_RAW: {e}");
+            return;
+        }
+    };
+    let limit = from_millis_f64(args.conn_interval_warn);
+    let requested = args.conn_interval_max.map(from_millis_f64);
+    tokio::spawn(async move {
+        while params.changed().await.is_ok() {
+            let connection = *params.borrow_and_update();
+            stats.set_connection(connection);
+            let Some(Connection {
+                central,
+                params: current,
+            }) = connection
+            else {
+                continue;
+            };
+            info!("Connection parameters of {central}: {current}");
+            if current.interval <= limit {
+                continue;
+            }
+            match requested {
+                Some(max) if current.interval > max => warn!(
+                    "connection interval {:?} is over {limit:?}: the central refused the \
+                     connection parameter update request for at most {max:?}",
+                    current.interval
+                ),
+                _ => warn!(
+                    "connection interval {:?} is over {limit:?}, frames reach the console late; \
+                     --conn-interval-min 7.5 --conn-interval-max 11.25 makes the kernel ask the \
+                     central for a shorter one with a connection parameter update request",
+                    current.interval
+                ),
+            }
+        }
+    });
+}
+
+fn from_millis_f64(ms: f64) -> Duration {
+    Duration::from_secs_f64(ms / 1000.0)
+}
+
+fn notify_mode(args: &RunArgs) -> NotifyMode {
+    if args.notify_on_change {
+        NotifyMode::OnChange {
+            keep_alive: Duration::from_millis(args.keep_alive),
+        }
+    } else {
+        NotifyMode::Periodic
+    }
+}
+
+fn notify_interval(args: &RunArgs) -> Duration {
+    let interval = Duration::from_millis(args.sleep_duration);
+    // 0 is paced by the link already
+    if !args.align_to_conn_interval || interval.is_zero() {
+        return interval;
+    }
+
+    let conn_interval = match args.conn_interval_hint {
+        Some(hint) => from_millis_f64(hint),
+        // the negotiated value is somewhere in the range; assume the slowest
+        None => match read_conn_interval() {
+            Ok(conn_interval) => conn_interval.max,
+            Err(e) => {
+                warn!(
+                    "failed to read connection interval, not aligning: {:#}",
+                    eyre::Report::new(e)
+                );
+                return interval;
+            }
+        },
+    };
+
+    let aligned = align_to_conn_interval(interval, conn_interval);
+    info!(
+        "Aligned notification interval {:?} to connection interval {:?}: {:?}",
+        interval, conn_interval, aligned
+    );
+    aligned
+}
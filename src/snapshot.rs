@@ -0,0 +1,199 @@
+// SIGUSR2: the whole session state as a block of log lines, so the output of
+// `kill -USR2 $(pidof beatble)` is enough to debug most reports. Everything
+// but the config and the devices is read from the same atomics as the status
+// endpoint and the exit summary.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use beatble::ble::{NotifyContext, NotifyMode};
+use beatble::clock;
+#[cfg(feature = "input")]
+use beatble::input::device_info;
+use beatble_protocol::{NormalButton, OptionButton};
+use tracing::info;
+
+use crate::build_info::BUILD_INFO;
+use crate::cli::RunArgs;
+use crate::config;
+
+/// The parts of a dump that are fixed for the session.
+pub struct Snapshot {
+    config: String,
+    devices: Vec<String>,
+}
+
+impl Snapshot {
+    /// Opens the devices once, a dump never touches them.
+    pub fn new(args: &RunArgs) -> Self {
+        let config = config::show(args).unwrap_or_else(|e| format!("unavailable: {e:#}"));
+        let devices = [args.input.as_deref(), args.dp_device.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(describe_device)
+            .collect();
+        Self { config, devices }
+    }
+
+    pub fn lines(&self, context: &NotifyContext) -> Vec<String> {
+        let stats = &context.stats;
+        let control = &context.control;
+        let mut lines = vec![
+            format!(
+                "build: {} ({}{}, {}, {}, features: {})",
+                BUILD_INFO.version,
+                BUILD_INFO.git_commit,
+                if BUILD_INFO.git_dirty {
+                    ", modified"
+                } else {
+                    ""
+                },
+                BUILD_INFO.build_date,
+                BUILD_INFO.rustc,
+                BUILD_INFO.features,
+            ),
+            "config:".to_string(),
+        ];
+        lines.extend(self.config.lines().map(|line| format!("  {line}")));
+        lines.push("devices:".to_string());
+        lines.extend(self.devices.iter().map(|device| format!("  {device}")));
+
+        let key_input = context.key_input.load();
+        let (latched_normal, latched_option) = context.key_input.latched();
+        lines.push(format!(
+            "key input: scratch={} analog={} normal={} option={}",
+            key_input.scratch,
+            key_input.analog,
+            normal_names(key_input.normal_button),
+            option_names(key_input.option_button),
+        ));
+        lines.push(format!(
+            "latched: normal={} option={}",
+            normal_names(latched_normal),
+            option_names(latched_option),
+        ));
+        if let Some(reference) = context.key_input.scratch_reference() {
+            lines.push(format!(
+                "turntable zero: position {reference}, scratch {}",
+                reference >> 8
+            ));
+        }
+        lines.push(format!(
+            "control: interval {:?}, {}, {}",
+            control.interval(),
+            match control.mode() {
+                NotifyMode::Periodic => "periodic".to_string(),
+                NotifyMode::OnChange { keep_alive } =>
+                    format!("on change, keep-alive {keep_alive:?}"),
+            },
+            if control.is_paused() {
+                "paused"
+            } else {
+                "running"
+            },
+        ));
+
+        let subscribers = stats.subscriber_list();
+        lines.push(format!("subscribers: {}", subscribers.len()));
+        lines.extend(subscribers.iter().map(|subscriber| {
+            format!(
+                "  #{}: counter={} sent={} dropped={} last tick {:?} ago",
+                subscriber.id,
+                subscriber.counter.load(Ordering::Relaxed),
+                load(&subscriber.sent_frames),
+                load(&subscriber.congested_frames),
+                subscriber.since_heartbeat(),
+            )
+        }));
+
+        lines.push(format!(
+            "frame interval: {}",
+            context.latency.frame_interval.summary()
+        ));
+        lines.push(format!("data age: {}", context.latency.data_age.summary()));
+        lines.push(format!(
+            "liveness: last input event {:?} ago, last notifier tick {:?} ago",
+            clock::since(context.key_input.updated_at()),
+            stats.since_last_tick(),
+        ));
+        let totals = stats.snapshot();
+        lines.push(format!(
+            "totals: input events {}, sent frames {}, repeated frames {}, dropped frames {}, \
+             notifier stalls {}, subscriptions {}",
+            totals.input_events(),
+            totals.sent_frames,
+            totals.repeated_frames,
+            totals.congested_frames,
+            totals.stalls,
+            totals.subscriptions,
+        ));
+        lines.push(format!(
+            "connections: {} connects, {} disconnects, {} unsubscriptions, {} readvertisements, \
+             {} bluetoothd losses, {} pairings, {} failed pairings, {} pings, {} throttled pings, \
+             last connection {:?}, last disconnect reason: {}",
+            totals.connections,
+            totals.disconnections,
+            totals.unsubscriptions,
+            totals.readvertisements,
+            totals.bluez_losses,
+            totals.pairings,
+            totals.failed_pairings,
+            totals.pings,
+            totals.throttled_pings,
+            Duration::from_millis(totals.last_connection_ms),
+            stats.disconnect_reason().as_deref().unwrap_or("unknown"),
+        ));
+        if let Some(central) = stats.central() {
+            lines.push(format!("central: {central}"));
+        }
+        if let Some(params) = stats.conn_params() {
+            lines.push(format!("connection parameters: {params}"));
+        }
+        lines
+    }
+
+    pub fn log(&self, context: &NotifyContext) {
+        info!("State dump");
+        for line in self.lines(context) {
+            info!("{line}");
+        }
+    }
+}
+
+#[cfg(feature = "input")]
+fn describe_device(path: &str) -> String {
+    match device_info(path) {
+        Ok(info) => format!(
+            "{path}: {info}, {} axes, {} buttons",
+            info.axes, info.buttons
+        ),
+        Err(e) => format!("{path}: {:#}", eyre::Report::new(e)),
+    }
+}
+
+#[cfg(not(feature = "input"))]
+fn describe_device(path: &str) -> String {
+    path.to_string()
+}
+
+fn load(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+fn normal_names(buttons: NormalButton) -> String {
+    names(buttons.iter_names().map(|(name, _)| name))
+}
+
+fn option_names(buttons: OptionButton) -> String {
+    names(buttons.iter_names().map(|(name, _)| name))
+}
+
+// "-" rather than nothing keeps the key=value pairs apart
+fn names<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    let names = names.collect::<Vec<_>>();
+    if names.is_empty() {
+        "-".to_string()
+    } else {
+        names.join("|")
+    }
+}
@@ -0,0 +1,277 @@
+// `beatble soak`: hours of churn against the notify pipeline, to catch what
+// only leaks slowly, like a notifier task left behind by every resubscribe.
+//
+// A built-in script plays into the key input service while a mock central
+// subscribes, drains the notifications as a 7.5 ms connection would, and drops
+// the link and reconnects every so often; the chaos hooks add forced
+// resubscriptions, late frames and a congested channel on top. Each sample
+// records the resident memory, live runtime tasks, open file descriptors and
+// registered subscriptions, and the run fails when any of them ended up
+// higher than it started by more than its allowance.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use beatble::ble::{
+    create_key_input_with_events, NotifyConfig, NotifyContext, NotifyMode, RepeatSpacing,
+};
+use beatble::chaos::{self, Chaos};
+use beatble::control::Control;
+use beatble::emulation::Emulation;
+use beatble::exit::ErrorKind;
+use beatble::input::SharedKeyInput;
+use beatble::latency::Latency;
+use beatble::payload::{Layout, ScratchMode};
+use beatble::stats::Stats;
+use bluster::gatt::event::{Event, NotifySubscribe};
+use eyre::{bail, eyre, Result, WrapErr};
+use futures::channel::mpsc::{channel, Sender};
+use futures::{FutureExt, SinkExt, StreamExt};
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+use tokio::time::{interval, sleep, Duration, Instant};
+use tracing::{info, warn};
+
+use crate::script::{self, Script};
+
+const DEMO: &str = "
+0.0   press B1
+0.1   release B1
+0.2   press B3
+0.2   press B5
+0.3   release B3
+0.3   release B5
+0.5   scratch +180deg over 0.3
+1.0   press E1
+1.1   release E1
+1.5   scratch -90deg
+2.0   release B1
+";
+
+const INTERVAL: Duration = Duration::from_millis(8);
+// how often the mock central takes the notifications, as a connection would
+const CONN_INTERVAL: Duration = Duration::from_micros(7500);
+// how long the mock central stays connected before dropping the link
+const CONNECTED_FOR: Duration = Duration::from_secs(45);
+// the first and last samples compared, so one sample caught mid-resubscribe
+// isn't taken for growth
+const WINDOW: usize = 3;
+
+// how much each measure may grow over the run
+const RSS_ALLOWANCE_KB: u64 = 8 * 1024;
+const TASK_ALLOWANCE: u64 = 8;
+const FD_ALLOWANCE: u64 = 8;
+const SUBSCRIPTION_ALLOWANCE: u64 = 2;
+
+#[derive(Clone, Copy, Serialize)]
+struct Sample {
+    elapsed_secs: u64,
+    rss_kb: u64,
+    tasks: u64,
+    fds: u64,
+    subscriptions: u64,
+}
+
+#[derive(Serialize)]
+struct Growth {
+    measure: &'static str,
+    start: u64,
+    end: u64,
+    allowance: u64,
+    leaked: bool,
+}
+
+pub async fn run(hours: f64, sample_every: u64, report: Option<PathBuf>) -> Result<()> {
+    let duration = Duration::try_from_secs_f64(hours * 3600.0)
+        .map_err(|_| eyre!("invalid duration: {hours} hours"))
+        .wrap_err(ErrorKind::Config)?;
+    let sample_every = Duration::from_secs(sample_every);
+    let samples = duration.as_secs_f64() / sample_every.as_secs_f64();
+    if sample_every.is_zero() || samples < (2 * WINDOW) as f64 {
+        return Err(eyre!(
+            "{hours} hours is too short to sample every {sample_every:?}; \
+             at least {} samples are compared",
+            2 * WINDOW
+        ))
+        .wrap_err(ErrorKind::Config);
+    }
+
+    chaos::install(Chaos {
+        resubscribe: 0.01,
+        delay_frames: 0.001,
+        full_channel: 0.001,
+        ..Default::default()
+    });
+    let notify_config = NotifyConfig {
+        interval: INTERVAL,
+        mode: NotifyMode::Periodic,
+        warmup_frames: 0,
+        counter_start: 0,
+        frame_repeat: 1,
+        repeat_spacing: RepeatSpacing::BackToBack,
+        busy_poll: false,
+        layout: Layout::default(),
+        emulation: Emulation::Iidx,
+        scratch_mode: ScratchMode::Position,
+        scratch_predict: None,
+    };
+    let stats = Arc::new(Stats::new());
+    let context = NotifyContext {
+        key_input: Arc::new(SharedKeyInput::new()),
+        control: Arc::new(Control::new(notify_config.interval, notify_config.mode)),
+        latency: Arc::new(Latency::new()),
+        stats: Arc::clone(&stats),
+        dump: None,
+    };
+    let script: Script = DEMO.parse()?;
+    script::spawn(
+        script,
+        Arc::clone(&context.key_input),
+        true,
+        Arc::new(Notify::new()),
+    );
+    // the mock central talks to the characteristic directly, the service is
+    // only kept so nothing of it is dropped during the run
+    let (_service, events) = create_key_input_with_events(context, notify_config);
+    let central = tokio::spawn(central(events));
+
+    info!("Soaking for {duration:?}, sampling every {sample_every:?}");
+    let started_at = Instant::now();
+    let mut ticker = interval(sample_every);
+    ticker.tick().await;
+    let mut samples = Vec::new();
+    while started_at.elapsed() < duration {
+        ticker.tick().await;
+        if central.is_finished() {
+            bail!("the mock central stopped");
+        }
+        let sample = sample(started_at, &stats)?;
+        info!(
+            "{:?}: {} kB resident, {} tasks, {} fds, {} subscriptions",
+            started_at.elapsed(),
+            sample.rss_kb,
+            sample.tasks,
+            sample.fds,
+            sample.subscriptions
+        );
+        samples.push(sample);
+    }
+    central.abort();
+
+    let growth = [
+        growth(&samples, "rss_kb", RSS_ALLOWANCE_KB, |s| s.rss_kb),
+        growth(&samples, "tasks", TASK_ALLOWANCE, |s| s.tasks),
+        growth(&samples, "fds", FD_ALLOWANCE, |s| s.fds),
+        growth(&samples, "subscriptions", SUBSCRIPTION_ALLOWANCE, |s| {
+            s.subscriptions
+        }),
+    ];
+    let stats = stats.snapshot();
+    let leaked = growth.iter().any(|growth| growth.leaked);
+    let result = serde_json::json!({
+        "seconds": started_at.elapsed().as_secs(),
+        "passed": !leaked,
+        "subscriptions": stats.subscriptions,
+        "sent_frames": stats.sent_frames,
+        "stalls": stats.stalls,
+        "congested_frames": stats.congested_frames,
+        "growth": growth,
+        "samples": samples,
+    });
+    write_report(report.as_deref(), &format!("{result:#}\n"))?;
+
+    let leaks = growth
+        .iter()
+        .filter(|growth| growth.leaked)
+        .map(|growth| format!("{} {} -> {}", growth.measure, growth.start, growth.end))
+        .collect::<Vec<_>>();
+    if !leaks.is_empty() {
+        bail!("grew beyond the allowance: {}", leaks.join(", "));
+    }
+    info!("Nothing grew beyond its allowance");
+    Ok(())
+}
+
+/// Subscribes, takes the notifications until the link drops, and reconnects,
+/// every other time through an orderly unsubscribe first.
+async fn central(mut events: Sender<Event>) -> Result<()> {
+    let mut orderly = false;
+    loop {
+        let (notification, mut notifications) = channel(1);
+        events
+            .send(Event::NotifySubscribe(NotifySubscribe { notification }))
+            .await?;
+        let connected_until = Instant::now() + CONNECTED_FOR;
+        let mut ticker = interval(CONN_INTERVAL);
+        while Instant::now() < connected_until {
+            ticker.tick().await;
+            while let Some(Some(_)) = notifications.next().now_or_never() {}
+        }
+        if orderly {
+            events.send(Event::NotifyUnsubscribe).await?;
+            drop(notifications);
+        } else {
+            // the notifier finds the channel closed before bluez reports it
+            drop(notifications);
+            sleep(CONN_INTERVAL).await;
+            events.send(Event::NotifyUnsubscribe).await?;
+        }
+        orderly = !orderly;
+    }
+}
+
+fn sample(started_at: Instant, stats: &Stats) -> Result<Sample> {
+    Ok(Sample {
+        elapsed_secs: started_at.elapsed().as_secs(),
+        rss_kb: resident_kb()?,
+        tasks: Handle::current().metrics().num_alive_tasks() as u64,
+        fds: fs::read_dir("/proc/self/fd")?.count() as u64,
+        subscriptions: stats.subscriber_list().len() as u64,
+    })
+}
+
+/// VmRSS of /proc/self/status
+fn resident_kb() -> Result<u64> {
+    let status = fs::read_to_string("/proc/self/status")?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|kb| kb.trim().trim_end_matches("kB").trim().parse().ok())
+        .ok_or_else(|| eyre!("no VmRSS in /proc/self/status"))
+}
+
+/// the highest of the first samples against the lowest of the last ones
+fn growth(
+    samples: &[Sample],
+    measure: &'static str,
+    allowance: u64,
+    value: impl Fn(&Sample) -> u64,
+) -> Growth {
+    let start = samples.iter().take(WINDOW).map(&value).max();
+    let end = samples.iter().rev().take(WINDOW).map(&value).min();
+    let (start, end) = (start.unwrap_or_default(), end.unwrap_or_default());
+    let leaked = end.saturating_sub(start) > allowance;
+    if leaked {
+        warn!("{measure} grew from {start} to {end}");
+    }
+    Growth {
+        measure,
+        start,
+        end,
+        allowance,
+        leaked,
+    }
+}
+
+fn write_report(path: Option<&Path>, report: &str) -> Result<()> {
+    match path {
+        Some(path) => fs::write(path, report)
+            .wrap_err_with(|| format!("failed to write the report to {}", path.display())),
+        None => {
+            print!("{report}");
+            Ok(())
+        }
+    }
+}
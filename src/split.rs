@@ -0,0 +1,152 @@
+// --split-privileges: the device is read by a child process that gives up
+// root once it has the device open, and relays its raw events over a pipe,
+// after a single byte that tells the session the device is open. Only the
+// session keeps the privileges bluetooth needs.
+//
+//   beatble run --split-privileges DEVICE            (root)
+//   └── beatble relay-input DEVICE --uid U --gid G   (U:G once DEVICE is open)
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::Arc;
+
+use beatble::exit::ErrorKind;
+use beatble::input::{
+    attach_event_stream, open_relay_source, InputError, InputMapping, SharedKeyInput,
+};
+use beatble::stats::Stats;
+use eyre::{bail, eyre, Result, WrapErr};
+use nix::unistd::{self, Gid, Uid};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+// who the input process runs as without sudo
+const NOBODY: u32 = 65534;
+const READY: u8 = 1;
+
+/// Starts the input process for device and reads its events into key_input
+/// once it has the device open. The handle fails with the input process, or
+/// resolves to Ok once the input is handed over, which ends the input process.
+pub async fn attach(
+    device: &str,
+    key_input: Arc<SharedKeyInput>,
+    mapping: InputMapping,
+    force: bool,
+    tui: bool,
+    stats: Arc<Stats>,
+) -> Result<JoinHandle<Result<()>>> {
+    let (uid, gid) = relay_user();
+    if !Uid::effective().is_root() {
+        warn!("not running as root, the input process keeps the same privileges");
+    }
+    let exe = env::current_exe().wrap_err("failed to find the beatble executable")?;
+    let mut command = Command::new(exe);
+    command
+        .args(["relay-input", device, "-q"])
+        .args(["--uid", &uid.to_string(), "--gid", &gid.to_string()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        // its warnings would tear through the dashboard
+        .stderr(if tui { Stdio::null() } else { Stdio::inherit() });
+    if force {
+        command.arg("--force");
+    }
+    let child = command
+        .spawn()
+        .wrap_err("failed to start the input process")?;
+    let pid = child.id();
+    let (mut child, events) = tokio::task::spawn_blocking(move || ready(child)).await??;
+    info!("input process {pid} reads {device} as {uid}:{gid}");
+
+    let handler = match attach_event_stream(device, events, key_input, mapping, stats) {
+        Ok(handler) => handler,
+        Err(e) => {
+            let _ = child.kill();
+            return Err(e.into());
+        }
+    };
+    let device = device.to_owned();
+    Ok(tokio::spawn(async move {
+        let result = handler.await;
+        // the end of its output is the end of the input process
+        let exited = matches!(result, Ok(Err(InputError::Disconnected)));
+        let status = tokio::task::spawn_blocking(move || reap(child, exited)).await??;
+        debug!("input process {pid} ended: {status}");
+        match result {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) if exited => Err(eyre!("input process {pid} for {device} ended: {status}")),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }))
+}
+
+/// waits until child has the device open, or has failed to
+fn ready(mut child: Child) -> Result<(Child, ChildStdout)> {
+    let mut events = child.stdout.take().expect("stdout is piped");
+    let mut ready = [0u8; 1];
+    if events.read(&mut ready)? == 1 {
+        return Ok((child, events));
+    }
+    let status = child.wait()?;
+    bail!(
+        "input process {} failed to open the device: {status}",
+        child.id()
+    )
+}
+
+/// waits for child, killing it first unless it ended on its own
+fn reap(mut child: Child, exited: bool) -> io::Result<ExitStatus> {
+    if !exited {
+        child.kill()?;
+    }
+    child.wait()
+}
+
+/// whoever ran sudo, or nobody
+fn relay_user() -> (u32, u32) {
+    let id = |name| env::var(name).ok().and_then(|id| id.parse().ok());
+    match (id("SUDO_UID"), id("SUDO_GID")) {
+        (Some(uid), Some(gid)) => (uid, gid),
+        _ => (NOBODY, NOBODY),
+    }
+}
+
+/// `beatble relay-input`: opens device, switches to uid and gid and relays
+/// the device's events to stdout until either side goes away.
+pub fn relay(device: &str, uid: u32, gid: u32, force: bool) -> Result<()> {
+    let source = open_relay_source(device, force).wrap_err(ErrorKind::InputDevice)?;
+    drop_privileges(uid, gid).wrap_err("failed to drop privileges")?;
+    info!("relaying {} at {device}", source.info());
+    let mut out = io::stdout().lock();
+    let relayed = out
+        .write_all(&[READY])
+        .and_then(|()| out.flush())
+        .map_err(InputError::from)
+        .and_then(|()| source.relay(out));
+    match relayed {
+        // the session ended, or crashed
+        Err(InputError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+            info!("session went away, stopping");
+            Ok(())
+        }
+        result => result.wrap_err(ErrorKind::InputRuntime),
+    }
+}
+
+/// switches to uid and gid for good, unless not root anyway
+pub fn drop_privileges(uid: u32, gid: u32) -> Result<()> {
+    if !Uid::effective().is_root() {
+        debug!("not root, nothing to drop");
+        return Ok(());
+    }
+    unistd::setgroups(&[])?;
+    unistd::setgid(Gid::from_raw(gid))?;
+    unistd::setuid(Uid::from_raw(uid))?;
+    if uid != 0 && unistd::setuid(Uid::from_raw(0)).is_ok() {
+        bail!("still able to switch back to root");
+    }
+    debug!("running as {uid}:{gid}");
+    Ok(())
+}
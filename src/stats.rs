@@ -0,0 +1,599 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::{interval, Instant, MissedTickBehavior};
+use tracing::info;
+
+use crate::ble::{Address, ConnParams, Connection, NotifyContext};
+use crate::clock;
+
+pub const BUTTON_CODES: usize = 12;
+/// button names by joystick button code, code 7 is unused
+#[cfg(feature = "metrics")]
+pub const BUTTON_NAMES: [Option<&str>; BUTTON_CODES] = [
+    Some("B1"),
+    Some("B2"),
+    Some("B3"),
+    Some("B4"),
+    Some("B5"),
+    Some("B6"),
+    Some("B7"),
+    None,
+    Some("E1"),
+    Some("E2"),
+    Some("E3"),
+    Some("E4"),
+];
+
+/// Counters shared across the pipeline for diagnostics.
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// subscriptions currently being notified
+    pub subscribers: AtomicU64,
+    /// frames handed to a subscriber
+    pub sent_frames: AtomicU64,
+    /// --frame-repeat copies handed to a subscriber on top of sent_frames
+    pub repeated_frames: AtomicU64,
+    /// notifier tasks that stopped making progress and were respawned
+    pub stalls: AtomicU64,
+    /// frames that didn't fit into the notification channel
+    pub congested_frames: AtomicU64,
+    /// longest run of consecutive congested frames
+    pub max_congestion_streak: AtomicU64,
+    /// clock time of the latest tick of any notifier
+    pub last_tick: AtomicU64,
+    /// subscriptions since start; more than one means a central reconnected
+    pub subscriptions: AtomicU64,
+    /// subscriptions ended by an unsubscribe request
+    pub unsubscriptions: AtomicU64,
+    /// times the subscribers went from none to some. bluster reports no link
+    /// events, so as far as beatble can tell this is a central connecting
+    pub connections: AtomicU64,
+    /// times the subscribers went back to none
+    pub disconnections: AtomicU64,
+    /// times advertising started over after stopping without a subscriber
+    pub readvertisements: AtomicU64,
+    /// times bluetoothd went away and the peripheral was set up again
+    pub bluez_losses: AtomicU64,
+    /// centrals that finished pairing through the --pairing agent
+    pub pairings: AtomicU64,
+    /// pairings the agent rejected or BlueZ cancelled
+    pub failed_pairings: AtomicU64,
+    /// tokens echoed by the ping characteristic
+    pub pings: AtomicU64,
+    /// pings not echoed: too soon after the last, too long or unsubscribed
+    pub throttled_pings: AtomicU64,
+    pub buttons_pressed: AtomicU64,
+    pub buttons_released: AtomicU64,
+    pub axis_changes: AtomicU64,
+    /// presses by button code, see BUTTON_NAMES
+    pub presses: [AtomicU64; BUTTON_CODES],
+    /// scratch movement in raw axis units, 65536 per full turn at the lowest sensitivity
+    pub scratch_travel: AtomicU64,
+    // the notifiers register here, see subscriber_list
+    active: Mutex<Vec<Arc<SubscriberStats>>>,
+    // held while the subscribers go to or from none
+    connection: Mutex<ConnectionTime>,
+    // why the latest central disconnected, when BlueZ said
+    disconnect_reason: Mutex<Option<String>>,
+    // the central of the current connection and what it set, when the HCI
+    // monitor could be read
+    conn: Mutex<Option<Connection>>,
+}
+
+/// How long the centrals stayed connected. Changed together with the
+/// subscribers going to or from none, so a snapshot never sees a connection
+/// that ended but isn't added up yet.
+#[derive(Debug, Default)]
+struct ConnectionTime {
+    /// clock time the current connection started, 0 without one
+    started_at: u64,
+    /// nanoseconds the latest ended connection lasted
+    last: u64,
+    /// nanoseconds of the ended connections together
+    total: u64,
+}
+
+impl ConnectionTime {
+    fn current(&self) -> Option<Duration> {
+        match self.started_at {
+            0 => None,
+            started_at => Some(clock::since(started_at)),
+        }
+    }
+}
+
+/// The counters of [`Stats`] at one point in time. Each counter is read on
+/// its own, so two of them may be a frame apart, but none goes backwards
+/// between two snapshots.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub subscribers: u64,
+    pub sent_frames: u64,
+    pub repeated_frames: u64,
+    pub stalls: u64,
+    pub congested_frames: u64,
+    pub max_congestion_streak: u64,
+    pub subscriptions: u64,
+    pub unsubscriptions: u64,
+    pub connections: u64,
+    pub disconnections: u64,
+    pub readvertisements: u64,
+    pub bluez_losses: u64,
+    pub pairings: u64,
+    pub failed_pairings: u64,
+    pub pings: u64,
+    pub throttled_pings: u64,
+    /// time spent connected, the current connection included
+    pub connected_ms: u64,
+    /// how long the latest ended connection lasted
+    pub last_connection_ms: u64,
+    pub buttons_pressed: u64,
+    pub buttons_released: u64,
+    pub axis_changes: u64,
+    pub presses: [u64; BUTTON_CODES],
+    pub scratch_travel: u64,
+}
+
+impl StatsSnapshot {
+    /// button presses and releases and axis changes read from the devices
+    pub fn input_events(&self) -> u64 {
+        self.buttons_pressed + self.buttons_released + self.axis_changes
+    }
+
+    /// subscriptions after the first one
+    pub fn reconnects(&self) -> u64 {
+        self.subscriptions.saturating_sub(1)
+    }
+
+    /// What was counted since `earlier`. The subscribers, the longest
+    /// congestion streak and the latest connection aren't counts and are kept
+    /// as they are now.
+    pub fn since(&self, earlier: &StatsSnapshot) -> StatsSnapshot {
+        let delta = |now: u64, then: u64| now.saturating_sub(then);
+        StatsSnapshot {
+            subscribers: self.subscribers,
+            sent_frames: delta(self.sent_frames, earlier.sent_frames),
+            repeated_frames: delta(self.repeated_frames, earlier.repeated_frames),
+            stalls: delta(self.stalls, earlier.stalls),
+            congested_frames: delta(self.congested_frames, earlier.congested_frames),
+            max_congestion_streak: self.max_congestion_streak,
+            subscriptions: delta(self.subscriptions, earlier.subscriptions),
+            unsubscriptions: delta(self.unsubscriptions, earlier.unsubscriptions),
+            connections: delta(self.connections, earlier.connections),
+            disconnections: delta(self.disconnections, earlier.disconnections),
+            readvertisements: delta(self.readvertisements, earlier.readvertisements),
+            bluez_losses: delta(self.bluez_losses, earlier.bluez_losses),
+            pairings: delta(self.pairings, earlier.pairings),
+            failed_pairings: delta(self.failed_pairings, earlier.failed_pairings),
+            pings: delta(self.pings, earlier.pings),
+            throttled_pings: delta(self.throttled_pings, earlier.throttled_pings),
+            connected_ms: delta(self.connected_ms, earlier.connected_ms),
+            last_connection_ms: self.last_connection_ms,
+            buttons_pressed: delta(self.buttons_pressed, earlier.buttons_pressed),
+            buttons_released: delta(self.buttons_released, earlier.buttons_released),
+            axis_changes: delta(self.axis_changes, earlier.axis_changes),
+            presses: std::array::from_fn(|i| delta(self.presses[i], earlier.presses[i])),
+            scratch_travel: delta(self.scratch_travel, earlier.scratch_travel),
+        }
+    }
+}
+
+/// Counters of a single subscription, listed in Stats while its notifier runs.
+#[derive(Debug, Default)]
+pub struct SubscriberStats {
+    /// the subscription number, as in the log spans
+    pub id: u64,
+    pub sent_frames: AtomicU64,
+    pub congested_frames: AtomicU64,
+    /// counter of the latest sent frame
+    pub counter: AtomicU8,
+    /// clock time of the notifier's latest tick
+    pub heartbeat: AtomicU64,
+}
+
+impl SubscriberStats {
+    pub fn since_heartbeat(&self) -> Duration {
+        clock::since(self.heartbeat.load(Ordering::Relaxed))
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// one line for the dashboard and the service status
+    pub fn session_state(&self, paused: bool) -> String {
+        let state = match (self.subscribers.load(Ordering::Relaxed), paused) {
+            (0, _) => return "advertising, no subscriber".to_string(),
+            (n, false) => format!("notifying {n} subscriber(s)"),
+            (n, true) => format!("paused, {n} subscriber(s)"),
+        };
+        match self.central() {
+            Some(central) => format!("connected to {central}, {state}"),
+            None => state,
+        }
+    }
+
+    /// every counter, for the periodic log, the state dump, the exit summary
+    /// and the status and metrics endpoints
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let (connected, last_connection) = match self.connection.lock() {
+            Ok(connection) => (
+                connection.total + connection.current().unwrap_or_default().as_nanos() as u64,
+                connection.last,
+            ),
+            Err(_) => (0, 0),
+        };
+        StatsSnapshot {
+            subscribers: load(&self.subscribers),
+            sent_frames: load(&self.sent_frames),
+            repeated_frames: load(&self.repeated_frames),
+            stalls: load(&self.stalls),
+            congested_frames: load(&self.congested_frames),
+            max_congestion_streak: load(&self.max_congestion_streak),
+            subscriptions: load(&self.subscriptions),
+            unsubscriptions: load(&self.unsubscriptions),
+            connections: load(&self.connections),
+            disconnections: load(&self.disconnections),
+            readvertisements: load(&self.readvertisements),
+            bluez_losses: load(&self.bluez_losses),
+            pairings: load(&self.pairings),
+            failed_pairings: load(&self.failed_pairings),
+            pings: load(&self.pings),
+            throttled_pings: load(&self.throttled_pings),
+            connected_ms: connected / 1_000_000,
+            last_connection_ms: last_connection / 1_000_000,
+            buttons_pressed: load(&self.buttons_pressed),
+            buttons_released: load(&self.buttons_released),
+            axis_changes: load(&self.axis_changes),
+            presses: std::array::from_fn(|i| load(&self.presses[i])),
+            scratch_travel: load(&self.scratch_travel),
+        }
+    }
+
+    /// A characteristic got a subscriber; the first one starts a connection.
+    pub fn subscribed(&self) {
+        let connection = self.connection.lock();
+        if self.subscribers.fetch_add(1, Ordering::Relaxed) != 0 {
+            return;
+        }
+        let connections = self.connections.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Ok(mut connection) = connection {
+            connection.started_at = clock::now();
+        }
+        info!(connections, "central connected (connection {connections})");
+    }
+
+    /// A characteristic lost its subscriber; the last one ends the connection.
+    pub fn unsubscribed(&self) {
+        self.unsubscriptions.fetch_add(1, Ordering::Relaxed);
+        let connection = self.connection.lock();
+        if self.subscribers.fetch_sub(1, Ordering::Relaxed) != 1 {
+            return;
+        }
+        self.disconnections.fetch_add(1, Ordering::Relaxed);
+        let Ok(mut connection) = connection else {
+            return;
+        };
+        let duration = clock::now().saturating_sub(connection.started_at);
+        connection.started_at = 0;
+        connection.last = duration;
+        connection.total += duration;
+        drop(connection);
+        let connected_ms = duration / 1_000_000;
+        info!(
+            connected_ms,
+            "central disconnected after {:?}",
+            Duration::from_millis(connected_ms)
+        );
+    }
+
+    /// how long the current connection has lasted, if there is one
+    pub fn connected_for(&self) -> Option<Duration> {
+        self.connection
+            .lock()
+            .ok()
+            .and_then(|connection| connection.current())
+    }
+
+    /// Records why a central disconnected, e.g. from BlueZ's Disconnected
+    /// signal.
+    pub fn set_disconnect_reason(&self, reason: String) {
+        if let Ok(mut disconnect_reason) = self.disconnect_reason.lock() {
+            *disconnect_reason = Some(reason);
+        }
+    }
+
+    /// why the latest central disconnected, if that is known
+    pub fn disconnect_reason(&self) -> Option<String> {
+        self.disconnect_reason
+            .lock()
+            .ok()
+            .and_then(|reason| reason.clone())
+    }
+
+    pub fn set_connection(&self, connection: Option<Connection>) {
+        if let Ok(mut conn) = self.conn.lock() {
+            *conn = connection;
+        }
+    }
+
+    /// the parameters of the current connection, if there is one and they are known
+    pub fn conn_params(&self) -> Option<ConnParams> {
+        self.conn
+            .lock()
+            .ok()
+            .and_then(|conn| conn.map(|conn| conn.params))
+    }
+
+    /// the address of the current central, if there is one and it is known
+    pub fn central(&self) -> Option<Address> {
+        self.conn
+            .lock()
+            .ok()
+            .and_then(|conn| conn.map(|conn| conn.central))
+    }
+
+    pub fn since_last_tick(&self) -> Duration {
+        clock::since(self.last_tick.load(Ordering::Relaxed))
+    }
+
+    /// whether the notifiers ticked within `limit`, or there is nobody to notify
+    pub fn is_healthy(&self, limit: Duration) -> bool {
+        self.subscribers.load(Ordering::Relaxed) == 0 || self.since_last_tick() < limit
+    }
+
+    pub fn register(&self, id: u64) -> Arc<SubscriberStats> {
+        let subscriber = Arc::new(SubscriberStats {
+            id,
+            heartbeat: AtomicU64::new(clock::now()),
+            ..Default::default()
+        });
+        if let Ok(mut active) = self.active.lock() {
+            active.push(Arc::clone(&subscriber));
+        }
+        subscriber
+    }
+
+    pub fn unregister(&self, subscriber: &Arc<SubscriberStats>) {
+        if let Ok(mut active) = self.active.lock() {
+            active.retain(|active| !Arc::ptr_eq(active, subscriber));
+        }
+    }
+
+    /// subscriptions with a running notifier, oldest first
+    pub fn subscriber_list(&self) -> Vec<Arc<SubscriberStats>> {
+        match self.active.lock() {
+            Ok(active) => active.clone(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn log_summary(&self) {
+        let StatsSnapshot {
+            sent_frames,
+            repeated_frames,
+            stalls,
+            congested_frames,
+            max_congestion_streak,
+            connections,
+            disconnections,
+            readvertisements,
+            bluez_losses,
+            pairings,
+            failed_pairings,
+            pings,
+            throttled_pings,
+            connected_ms,
+            ..
+        } = self.snapshot();
+        info!(sent_frames, "sent frames: {}", sent_frames);
+        if repeated_frames > 0 {
+            info!(repeated_frames, "repeated frames: {}", repeated_frames);
+        }
+        info!(stalls, "notifier stalls: {}", stalls);
+        info!(
+            congested_frames,
+            max_congestion_streak,
+            "congested frames: {} (longest streak: {})",
+            congested_frames,
+            max_congestion_streak
+        );
+        info!(
+            connections,
+            disconnections,
+            readvertisements,
+            connected_ms,
+            "connections: {} (disconnects: {}, readvertised: {}, connected for {:?})",
+            connections,
+            disconnections,
+            readvertisements,
+            Duration::from_millis(connected_ms)
+        );
+        if bluez_losses > 0 {
+            info!(bluez_losses, "bluetoothd lost: {} time(s)", bluez_losses);
+        }
+        if pairings + failed_pairings > 0 {
+            info!(
+                pairings,
+                failed_pairings, "pairings: {} (failed: {})", pairings, failed_pairings
+            );
+        }
+        if pings + throttled_pings > 0 {
+            info!(
+                pings,
+                throttled_pings, "pings echoed: {} (throttled: {})", pings, throttled_pings
+            );
+        }
+    }
+}
+
+/// `--stats`: logs one line every `period`, rates since the previous line and
+/// totals since start. The keys stay put so issue reports can be grepped:
+///
+///   stats: input_events_per_sec=41.2 frames_per_sec=125.0 dropped_frames=0 data_age_p50_us=700 data_age_p95_us=1200 data_age_p99_us=1800 data_age_max_us=2600 subscribers=1 reconnects=0 disconnects=0
+pub fn spawn_log(period: Duration, context: NotifyContext) {
+    tokio::spawn(async move {
+        let stats = &context.stats;
+        let mut ticker = interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker.tick().await;
+        let mut last_at = Instant::now();
+        let mut last = stats.snapshot();
+        loop {
+            ticker.tick().await;
+            let elapsed = last_at.elapsed().as_secs_f64();
+            let now = stats.snapshot();
+            let delta = now.since(&last);
+            let data_age = context.latency.data_age.summary();
+            info!(
+                "stats: input_events_per_sec={:.1} frames_per_sec={:.1} dropped_frames={} \
+                 data_age_p50_us={} data_age_p95_us={} data_age_p99_us={} data_age_max_us={} \
+                 subscribers={} reconnects={} disconnects={}",
+                delta.input_events() as f64 / elapsed,
+                delta.sent_frames as f64 / elapsed,
+                now.congested_frames,
+                data_age.p50,
+                data_age.p95,
+                data_age.p99,
+                data_age.max,
+                now.subscribers,
+                now.reconnects(),
+                now.disconnections,
+            );
+            last_at = Instant::now();
+            last = now;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    use super::*;
+
+    /// the counts of a snapshot, leaving out the gauges since() keeps
+    fn counts(snapshot: &StatsSnapshot) -> Vec<u64> {
+        let mut counts = vec![
+            snapshot.sent_frames,
+            snapshot.repeated_frames,
+            snapshot.stalls,
+            snapshot.congested_frames,
+            snapshot.subscriptions,
+            snapshot.unsubscriptions,
+            snapshot.connections,
+            snapshot.disconnections,
+            snapshot.pings,
+            snapshot.connected_ms,
+            snapshot.buttons_pressed,
+            snapshot.buttons_released,
+            snapshot.axis_changes,
+            snapshot.scratch_travel,
+        ];
+        counts.extend(snapshot.presses);
+        counts
+    }
+
+    #[test]
+    fn snapshots_taken_while_counting_never_go_backwards() {
+        let stats = Arc::new(Stats::new());
+        let done = Arc::new(AtomicBool::new(false));
+        // a central coming and going, and notifiers and readers counting
+        let central = {
+            let stats = Arc::clone(&stats);
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    stats.subscriptions.fetch_add(1, Ordering::Relaxed);
+                    stats.subscribed();
+                    thread::sleep(Duration::from_micros(300));
+                    stats.unsubscribed();
+                }
+            })
+        };
+        let writers: Vec<_> = (0..3)
+            .map(|writer| {
+                let stats = Arc::clone(&stats);
+                let done = Arc::clone(&done);
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        stats.sent_frames.fetch_add(1, Ordering::Relaxed);
+                        stats.repeated_frames.fetch_add(1, Ordering::Relaxed);
+                        stats.buttons_pressed.fetch_add(1, Ordering::Relaxed);
+                        stats.presses[writer].fetch_add(1, Ordering::Relaxed);
+                        stats.scratch_travel.fetch_add(7, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+
+        let mut last = stats.snapshot();
+        let mut snapshots = 0;
+        let started_at = std::time::Instant::now();
+        while started_at.elapsed() < Duration::from_secs(1) {
+            let snapshot = stats.snapshot();
+            for (i, (now, then)) in counts(&snapshot).into_iter().zip(counts(&last)).enumerate() {
+                assert!(now >= then, "count {i} went from {then} to {now}");
+            }
+            assert!(
+                snapshot.subscribers <= 1,
+                "{} subscribers",
+                snapshot.subscribers
+            );
+            last = snapshot;
+            snapshots += 1;
+        }
+        done.store(true, Ordering::Relaxed);
+        central.join().expect("the central");
+        for writer in writers {
+            writer.join().expect("a writer");
+        }
+
+        // and once quiet, they add up
+        let snapshot = stats.snapshot();
+        assert!(snapshots > 1000, "only {snapshots} snapshots");
+        assert_eq!(snapshot.subscribers, 0);
+        assert_eq!(snapshot.connections, snapshot.disconnections);
+        assert_eq!(snapshot.sent_frames, snapshot.repeated_frames);
+        assert_eq!(
+            snapshot.presses.iter().sum::<u64>(),
+            snapshot.buttons_pressed
+        );
+        assert_eq!(snapshot.scratch_travel, 7 * snapshot.sent_frames);
+        assert!(counts(&snapshot.since(&snapshot))
+            .iter()
+            .all(|&count| count == 0));
+    }
+
+    #[test]
+    fn the_state_names_the_central_once_it_is_known() {
+        let stats = Stats::new();
+        assert_eq!(stats.session_state(false), "advertising, no subscriber");
+        stats.subscribed();
+        assert_eq!(stats.session_state(false), "notifying 1 subscriber(s)");
+        stats.set_connection(Some(Connection {
+            central: Address([0x66, 0x55, 0x44, 0x33, 0x22, 0x11]),
+            params: ConnParams {
+                interval: Duration::from_micros(7500),
+                latency: 0,
+                supervision_timeout: Duration::from_secs(5),
+            },
+        }));
+        assert_eq!(
+            stats.session_state(false),
+            "connected to 11:22:33:44:55:66, notifying 1 subscriber(s)"
+        );
+        assert_eq!(
+            stats.session_state(true),
+            "connected to 11:22:33:44:55:66, paused, 1 subscriber(s)"
+        );
+        stats.unsubscribed();
+        assert_eq!(stats.session_state(false), "advertising, no subscriber");
+    }
+}
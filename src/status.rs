@@ -0,0 +1,172 @@
+// `--status-listen`: the session at a glance as one JSON document at /status,
+// and /healthz for probes. Like the metrics, a request only loads atomics.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use beatble::ble::{NotifyContext, NotifyMode};
+#[cfg(feature = "input")]
+use beatble::input::device_info;
+use beatble::latency::Summary;
+use beatble_protocol::KeyInput;
+use eyre::Result;
+use serde_json::{json, Value};
+use tokio::time::{Duration, Instant};
+use tracing::info;
+
+use crate::build_info::BUILD_INFO;
+use crate::cli::RunArgs;
+use crate::http::{self, Response};
+
+// the notifier watchdog respawns a stuck notifier within about a second and a
+// quarter, so a longer gap means the respawn didn't help either
+const HEALTHY_TICK_GAP: Duration = Duration::from_secs(2);
+const JSON: &str = "application/json";
+
+pub async fn spawn(address: SocketAddr, args: &RunArgs, context: NotifyContext) -> Result<()> {
+    let started_at = Instant::now();
+    let devices = [args.input.as_deref(), args.dp_device.as_deref()]
+        .into_iter()
+        .flatten()
+        .map(device)
+        .collect::<Vec<_>>();
+    // fixed for the whole session
+    let session = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "build": BUILD_INFO,
+        "emulate": args.emulate.to_string(),
+        "payload_format": args.payload_format.to_string(),
+        "devices": devices,
+    });
+    http::serve(address, move |path| match path {
+        "/status" => {
+            let status = status(&session, &context, started_at);
+            Response::ok(JSON, format!("{status:#}\n"))
+        }
+        "/healthz" if context.stats.is_healthy(HEALTHY_TICK_GAP) => {
+            Response::ok("text/plain", "ok\n".to_string())
+        }
+        "/healthz" => Response::new(
+            "503 Service Unavailable",
+            "text/plain",
+            format!(
+                "no notifier tick for {:?}\n",
+                context.stats.since_last_tick()
+            ),
+        ),
+        _ => Response::not_found(),
+    })
+    .await?;
+    info!("Serving status on http://{address}/status");
+    Ok(())
+}
+
+#[cfg(feature = "input")]
+fn device(path: &str) -> Value {
+    match device_info(path) {
+        Ok(info) => json!({
+            "path": path,
+            "name": info.name,
+            "axes": info.axes,
+            "buttons": info.buttons,
+        }),
+        Err(e) => json!({ "path": path, "error": format!("{:#}", eyre::Report::new(e)) }),
+    }
+}
+
+#[cfg(not(feature = "input"))]
+fn device(path: &str) -> Value {
+    json!({ "path": path })
+}
+
+fn status(session: &Value, context: &NotifyContext, started_at: Instant) -> Value {
+    let stats = &context.stats;
+    let control = &context.control;
+    let paused = control.is_paused();
+    let interval = control.interval();
+    let totals = stats.snapshot();
+    let subscriber_list = stats
+        .subscriber_list()
+        .iter()
+        .map(|subscriber| {
+            json!({
+                "id": subscriber.id,
+                "counter": subscriber.counter.load(Ordering::Relaxed),
+                "sent_frames": load(&subscriber.sent_frames),
+                "congested_frames": load(&subscriber.congested_frames),
+                "since_tick_ms": subscriber.since_heartbeat().as_millis() as u64,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "session": session,
+        "uptime_secs": started_at.elapsed().as_secs(),
+        "state": stats.session_state(paused),
+        "healthy": stats.is_healthy(HEALTHY_TICK_GAP),
+        "subscribers": totals.subscribers,
+        "subscriptions": totals.subscriptions,
+        "subscriber_list": subscriber_list,
+        "connections": {
+            "connects": totals.connections,
+            "disconnects": totals.disconnections,
+            "unsubscriptions": totals.unsubscriptions,
+            "readvertisements": totals.readvertisements,
+            "bluez_losses": totals.bluez_losses,
+            "pairings": totals.pairings,
+            "failed_pairings": totals.failed_pairings,
+            "pings": totals.pings,
+            "throttled_pings": totals.throttled_pings,
+            "connected_ms": stats.connected_for().map(|connected| connected.as_millis() as u64),
+            "last_connection_ms": totals.last_connection_ms,
+            "total_connected_ms": totals.connected_ms,
+            "last_disconnect_reason": stats.disconnect_reason(),
+            "central": stats.central().map(|central| central.to_string()),
+            "params": stats.conn_params().map(|params| json!({
+                "interval_ms": params.interval.as_secs_f64() * 1000.0,
+                "latency": params.latency,
+                "supervision_timeout_ms": params.supervision_timeout.as_millis() as u64,
+            })),
+        },
+        "paused": paused,
+        "key_input": key_input(context.key_input.load()),
+        "rates": {
+            "notify_interval_ms": interval.as_secs_f64() * 1000.0,
+            "notify_on_change": matches!(control.mode(), NotifyMode::OnChange { .. }),
+            "sent_frames": totals.sent_frames,
+            "repeated_frames": totals.repeated_frames,
+            "frame_interval_us": summary(context.latency.frame_interval.summary()),
+            "data_age_us": summary(context.latency.data_age.summary()),
+        },
+        "errors": {
+            "notifier_stalls": totals.stalls,
+            "congested_frames": totals.congested_frames,
+            "max_congestion_streak": totals.max_congestion_streak,
+        },
+    })
+}
+
+fn load(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+fn key_input(key_input: KeyInput) -> Value {
+    // the same "B1 | B3" names the serde feature uses
+    let normal_button = key_input.normal_button.iter_names().map(|(name, _)| name);
+    let option_button = key_input.option_button.iter_names().map(|(name, _)| name);
+    json!({
+        "scratch": key_input.scratch,
+        "analog": key_input.analog,
+        "normal_button": normal_button.collect::<Vec<_>>().join(" | "),
+        "option_button": option_button.collect::<Vec<_>>().join(" | "),
+    })
+}
+
+fn summary(summary: Summary) -> Value {
+    json!({
+        "count": summary.count,
+        "p50": summary.p50,
+        "p95": summary.p95,
+        "p99": summary.p99,
+        "max": summary.max,
+    })
+}
@@ -0,0 +1,474 @@
+// Watches the input readers of a session. A reader that ends with Ok was
+// handed over or played its script out; one that fails is reopened or ends
+// the session, per --on-input-error, unless the system just slept.
+
+use std::fmt;
+use std::str::FromStr;
+#[cfg(feature = "ble")]
+use std::sync::Arc;
+use std::sync::Mutex;
+
+#[cfg(feature = "ble")]
+use beatble::exit::ErrorKind;
+#[cfg(feature = "ble")]
+use beatble::input::SharedKeyInput;
+#[cfg(all(feature = "ble", feature = "input"))]
+use beatble::input::{attach_input_handler, Hotplug, InputMapping};
+#[cfg(all(feature = "ble", feature = "input"))]
+use beatble::stats::Stats;
+#[cfg(feature = "ble")]
+use eyre::Result;
+#[cfg(feature = "ble")]
+use futures::future::BoxFuture;
+#[cfg(feature = "ble")]
+use futures::stream::{FuturesUnordered, StreamExt};
+#[cfg(feature = "ble")]
+use tokio::sync::mpsc;
+#[cfg(feature = "ble")]
+use tokio::task::JoinHandle;
+
+#[cfg(feature = "ble")]
+use crate::ctl::ReaderHandle;
+#[cfg(all(feature = "ble", feature = "input"))]
+use crate::split;
+#[cfg(feature = "ble")]
+use crate::suspend::Sleep;
+#[cfg(all(feature = "ble", feature = "input"))]
+use tokio::time::Duration;
+#[cfg(all(feature = "ble", feature = "input"))]
+use tracing::{info, warn};
+
+/// What to do when an input reader fails or advertising stops unexpectedly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// reopen the device, advertise again or set bluetooth up again
+    Restart,
+    /// end the session with the error
+    #[default]
+    Exit,
+}
+
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "restart" => Ok(RestartPolicy::Restart),
+            "exit" => Ok(RestartPolicy::Exit),
+            _ => Err(format!("unknown policy: {s} (expected restart or exit)")),
+        }
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestartPolicy::Restart => write!(f, "restart"),
+            RestartPolicy::Exit => write!(f, "exit"),
+        }
+    }
+}
+
+/// Reader settings for reopening a device.
+#[cfg(feature = "ble")]
+// a build without input support never reopens
+#[cfg_attr(not(feature = "input"), allow(dead_code))]
+pub struct Reopen {
+    pub policy: RestartPolicy,
+    /// failures around a system sleep are reopened whatever the policy
+    pub sleep: Arc<Sleep>,
+    #[cfg(feature = "input")]
+    pub mapping: InputMapping,
+    #[cfg(feature = "input")]
+    pub busy_poll: bool,
+    #[cfg(feature = "input")]
+    pub force: bool,
+    /// read devices through an input process, see split.rs
+    #[cfg(feature = "input")]
+    pub split_privileges: bool,
+    #[cfg(feature = "input")]
+    pub tui: bool,
+    #[cfg(feature = "input")]
+    pub stats: Arc<Stats>,
+    /// cuts the wait for a device short once it is plugged back in
+    #[cfg(feature = "input")]
+    pub hotplug: Option<Arc<Hotplug>>,
+}
+
+/// The device the 1P input is read from; None from a failure of its reader
+/// until a reader reads it again, and with a script.
+#[derive(Debug, Default)]
+pub struct ActiveDevice(Mutex<Option<String>>);
+
+// only a session with bluetooth has one
+#[cfg_attr(not(feature = "ble"), allow(dead_code))]
+impl ActiveDevice {
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().ok().and_then(|device| device.clone())
+    }
+
+    fn set(&self, device: Option<&str>) {
+        if let Ok(mut active) = self.0.lock() {
+            *active = device.map(str::to_owned);
+        }
+    }
+}
+
+/// A reader and the input it feeds; without a device it plays a script.
+#[cfg(feature = "ble")]
+#[cfg_attr(not(feature = "input"), allow(dead_code))]
+struct Reader {
+    device: Option<String>,
+    key_input: Arc<SharedKeyInput>,
+}
+
+#[cfg(feature = "ble")]
+struct ReaderExit {
+    reader: Reader,
+    result: Result<()>,
+    // failed reopens in a row
+    #[cfg_attr(not(feature = "input"), allow(dead_code))]
+    attempts: u32,
+}
+
+#[cfg(feature = "ble")]
+#[cfg_attr(not(feature = "input"), allow(dead_code))]
+pub struct Supervisor {
+    reopen: Arc<Reopen>,
+    readers: FuturesUnordered<BoxFuture<'static, ReaderExit>>,
+    // the 1P input, which `beatble ctl device` swaps readers into
+    key_input: Arc<SharedKeyInput>,
+    active: Arc<ActiveDevice>,
+    swapped: mpsc::UnboundedReceiver<(String, ReaderHandle)>,
+}
+
+#[cfg(feature = "ble")]
+impl Supervisor {
+    /// swapped carries the readers the control socket starts for key_input,
+    /// active is kept up to date with the device read into it.
+    pub fn new(
+        reopen: Reopen,
+        key_input: &Arc<SharedKeyInput>,
+        active: Arc<ActiveDevice>,
+        swapped: mpsc::UnboundedReceiver<(String, ReaderHandle)>,
+    ) -> Self {
+        Self {
+            reopen: Arc::new(reopen),
+            readers: FuturesUnordered::new(),
+            key_input: Arc::clone(key_input),
+            active,
+            swapped,
+        }
+    }
+
+    /// Watches a running reader of device, or of a script without one.
+    pub fn watch<E>(
+        &mut self,
+        device: Option<&str>,
+        key_input: &Arc<SharedKeyInput>,
+        handler: JoinHandle<Result<(), E>>,
+    ) where
+        E: Into<eyre::Report> + Send + 'static,
+    {
+        if Arc::ptr_eq(key_input, &self.key_input) {
+            self.active.set(device);
+        }
+        let reader = Reader {
+            device: device.map(str::to_owned),
+            key_input: Arc::clone(key_input),
+        };
+        self.readers.push(Box::pin(join(reader, handler)));
+    }
+
+    /// the 1P active device, if reader read it, or failed to reopen it
+    fn active_for(&self, reader: &Reader) -> Option<Arc<ActiveDevice>> {
+        let current = self.active.get();
+        let active = Arc::ptr_eq(&reader.key_input, &self.key_input)
+            && reader.device.is_some()
+            && (current.is_none() || current == reader.device);
+        active.then(|| Arc::clone(&self.active))
+    }
+
+    /// Resolves once a reader fails and the policy gives up on it. Never
+    /// resolves while a reader is left to watch, or can still be swapped in.
+    pub async fn run(mut self) -> Result<()> {
+        loop {
+            tokio::select! {
+                Some(exit) = self.readers.next() => {
+                    if let Err(e) = exit.result {
+                        self.on_failure(exit.reader, exit.attempts, e)?;
+                    }
+                }
+                Some((device, handler)) = self.swapped.recv() => {
+                    let key_input = Arc::clone(&self.key_input);
+                    self.watch(Some(&device), &key_input, handler);
+                }
+                else => return Ok(()),
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "input"), allow(unused_variables))]
+    fn on_failure(&mut self, reader: Reader, attempts: u32, error: eyre::Report) -> Result<()> {
+        let error = error.wrap_err(ErrorKind::InputRuntime);
+        // a reader swapped in meanwhile stays the active one
+        let active = self.active_for(&reader);
+        if let Some(active) = &active {
+            active.set(None);
+        }
+        match &reader.device {
+            #[cfg(feature = "input")]
+            Some(device)
+                if self.reopen.policy == RestartPolicy::Restart
+                    || self.reopen.sleep.recovering() =>
+            {
+                let delay = reopen_delay(attempts);
+                warn!("input {device} failed, reopening in {delay:?}: {error:#}");
+                // the failed reader still holds the claim unless it was swapped out since
+                let claimed = reader.key_input.reader();
+                let device = device.clone();
+                self.readers.push(Box::pin(reopen(
+                    device,
+                    reader,
+                    Arc::clone(&self.reopen),
+                    active,
+                    claimed,
+                    attempts,
+                    delay,
+                )));
+                Ok(())
+            }
+            _ => Err(error),
+        }
+    }
+}
+
+/// 1s after a failure, doubling with every failed reopen up to 16s
+#[cfg(all(feature = "ble", feature = "input"))]
+fn reopen_delay(attempts: u32) -> Duration {
+    Duration::from_secs(1 << attempts.min(4))
+}
+
+// udev may still be setting up the node of a device the kernel just added
+#[cfg(all(feature = "ble", feature = "input"))]
+pub const HOTPLUG_SETTLE: Duration = Duration::from_millis(250);
+
+/// Waits for delay, or less once an input device is added and device is
+/// there again.
+#[cfg(all(feature = "ble", feature = "input"))]
+async fn wait_for(device: &str, delay: Duration, hotplug: Option<&Hotplug>) {
+    let Some(hotplug) = hotplug else {
+        return tokio::time::sleep(delay).await;
+    };
+    let timeout = tokio::time::sleep(delay);
+    tokio::pin!(timeout);
+    loop {
+        tokio::select! {
+            _ = &mut timeout => return,
+            _ = hotplug.added() => {
+                if std::path::Path::new(device).exists() {
+                    info!("input {device} plugged back in");
+                    tokio::time::sleep(HOTPLUG_SETTLE).await;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "ble", feature = "input"))]
+async fn reopen(
+    device: String,
+    reader: Reader,
+    reopen: Arc<Reopen>,
+    // the 1P active device, if device was it
+    active: Option<Arc<ActiveDevice>>,
+    claimed: u64,
+    attempts: u32,
+    delay: Duration,
+) -> ReaderExit {
+    wait_for(&device, delay, reopen.hotplug.as_deref()).await;
+    // a reader swapped in meanwhile takes precedence
+    if !reader.key_input.is_claimed_by(claimed) {
+        info!("input {device} replaced, not reopening");
+        return ReaderExit {
+            reader,
+            result: Ok(()),
+            attempts: 0,
+        };
+    }
+    if reopen.split_privileges {
+        let attached = split::attach(
+            &device,
+            Arc::clone(&reader.key_input),
+            reopen.mapping.clone(),
+            reopen.force,
+            reopen.tui,
+            Arc::clone(&reopen.stats),
+        )
+        .await;
+        return match attached {
+            Ok(handler) => {
+                info!("input {device} reopened");
+                if let Some(active) = &active {
+                    active.set(Some(&device));
+                }
+                join(reader, handler).await
+            }
+            Err(e) => ReaderExit {
+                reader,
+                result: Err(e),
+                attempts: attempts + 1,
+            },
+        };
+    }
+    match attach_input_handler(
+        &device,
+        Arc::clone(&reader.key_input),
+        reopen.mapping.clone(),
+        reopen.busy_poll,
+        reopen.force,
+        Arc::clone(&reopen.stats),
+    ) {
+        Ok(handler) => {
+            info!("input {device} reopened");
+            if let Some(active) = &active {
+                active.set(Some(&device));
+            }
+            join(reader, handler).await
+        }
+        Err(e) => ReaderExit {
+            reader,
+            result: Err(e.into()),
+            attempts: attempts + 1,
+        },
+    }
+}
+
+#[cfg(feature = "ble")]
+async fn join<E>(reader: Reader, handler: JoinHandle<Result<(), E>>) -> ReaderExit
+where
+    E: Into<eyre::Report>,
+{
+    // a panicking reader failed like any other
+    let result = match handler.await {
+        Ok(result) => result.map_err(Into::into),
+        Err(e) => Err(e.into()),
+    };
+    ReaderExit {
+        reader,
+        result,
+        attempts: 0,
+    }
+}
+
+#[cfg(all(test, feature = "ble", feature = "input"))]
+mod tests {
+    use beatble::ble::NotifyMode;
+    use beatble::control::Control;
+    use beatble::emulation::Emulation;
+    use beatble::input::InputError;
+    use tokio::task::JoinError;
+
+    use super::*;
+
+    const DEVICE: &str = "/dev/input/beatble-test-unplugged";
+    const SWAPPED: &str = "/dev/input/beatble-test-swapped";
+
+    struct Session {
+        key_input: Arc<SharedKeyInput>,
+        active: Arc<ActiveDevice>,
+        swap: mpsc::UnboundedSender<(String, ReaderHandle)>,
+        supervisor: JoinHandle<Result<()>>,
+    }
+
+    /// a supervisor running over one reader of DEVICE that ends with fault
+    fn session<F>(policy: RestartPolicy, fault: F) -> Session
+    where
+        F: std::future::Future<Output = Result<(), InputError>> + Send + 'static,
+    {
+        let interval = Duration::from_millis(8);
+        let reopen = Reopen {
+            policy,
+            sleep: Arc::new(Sleep::new(Arc::new(Control::new(
+                interval,
+                NotifyMode::Periodic,
+            )))),
+            mapping: InputMapping::from(Emulation::Iidx),
+            busy_poll: false,
+            force: false,
+            split_privileges: false,
+            tui: false,
+            stats: Arc::new(Stats::new()),
+            hotplug: None,
+        };
+        let key_input = Arc::new(SharedKeyInput::new());
+        let active = Arc::new(ActiveDevice::default());
+        let (swap, swapped) = mpsc::unbounded_channel();
+        let mut supervisor = Supervisor::new(reopen, &key_input, Arc::clone(&active), swapped);
+        key_input.claim();
+        supervisor.watch(Some(DEVICE), &key_input, tokio::spawn(fault));
+        Session {
+            key_input,
+            active,
+            swap,
+            supervisor: tokio::spawn(supervisor.run()),
+        }
+    }
+
+    fn finished(supervisor: JoinHandle<Result<()>>) -> Result<Result<()>, JoinError> {
+        assert!(supervisor.is_finished(), "the supervisor is still running");
+        futures::executor::block_on(supervisor)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failed_reader_ends_the_session_with_exit() {
+        let session = session(RestartPolicy::Exit, async { Err(InputError::Disconnected) });
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let error = finished(session.supervisor).unwrap().unwrap_err();
+        assert_eq!(beatble::exit::code(&error), 5);
+        assert_eq!(
+            format!("{:#}", error.root_cause()),
+            "controller disconnected"
+        );
+        assert_eq!(session.active.get(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_panicking_reader_fails_like_any_other() {
+        let session = session(RestartPolicy::Exit, async { panic!("injected") });
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let error = finished(session.supervisor).unwrap().unwrap_err();
+        assert_eq!(beatble::exit::code(&error), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restart_keeps_the_session_until_a_reader_takes_over() {
+        let session = session(RestartPolicy::Restart, async {
+            Err(InputError::ShortRead(3))
+        });
+        assert_eq!(session.active.get().as_deref(), Some(DEVICE));
+
+        // the device stays gone through reopens after 1, 2, 4, 8 and 16s
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!session.supervisor.is_finished(), "the session ended");
+        assert_eq!(session.active.get(), None);
+
+        // a device swapped in over the control socket stops the reopens
+        session.key_input.claim();
+        let reader: ReaderHandle = tokio::spawn(std::future::pending());
+        session.swap.send((SWAPPED.to_string(), reader)).unwrap();
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!session.supervisor.is_finished(), "the session ended");
+        assert_eq!(session.active.get().as_deref(), Some(SWAPPED));
+        session.supervisor.abort();
+    }
+
+    #[test]
+    fn reopens_back_off_up_to_16s() {
+        let delays = (0..6)
+            .map(|attempts| reopen_delay(attempts).as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 16]);
+    }
+}
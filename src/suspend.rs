@@ -0,0 +1,245 @@
+// System suspend and resume. A sleep takes the central's connection with it
+// and usually the input device too, which comes back as a new device that
+// the old reader only gets ENODEV from.
+//
+// logind's PrepareForSleep signal tells when the system is about to sleep and
+// when it woke up; a delay inhibitor gives the notifiers the time to release
+// every key with a neutral frame before it does. Without logind, or in a build
+// without the dbus feature, a resume still shows as CLOCK_BOOTTIME, which
+// counts the time asleep, running ahead of CLOCK_MONOTONIC, which doesn't.
+//
+// After waking up the session recovers from what the sleep broke: readers
+// that fail are reopened whatever --on-input-error says, the peripheral is
+// set up and advertises again, and the notifiers resume without the presses
+// latched meanwhile.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use beatble::clock;
+use beatble::control::{Command, Control};
+use nix::time::{clock_gettime, ClockId};
+use tokio::sync::Notify;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::info;
+#[cfg(feature = "dbus")]
+use tracing::warn;
+
+// how often the clocks are compared, a resume shows within this
+const CLOCK_POLL: Duration = Duration::from_secs(1);
+// CLOCK_BOOTTIME running this much further ahead than before is a sleep, not
+// the scheduler being late
+const MIN_SLEEP: Duration = Duration::from_secs(2);
+// readers fail and bluetoothd notices the lost connection within a moment of
+// waking up; the notifiers stay paused until then
+const SETTLE: Duration = Duration::from_secs(2);
+// reader failures this long after a resume are put down to the sleep, enough
+// for the reopens to back off to 8s
+const RECOVERY: Duration = Duration::from_secs(30);
+
+/// Whether the system sleeps or just woke up, shared by whatever recovers
+/// from it.
+pub struct Sleep {
+    control: Arc<Control>,
+    // the notifiers were paused for the sleep, not by the player
+    paused: AtomicBool,
+    asleep: AtomicBool,
+    // clock::now() of the latest resume, 0 before the first
+    resumed_at: AtomicU64,
+    // how far CLOCK_BOOTTIME was ahead of CLOCK_MONOTONIC at the latest check,
+    // in nanoseconds
+    slept: AtomicU64,
+    resumed: Notify,
+}
+
+impl Sleep {
+    pub fn new(control: Arc<Control>) -> Self {
+        Self {
+            control,
+            paused: AtomicBool::new(false),
+            asleep: AtomicBool::new(false),
+            resumed_at: AtomicU64::new(0),
+            slept: AtomicU64::new(slept().unwrap_or(0)),
+            resumed: Notify::new(),
+        }
+    }
+
+    /// Whether a failure now is likely the sleep's doing: while asleep, or
+    /// shortly after waking up.
+    #[cfg(feature = "input")]
+    pub fn recovering(&self) -> bool {
+        // a reader may fail before the next poll notices the resume
+        self.check_clocks();
+        if self.asleep.load(Ordering::Relaxed) {
+            return true;
+        }
+        let resumed_at = self.resumed_at.load(Ordering::Relaxed);
+        resumed_at != 0 && clock::since(resumed_at) < RECOVERY
+    }
+
+    /// Releases every key and goes quiet, unless the player paused already.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    fn suspending(&self) {
+        if self.asleep.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        info!("System going to sleep, releasing the keys");
+        self.pause();
+    }
+
+    fn resume(&self, asleep: Option<Duration>) {
+        let was_asleep = self.asleep.swap(false, Ordering::Relaxed);
+        // logind and the clocks both report the same resume
+        let resumed_at = self.resumed_at.load(Ordering::Relaxed);
+        if !was_asleep && resumed_at != 0 && clock::since(resumed_at) < RECOVERY {
+            return;
+        }
+        self.resumed_at.store(clock::now(), Ordering::Relaxed);
+        match asleep {
+            Some(asleep) => info!("System resumed after {}s asleep", asleep.as_secs()),
+            None => info!("System resumed"),
+        }
+        self.resumed.notify_one();
+    }
+
+    /// Resumes when CLOCK_BOOTTIME got ahead since the last check.
+    fn check_clocks(&self) {
+        let Some(slept) = slept() else {
+            return;
+        };
+        let before = self.slept.swap(slept, Ordering::Relaxed);
+        let asleep = Duration::from_nanos(slept.saturating_sub(before));
+        if asleep >= MIN_SLEEP {
+            self.resume(Some(asleep));
+        }
+    }
+
+    fn pause(&self) {
+        if self.control.is_paused() {
+            return;
+        }
+        self.paused.store(true, Ordering::Relaxed);
+        self.control.apply(Command::Pause);
+    }
+
+    /// resumes the notifiers if they were paused for the sleep
+    fn unpause(&self) {
+        if self.paused.swap(false, Ordering::Relaxed) {
+            self.control.apply(Command::Resume);
+        }
+    }
+}
+
+/// Watches for the system going to sleep and waking up; on every resume,
+/// rebuild is notified to set the peripheral up again.
+pub fn spawn(sleep: Arc<Sleep>, rebuild: Arc<Notify>) {
+    #[cfg(feature = "dbus")]
+    {
+        let sleep = Arc::clone(&sleep);
+        tokio::spawn(async move {
+            if let Err(e) = logind::watch(&sleep).await {
+                warn!("not watching logind for sleep, only the clocks: {e}");
+            }
+        });
+    }
+
+    {
+        let sleep = Arc::clone(&sleep);
+        tokio::spawn(async move {
+            let mut poll = interval(CLOCK_POLL);
+            poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                poll.tick().await;
+                sleep.check_clocks();
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        loop {
+            sleep.resumed.notified().await;
+            // whatever is still subscribed gets the keys released too
+            sleep.pause();
+            rebuild.notify_one();
+            tokio::time::sleep(SETTLE).await;
+            // unpausing drops the presses latched while paused
+            sleep.unpause();
+        }
+    });
+}
+
+/// how far CLOCK_BOOTTIME is ahead of CLOCK_MONOTONIC, the time spent asleep
+/// since boot, in nanoseconds
+fn slept() -> Option<u64> {
+    let boot = Duration::from(clock_gettime(ClockId::CLOCK_BOOTTIME).ok()?);
+    let monotonic = Duration::from(clock_gettime(ClockId::CLOCK_MONOTONIC).ok()?);
+    boot.saturating_sub(monotonic).as_nanos().try_into().ok()
+}
+
+#[cfg(feature = "dbus")]
+mod logind {
+    use futures::StreamExt;
+    use tokio::time::Duration;
+    use tracing::{debug, info};
+    use zbus::message::Type;
+    use zbus::zvariant::OwnedFd;
+    use zbus::{Connection, MatchRule, MessageStream};
+
+    use super::Sleep;
+
+    // for the neutral frame to go out before the connection does
+    const RELEASE: Duration = Duration::from_millis(100);
+
+    pub async fn watch(sleep: &Sleep) -> zbus::Result<()> {
+        let connection = Connection::system().await?;
+        let rule = MatchRule::builder()
+            .msg_type(Type::Signal)
+            .sender("org.freedesktop.login1")?
+            .interface("org.freedesktop.login1.Manager")?
+            .member("PrepareForSleep")?
+            .build();
+        let mut signals = MessageStream::for_match_rule(rule, &connection, None).await?;
+        let mut inhibitor = inhibit(&connection).await;
+        info!("Watching logind for sleep");
+        while let Some(signal) = signals.next().await {
+            let suspending: bool = signal?.body().deserialize()?;
+            if suspending {
+                sleep.suspending();
+                if inhibitor.is_some() {
+                    tokio::time::sleep(RELEASE).await;
+                }
+                // closing it lets the system sleep
+                inhibitor = None;
+            } else {
+                sleep.resume(None);
+                inhibitor = inhibit(&connection).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delays sleeping until the lock is dropped, or logind's InhibitDelayMaxSec.
+    async fn inhibit(connection: &Connection) -> Option<OwnedFd> {
+        let reply = connection
+            .call_method(
+                Some("org.freedesktop.login1"),
+                "/org/freedesktop/login1",
+                Some("org.freedesktop.login1.Manager"),
+                "Inhibit",
+                &(
+                    "sleep",
+                    "beatble",
+                    "releasing the controller's keys",
+                    "delay",
+                ),
+            )
+            .await;
+        match reply.and_then(|reply| reply.body().deserialize::<OwnedFd>()) {
+            Ok(fd) => Some(fd),
+            Err(e) => {
+                debug!("no sleep delay lock, the keys may stay pressed across a sleep: {e}");
+                None
+            }
+        }
+    }
+}
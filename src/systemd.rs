@@ -0,0 +1,95 @@
+// sd_notify(3) without libsystemd: KEY=VALUE datagrams to $NOTIFY_SOCKET.
+// Without NOTIFY_SOCKET, i.e. outside a Type=notify unit, all of this is a
+// no-op.
+
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+
+use tokio::time::{interval, Duration};
+use tracing::{debug, warn};
+
+use crate::ble::NotifyContext;
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tells systemd that startup finished.
+pub fn ready() {
+    notify("READY=1");
+}
+
+/// Sets the status line shown by `systemctl status`.
+pub fn status(status: &str) {
+    notify(&format!("STATUS={status}"));
+}
+
+fn notify(state: &str) {
+    if let Err(e) = send(state) {
+        debug!("sd_notify {state:?} failed: {e}");
+    }
+}
+
+fn send(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let address = match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+/// interval asked for by WatchdogSec=, if it is meant for this process
+fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(process::id()) {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec))
+}
+
+/// Keeps STATUS= current and pings the watchdog while the notifiers make
+/// progress. A failed input reader ends the process on its own.
+pub fn spawn_health_task(context: NotifyContext) {
+    if env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+    let watchdog = watchdog_interval();
+    let period = watchdog.map_or(STATUS_INTERVAL, |watchdog| {
+        (watchdog / 2).min(STATUS_INTERVAL)
+    });
+
+    tokio::spawn(async move {
+        let mut ticker = interval(period);
+        let mut last_status = String::new();
+        loop {
+            ticker.tick().await;
+            let stats = &context.stats;
+            let status = stats.session_state(context.control.is_paused());
+            if status != last_status {
+                self::status(&status);
+                last_status = status;
+            }
+
+            let Some(watchdog) = watchdog else {
+                continue;
+            };
+            // a stuck notifier is respawned by its own watchdog well before this
+            if stats.is_healthy(watchdog / 2) {
+                notify("WATCHDOG=1");
+            } else {
+                warn!(
+                    "no notifier tick for {:?}, not pinging the systemd watchdog",
+                    stats.since_last_tick()
+                );
+            }
+        }
+    });
+}
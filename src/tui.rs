@@ -0,0 +1,7 @@
+#[cfg(feature = "ble")]
+pub use self::dashboard::Dashboard;
+pub use self::log_tail::LogTail;
+
+#[cfg(feature = "ble")]
+mod dashboard;
+mod log_tail;
@@ -0,0 +1,180 @@
+// `beatble run --tui`: full-screen dashboard drawn from the same counters the
+// log summaries use. It only reads shared state, except for the pause key
+// which goes through Control like SIGUSR1 does.
+
+use std::io::{self, Stdout};
+use std::time::{Duration, Instant};
+
+use beatble::ble::NotifyContext;
+use beatble::control::Command;
+use beatble::emulation::Emulation;
+use beatble::stats::StatsSnapshot;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use eyre::Result;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::widgets::{Block, Borders, Paragraph, Sparkline};
+use ratatui::{Frame, Terminal};
+use tokio::task::JoinHandle;
+use tracing::info;
+
+use super::LogTail;
+use crate::live_view;
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(33);
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+pub struct Dashboard {
+    context: NotifyContext,
+    emulation: Emulation,
+    log_tail: LogTail,
+    // the counters at the start of the current rate window
+    window: (Instant, StatsSnapshot),
+    rate: f64,
+}
+
+impl Dashboard {
+    pub fn new(context: NotifyContext, emulation: Emulation, log_tail: LogTail) -> Self {
+        let window = (Instant::now(), context.stats.snapshot());
+        Self {
+            context,
+            emulation,
+            log_tail,
+            window,
+            rate: 0.0,
+        }
+    }
+
+    /// runs until the user quits; the terminal is restored either way
+    pub fn spawn(self) -> JoinHandle<Result<()>> {
+        tokio::task::spawn_blocking(move || {
+            enable_raw_mode()?;
+            execute!(io::stdout(), EnterAlternateScreen)?;
+            let result = Terminal::new(CrosstermBackend::new(io::stdout()))
+                .map_err(Into::into)
+                .and_then(|mut terminal| self.event_loop(&mut terminal));
+            disable_raw_mode()?;
+            execute!(io::stdout(), LeaveAlternateScreen)?;
+            result
+        })
+    }
+
+    fn event_loop(mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
+        loop {
+            self.update_rate();
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(REFRESH_INTERVAL)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                KeyCode::Char('p') => {
+                    self.context.control.apply(Command::TogglePause);
+                }
+                KeyCode::Char('r') => {
+                    self.context.latency.reset();
+                    info!("Latency histograms reset");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn update_rate(&mut self) {
+        let (started_at, then) = &self.window;
+        let elapsed = started_at.elapsed();
+        if elapsed < RATE_WINDOW {
+            return;
+        }
+        let now = self.context.stats.snapshot();
+        self.rate = now.since(then).sent_frames as f64 / elapsed.as_secs_f64();
+        self.window = (Instant::now(), now);
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [status, input, latency, logs, help] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(4),
+                Constraint::Length(3),
+                Constraint::Length(6),
+                Constraint::Min(3),
+                Constraint::Length(1),
+            ])
+            .split(frame.size())[..]
+        else {
+            return;
+        };
+
+        let state = self
+            .context
+            .stats
+            .session_state(self.context.control.is_paused());
+        let stats = self.context.stats.snapshot();
+        let status_text = format!(
+            "state: {state}\nsent: {} frames, {:.1} Hz   congested: {} (longest streak {})   stalls: {}",
+            stats.sent_frames,
+            self.rate,
+            stats.congested_frames,
+            stats.max_congestion_streak,
+            stats.stalls,
+        );
+        frame.render_widget(
+            Paragraph::new(status_text)
+                .block(Block::default().borders(Borders::ALL).title("beatble")),
+            status,
+        );
+
+        let key_input = self.context.key_input.load();
+        frame.render_widget(
+            Paragraph::new(live_view::render(key_input, self.emulation))
+                .block(Block::default().borders(Borders::ALL).title("input")),
+            input,
+        );
+
+        let latency_block = Block::default().borders(Borders::ALL).title("latency");
+        let latency_area = latency_block.inner(latency);
+        frame.render_widget(latency_block, latency);
+        let [summary, sparkline] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(2), Constraint::Min(1)])
+            .split(latency_area)[..]
+        else {
+            return;
+        };
+        let latency_text = format!(
+            "frame interval {}\ndata age       {}",
+            self.context.latency.frame_interval.summary(),
+            self.context.latency.data_age.summary(),
+        );
+        frame.render_widget(Paragraph::new(latency_text), summary);
+        let counts = self.context.latency.data_age.counts();
+        frame.render_widget(Sparkline::default().data(&counts), sparkline);
+
+        let lines = self.log_tail.last(logs.height.saturating_sub(2) as usize);
+        frame.render_widget(
+            Paragraph::new(lines.join("\n"))
+                .block(Block::default().borders(Borders::ALL).title("log")),
+            logs,
+        );
+
+        frame.render_widget(
+            Paragraph::new("q quit   p pause/resume   r reset latency"),
+            help,
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+const CAPACITY: usize = 200;
+
+/// Layer that keeps the last lines in memory, for crash reports and for the
+/// dashboard, which output on stderr would tear through.
+#[derive(Clone, Default)]
+pub struct LogTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl LogTail {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// up to count of the newest lines, oldest first
+    pub fn last(&self, count: usize) -> Vec<String> {
+        match self.lines.lock() {
+            Ok(lines) => lines
+                .iter()
+                .skip(lines.len().saturating_sub(count))
+                .cloned()
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl<S> Layer<S> for LogTail
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut line = format!("{:5} ", metadata.level());
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                let _ = write!(line, "{}:", span.name());
+            }
+            line.push(' ');
+        }
+        let _ = write!(line, "{}:", metadata.target());
+        event.record(&mut LineVisitor(&mut line));
+
+        if let Ok(mut lines) = self.lines.lock() {
+            if lines.len() == CAPACITY {
+                lines.pop_front();
+            }
+            lines.push_back(line);
+        }
+    }
+}
+
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        // writing into a String can't fail
+        let _ = if field.name() == "message" {
+            write!(self.0, " {value:?}")
+        } else {
+            write!(self.0, " {}={value:?}", field.name())
+        };
+    }
+}
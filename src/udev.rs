@@ -0,0 +1,201 @@
+// `beatble setup-udev`: a udev rule for the connected controller, so beatble
+// can read it without root and find it under a stable name. The ids come
+// from the USB device the js node hangs off in sysfs, e.g.
+//
+//   /sys/class/input/js0
+//     -> /sys/devices/.../usb1/1-1/1-1:1.0/0003:1CCF:8048.0001/input/input5/js0
+//
+// where 1-1 has the idVendor and idProduct attributes.
+
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind as IoErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use beatble::exit::ErrorKind;
+use beatble::input::{device_info, list_devices};
+use eyre::{eyre, Result, WrapErr};
+use tracing::info;
+
+const SYS_CLASS_INPUT: &str = "/sys/class/input";
+const RULES_PATH: &str = "/etc/udev/rules.d/70-beatble-controller.rules";
+const SYMLINK: &str = "input/beatble-controller";
+
+/// USB vendor and product id, as the lowercase hex udev matches against.
+#[derive(Debug)]
+struct UsbIds {
+    vendor: String,
+    product: String,
+}
+
+impl fmt::Display for UsbIds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.vendor, self.product)
+    }
+}
+
+/// ids of the USB device above the js node at sys_class_input/name
+fn usb_ids(sys_class_input: &Path, name: &str) -> Result<UsbIds> {
+    let node = sys_class_input.join(name);
+    let device = fs::canonicalize(&node)
+        .wrap_err_with(|| format!("{} has no sysfs node", node.display()))?;
+    for dir in device.ancestors() {
+        let read = |attr: &str| fs::read_to_string(dir.join(attr));
+        if let (Ok(vendor), Ok(product)) = (read("idVendor"), read("idProduct")) {
+            return Ok(UsbIds {
+                vendor: vendor.trim().to_lowercase(),
+                product: product.trim().to_lowercase(),
+            });
+        }
+    }
+    Err(eyre!(
+        "{name} is not a USB device: no idVendor above {}",
+        device.display()
+    ))
+}
+
+fn rule(ids: &UsbIds, description: &str, group: &str) -> String {
+    format!(
+        "# {description} ({ids}), generated by `beatble setup-udev`\n\
+         KERNEL==\"js*\", SUBSYSTEM==\"input\", \\\n  \
+         ATTRS{{idVendor}}==\"{}\", ATTRS{{idProduct}}==\"{}\", \\\n  \
+         GROUP=\"{group}\", MODE=\"0660\", SYMLINK+=\"{SYMLINK}\"\n",
+        ids.vendor, ids.product
+    )
+}
+
+pub fn setup(device: Option<&str>, group: &str, install: bool) -> Result<()> {
+    let device = match device {
+        Some(device) => device.to_string(),
+        None => list_devices()
+            .wrap_err(ErrorKind::InputDevice)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| eyre!("no joystick device found, pass --device"))
+            .wrap_err(ErrorKind::InputDevice)?,
+    };
+    // js.phoenixwan and friends are symlinks to the real node
+    let node = fs::canonicalize(&device)
+        .wrap_err_with(|| format!("no such device: {device}"))
+        .wrap_err(ErrorKind::InputDevice)?;
+    let name = node
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ids = usb_ids(Path::new(SYS_CLASS_INPUT), &name).wrap_err(ErrorKind::InputDevice)?;
+    let description = match device_info(&device) {
+        Ok(info) => info.name,
+        Err(_) => name,
+    };
+
+    let rule = rule(&ids, &description, group);
+    print!("{rule}");
+    if !install {
+        return Ok(());
+    }
+
+    let path = PathBuf::from(RULES_PATH);
+    match fs::write(&path, &rule) {
+        Err(e) if e.kind() == IoErrorKind::PermissionDenied => {
+            eyre::bail!("writing {} needs root, run it with sudo", path.display())
+        }
+        result => result.wrap_err_with(|| format!("failed to write {}", path.display()))?,
+    }
+    info!("Installed {}", path.display());
+    udevadm(&["control", "--reload-rules"])?;
+    udevadm(&["trigger", "--subsystem-match=input", "--action=add"])?;
+    info!("Reloaded udev rules; the controller is at /dev/{SYMLINK}");
+    Ok(())
+}
+
+fn udevadm(args: &[&str]) -> Result<()> {
+    let status = Command::new("udevadm")
+        .args(args)
+        .status()
+        .wrap_err("failed to run udevadm")?;
+    if !status.success() {
+        eyre::bail!("udevadm {} failed: {status}", args.join(" "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::os::unix::fs::symlink;
+    use std::process;
+
+    use super::*;
+
+    /// a sysfs look-alike of its own for each test, with /sys/class/input
+    /// linking into /sys/devices like the kernel's
+    fn sysfs(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("beatble-udev-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("class/input")).expect("a temp dir");
+        dir
+    }
+
+    /// a js node under devices/parent, linked from class/input
+    fn js_node(sysfs: &Path, parent: &str, name: &str) {
+        let node = sysfs.join("devices").join(parent).join(name);
+        fs::create_dir_all(&node).expect("a temp dir");
+        symlink(&node, sysfs.join("class/input").join(name)).expect("a symlink");
+    }
+
+    const USB_DEVICE: &str = "pci0000:00/0000:00:14.0/usb1/1-1";
+    const USB_JS: &str =
+        "pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0/0003:1CCF:8048.0001/input/input5";
+
+    #[test]
+    fn the_ids_come_from_the_usb_device_above_the_node() {
+        let sysfs = sysfs("usb");
+        js_node(&sysfs, USB_JS, "js0");
+        let device = sysfs.join("devices").join(USB_DEVICE);
+        fs::write(device.join("idVendor"), "1CCF\n").unwrap();
+        fs::write(device.join("idProduct"), "8048\n").unwrap();
+        // the interface in between has attributes too, but not the ids
+        fs::write(device.join("1-1:1.0/bInterfaceNumber"), "00\n").unwrap();
+
+        let ids = usb_ids(&sysfs.join("class/input"), "js0").unwrap();
+        assert_eq!(
+            (ids.vendor.as_str(), ids.product.as_str()),
+            ("1ccf", "8048")
+        );
+        fs::remove_dir_all(sysfs).unwrap();
+    }
+
+    #[test]
+    fn a_node_without_a_usb_device_is_an_error() {
+        let sysfs = sysfs("virtual");
+        js_node(&sysfs, "virtual/input/input9", "js1");
+
+        let error = usb_ids(&sysfs.join("class/input"), "js1").unwrap_err();
+        assert!(
+            error.to_string().starts_with("js1 is not a USB device"),
+            "{error}"
+        );
+        let error = usb_ids(&sysfs.join("class/input"), "js2").unwrap_err();
+        assert!(
+            error.to_string().ends_with("js2 has no sysfs node"),
+            "{error}"
+        );
+        fs::remove_dir_all(sysfs).unwrap();
+    }
+
+    #[test]
+    fn the_rule_matches_the_ids_and_grants_the_group() {
+        let ids = UsbIds {
+            vendor: "1ccf".to_string(),
+            product: "8048".to_string(),
+        };
+        assert_eq!(
+            rule(&ids, "PHOENIXWAN", "input"),
+            "# PHOENIXWAN (1ccf:8048), generated by `beatble setup-udev`\n\
+             KERNEL==\"js*\", SUBSYSTEM==\"input\", \\\n  \
+             ATTRS{idVendor}==\"1ccf\", ATTRS{idProduct}==\"8048\", \\\n  \
+             GROUP=\"input\", MODE=\"0660\", SYMLINK+=\"input/beatble-controller\"\n"
+        );
+    }
+}
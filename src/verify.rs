@@ -0,0 +1,261 @@
+// BLE central that subscribes to a key input characteristic and checks the
+// notification stream, against beatble itself or a real controller. With
+// --ping it also writes tokens to beatble's ping characteristic and times
+// their echoes while the stream runs; with --from it checks the frames of a
+// capture or a payload dump instead.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use beatble::latency::Histogram;
+use beatble_protocol::payload::{Layout, PayloadFormat};
+use btleplug::api::{
+    bleuuid::uuid_from_u16, BDAddr, Central, Manager as _, Peripheral as _, ScanFilter, WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use clap::{Args, ValueHint};
+use eyre::{eyre, Result, WrapErr};
+use futures::StreamExt;
+use tokio::time::{interval, sleep, timeout, timeout_at, Instant, MissedTickBehavior};
+use tracing::{info, warn};
+
+use self::report::Report;
+
+pub mod capture;
+mod dump;
+mod report;
+
+pub const KEY_INPUT_CHARACTERISTIC_UUID: u16 = 0xFF01;
+const PING_CHARACTERISTIC_UUID: u16 = 0xFF05;
+// beatble echoes at most every 50ms
+const MIN_PING_INTERVAL: u64 = 50;
+const SCAN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// address of the peripheral to check
+    #[arg(long, value_name = "MAC", required_unless_present = "from")]
+    target: Option<String>,
+
+    /// check the frames of a `beatble capture` file or a --dump-payloads file instead
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, conflicts_with_all = ["target", "ping"])]
+    from: Option<PathBuf>,
+
+    /// how long to record notifications in seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    duration: u64,
+
+    /// expected notification interval in ms
+    #[arg(long, value_name = "DURATION", default_value_t = 8)]
+    sleep_duration: u64,
+
+    /// payload layout the peripheral is expected to send
+    #[arg(long, value_name = "FORMAT", default_value_t = PayloadFormat::V1)]
+    payload_format: PayloadFormat,
+
+    /// expect the scratch low byte in the reserved byte
+    #[arg(long)]
+    scratch_hires: bool,
+
+    /// expect a single sub-report per notification
+    #[arg(long)]
+    single_report: bool,
+
+    /// also measure round trips through the ping characteristic, every this many ms
+    #[arg(long, value_name = "DURATION")]
+    ping: Option<u64>,
+}
+
+pub async fn run(args: VerifyArgs) -> Result<()> {
+    let layout = Layout {
+        format: args.payload_format,
+        scratch_hires: args.scratch_hires,
+        single_report: args.single_report,
+    };
+    let mut report = Report::new(layout, Duration::from_millis(args.sleep_duration));
+    let round_trips = match &args.from {
+        Some(path) => {
+            replay(path, &mut report)?;
+            None
+        }
+        None => record(&args, &mut report).await?,
+    };
+
+    println!("{report}");
+    if let Some(round_trips) = round_trips {
+        println!("{round_trips}");
+    }
+    if report.frames() == 0 {
+        eyre::bail!("no notifications received");
+    }
+    if !report.is_clean() {
+        eyre::bail!("notification stream check failed");
+    }
+    Ok(())
+}
+
+/// Connects to target and discovers its GATT database.
+pub async fn connect(target: &str) -> Result<(BDAddr, Peripheral)> {
+    let target: BDAddr = target
+        .parse()
+        .wrap_err_with(|| format!("invalid address: {target}"))?;
+
+    let manager = Manager::new().await?;
+    let adapter = manager
+        .adapters()
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| eyre!("no bluetooth adapter found"))?;
+
+    info!("Scanning for {}", target);
+    let peripheral = timeout(SCAN_TIMEOUT, find_peripheral(&adapter, target))
+        .await
+        .wrap_err_with(|| format!("{target} not found"))??;
+
+    peripheral.connect().await?;
+    info!("Connected to {}", target);
+    peripheral.discover_services().await?;
+    Ok((target, peripheral))
+}
+
+/// Feeds the frames of a capture or payload dump to the report, on their own
+/// timestamps.
+fn replay(path: &Path, report: &mut Report) -> Result<()> {
+    let frames = dump::read_frames(path)?;
+    info!("Checking {} frames from {}", frames.len(), path.display());
+    let start = std::time::Instant::now();
+    let first = frames.first().map_or(0, |&(t, _)| t);
+    for (t, bytes) in frames {
+        report.record(
+            &bytes,
+            start + Duration::from_nanos(t.saturating_sub(first)),
+        );
+    }
+    Ok(())
+}
+
+/// Records the peripheral's notifications into the report; returns the ping
+/// round trip summary with --ping.
+async fn record(args: &VerifyArgs, report: &mut Report) -> Result<Option<String>> {
+    let target = args
+        .target
+        .as_deref()
+        .ok_or_else(|| eyre!("no target given: pass --target or --from"))?;
+    let (target, peripheral) = connect(target).await?;
+    let characteristic_uuid = uuid_from_u16(KEY_INPUT_CHARACTERISTIC_UUID);
+    let characteristic = peripheral
+        .characteristics()
+        .into_iter()
+        .find(|c| c.uuid == characteristic_uuid)
+        .ok_or_else(|| eyre!("{target} has no key input characteristic"))?;
+
+    let ping = match args.ping {
+        Some(ms) if ms < MIN_PING_INTERVAL => {
+            eyre::bail!("ping must be at least {MIN_PING_INTERVAL}ms, got {ms}ms");
+        }
+        Some(ms) => {
+            let ping_uuid = uuid_from_u16(PING_CHARACTERISTIC_UUID);
+            let ping = peripheral
+                .characteristics()
+                .into_iter()
+                .find(|c| c.uuid == ping_uuid)
+                .ok_or_else(|| eyre!("{target} has no ping characteristic"))?;
+            Some((ping, Duration::from_millis(ms)))
+        }
+        None => None,
+    };
+
+    let mut notifications = peripheral.notifications().await?;
+    peripheral.subscribe(&characteristic).await?;
+    if let Some((ping, _)) = &ping {
+        peripheral.subscribe(ping).await?;
+    }
+    info!("Recording notifications for {}s", args.duration);
+
+    let round_trip = Histogram::new();
+    // tokens in flight, by when they were written
+    let mut sent: HashMap<u32, Instant> = HashMap::new();
+    let mut token = 0u32;
+    let mut pings = interval(
+        ping.as_ref()
+            .map_or(Duration::from_secs(1), |&(_, every)| every),
+    );
+    pings.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let deadline = Instant::now() + Duration::from_secs(args.duration);
+    loop {
+        let next = tokio::select! {
+            next = timeout_at(deadline, notifications.next()) => next,
+            _ = pings.tick(), if ping.is_some() => {
+                let Some((ping, every)) = &ping else { continue };
+                // an echo that didn't come back within a second is lost
+                sent.retain(|_, at| at.elapsed() < Duration::from_secs(1).max(*every));
+                token = token.wrapping_add(1);
+                sent.insert(token, Instant::now());
+                peripheral
+                    .write(ping, &token.to_le_bytes(), WriteType::WithoutResponse)
+                    .await?;
+                continue;
+            }
+        };
+        match next {
+            Ok(Some(notification)) if notification.uuid == characteristic_uuid => {
+                report.record(&notification.value, Instant::now().into_std());
+            }
+            Ok(Some(notification))
+                if ping
+                    .as_ref()
+                    .is_some_and(|(p, _)| p.uuid == notification.uuid) =>
+            {
+                let echoed = <[u8; 4]>::try_from(notification.value.as_slice())
+                    .ok()
+                    .and_then(|token| sent.remove(&u32::from_le_bytes(token)));
+                match echoed {
+                    Some(at) => round_trip.record(at.elapsed().as_micros() as u64),
+                    None => warn!("unexpected ping echo: {:?}", notification.value),
+                }
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                warn!("peripheral disconnected before the recording finished");
+                break;
+            }
+            Err(_) => break,
+        }
+    }
+
+    // best effort, the report is what matters
+    let _ = peripheral.unsubscribe(&characteristic).await;
+    if let Some((ping, _)) = &ping {
+        let _ = peripheral.unsubscribe(ping).await;
+    }
+    let _ = peripheral.disconnect().await;
+
+    if ping.is_none() {
+        return Ok(None);
+    }
+    let summary = round_trip.summary();
+    Ok(Some(format!(
+        "ping round trips: {} echoed, {} lost, p50 {}us, p95 {}us, p99 {}us, max {}us",
+        summary.count,
+        u64::from(token) - summary.count,
+        summary.p50,
+        summary.p95,
+        summary.p99,
+        summary.max
+    )))
+}
+
+async fn find_peripheral(adapter: &Adapter, target: BDAddr) -> Result<Peripheral> {
+    adapter.start_scan(ScanFilter::default()).await?;
+    loop {
+        for peripheral in adapter.peripherals().await? {
+            if peripheral.address() == target {
+                adapter.stop_scan().await?;
+                return Ok(peripheral);
+            }
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+}
@@ -0,0 +1,119 @@
+// `beatble capture`: the central side of a real controller, recording its GATT
+// database and key input notifications as ground truth for the emulation.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use btleplug::api::{bleuuid::uuid_from_u16, CharPropFlags, Peripheral as _};
+use clap::{Args, ValueHint};
+use eyre::{eyre, Result};
+use futures::StreamExt;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use super::dump::CaptureWriter;
+use super::{connect, KEY_INPUT_CHARACTERISTIC_UUID};
+
+#[derive(Args)]
+pub struct CaptureArgs {
+    /// address of the controller to capture
+    #[arg(long, value_name = "MAC")]
+    target: String,
+
+    /// file to write the capture to, see the README for its format
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    out: PathBuf,
+
+    /// stop after this many seconds instead of on Ctrl-C
+    #[arg(long, value_name = "SECONDS")]
+    duration: Option<u64>,
+}
+
+pub async fn run(args: CaptureArgs) -> Result<()> {
+    let (target, peripheral) = connect(&args.target).await?;
+    let name = peripheral
+        .properties()
+        .await?
+        .and_then(|properties| properties.local_name);
+    let mut writer = CaptureWriter::create(&args.out, target, name.as_deref())?;
+
+    let services = peripheral.services();
+    let mut values = BTreeMap::new();
+    for service in &services {
+        for characteristic in &service.characteristics {
+            if !characteristic.properties.contains(CharPropFlags::READ) {
+                continue;
+            }
+            // some need pairing first; the rest of the database is still worth having
+            match peripheral.read(characteristic).await {
+                Ok(value) => {
+                    values.insert(characteristic.clone(), value);
+                }
+                Err(e) => warn!("failed to read {}: {e}", characteristic.uuid),
+            }
+        }
+    }
+    writer.gatt(&services, |characteristic| {
+        values.get(characteristic).map(Vec::as_slice)
+    })?;
+    info!(
+        "Discovered {} services and {} characteristics",
+        services.len(),
+        services
+            .iter()
+            .map(|service| service.characteristics.len())
+            .sum::<usize>()
+    );
+
+    let characteristic_uuid = uuid_from_u16(KEY_INPUT_CHARACTERISTIC_UUID);
+    let characteristic = peripheral
+        .characteristics()
+        .into_iter()
+        .find(|c| c.uuid == characteristic_uuid)
+        .ok_or_else(|| eyre!("{target} has no key input characteristic"))?;
+    let mut notifications = peripheral.notifications().await?;
+    peripheral.subscribe(&characteristic).await?;
+    match args.duration {
+        Some(seconds) => info!("Capturing notifications for {seconds}s"),
+        None => info!("Capturing notifications until Ctrl-C"),
+    }
+
+    let stop = async {
+        match args.duration {
+            Some(seconds) => sleep(Duration::from_secs(seconds)).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(stop);
+    let mut captured = 0u64;
+    loop {
+        tokio::select! {
+            notification = notifications.next() => match notification {
+                Some(notification) if notification.uuid == characteristic_uuid => {
+                    writer.notification(&notification.value)?;
+                    captured += 1;
+                }
+                Some(_) => {}
+                None => {
+                    warn!("controller disconnected before the capture finished");
+                    break;
+                }
+            },
+            _ = &mut stop => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    // best effort, the capture is what matters
+    let _ = peripheral.unsubscribe(&characteristic).await;
+    let _ = peripheral.disconnect().await;
+    writer.finish()?;
+    info!(
+        "Captured {captured} notifications to {}",
+        args.out.display()
+    );
+    if captured == 0 {
+        eyre::bail!("no notifications received");
+    }
+    Ok(())
+}
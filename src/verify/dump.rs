@@ -0,0 +1,151 @@
+// Capture file written by `beatble capture`, one JSON object per line:
+//
+//   {"beatble":"0.1.0","target":"AA:BB:..","name":"IIDX Entry model","started_at":...,"clock":...}
+//   {"service":"0000ff00-...","primary":true}                          one per service
+//   {"characteristic":"0000ff01-...","service":"0000ff00-...",
+//    "properties":["READ","NOTIFY"],"value":"00.."}                   one per characteristic
+//   {"descriptor":"00002902-...","characteristic":"0000ff01-..."}     one per descriptor
+//   {"t":12345678,"bytes":"00..."}                                    one per notification
+//
+// The GATT database comes first, in discovery order; value is only there for
+// a characteristic that could be read. Notification lines share t and bytes
+// with the frames of a --dump-payloads file: t is nanoseconds on the process
+// clock, the header's clock is the same clock when the file was created and
+// started_at the matching wall-clock time in unix milliseconds. `beatble verify
+// --from` checks the frames of either kind of file and skips every other line.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use beatble::clock;
+use btleplug::api::{BDAddr, Characteristic, Service};
+use eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+use serde_json::json;
+
+/// Writes a capture as it is recorded.
+pub struct CaptureWriter(BufWriter<File>);
+
+impl CaptureWriter {
+    pub fn create(path: &Path, target: BDAddr, name: Option<&str>) -> Result<Self> {
+        let file =
+            File::create(path).wrap_err_with(|| format!("failed to create {}", path.display()))?;
+        let mut writer = Self(BufWriter::new(file));
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        writer.line(json!({
+            "beatble": env!("VERSION"),
+            "target": target.to_string(),
+            "name": name,
+            "started_at": started_at,
+            "clock": clock::now(),
+        }))?;
+        Ok(writer)
+    }
+
+    /// The GATT database, with what reading a characteristic returned, if it
+    /// could be read.
+    pub fn gatt<'a>(
+        &mut self,
+        services: impl IntoIterator<Item = &'a Service>,
+        value: impl Fn(&Characteristic) -> Option<&'a [u8]>,
+    ) -> Result<()> {
+        for service in services {
+            self.line(json!({
+                "service": service.uuid.to_string(),
+                "primary": service.primary,
+            }))?;
+            for characteristic in &service.characteristics {
+                let properties: Vec<_> = characteristic
+                    .properties
+                    .iter_names()
+                    .map(|(name, _)| name)
+                    .collect();
+                let mut line = json!({
+                    "characteristic": characteristic.uuid.to_string(),
+                    "service": service.uuid.to_string(),
+                    "properties": properties,
+                });
+                if let Some(bytes) = value(characteristic) {
+                    line["value"] = hex(bytes).into();
+                }
+                self.line(line)?;
+                for descriptor in &characteristic.descriptors {
+                    self.line(json!({
+                        "descriptor": descriptor.uuid.to_string(),
+                        "characteristic": characteristic.uuid.to_string(),
+                    }))?;
+                }
+            }
+        }
+        self.0.flush()?;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn notification(&mut self, bytes: &[u8]) -> io::Result<()> {
+        writeln!(
+            self.0,
+            r#"{{"t":{},"bytes":"{}"}}"#,
+            clock::now(),
+            hex(bytes)
+        )
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn line(&mut self, value: serde_json::Value) -> io::Result<()> {
+        writeln!(self.0, "{value}")
+    }
+}
+
+#[derive(Deserialize)]
+struct FrameLine {
+    t: u64,
+    bytes: String,
+}
+
+/// The frames of a capture or a payload dump, as (t, bytes).
+pub fn read_frames(path: &Path) -> Result<Vec<(u64, Vec<u8>)>> {
+    let file = File::open(path).wrap_err_with(|| format!("failed to open {}", path.display()))?;
+    let mut frames = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let at = || format!("{}:{}", path.display(), number + 1);
+        let value: serde_json::Value =
+            serde_json::from_str(&line).wrap_err_with(|| format!("{}: not JSON", at()))?;
+        if value.get("bytes").is_none() {
+            continue;
+        }
+        let frame: FrameLine =
+            serde_json::from_value(value).wrap_err_with(|| format!("{}: not a frame", at()))?;
+        let bytes = unhex(&frame.bytes).ok_or_else(|| eyre!("{}: bytes are not hex", at()))?;
+        frames.push((frame.t, bytes));
+    }
+    Ok(frames)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => u8::from_str_radix(std::str::from_utf8(&[*high, *low]).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,171 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use beatble_protocol::payload::{DecodeError, Frame, Layout, COUNTER};
+
+// only the first few gaps are listed, the rest are counted
+const MAX_LISTED_GAPS: usize = 20;
+
+#[derive(Clone, Copy, Debug)]
+struct Gap {
+    frame: u64,
+    expected: u8,
+    actual: u8,
+    missed: u8,
+}
+
+/// Stream statistics collected while verifying a peripheral.
+#[derive(Debug)]
+pub struct Report {
+    layout: Layout,
+    expected_interval: Duration,
+    frames: u64,
+    bad_length: u64,
+    bad_reserved: u64,
+    bad_sub_counter: u64,
+    // frames with the counter of the one before, from --frame-repeat
+    repeats: u64,
+    gaps: Vec<Gap>,
+    gap_count: u64,
+    missed_frames: u64,
+    last_counter: Option<u8>,
+    first_at: Option<Instant>,
+    last_at: Option<Instant>,
+    max_interval: Duration,
+}
+
+impl Report {
+    pub fn new(layout: Layout, expected_interval: Duration) -> Self {
+        Self {
+            layout,
+            expected_interval,
+            frames: 0,
+            bad_length: 0,
+            bad_reserved: 0,
+            bad_sub_counter: 0,
+            repeats: 0,
+            gaps: Vec::new(),
+            gap_count: 0,
+            missed_frames: 0,
+            last_counter: None,
+            first_at: None,
+            last_at: None,
+            max_interval: Duration::ZERO,
+        }
+    }
+
+    pub fn record(&mut self, bytes: &[u8], at: Instant) {
+        self.frames += 1;
+        if let Some(last_at) = self.last_at {
+            self.max_interval = self.max_interval.max(at - last_at);
+        }
+        self.first_at.get_or_insert(at);
+        self.last_at = Some(at);
+
+        let counter = match Frame::decode(bytes, self.layout) {
+            Ok(frame) => frame.counter,
+            Err(DecodeError::Length { .. }) => {
+                self.bad_length += 1;
+                // counters can't be trusted in a truncated frame
+                self.last_counter = None;
+                return;
+            }
+            Err(DecodeError::Reserved { .. }) => {
+                self.bad_reserved += 1;
+                bytes[COUNTER]
+            }
+            Err(DecodeError::SubReportCounter { .. }) => {
+                self.bad_sub_counter += 1;
+                bytes[COUNTER]
+            }
+        };
+
+        if self.last_counter == Some(counter) {
+            self.repeats += 1;
+            return;
+        }
+        if let Some(last_counter) = self.last_counter {
+            let step = self.layout.counter_step();
+            let expected = last_counter.wrapping_add(step);
+            if counter != expected {
+                let missed = counter.wrapping_sub(last_counter) / step;
+                self.gap_count += 1;
+                self.missed_frames += missed.saturating_sub(1) as u64;
+                if self.gaps.len() < MAX_LISTED_GAPS {
+                    self.gaps.push(Gap {
+                        frame: self.frames,
+                        expected,
+                        actual: counter,
+                        missed: missed.saturating_sub(1),
+                    });
+                }
+            }
+        }
+        self.last_counter = Some(counter);
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// true when no anomaly was seen
+    pub fn is_clean(&self) -> bool {
+        self.bad_length == 0
+            && self.bad_reserved == 0
+            && self.bad_sub_counter == 0
+            && self.gap_count == 0
+    }
+
+    fn elapsed(&self) -> Duration {
+        match (self.first_at, self.last_at) {
+            (Some(first_at), Some(last_at)) => last_at - first_at,
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let elapsed = self.elapsed();
+        writeln!(f, "frames: {} in {:?}", self.frames, elapsed)?;
+        if self.frames > 1 && !elapsed.is_zero() {
+            let rate = (self.frames - 1) as f64 / elapsed.as_secs_f64();
+            let expected_rate = 1.0 / self.expected_interval.as_secs_f64();
+            writeln!(
+                f,
+                "rate: {:.1} Hz (expected {:.1} Hz), longest interval {:?}",
+                rate, expected_rate, self.max_interval
+            )?;
+        }
+        writeln!(f, "payload length mismatches: {}", self.bad_length)?;
+        writeln!(f, "reserved byte mismatches: {}", self.bad_reserved)?;
+        writeln!(f, "sub-report counter mismatches: {}", self.bad_sub_counter)?;
+        if self.repeats > 0 {
+            writeln!(f, "repeated frames: {}", self.repeats)?;
+        }
+        writeln!(
+            f,
+            "counter gaps: {} ({} frames missed)",
+            self.gap_count, self.missed_frames
+        )?;
+        for gap in &self.gaps {
+            writeln!(
+                f,
+                "  frame {}: expected counter {}, got {} ({} missed)",
+                gap.frame, gap.expected, gap.actual, gap.missed
+            )?;
+        }
+        if self.gap_count > self.gaps.len() as u64 {
+            writeln!(f, "  ...")?;
+        }
+        write!(
+            f,
+            "result: {}",
+            if self.is_clean() {
+                "ok"
+            } else {
+                "anomalies found"
+            }
+        )
+    }
+}
@@ -0,0 +1,104 @@
+// Heap allocations of the notifier in steady state, counted by a global
+// allocator. A test binary of its own, as the allocator replaces the one of
+// every test in its binary. The payloads are taken before they are copied for
+// bluster, which allocates per frame as long as its API takes a Vec<u8>; see
+// benches/notify.rs for the whole path.
+
+use std::alloc::{GlobalAlloc, Layout as AllocLayout, System};
+use std::cell::Cell;
+use std::sync::Arc;
+use std::time::Duration;
+
+use beatble::ble::{spawn_local_payloads, NotifyConfig, NotifyContext, NotifyMode, RepeatSpacing};
+use beatble::control::Control;
+use beatble::emulation::Emulation;
+use beatble::input::SharedKeyInput;
+use beatble::latency::Latency;
+use beatble::payload::{Frame, Layout, Payload, ScratchMode};
+use beatble::stats::Stats;
+use beatble_protocol::{KeyInput, NormalButton};
+use tokio::sync::mpsc::Receiver;
+
+// per thread, so the harness's own allocations don't count
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: AllocLayout) -> *mut u8 {
+        // the thread local is gone while its thread exits
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: AllocLayout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+async fn next(payloads: &mut Receiver<Payload>) -> Frame {
+    let payload = payloads.recv().await.expect("the notifier stopped");
+    Frame::decode(payload.as_bytes(), Layout::default()).expect("a valid frame")
+}
+
+// past the first timer registrations and the channel's first blocks
+const WARMUP: u8 = 100;
+
+// the current-thread runtime of tokio::test runs the notifier on this thread
+#[tokio::test(start_paused = true)]
+async fn steady_state_frames_allocate_nothing() {
+    let interval = Duration::from_millis(8);
+    let context = NotifyContext {
+        key_input: Arc::new(SharedKeyInput::new()),
+        control: Arc::new(Control::new(interval, NotifyMode::Periodic)),
+        latency: Arc::new(Latency::new()),
+        stats: Arc::new(Stats::new()),
+        dump: None,
+    };
+    let config = NotifyConfig {
+        interval,
+        mode: NotifyMode::Periodic,
+        warmup_frames: 3,
+        counter_start: 0,
+        // repeats too
+        frame_repeat: 2,
+        repeat_spacing: RepeatSpacing::BackToBack,
+        busy_poll: false,
+        layout: Layout::default(),
+        emulation: Emulation::Iidx,
+        scratch_mode: ScratchMode::Position,
+        scratch_predict: None,
+    };
+    let key_input = Arc::clone(&context.key_input);
+    let mut payloads = spawn_local_payloads(context, config);
+    let press = |buttons: u8| {
+        key_input.store(KeyInput {
+            normal_button: NormalButton::from_bits_truncate(buttons),
+            ..KeyInput::init()
+        })
+    };
+
+    for buttons in 0..WARMUP {
+        next(&mut payloads).await;
+        press(buttons);
+    }
+    let before = allocations();
+    let mut sent = 0;
+    for buttons in 0..=u8::MAX {
+        let frame = next(&mut payloads).await;
+        let repeat = next(&mut payloads).await;
+        assert_eq!(frame.counter, repeat.counter);
+        press(buttons);
+        sent += 2;
+    }
+    assert_eq!(allocations() - before, 0, "in {sent} frames");
+}